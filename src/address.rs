@@ -0,0 +1,141 @@
+//! Server addresses like players type them: `host`, `host:port` or `[ipv6]:port`. Without a port,
+//! the Minecraft client looks for a `_minecraft._tcp.<host>` SRV record first, so the bot does too
+
+use hickory_resolver::net::NetError;
+use hickory_resolver::proto::rr::RData;
+use hickory_resolver::TokioResolver;
+use std::net::IpAddr;
+
+/// Splits the port off an address. `None` if the port isn't a number
+pub fn parse_address(address: &str) -> Option<(String, Option<u16>)> {
+  let address = address.trim();
+
+  if let Some(rest) = address.strip_prefix('[') {
+    let (host, rest) = rest.split_once(']')?;
+    let port = match rest.strip_prefix(':') {
+      Some(port) => Some(port.parse().ok()?),
+      None if rest.is_empty() => None,
+      None => return None,
+    };
+    return (!host.is_empty()).then(|| (host.to_string(), port));
+  }
+
+  let (host, port) = match address.split_once(':') {
+    // A bare IPv6 address has several colons and no port
+    Some(_) if address.parse::<IpAddr>().is_ok() => (address, None),
+    Some((host, port)) => (host, Some(port.parse().ok()?)),
+    None => (address, None),
+  };
+  (!host.is_empty()).then(|| (host.to_string(), port))
+}
+
+/// Where the Minecraft client would connect to, from an SRV record
+#[derive(Debug, PartialEq)]
+pub struct SrvRecord {
+  pub priority: u16,
+  pub weight: u16,
+  pub port: u16,
+  pub target: String,
+}
+
+/// The record the Minecraft client would pick: the lowest priority, then the heaviest weight.
+/// A target of "." means the host explicitly has no Minecraft server
+fn preferred_record(mut records: Vec<SrvRecord>) -> Option<SrvRecord> {
+  records.sort_by_key(|record| (record.priority, u16::MAX - record.weight));
+
+  records
+    .into_iter()
+    .next()
+    .filter(|record| !record.target.is_empty())
+}
+
+/// Looks up the `_minecraft._tcp` SRV record of the host with the system's resolver config.
+/// `None` if it has none
+pub async fn lookup_minecraft_srv(host: &str) -> Result<Option<SrvRecord>, NetError> {
+  let resolver = TokioResolver::builder_tokio()?.build()?;
+
+  let lookup = match resolver
+    .srv_lookup(format!("_minecraft._tcp.{host}."))
+    .await
+  {
+    Ok(lookup) => lookup,
+    Err(error) if error.is_no_records_found() => return Ok(None),
+    Err(error) => return Err(error),
+  };
+
+  // Answers can also be the CNAMEs that led to the record
+  let records = lookup
+    .answers()
+    .iter()
+    .filter_map(|record| match &record.data {
+      RData::SRV(srv) => Some(SrvRecord {
+        priority: srv.priority,
+        weight: srv.weight,
+        port: srv.port,
+        target: srv.target.to_string().trim_end_matches('.').to_string(),
+      }),
+      _ => None,
+    })
+    .collect();
+
+  Ok(preferred_record(records))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn addresses_are_split_into_host_and_port() {
+    assert_eq!(
+      parse_address("play.example.com"),
+      Some(("play.example.com".to_string(), None))
+    );
+    assert_eq!(
+      parse_address("play.example.com:25570"),
+      Some(("play.example.com".to_string(), Some(25570)))
+    );
+    assert_eq!(
+      parse_address("[2001:db8::1]:25570"),
+      Some(("2001:db8::1".to_string(), Some(25570)))
+    );
+    assert_eq!(
+      parse_address("2001:db8::1"),
+      Some(("2001:db8::1".to_string(), None))
+    );
+    assert_eq!(parse_address("play.example.com:port"), None);
+    assert_eq!(parse_address(""), None);
+    assert_eq!(parse_address("  "), None);
+    assert_eq!(parse_address(":25565"), None);
+    assert_eq!(parse_address("[]:25565"), None);
+  }
+
+  fn record(priority: u16, weight: u16, target: &str) -> SrvRecord {
+    SrvRecord {
+      priority,
+      weight,
+      port: 25565,
+      target: target.to_string(),
+    }
+  }
+
+  #[test]
+  fn the_lowest_priority_and_heaviest_weight_is_preferred() {
+    let records = vec![
+      record(10, 100, "backup.example.com"),
+      record(5, 10, "light.example.com"),
+      record(5, 50, "heavy.example.com"),
+    ];
+
+    assert_eq!(
+      preferred_record(records),
+      Some(record(5, 50, "heavy.example.com"))
+    );
+  }
+
+  #[test]
+  fn an_empty_target_means_no_server() {
+    assert_eq!(preferred_record(vec![record(0, 0, "")]), None);
+    assert_eq!(preferred_record(Vec::new()), None);
+  }
+}
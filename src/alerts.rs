@@ -0,0 +1,243 @@
+//! Notices for the operator when background work keeps failing. A single failure is only a log
+//! line, a failure that lasts gets a message, and so does its end
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// The kinds of background work whose failures are counted separately
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Operation {
+  ChannelRename,
+  /// Per server, so one being down doesn't hide another
+  RconConnect(String),
+  Mojang,
+}
+
+impl fmt::Display for Operation {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::ChannelRename => write!(f, "Renaming the status channel"),
+      Self::RconConnect(server) => write!(f, "Connecting to the {server} server over RCON"),
+      Self::Mojang => write!(f, "Looking up accounts at Mojang"),
+    }
+  }
+}
+
+/// When a failing operation is worth a notice
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AlertThresholds {
+  /// Failures in a row
+  pub failures: u32,
+  /// Or failing for this long, for work that doesn't run often
+  pub downtime: Duration,
+  /// The least time between two notices about the same operation, so a flapping one can't spam
+  pub cooldown: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Alert {
+  Failing {
+    operation: Operation,
+    failures: u32,
+    failing_for: Duration,
+    /// The latest error
+    error: String,
+  },
+  Recovered {
+    operation: Operation,
+    downtime: Duration,
+  },
+}
+
+/// Like "12 minutes", or "less than a minute"
+fn format_minutes(duration: Duration) -> String {
+  match duration.as_secs() / 60 {
+    0 => "less than a minute".to_string(),
+    1 => "1 minute".to_string(),
+    minutes => format!("{minutes} minutes"),
+  }
+}
+
+impl Alert {
+  pub fn message(&self) -> String {
+    match self {
+      Self::Failing {
+        operation,
+        failures,
+        failing_for,
+        error,
+      } => format!(
+        "{operation} failed {failures} times in a row, for {}: {error}",
+        format_minutes(*failing_for)
+      ),
+      Self::Recovered {
+        operation,
+        downtime,
+      } => format!(
+        "{operation} works again, after failing for {}",
+        format_minutes(*downtime)
+      ),
+    }
+  }
+}
+
+#[derive(Default)]
+struct Failures {
+  consecutive: u32,
+  since: Option<Instant>,
+  /// Whether a notice about the current failure went out, so its end gets one too
+  alerted: bool,
+  /// Kept after recovering, for the cooldown
+  last_alert: Option<Instant>,
+}
+
+/// Counts the failures in a row of each operation, and decides when they're worth a notice
+pub struct ErrorReporter {
+  thresholds: AlertThresholds,
+  operations: HashMap<Operation, Failures>,
+}
+
+impl ErrorReporter {
+  pub fn new(thresholds: AlertThresholds) -> Self {
+    Self {
+      thresholds,
+      operations: HashMap::new(),
+    }
+  }
+
+  pub fn thresholds(&self) -> AlertThresholds {
+    self.thresholds
+  }
+
+  /// Counts a failure. Returns the notice to send, once per failure that lasts long enough
+  pub fn failed(&mut self, operation: Operation, error: &str, now: Instant) -> Option<Alert> {
+    let failures = self.operations.entry(operation.clone()).or_default();
+    failures.consecutive += 1;
+    let failing_for = now.duration_since(*failures.since.get_or_insert(now));
+
+    if failures.alerted
+      || (failures.consecutive < self.thresholds.failures && failing_for < self.thresholds.downtime)
+    {
+      return None;
+    }
+    // Still failing, so it's sent once the cooldown is over
+    if failures
+      .last_alert
+      .is_some_and(|last_alert| now.duration_since(last_alert) < self.thresholds.cooldown)
+    {
+      return None;
+    }
+
+    failures.alerted = true;
+    failures.last_alert = Some(now);
+    Some(Alert::Failing {
+      operation,
+      failures: failures.consecutive,
+      failing_for,
+      error: error.to_string(),
+    })
+  }
+
+  /// Resets the count. Returns the all clear if there was a notice about the failure
+  pub fn succeeded(&mut self, operation: &Operation, now: Instant) -> Option<Alert> {
+    let failures = self.operations.get_mut(operation)?;
+    let since = failures.since.take();
+    failures.consecutive = 0;
+
+    if !std::mem::take(&mut failures.alerted) {
+      return None;
+    }
+    Some(Alert::Recovered {
+      operation: operation.clone(),
+      downtime: since.map_or(Duration::ZERO, |since| now.duration_since(since)),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const THRESHOLDS: AlertThresholds = AlertThresholds {
+    failures: 3,
+    downtime: Duration::from_secs(30 * 60),
+    cooldown: Duration::from_secs(60 * 60),
+  };
+
+  #[test]
+  fn lasting_failures_are_reported_once_and_so_is_the_recovery() {
+    let mut reporter = ErrorReporter::new(THRESHOLDS);
+    let start = Instant::now();
+    let minute = Duration::from_secs(60);
+
+    assert_eq!(reporter.failed(Operation::Mojang, "timed out", start), None);
+    assert_eq!(
+      reporter.failed(Operation::Mojang, "timed out", start + minute),
+      None
+    );
+    assert_eq!(
+      reporter.failed(Operation::Mojang, "timed out", start + minute * 2),
+      Some(Alert::Failing {
+        operation: Operation::Mojang,
+        failures: 3,
+        failing_for: minute * 2,
+        error: "timed out".to_string(),
+      })
+    );
+    assert_eq!(
+      reporter.failed(Operation::Mojang, "timed out", start + minute * 3),
+      None
+    );
+
+    // Other operations are counted on their own
+    assert_eq!(
+      reporter.succeeded(&Operation::ChannelRename, start + minute * 4),
+      None
+    );
+    assert_eq!(
+      reporter
+        .succeeded(&Operation::Mojang, start + minute * 4)
+        .unwrap()
+        .message(),
+      "Looking up accounts at Mojang works again, after failing for 4 minutes"
+    );
+  }
+
+  #[test]
+  fn a_flapping_operation_waits_out_the_cooldown() {
+    let mut reporter = ErrorReporter::new(THRESHOLDS);
+    let start = Instant::now();
+    let operation = Operation::RconConnect("survival".to_string());
+
+    // Failing for long enough counts too
+    assert!(reporter
+      .failed(operation.clone(), "refused", start)
+      .is_none());
+    assert!(reporter
+      .failed(operation.clone(), "refused", start + THRESHOLDS.downtime)
+      .is_some());
+    assert!(reporter
+      .succeeded(&operation, start + THRESHOLDS.downtime)
+      .is_some());
+
+    let later = start + THRESHOLDS.downtime + Duration::from_secs(60);
+    for _ in 0..5 {
+      assert!(reporter
+        .failed(operation.clone(), "refused", later)
+        .is_none());
+    }
+    // Without a notice about it, the recovery isn't worth one either
+    assert!(reporter.succeeded(&operation, later).is_none());
+
+    let after_cooldown = start + THRESHOLDS.downtime + THRESHOLDS.cooldown;
+    for _ in 0..2 {
+      assert!(reporter
+        .failed(operation.clone(), "refused", after_cooldown)
+        .is_none());
+    }
+    assert!(reporter
+      .failed(operation.clone(), "refused", after_cooldown)
+      .is_some());
+  }
+}
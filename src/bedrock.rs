@@ -0,0 +1,73 @@
+//! Status of Bedrock servers (or a Geyser proxy) through the RakNet unconnected ping
+
+use std::io;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time;
+
+const UNCONNECTED_PING: u8 = 0x01;
+const UNCONNECTED_PONG: u8 = 0x1c;
+const MAGIC: [u8; 16] = [
+  0x00, 0xff, 0xff, 0x00, 0xfe, 0xfe, 0xfe, 0xfe, 0xfd, 0xfd, 0xfd, 0xfd, 0x12, 0x34, 0x56, 0x78,
+];
+
+/// UDP has no connection to fail, so a server that is down just never answers
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct BedrockStatus {
+  pub players_online: u32,
+  pub players_max: u32,
+}
+
+fn invalid_data(message: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Parses the server id string, like "MCPE;Motd;622;1.20.40;2;20;..."
+fn parse_server_id(server_id: &str) -> io::Result<BedrockStatus> {
+  let fields: Vec<&str> = server_id.split(';').collect();
+
+  let players_online = fields
+    .get(4)
+    .and_then(|online| online.parse().ok())
+    .ok_or_else(|| invalid_data("missing online player count"))?;
+  let players_max = fields
+    .get(5)
+    .and_then(|max| max.parse().ok())
+    .ok_or_else(|| invalid_data("missing max player count"))?;
+
+  Ok(BedrockStatus {
+    players_online,
+    players_max,
+  })
+}
+
+pub async fn status(host: &str, port: u16) -> io::Result<BedrockStatus> {
+  let socket = UdpSocket::bind("0.0.0.0:0").await?;
+  socket.connect((host, port)).await?;
+
+  let mut ping = vec![UNCONNECTED_PING];
+  ping.extend_from_slice(&0i64.to_be_bytes());
+  ping.extend_from_slice(&MAGIC);
+  ping.extend_from_slice(&0i64.to_be_bytes());
+  socket.send(&ping).await?;
+
+  let mut pong = [0; 1500];
+  let length = time::timeout(PING_TIMEOUT, socket.recv(&mut pong))
+    .await
+    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no answer to the ping"))??;
+  let pong = &pong[..length];
+
+  // id (1) + time (8) + server guid (8) + magic (16) + string length (2)
+  let header_length = 1 + 8 + 8 + 16 + 2;
+  if pong.len() < header_length || pong[0] != UNCONNECTED_PONG {
+    return Err(invalid_data("unexpected answer to the ping"));
+  }
+
+  let string_length = u16::from_be_bytes([pong[33], pong[34]]) as usize;
+  let server_id = pong
+    .get(header_length..header_length + string_length)
+    .ok_or_else(|| invalid_data("truncated server id"))?;
+
+  parse_server_id(&String::from_utf8_lossy(server_id))
+}
@@ -0,0 +1,2071 @@
+//! The Discord side of the bot: the event handler, the slash commands and the background tasks
+
+use crate::alerts::{Alert, ErrorReporter, Operation};
+use crate::chat::{ChatLine, LogTail};
+use crate::commands::{
+  ImportReport, Mojang, MojangApi, OfflineProfiles, Rcon, RconConnector, Reply, ReplyEmbed,
+  ServerInfo, VerifyOutcome, VerifyRequest, WhoisAccount,
+};
+use crate::config::{
+  ChatBridgeConfig, Config, GuildConfig, ReconcileConfig, ServerConfig, Settings, WebVerifyConfig,
+};
+use crate::error::BotError;
+use crate::geyser::{floodgate_xuid, validate_gamertag, Floodgate};
+use crate::logging::{self, LogFormat};
+use crate::messages::{MessageKey, Messages};
+use crate::metrics::{self, Metrics, METRICS};
+use crate::mojang::*;
+use crate::observers::{
+  AccountAction, AccountEvent, AccountOutcome, AuditChannelObserver, VerificationObserver,
+};
+use crate::offline::offline_uuid;
+use crate::rcon::{self, *};
+use crate::schedule::{Cron, Schedule, Timezone};
+use crate::status::*;
+use crate::store::{
+  Link, LinkCode, ModerationAction, ModerationKind, OperationKind, PendingOperation,
+  PendingVerification, Platform, StatusState, Store, StoreData, UnclaimedEntry, UnmanagedEntry,
+  UnvalidatedVerification, WebVerifyToken,
+};
+use crate::{address, bedrock, chat, ping, web};
+use chrono::{DateTime, Days, FixedOffset, NaiveDate, NaiveTime, Timelike, Utc};
+use mc_query::rcon::RconClient;
+use mc_query::status::ChatObject;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use regex::Regex;
+use serenity::all::*;
+use serenity::async_trait;
+use serenity::Error as SerenityError;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use subtle::ConstantTimeEq;
+use tokio::net::TcpListener;
+use tokio::sync::{oneshot, watch, Semaphore};
+use tokio::time;
+use tracing::{debug, error, info, warn, Instrument};
+
+mod background;
+mod commands;
+mod console;
+mod moderation;
+mod status;
+mod verify;
+
+pub use background::parse_role_ttl_days;
+use background::VerificationExpiry;
+use commands::{
+  can_manage_roles, is_admin, tell_something_went_wrong, CommandState, COMMAND_TIMEOUT,
+};
+use console::{tellraw_command, Console, RconAuthFailures, RconBackoff, ServerConnector};
+use moderation::{forget_accounts, DepartedAccounts};
+use status::{QueryAddress, StatusMonitor};
+use verify::{generate_link_code, AttemptLimiter, StaffTier, VerifyChallenge, WelcomeDm};
+pub use verify::{parse_role_commands, MojangOutagePolicy, RoleCommand, VerifyHours};
+
+/// The reason given to the server when an account is banned because of a Discord ban
+const DISCORD_BAN_REASON: &str = "Banned from Discord";
+
+/// How old an audit log entry can be to still belong to a ban or kick that just happened
+const AUDIT_LOG_MAX_AGE: Duration = Duration::from_secs(30);
+
+/// Longer Discord messages are split over several lines of in-game chat
+const CHAT_PART_LENGTH: usize = 256;
+
+/// A message has room for 5 rows of 5 buttons
+const MAX_VERIFY_BUTTONS: usize = 25;
+
+/// How many recent messages of the verify channel are searched for the verify info message
+const VERIFY_INFO_SEARCH_LIMIT: u8 = 50;
+
+/// How long verifications that are still running get to finish when the bot shuts down
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(8);
+
+/// The parts of the guild the bot works with. Kept fresh from the HTTP API, so the handlers don't
+/// depend on the gateway cache being warm
+#[derive(Clone, Debug)]
+struct GuildSnapshot {
+  id: GuildId,
+  name: String,
+  roles: HashMap<RoleId, Role>,
+  channels: HashMap<ChannelId, GuildChannel>,
+}
+
+impl GuildSnapshot {
+  /// Fetches the guild the channel is in
+  async fn fetch(http: &Http, channel_id: ChannelId) -> Result<Self, SerenityError> {
+    let guild_id = http
+      .get_channel(channel_id)
+      .await?
+      .guild()
+      .ok_or(SerenityError::Model(ModelError::InvalidChannelType))?
+      .guild_id;
+
+    Self::fetch_guild(http, guild_id).await
+  }
+
+  async fn fetch_guild(http: &Http, guild_id: GuildId) -> Result<Self, SerenityError> {
+    let guild = http.get_guild(guild_id).await?;
+    let channels = guild_id.channels(http).await?;
+
+    Ok(Self {
+      id: guild_id,
+      name: guild.name,
+      roles: guild.roles,
+      channels,
+    })
+  }
+
+  fn role_by_name(&self, role_name: &str) -> Option<&Role> {
+    self.roles.values().find(|role| role.name == role_name)
+  }
+}
+
+/// How a failed Discord request should be treated
+#[derive(Debug, PartialEq)]
+enum DiscordFailure {
+  /// Server errors, rate limits and network problems, worth retrying
+  Transient,
+  /// Missing permissions, unknown members and the like, retrying won't help
+  Permanent,
+}
+
+fn classify_discord_error(error: &SerenityError) -> DiscordFailure {
+  match error {
+    SerenityError::Http(HttpError::UnsuccessfulRequest(response)) => {
+      classify_discord_status(response.status_code)
+    }
+    SerenityError::Http(HttpError::Request(error)) if error.is_timeout() || error.is_connect() => {
+      DiscordFailure::Transient
+    }
+    SerenityError::Io(_) => DiscordFailure::Transient,
+    _ => DiscordFailure::Permanent,
+  }
+}
+
+fn classify_discord_status(status: StatusCode) -> DiscordFailure {
+  if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+    DiscordFailure::Transient
+  } else {
+    DiscordFailure::Permanent
+  }
+}
+
+/// Whether Discord rejected the request with "Missing Permissions" (code 50013)
+fn is_missing_permissions(error: &SerenityError) -> bool {
+  matches!(
+    error,
+    SerenityError::Http(HttpError::UnsuccessfulRequest(response)) if response.error.code == 50013
+  )
+}
+
+/// Whether Discord rejected the request with "Unknown Message" (code 10008), e.g. it was deleted
+fn is_unknown_message(error: &SerenityError) -> bool {
+  matches!(
+    error,
+    SerenityError::Http(HttpError::UnsuccessfulRequest(response)) if response.error.code == 10008
+  )
+}
+
+/// Whether Discord rejected the request with "Unknown Member" (code 10007), e.g. they left
+fn is_unknown_member(error: &SerenityError) -> bool {
+  matches!(
+    error,
+    SerenityError::Http(HttpError::UnsuccessfulRequest(response)) if response.error.code == 10007
+  )
+}
+
+/// DMs a user who might not be in the cache anymore
+async fn direct_message(
+  ctx: &Context,
+  user_id: UserId,
+  message: CreateMessage,
+) -> Result<Message, SerenityError> {
+  user_id
+    .create_dm_channel(ctx)
+    .await?
+    .send_message(ctx, message)
+    .await
+}
+
+/// Joins the entries for an embed field, leaving out what doesn't fit in Discord's 1024 characters
+fn limited_list(entries: &[String]) -> String {
+  let mut list = String::new();
+
+  for (index, entry) in entries.iter().enumerate() {
+    let more = format!("\n...and {} more", entries.len() - index);
+    if list.len() + entry.len() + 2 + more.len() > 1024 {
+      list.push_str(&more);
+      break;
+    }
+    if !list.is_empty() {
+      list.push_str(", ");
+    }
+    list.push_str(entry);
+  }
+
+  list
+}
+
+/// The user's permissions in the channel. The guild and the member come from the cache when it
+/// has them, and over HTTP when it doesn't, like for the offline members of big guilds
+async fn permissions_in(
+  ctx: &Context,
+  channel: &GuildChannel,
+  user_id: UserId,
+) -> Result<Permissions, SerenityError> {
+  let guild = channel.guild_id.to_partial_guild(ctx).await?;
+  let member = channel.guild_id.member(ctx, user_id).await?;
+
+  Ok(guild.user_permissions_in(channel, &member))
+}
+
+struct Handler {
+  /// The main server comes first, the others only take part in `/verify` and the status channels
+  servers: Vec<Server>,
+  status: StatusMonitor,
+  console: Console,
+  commands: CommandState,
+  verified_role_name: String,
+  verified_role_colour: Colour,
+  verified_role_hoist: bool,
+  log_format: LogFormat,
+  reconcile: ReconcileConfig,
+  /// What the schedules and the days of the daily summary run by, TIMEZONE or else
+  /// TIMEZONE_OFFSET
+  timezone: Timezone,
+  schedules: Vec<Schedule>,
+  /// When yesterday's activity is posted to the status channel, `None` to not post it
+  daily_summary_time: Option<NaiveTime>,
+  /// The messages members see
+  messages: Messages,
+  /// Whether verified members get their Minecraft name as their nickname
+  sync_nickname: bool,
+  verify_channel_id: u64,
+  welcome_dm: Option<WelcomeDm>,
+  /// Whether to mention in the verify response that the server is full
+  full_server_note: bool,
+  verify_hours: Option<VerifyHours>,
+  role_retry_attempts: u32,
+  staff_tier: Option<StaffTier>,
+  role_commands: Vec<RoleCommand>,
+  /// Whether matching role commands are run instead of adding the player to the whitelist
+  role_commands_replace_whitelist: bool,
+  store: Store,
+  /// Template of the name history URL, `{uuid}` is replaced with the account's uuid
+  name_history_url: Option<String>,
+  name_recently_freed_days: i64,
+  /// Run after verifying if the player is online, `{name}` is replaced with the minecraft username
+  teleport_command: Option<String>,
+  /// Delete the messages of members in the verify channel, except those who can manage messages
+  clean_verify_channel: bool,
+  /// How many accounts a member can link per server
+  max_accounts_per_user: usize,
+  /// Ban the accounts of members kicked from Discord too, not only of banned members
+  ban_on_discord_kick: bool,
+  verify_attempts: AttemptLimiter,
+  verification_expiry: Option<VerificationExpiry>,
+  mojang_outage_policy: MojangOutagePolicy,
+  /// The server doesn't check accounts with Mojang, so neither does the bot
+  offline_mode: bool,
+  web_verify: Option<WebVerifyConfig>,
+  /// Where `/healthz` and `/metrics` are served
+  metrics_addr: Option<String>,
+  chat_bridge: Option<ChatBridgeConfig>,
+  /// Ask for a button click before verifying, against scripted verifications
+  require_human_gate: bool,
+  /// The username each user is verifying, and when the human gate was shown
+  human_gates: Mutex<HashMap<UserId, (String, String, Instant)>>,
+  /// Whether `/verify` sends a code in-game, which has to be confirmed with `/confirm`
+  verify_challenge: bool,
+  verify_challenges: Mutex<HashMap<UserId, VerifyChallenge>>,
+  /// Refuse to whitelist accounts on the server's banlist
+  check_banlist: bool,
+  /// Discord webhook that gets the errors an operator has to act on
+  error_webhook_url: Option<String>,
+  /// For the Mojang and Geyser APIs and the webhooks
+  http: reqwest::Client,
+  http_timeout: Duration,
+  mojang_api: MojangApi,
+  /// Counts the failures of the background work, see `operation_failed`
+  error_reporter: Mutex<ErrorReporter>,
+  /// The notices the reporter decided on, sent by `alert_loop`
+  pending_alerts: Mutex<Vec<Alert>>,
+  /// `None` only logs the notices
+  alert_target: Option<AlertTarget>,
+  /// Bedrock players through Geyser, `None` unless BEDROCK_VERIFY is on
+  floodgate: Option<Floodgate>,
+  /// Limits how many verifications run at once
+  verify_permits: Semaphore,
+  verify_concurrency: usize,
+  /// Set to true once the bot is shutting down, which stops the background tasks
+  shutdown: watch::Sender<bool>,
+  /// `None` allows every guild
+  allowed_guild_ids: Option<Vec<GuildId>>,
+  leave_unlisted_guilds: bool,
+  guild_snapshot: RwLock<Option<GuildSnapshot>>,
+  /// The guilds besides the one with the verify channel, by id
+  other_guilds: HashMap<GuildId, OtherGuild>,
+  /// Notified after every successful verification
+  observers: Vec<Box<dyn VerificationObserver>>,
+  /// Set once the one-time setup succeeded and the background tasks were started
+  initialized: AtomicBool,
+  /// Held while the setup runs, so `ready` and `guild_create` can't run it twice at once
+  setting_up: tokio::sync::Mutex<()>,
+  /// Hands the context of the setup to the background tasks spawned in `main`
+  background_start: Mutex<Option<oneshot::Sender<Context>>>,
+  /// The members whose Verified role the bot is changing itself, so `guild_member_update` leaves
+  /// the change alone
+  own_role_changes: Mutex<HashMap<UserId, Instant>>,
+  /// The accounts of members who just left, for a ban that arrives after the removal
+  departed_accounts: DepartedAccounts,
+}
+
+/// Whether the account is on the server's banlist. If the banlist can't be read, the account is
+/// treated as not banned, so a broken banlist doesn't block everyone
+pub async fn is_banned(rcon_client: &mut dyn Rcon, name: &str) -> bool {
+  let output = match rcon_client.run_command("banlist players").await {
+    Ok(output) => output,
+    Err(err) => {
+      warn!("Couldn't read the banlist: {err}");
+      return false;
+    }
+  };
+
+  match parse_banlist_output(&output) {
+    Some(names) => names.iter().any(|banned| banned.eq_ignore_ascii_case(name)),
+    None => {
+      warn!("Couldn't parse the banlist: {output}");
+      false
+    }
+  }
+}
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// A minecraft server and the state of its RCON connection
+struct Server {
+  config: ServerConfig,
+  rcon_auth_failures: Mutex<RconAuthFailures>,
+  /// Kept open between commands, `None` until the next command connects
+  rcon_connection: tokio::sync::Mutex<Option<RconClient>>,
+  rcon_backoff: Mutex<RconBackoff>,
+  /// What the server's status channel was last renamed to, for the servers besides the main one
+  channel_name: Mutex<Option<String>>,
+  /// The renames of the server's status channel
+  rename_limiter: Mutex<RenameLimiter>,
+  query_address: Mutex<QueryAddress>,
+}
+
+/// Where the notices about failing background work go
+enum AlertTarget {
+  User(UserId),
+  Channel(ChannelId),
+}
+
+/// A guild from `[[guilds]]`, with its own verify channel, status channel and Verified role. The
+/// member events and the scheduled checks stay about the main guild
+struct OtherGuild {
+  config: GuildConfig,
+  snapshot: RwLock<Option<GuildSnapshot>>,
+  /// What the guild's status channel was last renamed to
+  channel_name: Mutex<Option<String>>,
+  rename_limiter: Mutex<RenameLimiter>,
+  /// Set once the role, the verify info message and the commands were set up
+  set_up: AtomicBool,
+}
+
+impl OtherGuild {
+  fn new(config: GuildConfig) -> Self {
+    Self {
+      config,
+      snapshot: RwLock::new(None),
+      channel_name: Mutex::new(None),
+      rename_limiter: Mutex::new(RenameLimiter::default()),
+      set_up: AtomicBool::new(false),
+    }
+  }
+}
+
+impl Server {
+  fn new(config: ServerConfig) -> Self {
+    Self {
+      config,
+      rcon_auth_failures: Mutex::new(RconAuthFailures::default()),
+      rcon_connection: tokio::sync::Mutex::new(None),
+      rcon_backoff: Mutex::new(RconBackoff::default()),
+      channel_name: Mutex::new(None),
+      rename_limiter: Mutex::new(RenameLimiter::default()),
+      query_address: Mutex::new(QueryAddress::default()),
+    }
+  }
+}
+
+impl Handler {
+  async fn new(
+    config: Config,
+    messages: Messages,
+    observers: Vec<Box<dyn VerificationObserver>>,
+  ) -> Self {
+    let Config {
+      server,
+      extra_servers,
+      verify_channel_id,
+      status_interval,
+      verified_role_name,
+      verified_role_colour,
+      verified_role_hoist,
+      reconcile,
+      timezone,
+      schedules,
+      log_format,
+      guilds,
+      settings,
+    } = config;
+    let status_channel_id = server.status_channel_id;
+    let server_address = server.address.clone();
+    let servers = std::iter::once(server)
+      .chain(extra_servers)
+      .map(Server::new)
+      .collect();
+    // Built before the other settings are moved out
+    let status = StatusMonitor::new(status_interval, status_channel_id, &settings, &messages);
+    let console = Console::new(&settings);
+    let commands = CommandState::new(&settings);
+
+    let timezone = timezone.unwrap_or(Timezone::Fixed(settings.timezone_offset));
+
+    let staff_tier = match (settings.staff_role_name, settings.staff_verify_command) {
+      (Some(role_name), Some(command)) => Some(StaffTier { role_name, command }),
+      _ => None,
+    };
+
+    let store = Store::load(settings.store_path);
+    store.write(|data| data.record_restart(timezone.to_local(Utc::now()).date()));
+
+    let default_ttl_days = settings.verification_ttl_days;
+    let role_ttl_days = settings.verification_role_ttl_days;
+    let verification_expiry =
+      (default_ttl_days.is_some() || !role_ttl_days.is_empty()).then_some(VerificationExpiry {
+        default_ttl_days,
+        role_ttl_days,
+        warning_days: settings.verification_expiry_warning_days,
+      });
+
+    let alert_target = match (settings.alert_user_id, settings.audit_channel_id) {
+      (Some(user_id), _) => Some(AlertTarget::User(UserId::new(user_id))),
+      (None, Some(channel_id)) => Some(AlertTarget::Channel(ChannelId::new(channel_id))),
+      _ => None,
+    };
+
+    let http_timeout = settings.http_timeout;
+    let http = http_client(http_timeout);
+
+    let floodgate = settings.bedrock_verify.then(|| Floodgate {
+      prefix: settings.floodgate_prefix,
+      command: settings.floodgate_command,
+      http: http.clone(),
+    });
+
+    let welcome_dm = settings.send_welcome_dm.then(|| WelcomeDm {
+      text: settings
+        .welcome_dm_text
+        .map(|text| text.replace("\\n", "\n"))
+        .unwrap_or_else(|| {
+          "You're now whitelisted! Please be respectful to other players and have fun.".to_string()
+        }),
+      join_address: settings.server_join_address.unwrap_or(server_address),
+    });
+
+    Self {
+      servers,
+      status,
+      console,
+      commands,
+      verified_role_name,
+      verified_role_colour: Colour::new(verified_role_colour),
+      verified_role_hoist,
+      log_format,
+      reconcile,
+      timezone,
+      schedules,
+      daily_summary_time: settings.daily_summary_time,
+      messages,
+      sync_nickname: settings.sync_nickname,
+      verify_channel_id,
+      welcome_dm,
+      full_server_note: settings.full_server_note,
+      verify_hours: settings.verify_hours,
+      role_retry_attempts: settings.role_retry_attempts,
+      staff_tier,
+      role_commands: settings.role_commands,
+      role_commands_replace_whitelist: settings.role_commands_replace_whitelist,
+      store,
+      name_history_url: settings.name_history_url,
+      name_recently_freed_days: settings.name_recently_freed_days,
+      teleport_command: settings.teleport_command,
+      clean_verify_channel: settings.clean_verify_channel,
+      max_accounts_per_user: settings.max_accounts_per_user,
+      ban_on_discord_kick: settings.ban_on_discord_kick,
+      verify_attempts: AttemptLimiter::new(
+        settings.verify_max_failed_attempts,
+        settings.verify_attempt_window,
+      ),
+      check_banlist: settings.check_banlist,
+      mojang_outage_policy: settings.mojang_outage_policy,
+      offline_mode: settings.offline_mode,
+      web_verify: settings.web_verify,
+      metrics_addr: settings.metrics_addr,
+      chat_bridge: settings.chat_bridge,
+      require_human_gate: settings.require_human_gate,
+      human_gates: Mutex::new(HashMap::new()),
+      verify_challenge: settings.verify_challenge,
+      verify_challenges: Mutex::new(HashMap::new()),
+      verification_expiry,
+      error_webhook_url: settings.error_webhook_url,
+      mojang_api: MojangApi { http: http.clone() },
+      http,
+      http_timeout,
+      error_reporter: Mutex::new(ErrorReporter::new(settings.alert_thresholds)),
+      pending_alerts: Mutex::new(Vec::new()),
+      alert_target,
+      floodgate,
+      verify_permits: Semaphore::new(settings.verify_concurrency),
+      verify_concurrency: settings.verify_concurrency,
+      shutdown: watch::Sender::new(false),
+      allowed_guild_ids: settings
+        .allowed_guild_ids
+        .map(|ids| ids.into_iter().map(GuildId::new).collect()),
+      leave_unlisted_guilds: settings.leave_unlisted_guilds,
+      guild_snapshot: RwLock::new(None),
+      other_guilds: guilds
+        .into_iter()
+        .map(|config| (GuildId::new(config.id), OtherGuild::new(config)))
+        .collect(),
+      observers,
+      initialized: AtomicBool::new(false),
+      setting_up: tokio::sync::Mutex::new(()),
+      background_start: Mutex::new(None),
+      own_role_changes: Mutex::new(HashMap::new()),
+      departed_accounts: DepartedAccounts::new(AUDIT_LOG_MAX_AGE),
+    }
+  }
+
+  /// Waits until the bot starts shutting down
+  async fn shutdown_requested(&self) {
+    let mut shutdown = self.shutdown.subscribe();
+    // Only fails once the sender is dropped, together with the handler
+    let _ = shutdown.wait_for(|shutting_down| *shutting_down).await;
+  }
+
+  /// Stops the background tasks and waits for the verifications that are still running, so nobody
+  /// is left with the Verified role but no whitelist entry
+  async fn shut_down(&self) {
+    self.shutdown.send_replace(true);
+    // The background tasks never started if the bot never got ready
+    self.background_start.lock().unwrap().take();
+
+    info!("Waiting for running verifications to finish...");
+    let permits = self.verify_concurrency.try_into().unwrap_or(u32::MAX);
+    if time::timeout(
+      SHUTDOWN_GRACE_PERIOD,
+      self.verify_permits.acquire_many(permits),
+    )
+    .await
+    .is_err()
+    {
+      warn!("Some verifications didn't finish within {SHUTDOWN_GRACE_PERIOD:?}, stopping anyway");
+    }
+
+    self.store.flush();
+  }
+
+  /// The latest guild snapshot, `None` until the first one was fetched
+  fn guild_snapshot(&self) -> Option<GuildSnapshot> {
+    self.guild_snapshot.read().unwrap().clone()
+  }
+
+  async fn refresh_guild_snapshot(&self, http: &Http) -> Result<GuildSnapshot, SerenityError> {
+    let guild_snapshot = GuildSnapshot::fetch(http, ChannelId::new(self.verify_channel_id)).await?;
+    *self.guild_snapshot.write().unwrap() = Some(guild_snapshot.clone());
+
+    Ok(guild_snapshot)
+  }
+
+  /// The snapshot of one of the other guilds, or the main guild's for any other id
+  fn guild_snapshot_of(&self, guild_id: GuildId) -> Option<GuildSnapshot> {
+    match self.other_guilds.get(&guild_id) {
+      Some(other_guild) => other_guild.snapshot.read().unwrap().clone(),
+      None => self.guild_snapshot(),
+    }
+  }
+
+  async fn refresh_guild_snapshot_of(
+    &self,
+    http: &Http,
+    guild_id: GuildId,
+  ) -> Result<GuildSnapshot, SerenityError> {
+    let Some(other_guild) = self.other_guilds.get(&guild_id) else {
+      return self.refresh_guild_snapshot(http).await;
+    };
+
+    let guild_snapshot = GuildSnapshot::fetch_guild(http, guild_id).await?;
+    *other_guild.snapshot.write().unwrap() = Some(guild_snapshot.clone());
+
+    Ok(guild_snapshot)
+  }
+
+  /// Whether the author of the message can manage messages in its channel. When that can't be
+  /// found out, the message is treated like a moderator's, so it isn't deleted by mistake
+  async fn can_manage_messages(&self, ctx: &Context, message: &Message) -> bool {
+    let channel = self
+      .guild_snapshot()
+      .and_then(|guild| guild.channels.get(&message.channel_id).cloned());
+    let channel = match channel {
+      Some(channel) => channel,
+      None => match message.channel(ctx).await.map(Channel::guild) {
+        Ok(Some(channel)) => channel,
+        Ok(None) => return false,
+        Err(error) => {
+          warn!("Couldn't fetch the channel of a message: {error}");
+          return true;
+        }
+      },
+    };
+
+    match permissions_in(ctx, &channel, message.author.id).await {
+      Ok(permissions) => permissions.manage_messages(),
+      Err(error) => {
+        warn!(
+          "Couldn't check the permissions of {}: {error}",
+          message.author.id
+        );
+        true
+      }
+    }
+  }
+
+  /// Sets up a guild from `[[guilds]]`: its Verified role, the verify info message and the slash
+  /// commands. Runs once, from ready or when the bot joins the guild later
+  async fn set_up_other_guild(&self, ctx: &Context, guild_id: GuildId) {
+    let Some(other_guild) = self.other_guilds.get(&guild_id) else {
+      return;
+    };
+    if other_guild.set_up.swap(true, Ordering::SeqCst) {
+      return;
+    }
+
+    let guild = match self.refresh_guild_snapshot_of(&ctx.http, guild_id).await {
+      Ok(guild) => guild,
+      Err(error) => {
+        // Most likely the bot wasn't invited yet, joining sets it up
+        other_guild.set_up.store(false, Ordering::SeqCst);
+        warn!("Couldn't fetch the guild {guild_id}, it's set up once the bot joins it: {error}");
+        return;
+      }
+    };
+
+    let verify_channel_id = ChannelId::new(other_guild.config.verify_channel_id);
+    let Some(verify_channel) = guild.channels.get(&verify_channel_id).cloned() else {
+      error!(
+        "'{}' ({}) has no channel {verify_channel_id}, check its verify_channel_id",
+        guild.name, guild.id
+      );
+      return;
+    };
+
+    if let Err(error) = self.create_verified_role(ctx, &guild).await {
+      self
+        .report_error(&format!(
+          "Couldn't set up the Verified role in '{}', continuing without it: {error}",
+          guild.name
+        ))
+        .await;
+    }
+
+    if let Err(error) = self.send_verify_info(ctx, &verify_channel).await {
+      self
+        .report_error(&format!(
+          "Couldn't send the verify info message in '{}': {error}",
+          guild.name
+        ))
+        .await;
+    }
+
+    if let Err(error) = self.register_commands(ctx, guild.id).await {
+      self
+        .report_error(&format!(
+          "Couldn't register the slash commands in '{}': {error}",
+          guild.name
+        ))
+        .await;
+    }
+
+    info!("Set up '{}' ({})", guild.name, guild.id);
+  }
+
+  /// Lists the guilds the bot is in, to help find a wrong channel id
+  async fn log_joined_guilds(&self, http: &Http) {
+    match http.get_guilds(None, None).await {
+      Ok(guilds) if guilds.is_empty() => warn!("The bot isn't in any guild, invite it first"),
+      Ok(guilds) => {
+        info!("The bot is in these guilds:");
+        for guild in guilds {
+          info!("{} ({})", guild.name, guild.id);
+        }
+      }
+      Err(error) => warn!("Couldn't list the guilds the bot is in: {error}"),
+    }
+  }
+
+  /// The day it is in TIMEZONE
+  fn today(&self) -> NaiveDate {
+    self.timezone.to_local(Utc::now()).date()
+  }
+
+  /// Whether the guild is the one with the verify channel
+  fn is_verify_guild(&self, guild_id: GuildId) -> bool {
+    self
+      .guild_snapshot()
+      .is_some_and(|guild| guild.id == guild_id)
+  }
+
+  /// Whether the bot was set up to work in the guild. Until the main guild is known, any guild
+  /// could be it
+  fn is_configured_guild(&self, guild_id: GuildId) -> bool {
+    self.other_guilds.contains_key(&guild_id)
+      || self
+        .guild_snapshot()
+        .is_none_or(|guild| guild.id == guild_id)
+  }
+
+  /// Whether the bot should operate in the guild. Events outside of guilds are always allowed, and
+  /// so are the guilds from `[[guilds]]`
+  fn is_guild_allowed(&self, guild_id: Option<GuildId>) -> bool {
+    match (&self.allowed_guild_ids, guild_id) {
+      (Some(allowed_guild_ids), Some(guild_id)) => {
+        allowed_guild_ids.contains(&guild_id) || self.other_guilds.contains_key(&guild_id)
+      }
+      _ => true,
+    }
+  }
+
+  /// Whether the channel is the verify channel of the main guild or one of the others
+  fn is_verify_channel(&self, channel_id: ChannelId) -> bool {
+    channel_id == self.verify_channel_id
+      || self
+        .other_guilds
+        .values()
+        .any(|other_guild| channel_id == other_guild.config.verify_channel_id)
+  }
+
+  /// Adds a role, retrying with backoff when Discord fails transiently.
+  /// Serenity already waits out 429s using the retry-after header, so those only reach here when
+  /// the wait wasn't enough
+  /// Sets the member's nickname to their Minecraft name, if SYNC_NICKNAME is on. Members above the
+  /// bot's role, like the owner, can't be renamed. Returns a note for the member if it failed
+  async fn sync_member_nickname(
+    &self,
+    ctx: &Context,
+    guild_id: GuildId,
+    user_id: UserId,
+    name: &str,
+  ) -> Option<String> {
+    if !self.sync_nickname {
+      return None;
+    }
+
+    match guild_id
+      .edit_member(ctx, user_id, EditMember::new().nickname(name))
+      .await
+    {
+      Ok(_) => None,
+      Err(error) => {
+        warn!("Couldn't change the nickname of {user_id} to '{name}': {error}");
+        Some(self.messages.text(MessageKey::NicknameFailed))
+      }
+    }
+  }
+
+  async fn add_role_with_retry(
+    &self,
+    ctx: &Context,
+    member: &Member,
+    role_id: RoleId,
+  ) -> Result<(), SerenityError> {
+    let mut backoff = Duration::from_millis(500);
+    let mut attempt = 1;
+    // Only the main guild's role changes are watched
+    let own_role_change = self.is_verify_guild(member.guild_id);
+    if own_role_change {
+      self.mark_own_role_change(member.user.id);
+    }
+
+    loop {
+      match member.add_role(ctx, role_id).await {
+        Ok(()) => return Ok(()),
+        Err(error)
+          if attempt < self.role_retry_attempts
+            && classify_discord_error(&error) == DiscordFailure::Transient =>
+        {
+          warn!("Couldn't add a role (attempt {attempt}), retrying in {backoff:?}: {error}");
+          time::sleep(backoff).await;
+          backoff *= 2;
+          attempt += 1;
+        }
+        Err(error) => {
+          if own_role_change {
+            self.take_own_role_change(member.user.id);
+          }
+          return Err(error);
+        }
+      }
+    }
+  }
+
+  /// Tells the observers about a verification or a removal of an account
+  async fn notify_account_event(&self, ctx: &Context, event: AccountEvent<'_>) {
+    for observer in &self.observers {
+      observer.on_account_event(ctx, &event).await;
+    }
+  }
+
+  /// Sends a message from the bridge channel to the in-game chat
+  async fn relay_to_minecraft(&self, ctx: &Context, message: &Message) {
+    // The bridge posts the in-game chat through a webhook, relaying that back would loop
+    if message.webhook_id.is_some() || message.author.id == ctx.cache.current_user().id {
+      return;
+    }
+
+    let mut text = message.content_safe(&ctx.cache);
+    for attachment in &message.attachments {
+      text.push_str(&format!(" [{}]", attachment.filename));
+    }
+
+    let author = message
+      .member
+      .as_ref()
+      .and_then(|member| member.nick.clone())
+      .or_else(|| message.author.global_name.clone())
+      .unwrap_or_else(|| message.author.name.clone());
+
+    for part in chat::split_message(&text, CHAT_PART_LENGTH) {
+      if let Err(error) = self
+        .run_rcon_command(&tellraw_command(&author, &part))
+        .await
+      {
+        warn!("Couldn't relay {author}'s message to the server: {error}");
+        return;
+      }
+    }
+  }
+
+  async fn relay_to_discord(&self, webhook_url: &str, chat_line: &ChatLine) {
+    let linked_uuid = self.store.read(|data| {
+      data
+        .links
+        .iter()
+        .find(|link| link.minecraft_name.eq_ignore_ascii_case(&chat_line.player))
+        .map(|link| link.uuid.clone())
+    });
+    let uuid = match linked_uuid {
+      Some(uuid) => Some(uuid),
+      None => self
+        .profiles()
+        .profile(&chat_line.player)
+        .await
+        .ok()
+        .map(|profile| profile.id),
+    };
+
+    let result = self
+      .http
+      .post(webhook_url)
+      .json(&serde_json::json!({
+        "username": chat_line.player,
+        "avatar_url": uuid.map(|uuid| format!("https://crafatar.com/avatars/{uuid}?overlay")),
+        "content": chat_line.message,
+        "allowed_mentions": { "parse": [] },
+      }))
+      .send()
+      .await
+      .and_then(|response| response.error_for_status());
+
+    if let Err(error) = result {
+      warn!(
+        "Couldn't relay {}'s message to Discord: {error}",
+        chat_line.player
+      );
+    }
+  }
+
+  /// Logs the error and posts it to the error webhook, if there is one
+  async fn report_error(&self, message: &str) {
+    error!("{message}");
+
+    let Some(webhook_url) = &self.error_webhook_url else {
+      return;
+    };
+
+    let result = self
+      .http
+      .post(webhook_url)
+      .json(&serde_json::json!({ "content": message }))
+      .send()
+      .await
+      .and_then(|response| response.error_for_status());
+
+    if let Err(err) = result {
+      warn!("Couldn't post to the error webhook: {err}");
+    }
+  }
+
+  /// Counts a failure of background work. Once it lasts, the operator gets a notice
+  fn operation_failed(&self, operation: Operation, error: &dyn fmt::Display) {
+    let alert =
+      self
+        .error_reporter
+        .lock()
+        .unwrap()
+        .failed(operation, &error.to_string(), Instant::now());
+    self.pending_alerts.lock().unwrap().extend(alert);
+  }
+
+  fn operation_succeeded(&self, operation: &Operation) {
+    let alert = self
+      .error_reporter
+      .lock()
+      .unwrap()
+      .succeeded(operation, Instant::now());
+    self.pending_alerts.lock().unwrap().extend(alert);
+  }
+
+  async fn send_alert(&self, ctx: &Context, alert: &Alert) {
+    let message = alert.message();
+    let (title, colour) = match alert {
+      Alert::Failing { .. } => {
+        error!("{message}");
+        ("Keeps failing", Colour::RED)
+      }
+      Alert::Recovered { .. } => {
+        info!("{message}");
+        ("Recovered", Colour::DARK_GREEN)
+      }
+    };
+
+    let message = CreateMessage::new().embed(
+      CreateEmbed::new()
+        .title(title)
+        .description(message)
+        .timestamp(Timestamp::now())
+        .colour(colour),
+    );
+    let result = match self.alert_target {
+      Some(AlertTarget::User(user_id)) => direct_message(ctx, user_id, message).await,
+      Some(AlertTarget::Channel(channel_id)) => channel_id.send_message(ctx, message).await,
+      None => return,
+    };
+
+    if let Err(error) = result {
+      warn!("Couldn't send a notice about failing background work: {error}");
+    }
+  }
+
+  fn main_server(&self) -> &Server {
+    &self.servers[0]
+  }
+
+  /// Where accounts are looked up by name
+  fn profiles(&self) -> &dyn Mojang {
+    if self.offline_mode {
+      &OfflineProfiles
+    } else {
+      &self.mojang_api
+    }
+  }
+
+  /// Where the account is looked up, Geyser for a gamertag with the Floodgate prefix
+  fn profiles_for(&self, username: &str) -> &dyn Mojang {
+    match &self.floodgate {
+      Some(floodgate) if floodgate.is_bedrock_name(username) => floodgate,
+      _ => self.profiles(),
+    }
+  }
+
+  fn is_bedrock_name(&self, username: &str) -> bool {
+    self
+      .floodgate
+      .as_ref()
+      .is_some_and(|floodgate| floodgate.is_bedrock_name(username))
+  }
+
+  /// Checks a typed Java username, or a gamertag with the Floodgate prefix
+  fn validate_name(&self, username: &str) -> Result<(), crate::commands::UsernameError> {
+    match &self.floodgate {
+      Some(floodgate) if floodgate.is_bedrock_name(username) => {
+        validate_gamertag(floodgate.gamertag(username))
+      }
+      _ => crate::commands::validate_username(username),
+    }
+  }
+
+  /// Floodgate's whitelist command for Bedrock players, if there is one
+  fn whitelist_add_command(&self, name: &str) -> String {
+    match &self.floodgate {
+      Some(floodgate) if floodgate.is_bedrock_name(name) => floodgate.whitelist_command(name),
+      _ => format!("whitelist add {name}"),
+    }
+  }
+
+  /// The server with the name, or the main server for `None` and for servers that were removed
+  /// from the config
+  fn server_by_name(&self, name: Option<&str>) -> &Server {
+    name
+      .and_then(|name| {
+        self
+          .servers
+          .iter()
+          .find(|server| server.config.name.eq_ignore_ascii_case(name))
+      })
+      .unwrap_or_else(|| self.main_server())
+  }
+
+  /// How the server is referred to in the store, `None` for the main server
+  fn server_key(&self, server: &Server) -> Option<String> {
+    if std::ptr::eq(server, self.main_server()) {
+      None
+    } else {
+      Some(server.config.name.clone())
+    }
+  }
+
+  /// Counts the guild members that have the role
+  async fn count_members_with_role(
+    &self,
+    http: &Http,
+    guild_id: GuildId,
+    role_id: RoleId,
+  ) -> Result<usize, SerenityError> {
+    let members = self.fetch_members(http, guild_id).await?;
+
+    Ok(
+      members
+        .values()
+        .filter(|member| member.roles.contains(&role_id))
+        .count(),
+    )
+  }
+
+  /// Fetches every member of the guild, paging through the member list
+  async fn fetch_members(
+    &self,
+    http: &Http,
+    guild_id: GuildId,
+  ) -> Result<HashMap<UserId, Member>, SerenityError> {
+    let mut members = HashMap::new();
+    let mut after = None;
+
+    loop {
+      let page = guild_id.members(http, Some(1000), after).await?;
+      let full_page = page.len() == 1000;
+      after = page.last().map(|last| last.user.id);
+
+      members.extend(page.into_iter().map(|member| (member.user.id, member)));
+
+      if !full_page {
+        return Ok(members);
+      }
+    }
+  }
+
+  /// How many members have the Verified role, or why that's unknown
+  async fn verified_member_count(&self, http: &Http) -> String {
+    let Some(guild) = self.guild_snapshot() else {
+      return "unavailable".to_string();
+    };
+    let Some(role) = self.verified_role(&guild) else {
+      return "unavailable (no Verified role)".to_string();
+    };
+
+    match self.count_members_with_role(http, guild.id, role.id).await {
+      Ok(count) => count.to_string(),
+      Err(error) => {
+        warn!("Couldn't fetch the guild members: {error}");
+        "unavailable".to_string()
+      }
+    }
+  }
+
+  async fn post_daily_summary(&self, ctx: &Context, day: NaiveDate) {
+    let Some(status_channel_id) = self.status.channel_id else {
+      warn!("There is no status channel to post the daily summary in");
+      return;
+    };
+    let Some(activity) = self
+      .store
+      .read(|data| data.activity(day).cloned())
+      .filter(|activity| activity.observed_seconds > 0)
+    else {
+      info!("Nothing was recorded on {day}, skipped the daily summary");
+      return;
+    };
+
+    let peak = match activity.peak_at {
+      Some(peak_at) if activity.peak_players > 0 => {
+        let peak_at = DateTime::from_timestamp(peak_at, 0).unwrap_or_default();
+        format!(
+          "{} at {}",
+          activity.peak_players,
+          self.timezone.to_local(peak_at).format("%H:%M")
+        )
+      }
+      _ => "Nobody was online".to_string(),
+    };
+
+    let mut embed = CreateEmbed::new()
+      .title(format!("Activity on {}", day.format("%A, %B %-d")))
+      .field("Peak players", peak, true)
+      .field("Players seen", activity.players.len().to_string(), true)
+      .field(
+        "Offline",
+        format!("{} minutes", activity.offline_seconds / 60),
+        true,
+      )
+      .field(
+        "New verifications",
+        activity.verifications.to_string(),
+        true,
+      )
+      .footer(CreateEmbedFooter::new(
+        "Players are sampled by the status checks, short visits between two checks are missed",
+      ))
+      .colour(Colour::BLUE);
+    if activity.is_partial() {
+      let reason = if activity.restarted {
+        "the bot was restarted during the day"
+      } else {
+        "the bot wasn't running all day"
+      };
+      embed = embed.description(format!(
+        "Partial data, {reason}. Only {:.1} hours were observed.",
+        activity.observed_seconds as f64 / 3600.0
+      ));
+    }
+
+    match ChannelId::new(status_channel_id)
+      .send_message(ctx, CreateMessage::new().embed(embed))
+      .await
+    {
+      Ok(_) => info!("Posted the daily summary of {day}"),
+      Err(error) => warn!("Couldn't post the daily summary of {day}: {error}"),
+    }
+  }
+
+  /// Compares every server's whitelist, the stored links and the Verified role, and reports where
+  /// they disagree. With auto fix on, linked accounts missing from the whitelist are added back.
+  /// `None` if the guild or its members couldn't be fetched
+  async fn reconcile(&self, ctx: &Context) -> Option<CreateEmbed> {
+    let guild = self.guild_snapshot()?;
+    let members = self
+      .fetch_members(&ctx.http, guild.id)
+      .await
+      .inspect_err(|error| warn!("Couldn't fetch the members to reconcile: {error}"))
+      .ok()?;
+    let verified_role_id = self.verified_role(&guild).map(|role| role.id);
+
+    // Unvalidated verifications are whitelisted too, they just have no uuid yet
+    // Suspended accounts are off the whitelist and without the role on purpose
+    let linked: Vec<(u64, String, Option<String>)> = self.store.read(|data| {
+      data
+        .links
+        .iter()
+        .filter(|link| link.is_active())
+        .map(|link| {
+          (
+            link.discord_id,
+            link.minecraft_name.clone(),
+            link.server.clone(),
+          )
+        })
+        .chain(data.unvalidated.iter().map(|verification| {
+          (
+            verification.discord_id,
+            verification.minecraft_name.clone(),
+            verification.server.clone(),
+          )
+        }))
+        .collect()
+    });
+    let unmanaged: Vec<String> = self.store.read(|data| {
+      data
+        .unmanaged
+        .iter()
+        .map(|entry| entry.minecraft_name.clone())
+        .collect()
+    });
+    // Imported entries are waiting for their owner, they aren't drift either
+    let unclaimed: Vec<(String, Option<String>)> = self.store.read(|data| {
+      data
+        .unclaimed
+        .iter()
+        .map(|entry| (entry.minecraft_name.clone(), entry.server.clone()))
+        .collect()
+    });
+
+    let mut unlinked_entries = Vec::new();
+    let mut missing_entries = Vec::new();
+    let mut unreachable = Vec::new();
+    let mut fixed = Vec::new();
+
+    for server in &self.servers {
+      let key = self.server_key(server);
+      let whitelist = match self.run_rcon_command_on(server, "whitelist list").await {
+        Ok(output) => match parse_whitelist_output(&output) {
+          Some(whitelist) => whitelist,
+          None => {
+            warn!(
+              "Couldn't parse the whitelist of {}: {output}",
+              server.config.name
+            );
+            unreachable.push(server.config.name.clone());
+            continue;
+          }
+        },
+        Err(_) => {
+          unreachable.push(server.config.name.clone());
+          continue;
+        }
+      };
+      let whitelisted = |name: &str| {
+        whitelist
+          .iter()
+          .any(|entry| entry.eq_ignore_ascii_case(name))
+      };
+
+      let server_links: Vec<&String> = linked
+        .iter()
+        .filter(|(_, _, server)| *server == key)
+        .map(|(_, name, _)| name)
+        .collect();
+      let known = |entry: &String| {
+        server_links
+          .iter()
+          .any(|name| name.eq_ignore_ascii_case(entry))
+          || (key.is_none()
+            && unmanaged
+              .iter()
+              .any(|name| name.eq_ignore_ascii_case(entry)))
+          || unclaimed
+            .iter()
+            .any(|(name, server)| *server == key && name.eq_ignore_ascii_case(entry))
+      };
+      let prefix = |name: &str| {
+        if self.servers.len() > 1 {
+          format!("{} ({})", name, server.config.name)
+        } else {
+          name.to_string()
+        }
+      };
+
+      unlinked_entries.extend(
+        whitelist
+          .iter()
+          .filter(|entry| !known(entry))
+          .map(|entry| prefix(entry)),
+      );
+
+      // The role commands grant access instead of the whitelist
+      if self.role_commands_replace_whitelist {
+        continue;
+      }
+
+      for name in server_links.into_iter().filter(|name| !whitelisted(name)) {
+        if self.reconcile.auto_fix
+          && self
+            .run_rcon_command_on(server, &format!("whitelist add {name}"))
+            .await
+            .is_ok()
+        {
+          info!(
+            "Re-added '{name}' to the whitelist of {}",
+            server.config.name
+          );
+          fixed.push(prefix(name));
+        } else {
+          missing_entries.push(prefix(name));
+        }
+      }
+    }
+
+    let mut missing_role = Vec::new();
+    let mut left_guild = Vec::new();
+    for (discord_id, name, _) in &linked {
+      match members.get(&UserId::new(*discord_id)) {
+        Some(member) => {
+          if verified_role_id.is_some_and(|role_id| !member.roles.contains(&role_id)) {
+            missing_role.push(format!("<@{discord_id}> ({name})"));
+          }
+        }
+        None => left_guild.push(format!("<@{discord_id}> ({name})")),
+      }
+    }
+    let role_without_link: Vec<String> = members
+      .values()
+      .filter(|member| {
+        verified_role_id.is_some_and(|role_id| member.roles.contains(&role_id))
+          && !linked
+            .iter()
+            .any(|(discord_id, _, _)| *discord_id == member.user.id.get())
+      })
+      .map(|member| member.user.id.mention().to_string())
+      .collect();
+
+    let sections = [
+      ("Whitelisted without a link", unlinked_entries),
+      ("Linked but not whitelisted", missing_entries),
+      ("Re-added to the whitelist", fixed),
+      ("Linked but missing the Verified role", missing_role),
+      ("Linked but left the guild", left_guild),
+      ("Verified role without a link", role_without_link),
+      ("Whitelist unavailable", unreachable),
+    ];
+    let discrepancies = sections
+      .iter()
+      .filter(|(title, _)| *title != "Re-added to the whitelist")
+      .map(|(_, entries)| entries.len())
+      .sum::<usize>();
+    info!("Reconciled the whitelist, links and roles: {discrepancies} discrepancies");
+
+    let mut embed = CreateEmbed::new()
+      .title("Whitelist reconciliation")
+      .timestamp(Timestamp::now());
+    if discrepancies == 0 {
+      embed = embed
+        .description("The whitelist, the links and the Verified role all match.")
+        .colour(Colour::DARK_GREEN);
+    } else {
+      embed = embed.colour(Colour::ORANGE);
+    }
+    for (title, entries) in sections {
+      if !entries.is_empty() {
+        embed = embed.field(
+          format!("{title} ({})", entries.len()),
+          limited_list(&entries),
+          false,
+        );
+      }
+    }
+
+    Some(embed)
+  }
+
+  /// The Verified role, by its stored id. Falls back to the configured name for a new store or a
+  /// role made by hand, and remembers that role's id. A role set in `[[guilds]]` is always used
+  fn verified_role<'a>(&self, guild: &'a GuildSnapshot) -> Option<&'a Role> {
+    let configured_role_id = self
+      .other_guilds
+      .get(&guild.id)
+      .and_then(|other_guild| other_guild.config.role_id);
+    if let Some(role_id) = configured_role_id {
+      return guild.roles.get(&RoleId::new(role_id));
+    }
+
+    let stored_role_id = self.stored_verified_role_id(guild.id);
+    if let Some(role) = stored_role_id.and_then(|role_id| guild.roles.get(&RoleId::new(role_id))) {
+      return Some(role);
+    }
+
+    let role = guild.role_by_name(&self.verified_role_name)?;
+    if stored_role_id != Some(role.id.get()) {
+      self.store_verified_role_id(guild.id, role.id);
+    }
+    Some(role)
+  }
+
+  fn stored_verified_role_id(&self, guild_id: GuildId) -> Option<u64> {
+    self.store.read(|data| {
+      if self.other_guilds.contains_key(&guild_id) {
+        data.guilds.get(&guild_id.get())?.role_id
+      } else {
+        data.verified_role_id
+      }
+    })
+  }
+
+  fn store_verified_role_id(&self, guild_id: GuildId, role_id: RoleId) {
+    self.store.write(|data| {
+      if self.other_guilds.contains_key(&guild_id) {
+        data.guilds.entry(guild_id.get()).or_default().role_id = Some(role_id.get());
+      } else {
+        data.verified_role_id = Some(role_id.get());
+      }
+    });
+  }
+
+  fn stored_verify_message_id(&self, guild_id: GuildId) -> Option<u64> {
+    self.store.read(|data| {
+      if self.other_guilds.contains_key(&guild_id) {
+        data.guilds.get(&guild_id.get())?.verify_message_id
+      } else {
+        data.verify_message_id
+      }
+    })
+  }
+
+  fn store_verify_message_id(&self, guild_id: GuildId, message_id: MessageId) {
+    self.store.write(|data| {
+      if self.other_guilds.contains_key(&guild_id) {
+        data
+          .guilds
+          .entry(guild_id.get())
+          .or_default()
+          .verify_message_id = Some(message_id.get());
+      } else {
+        data.verify_message_id = Some(message_id.get());
+      }
+    });
+  }
+
+  /// Creates the Verified role if it doesn't exist, otherwise brings its name, colour and hoist in
+  /// line with the config. A renamed role is found by its id, so it isn't created twice
+  async fn create_verified_role(
+    &self,
+    ctx: &Context,
+    guild: &GuildSnapshot,
+  ) -> Result<Role, BotError> {
+    // A role picked in `[[guilds]]` is left as it is
+    let configured_role_id = self
+      .other_guilds
+      .get(&guild.id)
+      .and_then(|other_guild| other_guild.config.role_id);
+    if let Some(role_id) = configured_role_id {
+      return self.verified_role(guild).cloned().ok_or_else(|| {
+        BotError::Config(format!(
+          "the role {role_id} of the guild {} doesn't exist",
+          guild.id
+        ))
+      });
+    }
+
+    let edit_role = EditRole::new()
+      .name(&self.verified_role_name)
+      .colour(self.verified_role_colour)
+      .hoist(self.verified_role_hoist);
+
+    let role = match self.verified_role(guild) {
+      Some(role)
+        if role.name == self.verified_role_name
+          && role.colour == self.verified_role_colour
+          && role.hoist == self.verified_role_hoist =>
+      {
+        return Ok(role.clone());
+      }
+      Some(role) => {
+        let role = guild.id.edit_role(ctx, role.id, edit_role).await?;
+        info!("Updated the Verified role to match the config");
+        role
+      }
+      None => {
+        let role = guild.id.create_role(ctx, edit_role).await?;
+        info!("Created the Verified role");
+        role
+      }
+    };
+
+    self.store_verified_role_id(guild.id, role.id);
+    self.refresh_guild_snapshot_of(&ctx.http, guild.id).await?;
+    Ok(role)
+  }
+
+  /// Posts the verify info message, or brings the one posted before up to date. It's found by
+  /// its stored id, or among the channel's recent messages for a new store
+  async fn send_verify_info(
+    &self,
+    ctx: &Context,
+    verify_channel: &GuildChannel,
+  ) -> Result<(), BotError> {
+    let title = self.messages.text(MessageKey::VerifyInfoTitle);
+    let embed = CreateEmbed::new()
+      .title(&title)
+      .description(self.messages.text(MessageKey::VerifyInfoDescription))
+      .footer(CreateEmbedFooter::new(
+        self.messages.text(MessageKey::VerifyInfoFooter),
+      ))
+      .colour(Colour::DARK_GREEN);
+    let buttons = self.verify_buttons();
+
+    let stored_message_id = self.stored_verify_message_id(verify_channel.guild_id);
+    let message_id = match stored_message_id {
+      Some(message_id) => Some(MessageId::new(message_id)),
+      None => {
+        let bot_id = ctx.cache.current_user().id;
+        verify_channel
+          .messages(ctx, GetMessages::new().limit(VERIFY_INFO_SEARCH_LIMIT))
+          .await?
+          .into_iter()
+          .find(|message| {
+            message.author.id == bot_id
+              && message
+                .embeds
+                .iter()
+                .any(|embed| embed.title.as_ref() == Some(&title))
+          })
+          .map(|message| message.id)
+      }
+    };
+
+    if let Some(message_id) = message_id {
+      match verify_channel
+        .id
+        .edit_message(
+          ctx,
+          message_id,
+          EditMessage::new()
+            .embed(embed.clone())
+            .components(buttons.clone()),
+        )
+        .await
+      {
+        Ok(_) => {
+          if stored_message_id != Some(message_id.get()) {
+            self.store_verify_message_id(verify_channel.guild_id, message_id);
+          }
+          info!("Updated the verify info message");
+          return Ok(());
+        }
+        // Deleted by someone, a new one is posted
+        Err(error) if is_unknown_message(&error) => {}
+        Err(error) => return Err(error.into()),
+      }
+    }
+
+    let message = verify_channel
+      .send_message(ctx, CreateMessage::new().embed(embed).components(buttons))
+      .await?;
+    self.store_verify_message_id(verify_channel.guild_id, message.id);
+    info!("Sent the verify info message");
+
+    Ok(())
+  }
+
+  /// A verify button per server, five to a row
+  fn verify_buttons(&self) -> Vec<CreateActionRow> {
+    let buttons: Vec<CreateButton> = self
+      .servers
+      .iter()
+      .take(MAX_VERIFY_BUTTONS)
+      .map(|server| {
+        let label = if self.servers.len() > 1 {
+          self.messages.render(
+            MessageKey::VerifyButtonServer,
+            &[("server", &server.config.name)],
+          )
+        } else {
+          self.messages.text(MessageKey::VerifyButton)
+        };
+        CreateButton::new(format!("verify:{}", server.config.name)).label(label)
+      })
+      .collect();
+
+    buttons
+      .chunks(5)
+      .map(|row| CreateActionRow::Buttons(row.to_vec()))
+      .collect()
+  }
+}
+
+#[async_trait]
+impl EventHandler for Handler {
+  async fn message(&self, ctx: Context, new_message: Message) {
+    if !self.is_guild_allowed(new_message.guild_id) {
+      return;
+    }
+
+    // Delete all new messages that are not sent by the bot in the verify channel
+    if self.clean_verify_channel
+      && self.is_verify_channel(new_message.channel_id)
+      && new_message.author != **ctx.cache.current_user()
+      && !self.can_manage_messages(&ctx, &new_message).await
+    {
+      if let Err(error) = new_message.delete(&ctx).await {
+        self
+          .report_error(&format!(
+            "Couldn't delete a message in the verify channel: {}",
+            BotError::from(error)
+          ))
+          .await;
+      }
+    }
+
+    if self
+      .chat_bridge
+      .as_ref()
+      .is_some_and(|chat_bridge| chat_bridge.channel_id == new_message.channel_id.get())
+    {
+      self.relay_to_minecraft(&ctx, &new_message).await;
+    }
+  }
+
+  async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+    let guild_id = match &interaction {
+      Interaction::Command(command) => command.guild_id,
+      Interaction::Component(component) => component.guild_id,
+      Interaction::Modal(modal) => modal.guild_id,
+      _ => None,
+    };
+
+    if !self.is_guild_allowed(guild_id) {
+      return;
+    }
+    if let Some(guild_id) = guild_id.filter(|guild_id| !self.is_configured_guild(*guild_id)) {
+      info!("Ignoring an interaction in the guild {guild_id}, it isn't in [[guilds]]");
+      return;
+    }
+
+    // Everything logged while handling it carries who did what where
+    let guild_id = guild_id.map(|guild_id| guild_id.get());
+    match &interaction {
+      Interaction::Component(component) => {
+        let span = tracing::info_span!(
+          "button",
+          custom_id = %component.data.custom_id,
+          user_id = component.user.id.get(),
+          guild_id,
+        );
+        async {
+          if let Err(error) = self.handle_component(&ctx, component).await {
+            self
+              .report_error(&format!(
+                "Couldn't handle the {} button: {error}",
+                component.data.custom_id
+              ))
+              .await;
+          }
+        }
+        .instrument(span)
+        .await;
+      }
+      Interaction::Modal(modal) => {
+        let span = tracing::info_span!(
+          "form",
+          custom_id = %modal.data.custom_id,
+          user_id = modal.user.id.get(),
+          guild_id,
+        );
+        async {
+          if let Err(error) = self.handle_modal(&ctx, modal).await {
+            self
+              .report_error(&format!(
+                "Couldn't handle the {} form: {error}",
+                modal.data.custom_id
+              ))
+              .await;
+          }
+        }
+        .instrument(span)
+        .await;
+      }
+      Interaction::Command(command) => {
+        let span = tracing::info_span!(
+          "command",
+          name = %command.data.name,
+          user_id = command.user.id.get(),
+          guild_id,
+        );
+        async {
+          if let Err(error) = self.handle_command(&ctx, command).await {
+            self
+              .report_error(&format!("Couldn't handle /{}: {error}", command.data.name))
+              .await;
+            tell_something_went_wrong(&ctx, command).await;
+          }
+        }
+        .instrument(span)
+        .await;
+      }
+      _ => {}
+    }
+  }
+
+  /// Notices a deleted Verified role right away, instead of at the next guild snapshot
+  async fn guild_role_delete(
+    &self,
+    ctx: Context,
+    guild_id: GuildId,
+    removed_role_id: RoleId,
+    _removed_role: Option<Role>,
+  ) {
+    if self.stored_verified_role_id(guild_id) != Some(removed_role_id.get()) {
+      return;
+    }
+
+    info!("The Verified role was deleted, it's created again on the next verification");
+    if let Err(error) = self.refresh_guild_snapshot_of(&ctx.http, guild_id).await {
+      warn!("Couldn't refresh the guild snapshot: {error}");
+    }
+  }
+
+  async fn guild_ban_addition(&self, ctx: Context, guild_id: GuildId, banned_user: User) {
+    self.member_banned(&ctx, guild_id, banned_user).await;
+  }
+
+  async fn guild_ban_removal(&self, _ctx: Context, guild_id: GuildId, unbanned_user: User) {
+    self.member_unbanned(guild_id, unbanned_user).await;
+  }
+
+  async fn guild_member_update(
+    &self,
+    ctx: Context,
+    old_if_available: Option<Member>,
+    _new: Option<Member>,
+    event: GuildMemberUpdateEvent,
+  ) {
+    self
+      .member_roles_changed(&ctx, old_if_available, event)
+      .await;
+  }
+
+  async fn guild_member_addition(&self, _ctx: Context, member: Member) {
+    self.member_rejoined(&member);
+  }
+
+  async fn guild_member_removal(
+    &self,
+    ctx: Context,
+    guild_id: GuildId,
+    user: User,
+    _member: Option<Member>,
+  ) {
+    self.member_left(&ctx, guild_id, user).await;
+  }
+
+  async fn guild_create(&self, ctx: Context, guild: Guild, _is_new: Option<bool>) {
+    if self.is_guild_allowed(Some(guild.id)) {
+      let has_verify_channel = guild
+        .channels
+        .contains_key(&ChannelId::new(self.verify_channel_id));
+      if has_verify_channel && !self.initialized.load(Ordering::SeqCst) {
+        // The setup failed at `ready`, like when the bot wasn't in the guild yet
+        info!(
+          "'{}' ({}) is available, setting up again",
+          guild.name, guild.id
+        );
+        self.set_up(&ctx).await;
+      } else if self.other_guilds.contains_key(&guild.id) {
+        // At startup ready sets it up, this is for a guild the bot was invited to later
+        if self.initialized.load(Ordering::SeqCst) {
+          self.set_up_other_guild(&ctx, guild.id).await;
+        }
+      } else if !has_verify_channel {
+        info!(
+          "'{}' ({}) isn't in [[guilds]], ignoring it",
+          guild.name, guild.id
+        );
+      }
+      return;
+    }
+
+    if self.leave_unlisted_guilds {
+      match guild.id.leave(&ctx).await {
+        Ok(()) => info!(
+          "Left '{}' ({}), it isn't in ALLOWED_GUILD_IDS",
+          guild.name, guild.id
+        ),
+        Err(error) => warn!("Couldn't leave '{}' ({}): {error}", guild.name, guild.id),
+      }
+    } else {
+      warn!(
+        "Ignoring '{}' ({}), it isn't in ALLOWED_GUILD_IDS",
+        guild.name, guild.id
+      );
+    }
+  }
+
+  async fn resume(&self, _ctx: Context, _event: ResumedEvent) {
+    info!("Resumed the gateway session");
+  }
+
+  async fn ready(&self, ctx: Context, ready: Ready) {
+    // Ready also fires when the gateway has to start a new session. The setup was done and the
+    // background tasks are still running, so doing them again would only duplicate them
+    if self.initialized.load(Ordering::SeqCst) {
+      info!("{} reconnected with a new session", ready.user.name);
+      return;
+    }
+
+    info!("{} is connected!", ready.user.name);
+    self.set_up(&ctx).await;
+  }
+}
+
+impl Handler {
+  /// The one-time setup of the guild, which starts the background tasks once it succeeded. Until
+  /// then, it's tried again on every `ready` and when the guild becomes available
+  async fn set_up(&self, ctx: &Context) {
+    let _setting_up = self.setting_up.lock().await;
+    if self.initialized.load(Ordering::SeqCst) {
+      return;
+    }
+
+    info!("Loading everything...");
+    // Without the guild there is nothing to set up, but staying connected lets the operator fix
+    // the config or invite the bot without the process crash looping
+    let guild = match self.refresh_guild_snapshot(&ctx.http).await {
+      Ok(guild) => guild,
+      Err(error) => {
+        error!(
+          "Couldn't find the guild with the verify channel {}: {error}",
+          self.verify_channel_id
+        );
+        self.log_joined_guilds(&ctx.http).await;
+        return;
+      }
+    };
+
+    let Some(verify_channel) = guild
+      .channels
+      .get(&ChannelId::new(self.verify_channel_id))
+      .cloned()
+    else {
+      error!(
+        "'{}' ({}) has no channel {}, check DISCORD_VERIFY_CHANNEL_ID",
+        guild.name, guild.id, self.verify_channel_id
+      );
+      return;
+    };
+
+    if !self.is_guild_allowed(Some(guild.id)) {
+      info!(
+        "The verify channel is in '{}' ({}), which isn't in ALLOWED_GUILD_IDS. Not setting anything up",
+        guild.name, guild.id
+      );
+      return;
+    }
+
+    self.check_status_channel(&ctx.http).await;
+
+    // Verifying fails without the role, but everything else still works
+    if let Err(error) = self.create_verified_role(ctx, &guild).await {
+      self
+        .report_error(&format!(
+          "Couldn't create the Verified role, continuing without it: {error}"
+        ))
+        .await;
+    }
+
+    if let Err(error) = self.send_verify_info(ctx, &verify_channel).await {
+      self
+        .report_error(&format!("Couldn't send the verify info message: {error}"))
+        .await;
+    }
+
+    if let Err(error) = self.register_commands(ctx, guild.id).await {
+      self
+        .report_error(&format!("Couldn't register the slash commands: {error}"))
+        .await;
+    }
+
+    // Role changes are compared with the cached member, so every member has to be in the cache
+    ctx
+      .shard
+      .chunk_guild(guild.id, None, false, ChunkGuildFilter::None, None);
+
+    for guild_id in self.other_guilds.keys() {
+      self.set_up_other_guild(ctx, *guild_id).await;
+    }
+
+    self.initialized.store(true, Ordering::SeqCst);
+    if let Some(background_start) = self.background_start.lock().unwrap().take() {
+      // Only fails if the background tasks were already stopped, when the client is shutting down
+      let _ = background_start.send(ctx.clone());
+    }
+  }
+}
+
+/// Runs the bot until it's shut down
+pub async fn run() {
+  // The problems are logged too, so the format falls back to the default when the config is broken
+  let config = Config::load();
+  logging::init(
+    config
+      .as_ref()
+      .map_or(LogFormat::default(), |config| config.log_format),
+  );
+  let config = config.unwrap_or_else(|problems| {
+    error!("The configuration has problems:");
+    for problem in problems {
+      error!("- {problem}");
+    }
+    std::process::exit(1);
+  });
+
+  let mut observers: Vec<Box<dyn VerificationObserver>> = Vec::new();
+  if let Some(audit_channel_id) = config.settings.audit_channel_id {
+    observers.push(Box::new(AuditChannelObserver::new(ChannelId::new(
+      audit_channel_id,
+    ))));
+  }
+  let token = config.settings.discord_token.clone();
+
+  let (messages, missing_messages) = Messages::load().unwrap_or_else(|problems| {
+    error!("The messages file has problems:");
+    for problem in problems {
+      error!("- {problem}");
+    }
+    std::process::exit(1);
+  });
+  if !missing_messages.is_empty() {
+    warn!(
+      "The messages file doesn't have {}, they stay in English",
+      missing_messages.join(", ")
+    );
+  }
+
+  let handler = Arc::new(Handler::new(config, messages, observers).await);
+  let (background_start, started) = oneshot::channel();
+  *handler.background_start.lock().unwrap() = Some(background_start);
+
+  // Members for the joins, leaves and role changes, moderation for the bans, and the message
+  // content only for the chat bridge. Presences aren't needed
+  let mut intents = GatewayIntents::GUILDS
+    | GatewayIntents::GUILD_MEMBERS
+    | GatewayIntents::GUILD_MODERATION
+    | GatewayIntents::GUILD_MESSAGES;
+  if handler.chat_bridge.is_some() {
+    intents |= GatewayIntents::MESSAGE_CONTENT;
+  }
+
+  // Start with the last applied presence, so it doesn't flicker on every restart
+  let mut client_builder = Client::builder(&token, intents);
+  let stored_state = handler.store.read(|data| data.status.clone());
+  if let Some(state) = stored_state.filter(|_| handler.status.update_presence) {
+    let (activity, online_status) = status_presence(&state);
+    client_builder = client_builder.activity(activity).status(online_status);
+  }
+
+  let mut client = client_builder
+    .event_handler_arc(handler.clone())
+    .await
+    .expect("Error creating client");
+
+  // Health checks are answered before the gateway is ready, with a 503
+  let metrics_handler = handler.clone();
+  tokio::spawn(async move { metrics_handler.metrics_loop().await });
+
+  let background_handler = handler.clone();
+  let mut background_tasks =
+    tokio::spawn(async move { background_handler.run_background_tasks(started).await });
+
+  let shard_manager = client.shard_manager.clone();
+  tokio::spawn(async move {
+    shutdown_signal().await;
+    info!("Shutting down...");
+    shard_manager.shutdown_all().await;
+  });
+
+  if let Err(error) = client.start().await {
+    error!("Client error: {:?}", error);
+  }
+
+  handler.shut_down().await;
+  if time::timeout(SHUTDOWN_GRACE_PERIOD, &mut background_tasks)
+    .await
+    .is_err()
+  {
+    background_tasks.abort();
+  }
+  info!("Shut down");
+}
+
+/// Waits for Ctrl-C, or SIGTERM from e.g. `docker stop`
+async fn shutdown_signal() {
+  #[cfg(unix)]
+  {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("Couldn't listen for SIGTERM");
+    tokio::select! {
+      _ = tokio::signal::ctrl_c() => {}
+      _ = terminate.recv() => {}
+    }
+  }
+
+  #[cfg(not(unix))]
+  let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A handler with its store in a fresh temporary file, for a server that can't be reached.
+  /// `vars` are set on top of the required settings
+  pub(super) async fn test_handler(name: &str, vars: &[(&str, &str)]) -> Handler {
+    let store_path =
+      std::env::temp_dir().join(format!("mc_discord_bot_{name}_{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&store_path);
+    let store_path = store_path.display().to_string();
+
+    let vars: HashMap<String, String> = [
+      ("DISCORD_TOKEN", "token"),
+      ("SERVER_ADDRESS", "127.0.0.1"),
+      ("RCON_PORT", "1"),
+      ("RCON_PASSWORD", "password"),
+      ("DISCORD_VERIFY_CHANNEL_ID", "1"),
+      ("STORE_PATH", &store_path),
+    ]
+    .iter()
+    .chain(vars)
+    .map(|(name, value)| (name.to_string(), value.to_string()))
+    .collect();
+    let config = Config::from_env(|name| vars.get(name).cloned()).unwrap();
+
+    Handler::new(config, Messages::default(), Vec::new()).await
+  }
+
+  #[test]
+  fn server_errors_and_rate_limits_are_transient() {
+    for status in [500, 502, 503, 429] {
+      assert_eq!(
+        classify_discord_status(StatusCode::from_u16(status).unwrap()),
+        DiscordFailure::Transient,
+        "{status}"
+      );
+    }
+    assert_eq!(
+      classify_discord_error(&SerenityError::Io(io::ErrorKind::ConnectionReset.into())),
+      DiscordFailure::Transient
+    );
+  }
+
+  #[test]
+  fn client_errors_are_permanent() {
+    for status in [400, 403, 404] {
+      assert_eq!(
+        classify_discord_status(StatusCode::from_u16(status).unwrap()),
+        DiscordFailure::Permanent,
+        "{status}"
+      );
+    }
+    assert_eq!(
+      classify_discord_error(&SerenityError::Model(ModelError::InvalidChannelType)),
+      DiscordFailure::Permanent
+    );
+  }
+
+  #[test]
+  fn long_lists_are_cut_off() {
+    let entries: Vec<String> = (0..200).map(|index| format!("Player{index:04}")).collect();
+
+    let list = limited_list(&entries);
+
+    assert!(list.len() <= 1024);
+    assert!(list.ends_with("more"));
+    assert_eq!(limited_list(&entries[..2]), "Player0000, Player0001");
+  }
+}
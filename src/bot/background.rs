@@ -0,0 +1,1101 @@
+//! The tasks that run for as long as the bot does: the status, the offline queue, verification
+//! expiry, the chat bridge, alerts, schedules and the web and metrics servers
+
+use super::*;
+
+/// How often the guild snapshot is refreshed
+const GUILD_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How often expired verifications are looked for
+const VERIFICATION_EXPIRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often queued and trusted verifications are retried during a Mojang outage
+const MOJANG_OUTAGE_RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How long a web verification callback can take to send its request
+const WEB_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often the server log is checked for new chat messages
+const CHAT_LOG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the notices about failing background work are sent
+const ALERT_SEND_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often expired verification codes and failed attempts are forgotten
+const VERIFY_CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often the stored accounts are checked for name changes
+const NAME_SYNC_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The pause between the name change lookups, to stay under the session server's rate limit
+const NAME_SYNC_LOOKUP_DELAY: Duration = Duration::from_secs(1);
+
+/// How long a schedule sleeps at most before looking at the clock again, so it notices the clock
+/// being set or the machine waking up
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A scheduled run this late is skipped, like after the machine slept through it. A restart
+/// warning is wrong hours later
+const SCHEDULE_MAX_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Verifications have to be renewed after a number of days, with a DM warning `warning_days`
+/// before. `None` means the verification never expires
+pub(super) struct VerificationExpiry {
+  /// For members without any of the `role_ttl_days` roles, and members who left
+  pub(super) default_ttl_days: Option<i64>,
+  pub(super) role_ttl_days: Vec<(String, Option<i64>)>,
+  pub(super) warning_days: i64,
+}
+
+impl VerificationExpiry {
+  /// The member's verification lasts as long as the longest lasting of their roles allows
+  fn ttl_days(&self, guild: &GuildSnapshot, member: Option<&Member>) -> Option<i64> {
+    let Some(member) = member else {
+      return self.default_ttl_days;
+    };
+
+    let role_ttls: Vec<Option<i64>> = self
+      .role_ttl_days
+      .iter()
+      .filter(|(role_name, _)| {
+        guild
+          .role_by_name(role_name)
+          .is_some_and(|role| member.roles.contains(&role.id))
+      })
+      .map(|(_, ttl_days)| *ttl_days)
+      .collect();
+
+    if role_ttls.is_empty() {
+      self.default_ttl_days
+    } else if role_ttls.contains(&None) {
+      None
+    } else {
+      role_ttls.into_iter().flatten().max()
+    }
+  }
+
+  /// Whether the link might need a warning or expire already, going by the shortest TTL any
+  /// member can have. A warned link is always due, a role that never expires resets its warning
+  fn is_due(&self, link: &Link, now: i64) -> bool {
+    let shortest_ttl_days = self
+      .role_ttl_days
+      .iter()
+      .map(|(_, ttl_days)| *ttl_days)
+      .chain([self.default_ttl_days])
+      .flatten()
+      .min();
+
+    link.expiry_warned
+      || shortest_ttl_days.is_some_and(|ttl_days| {
+        now - link.linked_at >= (ttl_days - self.warning_days) * SECONDS_PER_DAY
+      })
+  }
+}
+
+/// Parses per role TTLs like "Member=30;Donor=never"
+pub fn parse_role_ttl_days(config: &str) -> Option<Vec<(String, Option<i64>)>> {
+  config
+    .split(';')
+    .filter(|entry| !entry.trim().is_empty())
+    .map(|entry| {
+      let (role_name, ttl_days) = entry.split_once('=')?;
+      let ttl_days = match ttl_days.trim() {
+        "never" => None,
+        days => Some(days.parse().ok()?),
+      };
+
+      Some((role_name.trim().to_string(), ttl_days))
+    })
+    .collect()
+}
+
+impl Handler {
+  /// Runs the status loop and the other periodic tasks, once the setup succeeded. They run in
+  /// their own task, so `ready` returns and a reconnect can't start a second copy
+  pub(super) async fn run_background_tasks(self: &Arc<Self>, started: oneshot::Receiver<Context>) {
+    // The client stopped before it was ever ready
+    let Ok(ctx) = started.await else {
+      return;
+    };
+
+    info!("Starting the background tasks");
+    let other_tasks = async {
+      tokio::join!(
+        self.extra_status_loop(&ctx),
+        self.other_guild_status_loop(&ctx),
+        self.guild_snapshot_loop(&ctx),
+        self.verification_expiry_loop(&ctx),
+        self.mojang_outage_loop(&ctx),
+        self.web_verify_loop(&ctx),
+        self.name_sync_loop(&ctx),
+        self.verify_cleanup_loop(),
+        self.reconcile_loop(&ctx),
+        self.schedule_loop(&ctx),
+        self.daily_summary_loop(&ctx),
+        self.chat_bridge_loop(),
+        self.alert_loop(&ctx)
+      )
+    };
+
+    // The status loop finishes its tick on its own, the others are simply stopped
+    tokio::join!(self.status_loop(&ctx), async {
+      tokio::select! {
+        _ = other_tasks => {}
+        _ = self.shutdown_requested() => {}
+      }
+    });
+  }
+
+  async fn status_loop(&self, ctx: &Context) {
+    let mut interval = time::interval(self.status.tick_interval());
+
+    loop {
+      tokio::select! {
+        _ = interval.tick() => {}
+        _ = self.shutdown_requested() => return,
+      }
+
+      self.poll_server(ctx).await;
+      self.record_activity();
+      self.record_sightings().await;
+      self.replay_pending_operations().await;
+
+      *self.status.last_tick.lock().unwrap() = Some(Instant::now());
+      debug!("Tick complete");
+    }
+  }
+
+  /// Replays the whitelist changes queued while the server couldn't be reached, in the order they
+  /// were made. Runs once the server is seen online again. A server that still can't be reached
+  /// keeps the rest of its operations for the next check
+  async fn replay_pending_operations(&self) {
+    let is_online = self
+      .status
+      .latest_snapshot
+      .lock()
+      .unwrap()
+      .as_ref()
+      .is_some_and(|snapshot| snapshot.online);
+    let operations = self.store.read(|data| data.pending_operations.clone());
+
+    if !is_online || operations.is_empty() {
+      return;
+    }
+
+    let mut unreachable: Vec<Option<String>> = Vec::new();
+    for operation in operations {
+      if unreachable.contains(&operation.server) {
+        continue;
+      }
+
+      let name = &operation.minecraft_name;
+      if self.store.read(|data| data.is_stale(&operation)) {
+        info!(
+          "Dropped the queued {:?} of '{name}', it no longer matches the links",
+          operation.kind
+        );
+        self.forget_operation(&operation);
+        continue;
+      }
+
+      let server = self.server_by_name(operation.server.as_deref());
+      let commands = match operation.kind {
+        OperationKind::WhitelistAdd => vec![self.whitelist_add_command(name)],
+        OperationKind::WhitelistRemove => vec![format!("whitelist remove {name}")],
+        OperationKind::Ban => vec![
+          format!("ban {name} {DISCORD_BAN_REASON}"),
+          format!("whitelist remove {name}"),
+        ],
+        OperationKind::Pardon => vec![format!("pardon {name}")],
+      };
+
+      let mut replayed = true;
+      for command in &commands {
+        if let Err(err) = self.run_rcon_command_on(server, command).await {
+          warn!(
+            "Couldn't run `{command}` for {}: {err}",
+            operation.discord_id
+          );
+          replayed = false;
+          break;
+        }
+      }
+      if !replayed {
+        unreachable.push(operation.server.clone());
+        continue;
+      }
+
+      self.store.write(|data| {
+        if operation.forget_link {
+          forget_accounts(data, operation.discord_id, std::slice::from_ref(name));
+        }
+      });
+      self.forget_operation(&operation);
+      info!(
+        "Ran `{}` for {}, queued while the server was offline",
+        commands.join("`, `"),
+        operation.discord_id
+      );
+    }
+  }
+
+  fn forget_operation(&self, operation: &PendingOperation) {
+    self.store.write(|data| {
+      if let Some(index) = data
+        .pending_operations
+        .iter()
+        .position(|queued| queued == operation)
+      {
+        data.pending_operations.remove(index);
+      }
+    });
+  }
+
+  /// Queues a whitelist change for when the server is back online
+  pub(super) fn queue_operation(
+    &self,
+    kind: OperationKind,
+    discord_id: u64,
+    minecraft_name: &str,
+    server: Option<String>,
+    forget_link: bool,
+  ) {
+    self.store.write(|data| {
+      data.queue_operation(PendingOperation {
+        kind,
+        discord_id,
+        minecraft_name: minecraft_name.to_string(),
+        server,
+        forget_link,
+        queued_at: Utc::now().timestamp(),
+      })
+    });
+  }
+
+  /// Keeps refreshing the guild snapshot, so roles and channels changed at runtime are picked up
+  async fn guild_snapshot_loop(&self, ctx: &Context) {
+    let mut interval = time::interval(GUILD_SNAPSHOT_INTERVAL);
+    // The first tick would fire right away, but ready just fetched a snapshot
+    interval.tick().await;
+
+    loop {
+      interval.tick().await;
+
+      if let Err(error) = self.refresh_guild_snapshot(&ctx.http).await {
+        warn!("Couldn't refresh the guild snapshot: {error}");
+      }
+
+      for (guild_id, other_guild) in &self.other_guilds {
+        if !other_guild.set_up.load(Ordering::SeqCst) {
+          continue;
+        }
+        if let Err(error) = self.refresh_guild_snapshot_of(&ctx.http, *guild_id).await {
+          warn!("Couldn't refresh the snapshot of the guild {guild_id}: {error}");
+        }
+      }
+    }
+  }
+
+  /// The whitelist works by name, so an account that changed its name would lose access. Re-resolves
+  /// the stored uuids and moves the whitelist entries over to the new names
+  async fn name_sync_loop(&self, ctx: &Context) {
+    // Offline mode UUIDs come from the names, so they can't be renamed
+    if self.offline_mode {
+      return;
+    }
+
+    let mut interval = time::interval(NAME_SYNC_INTERVAL);
+
+    loop {
+      interval.tick().await;
+
+      let links: Vec<Link> = self.store.read(|data| data.links.iter().cloned().collect());
+
+      // Mojang doesn't know Bedrock accounts
+      for link in links
+        .into_iter()
+        .filter(|link| link.platform == Platform::Java)
+      {
+        time::sleep(NAME_SYNC_LOOKUP_DELAY).await;
+
+        let Some(uuid) = dashed_uuid(&link.uuid) else {
+          continue;
+        };
+        let name = match get_profile_by_uuid(&self.http, &uuid).await {
+          Ok(MojangResponse::Success { name, .. }) => name,
+          Ok(MojangResponse::Failure { error_message, .. }) => {
+            warn!("Couldn't look up the name of {uuid}: {error_message}");
+            continue;
+          }
+          Err(reason) => {
+            warn!("Couldn't look up the name of {uuid}: {reason}");
+            continue;
+          }
+        };
+
+        if name == link.minecraft_name {
+          continue;
+        }
+
+        let old_name = &link.minecraft_name;
+        // With the role commands replacing the whitelist, there is no whitelist entry to move. A
+        // suspended account isn't on the whitelist either
+        if !self.role_commands_replace_whitelist && link.is_active() {
+          let moved = async {
+            let server = self.server_by_name(link.server.as_deref());
+            let mut rcon_client = self.connect_to(server).await?;
+            rcon_client
+              .run_command(&format!("whitelist remove {old_name}"))
+              .await?;
+            rcon_client
+              .run_command(&format!("whitelist add {name}"))
+              .await
+          };
+
+          if let Err(err) = moved.await {
+            warn!("Couldn't move the whitelist entry of '{old_name}' to '{name}': {err}");
+            continue;
+          }
+        }
+
+        self.store.write(|data| {
+          if let Some(link) = data.links.by_uuid_mut(&link.uuid, link.server.as_deref()) {
+            link.minecraft_name = name.clone();
+          }
+        });
+        info!("'{old_name}' ({uuid}) is now called '{name}', updated the whitelist");
+
+        if let Some(guild) = self.guild_snapshot() {
+          self
+            .sync_member_nickname(ctx, guild.id, UserId::new(link.discord_id), &name)
+            .await;
+        }
+      }
+    }
+  }
+
+  /// Warns about and expires old verifications, if VERIFICATION_TTL_DAYS is set
+  async fn verification_expiry_loop(&self, ctx: &Context) {
+    let Some(expiry) = &self.verification_expiry else {
+      return;
+    };
+
+    let mut interval = time::interval(VERIFICATION_EXPIRY_INTERVAL);
+
+    loop {
+      interval.tick().await;
+
+      let Some(guild) = self.guild_snapshot() else {
+        continue;
+      };
+
+      let now = Utc::now().timestamp();
+      // Only the members of links that might be due are looked up, not the whole guild
+      let links: Vec<Link> = self.store.read(|data| {
+        data
+          .links
+          .iter()
+          .filter(|link| expiry.is_due(link, now))
+          .cloned()
+          .collect()
+      });
+      let mut members: HashMap<UserId, Option<Member>> = HashMap::new();
+
+      for link in links {
+        let user_id = UserId::new(link.discord_id);
+        // The roles are checked every time, so role changes since verifying are picked up
+        let member = match members.get(&user_id) {
+          Some(member) => member.clone(),
+          None => match self.expiring_member(ctx, guild.id, user_id).await {
+            Ok(member) => {
+              members.insert(user_id, member.clone());
+              member
+            }
+            Err(error) => {
+              warn!("Couldn't fetch {user_id} to check their verification expiry: {error}");
+              continue;
+            }
+          },
+        };
+        let Some(ttl_days) = expiry.ttl_days(&guild, member.as_ref()) else {
+          // A role that never expires may have been added after the warning
+          if link.expiry_warned {
+            self.store.write(|data| {
+              if let Some(link) = data.links.by_uuid_mut(&link.uuid, link.server.as_deref()) {
+                link.expiry_warned = false;
+              }
+            });
+          }
+          continue;
+        };
+
+        let age = now - link.linked_at;
+
+        if age >= ttl_days * SECONDS_PER_DAY {
+          self.expire_verification(ctx, &link).await;
+        } else if !link.expiry_warned && age >= (ttl_days - expiry.warning_days) * SECONDS_PER_DAY {
+          let days_left =
+            (ttl_days * SECONDS_PER_DAY - age + SECONDS_PER_DAY - 1) / SECONDS_PER_DAY;
+          self.warn_verification_expiry(ctx, &link, days_left).await;
+        }
+      }
+    }
+  }
+
+  /// The member from the cache, or fetched when it isn't cached. `None` when they left the guild
+  async fn expiring_member(
+    &self,
+    ctx: &Context,
+    guild_id: GuildId,
+    user_id: UserId,
+  ) -> Result<Option<Member>, SerenityError> {
+    if let Some(member) = ctx.cache.member(guild_id, user_id) {
+      return Ok(Some(member.clone()));
+    }
+
+    match ctx.http.get_member(guild_id, user_id).await {
+      Ok(member) => Ok(Some(member)),
+      Err(error) if is_unknown_member(&error) => Ok(None),
+      Err(error) => Err(error),
+    }
+  }
+
+  async fn warn_verification_expiry(&self, ctx: &Context, link: &Link, days_left: i64) {
+    let message = format!(
+      "Your verification of '{}' expires in {days_left} day(s). Use /verify again after it expires to keep playing.",
+      link.minecraft_name
+    );
+
+    // Only warn once, even if the DM fails because the user left or closed their DMs
+    if let Err(error) = direct_message(
+      ctx,
+      UserId::new(link.discord_id),
+      CreateMessage::new().content(message),
+    )
+    .await
+    {
+      warn!(
+        "Couldn't warn {} about the expiring verification: {error}",
+        link.discord_id
+      );
+    }
+
+    self.store.write(|data| {
+      if let Some(link) = data.links.by_uuid_mut(&link.uuid, link.server.as_deref()) {
+        link.expiry_warned = true;
+      }
+    });
+  }
+
+  /// Removes the whitelist entry, and the Verified role if it was the user's last account. If the
+  /// server can't be reached, the link stays and the next run tries again
+  async fn expire_verification(&self, ctx: &Context, link: &Link) {
+    let name = &link.minecraft_name;
+    let server = self.server_by_name(link.server.as_deref());
+    if let Err(err) = self
+      .run_rcon_command_on(server, &format!("whitelist remove {name}"))
+      .await
+    {
+      warn!("Couldn't remove the expired verification of '{name}' from the whitelist: {err}");
+      return;
+    }
+
+    let user_id = UserId::new(link.discord_id);
+    self
+      .notify_account_event(
+        ctx,
+        AccountEvent {
+          action: AccountAction::AutomaticRemoval,
+          discord_id: user_id,
+          minecraft_name: name,
+          uuid: Some(&link.uuid),
+          outcome: AccountOutcome::Done,
+          admin: None,
+          reason: Some("The verification expired"),
+        },
+      )
+      .await;
+    self
+      .store
+      .write(|data| data.links.remove(&link.uuid, link.server.as_deref()));
+
+    if let Err(error) = self
+      .remove_verified_role(ctx, user_id, "Verification expired")
+      .await
+    {
+      warn!("Couldn't remove the Verified role from {user_id}: {error}");
+    }
+
+    info!("The verification of '{name}' by {user_id} expired");
+
+    if let Err(error) = direct_message(
+      ctx,
+      user_id,
+      CreateMessage::new().content(format!(
+        "Your verification of '{name}' expired. Use /verify again to keep playing."
+      )),
+    )
+    .await
+    {
+      warn!("Couldn't tell {user_id} their verification expired: {error}");
+    }
+  }
+
+  /// Gives the status channels of the other guilds the name of the main status channel
+  async fn other_guild_status_loop(&self, ctx: &Context) {
+    if self
+      .other_guilds
+      .values()
+      .all(|other_guild| other_guild.config.status_channel_id.is_none())
+    {
+      return;
+    }
+
+    let mut interval = time::interval(self.status.interval);
+
+    loop {
+      interval.tick().await;
+
+      let Some(new_channel_name) = self.store.read(|data| {
+        data
+          .status
+          .as_ref()
+          .map(|status| status.channel_name.clone())
+      }) else {
+        continue;
+      };
+
+      for (guild_id, other_guild) in &self.other_guilds {
+        let Some(status_channel_id) = other_guild.config.status_channel_id else {
+          continue;
+        };
+        if other_guild.channel_name.lock().unwrap().as_deref() == Some(new_channel_name.as_str()) {
+          continue;
+        }
+
+        match rename_channel(
+          ctx,
+          &other_guild.rename_limiter,
+          ChannelId::new(status_channel_id),
+          &new_channel_name,
+        )
+        .await
+        {
+          Ok(_) => {
+            info!("Channel name in the guild {guild_id} changed to '{new_channel_name}'");
+            *other_guild.channel_name.lock().unwrap() = Some(new_channel_name.clone());
+          }
+          Err(error) => {
+            warn!("Couldn't change the name of the status channel in the guild {guild_id}: {error}")
+          }
+        }
+      }
+    }
+  }
+
+  /// Keeps the status channels of the extra servers up to date. The presence and the status
+  /// commands stay about the main server
+  async fn extra_status_loop(&self, ctx: &Context) {
+    let extra_servers = &self.servers[1..];
+    if extra_servers
+      .iter()
+      .all(|server| server.config.status_channel_id.is_none())
+    {
+      return;
+    }
+
+    let mut interval = time::interval(self.status.interval);
+
+    loop {
+      interval.tick().await;
+
+      for server in extra_servers {
+        let Some(status_channel_id) = server.config.status_channel_id else {
+          continue;
+        };
+
+        let snapshot = self.fetch_server_snapshot(server, None).await;
+        let new_channel_name = fit_channel_name(&format!(
+          "{}: {}",
+          server.config.name,
+          snapshot.channel_name(&self.status.format)
+        ));
+
+        if server.channel_name.lock().unwrap().as_deref() == Some(new_channel_name.as_str()) {
+          continue;
+        }
+
+        match rename_channel(
+          ctx,
+          &server.rename_limiter,
+          ChannelId::new(status_channel_id),
+          &new_channel_name,
+        )
+        .await
+        {
+          Ok(_) => {
+            info!(
+              "Channel name of the {} server changed to '{new_channel_name}'",
+              server.config.name
+            );
+            *server.channel_name.lock().unwrap() = Some(new_channel_name);
+          }
+          Err(error) => warn!(
+            "Couldn't change the name of the {} server's status channel: {error}",
+            server.config.name
+          ),
+        }
+      }
+    }
+  }
+
+  /// Forgets the codes and human gates that weren't confirmed in time and the failed attempts that
+  /// don't count anymore, so none of them grows forever
+  async fn verify_cleanup_loop(&self) {
+    let mut interval = time::interval(VERIFY_CLEANUP_INTERVAL);
+
+    loop {
+      interval.tick().await;
+
+      self.forget_expired_verifications(Instant::now());
+    }
+  }
+
+  /// Serves the callback the verification website calls once the user is done there
+  async fn web_verify_loop(self: &Arc<Self>, ctx: &Context) {
+    let Some(web_verify) = &self.web_verify else {
+      return;
+    };
+
+    let listener = match TcpListener::bind(&web_verify.listen_address).await {
+      Ok(listener) => listener,
+      Err(error) => {
+        self
+          .report_error(&format!(
+            "Couldn't listen on {} for web verification callbacks: {error}",
+            web_verify.listen_address
+          ))
+          .await;
+        return;
+      }
+    };
+    info!(
+      "Listening for web verification callbacks on {}",
+      web_verify.listen_address
+    );
+
+    loop {
+      let stream = match listener.accept().await {
+        Ok((stream, _)) => stream,
+        Err(error) => {
+          warn!("Couldn't accept a web verification connection: {error}");
+          continue;
+        }
+      };
+
+      // Every connection gets its own task, so a slow client or a slow verification doesn't hold
+      // up the others
+      let handler = self.clone();
+      let ctx = ctx.clone();
+      tokio::spawn(async move { handler.web_verify_connection(&ctx, stream).await });
+    }
+  }
+
+  async fn web_verify_connection(&self, ctx: &Context, mut stream: tokio::net::TcpStream) {
+    let Some(web_verify) = &self.web_verify else {
+      return;
+    };
+
+    let Ok(Ok(request)) = time::timeout(WEB_REQUEST_TIMEOUT, web::read_request(&mut stream)).await
+    else {
+      return;
+    };
+
+    let (status, body) = self.web_verify_callback(ctx, web_verify, &request).await;
+    if let Err(error) = web::write_response(&mut stream, status, &body).await {
+      warn!("Couldn't answer a web verification callback: {error}");
+    }
+  }
+
+  /// Serves `/healthz` and `/metrics` on METRICS_ADDR until the bot shuts down
+  pub(super) async fn metrics_loop(self: &Arc<Self>) {
+    let Some(metrics_addr) = &self.metrics_addr else {
+      return;
+    };
+
+    let listener = match TcpListener::bind(metrics_addr).await {
+      Ok(listener) => listener,
+      Err(error) => {
+        error!("Couldn't listen on {metrics_addr} for health checks and metrics: {error}");
+        return;
+      }
+    };
+    info!("Serving health checks and metrics on {metrics_addr}");
+
+    loop {
+      let stream = tokio::select! {
+        accepted = listener.accept() => match accepted {
+          Ok((stream, _)) => stream,
+          Err(error) => {
+            warn!("Couldn't accept a metrics connection: {error}");
+            continue;
+          }
+        },
+        _ = self.shutdown_requested() => return,
+      };
+
+      // A client that stalls only holds up its own task, not the next health check
+      let handler = self.clone();
+      tokio::spawn(async move { handler.metrics_connection(stream).await });
+    }
+  }
+
+  async fn metrics_connection(&self, mut stream: tokio::net::TcpStream) {
+    let Ok(Ok(request)) = time::timeout(WEB_REQUEST_TIMEOUT, web::read_request(&mut stream)).await
+    else {
+      return;
+    };
+
+    let (status, body) = match (request.method.as_str(), request.path.as_str()) {
+      ("GET", "/healthz") => {
+        let healthy = metrics::is_healthy(
+          self.initialized.load(Ordering::SeqCst),
+          *self.status.last_tick.lock().unwrap(),
+          self.status.tick_interval(),
+          Instant::now(),
+        );
+        if healthy {
+          (200, "ok".to_string())
+        } else {
+          (503, "unhealthy".to_string())
+        }
+      }
+      ("GET", "/metrics") => (200, METRICS.render()),
+      (_, "/healthz" | "/metrics") => (405, "Method not allowed".to_string()),
+      _ => (404, "Not found".to_string()),
+    };
+    if let Err(error) = web::write_response(&mut stream, status, &body).await {
+      warn!("Couldn't answer a metrics request: {error}");
+    }
+  }
+
+  /// Retries queued verifications and checks the accounts whitelisted without Mojang, once the
+  /// Mojang API is back
+  async fn mojang_outage_loop(&self, ctx: &Context) {
+    let mut interval = time::interval(MOJANG_OUTAGE_RETRY_INTERVAL);
+
+    loop {
+      interval.tick().await;
+
+      self.retry_queued_verifications(ctx).await;
+      self.validate_trusted_verifications(ctx).await;
+    }
+  }
+
+  /// Posts the in-game chat to the bridge channel. Messages relayed from Discord are sent with
+  /// `tellraw`, which isn't logged as chat, so they don't come back
+  async fn chat_bridge_loop(&self) {
+    let Some((webhook_url, log_path)) = self.chat_bridge.as_ref().and_then(|chat_bridge| {
+      chat_bridge
+        .webhook_url
+        .as_ref()
+        .zip(chat_bridge.log_path.as_ref())
+    }) else {
+      return;
+    };
+
+    let mut log_tail = LogTail::new(log_path);
+    let mut interval = time::interval(CHAT_LOG_POLL_INTERVAL);
+    let mut unreadable = false;
+
+    loop {
+      interval.tick().await;
+
+      let lines = match log_tail.read_lines().await {
+        Ok(lines) => {
+          unreadable = false;
+          lines
+        }
+        Err(error) => {
+          // Only once, the log is missing for a moment whenever the server rotates it
+          if !unreadable {
+            warn!("Couldn't read the server log at {log_path}: {error}");
+            unreadable = true;
+          }
+          continue;
+        }
+      };
+
+      for chat_line in lines.iter().filter_map(|line| chat::parse_chat_line(line)) {
+        self.relay_to_discord(webhook_url, &chat_line).await;
+      }
+    }
+  }
+
+  /// Sends the notices about failing background work. They're decided where the work fails,
+  /// which doesn't always have a context to send them with
+  async fn alert_loop(&self, ctx: &Context) {
+    let mut interval = time::interval(ALERT_SEND_INTERVAL);
+
+    loop {
+      interval.tick().await;
+
+      let alerts = std::mem::take(&mut *self.pending_alerts.lock().unwrap());
+      for alert in alerts {
+        self.send_alert(ctx, &alert).await;
+      }
+    }
+  }
+
+  /// Runs the reconciliation every interval, if there is a channel for the report
+  async fn reconcile_loop(&self, ctx: &Context) {
+    let Some(channel_id) = self.reconcile.channel_id else {
+      return;
+    };
+
+    let mut interval = time::interval(self.reconcile.interval);
+    // Not on every restart, only once the interval has passed
+    interval.tick().await;
+
+    loop {
+      interval.tick().await;
+
+      let Some(embed) = self.reconcile(ctx).await else {
+        continue;
+      };
+      if let Err(error) = ChannelId::new(channel_id)
+        .send_message(ctx, CreateMessage::new().embed(embed))
+        .await
+      {
+        warn!("Couldn't post the reconciliation report: {error}");
+      }
+    }
+  }
+
+  /// Posts yesterday's activity to the status channel every day at DAILY_SUMMARY_TIME
+  async fn daily_summary_loop(&self, ctx: &Context) {
+    let Some(summary_time) = self.daily_summary_time else {
+      return;
+    };
+    let cron = Cron::parse(&format!(
+      "{} {} * * *",
+      summary_time.minute(),
+      summary_time.hour()
+    ))
+    .expect("A time of day is a valid cron expression");
+
+    let mut after = Utc::now();
+    while let Some(next) = cron.next_after(after, &self.timezone) {
+      while let Ok(remaining) = (next - Utc::now()).to_std() {
+        time::sleep(remaining.min(SCHEDULE_CHECK_INTERVAL)).await;
+      }
+
+      let yesterday = self.timezone.to_local(next).date() - Days::new(1);
+      self.post_daily_summary(ctx, yesterday).await;
+      after = next.max(Utc::now());
+    }
+  }
+
+  /// Runs every `[[schedules]]` entry at its times. Each one waits on its own, so a slow command
+  /// doesn't hold up the others
+  async fn schedule_loop(&self, ctx: &Context) {
+    serenity::futures::future::join_all(
+      self
+        .schedules
+        .iter()
+        .map(|schedule| self.run_schedule(ctx, schedule)),
+    )
+    .await;
+  }
+
+  async fn run_schedule(&self, ctx: &Context, schedule: &Schedule) {
+    let mut after = Utc::now();
+
+    loop {
+      let Some(next) = schedule.cron.next_after(after, &self.timezone) else {
+        warn!("The schedule '{}' never runs", schedule.expression);
+        return;
+      };
+      debug!(
+        "The schedule '{}' runs next at {} UTC",
+        schedule.expression,
+        next.format("%Y-%m-%d %H:%M")
+      );
+
+      while let Ok(remaining) = (next - Utc::now()).to_std() {
+        time::sleep(remaining.min(SCHEDULE_CHECK_INTERVAL)).await;
+      }
+
+      let delay = (Utc::now() - next).to_std().unwrap_or_default();
+      if delay > SCHEDULE_MAX_DELAY {
+        warn!(
+          "Skipped the schedule '{}', it's {} minutes late",
+          schedule.expression,
+          delay.as_secs() / 60
+        );
+      } else {
+        self.run_scheduled(ctx, schedule).await;
+      }
+      after = next.max(Utc::now());
+    }
+  }
+
+  /// Posts the schedule's message and runs its command. The command is skipped while the server
+  /// is unreachable, it would only fail
+  async fn run_scheduled(&self, ctx: &Context, schedule: &Schedule) {
+    info!("Running the schedule '{}'", schedule.expression);
+
+    if let Some(message) = &schedule.message {
+      match self.status.channel_id {
+        Some(status_channel_id) => match ChannelId::new(status_channel_id)
+          .send_message(ctx, CreateMessage::new().content(message))
+          .await
+        {
+          Ok(_) => info!(
+            "Posted the message of the schedule '{}'",
+            schedule.expression
+          ),
+          Err(error) => warn!(
+            "Couldn't post the message of the schedule '{}': {error}",
+            schedule.expression
+          ),
+        },
+        None => warn!(
+          "The schedule '{}' has a message, but there is no status channel to post it in",
+          schedule.expression
+        ),
+      }
+    }
+
+    if let Some(command) = &schedule.command {
+      let online = self
+        .status
+        .latest_snapshot
+        .lock()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|snapshot| snapshot.online);
+      if !online {
+        info!(
+          "Skipped the command of the schedule '{}', the server is unreachable",
+          schedule.expression
+        );
+        return;
+      }
+
+      match self.run_rcon_command(command).await {
+        Ok(output) => info!(
+          "Ran the command of the schedule '{}': {}",
+          schedule.expression,
+          output.trim()
+        ),
+        Err(error) => warn!(
+          "Couldn't run the command of the schedule '{}': {error}",
+          schedule.expression
+        ),
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn expiry_with_roles() -> (VerificationExpiry, GuildSnapshot) {
+    let expiry = VerificationExpiry {
+      default_ttl_days: Some(30),
+      role_ttl_days: parse_role_ttl_days("Member=60;Veteran=90;Donor=never").unwrap(),
+      warning_days: 3,
+    };
+    let roles = [(1, "Member"), (2, "Veteran"), (3, "Donor")]
+      .into_iter()
+      .map(|(id, name)| {
+        let mut role = Role::default();
+        role.id = RoleId::new(id);
+        role.name = name.to_string();
+        (role.id, role)
+      })
+      .collect();
+    let guild = GuildSnapshot {
+      id: GuildId::new(1),
+      name: "Guild".to_string(),
+      roles,
+      channels: HashMap::new(),
+    };
+    (expiry, guild)
+  }
+
+  fn member_with_roles(role_ids: &[u64]) -> Member {
+    let mut member = Member::default();
+    member.roles = role_ids.iter().map(|id| RoleId::new(*id)).collect();
+    member
+  }
+
+  #[test]
+  fn role_ttls_are_parsed() {
+    assert_eq!(
+      parse_role_ttl_days("Member=30; Donor = never;"),
+      Some(vec![
+        ("Member".to_string(), Some(30)),
+        ("Donor".to_string(), None)
+      ])
+    );
+    assert_eq!(parse_role_ttl_days(""), Some(Vec::new()));
+    assert_eq!(parse_role_ttl_days("Member"), None);
+    assert_eq!(parse_role_ttl_days("Member=soon"), None);
+  }
+
+  #[test]
+  fn the_longest_role_ttl_wins() {
+    let (expiry, guild) = expiry_with_roles();
+
+    assert_eq!(
+      expiry.ttl_days(&guild, Some(&member_with_roles(&[1, 2]))),
+      Some(90)
+    );
+    assert_eq!(
+      expiry.ttl_days(&guild, Some(&member_with_roles(&[1, 3]))),
+      None
+    );
+    // Without any of the roles, or after leaving, the default applies
+    assert_eq!(
+      expiry.ttl_days(&guild, Some(&member_with_roles(&[]))),
+      Some(30)
+    );
+    assert_eq!(expiry.ttl_days(&guild, None), Some(30));
+  }
+
+  #[test]
+  fn a_role_missing_from_the_guild_is_ignored() {
+    let (mut expiry, guild) = expiry_with_roles();
+    expiry.role_ttl_days.push(("Deleted".to_string(), None));
+
+    assert_eq!(
+      expiry.ttl_days(&guild, Some(&member_with_roles(&[4]))),
+      Some(30)
+    );
+  }
+
+  #[test]
+  fn only_links_near_the_shortest_ttl_are_due() {
+    let (expiry, _) = expiry_with_roles();
+    let now = 100 * SECONDS_PER_DAY;
+    let link = |linked_days_ago: i64, expiry_warned: bool| Link {
+      discord_id: 1,
+      uuid: "069a79f444e94726a5befca90e38aaf5".to_string(),
+      minecraft_name: "Notch".to_string(),
+      linked_at: now - linked_days_ago * SECONDS_PER_DAY,
+      expiry_warned,
+      suspended: false,
+      banned: false,
+      server: None,
+      platform: Platform::Java,
+      xuid: None,
+    };
+
+    assert!(!expiry.is_due(&link(26, false), now));
+    assert!(expiry.is_due(&link(27, false), now));
+    assert!(expiry.is_due(&link(1, true), now));
+  }
+}
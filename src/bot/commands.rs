@@ -0,0 +1,1905 @@
+//! Answering the slash commands, buttons and forms, and keeping the registered slash commands in
+//! line with the ones the bot defines
+
+use super::*;
+
+/// The wait between two lookups of `/import-whitelist`, so a big whitelist stays under Mojang's
+/// rate limit
+const IMPORT_LOOKUP_DELAY: Duration = Duration::from_secs(1);
+
+/// How many names `/import-whitelist` looks up between two progress messages
+const IMPORT_PROGRESS_INTERVAL: usize = 25;
+
+/// How long `/import-whitelist` waits out a rate limit that didn't say how long
+const IMPORT_RATE_LIMIT_WAIT: Duration = Duration::from_secs(60);
+
+/// How many rate limits are waited out for one name before it's skipped
+const IMPORT_RATE_LIMIT_RETRIES: u32 = 3;
+
+/// How long a deferred command can take before the user is told it failed
+pub(super) const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// What the commands keep besides the store
+pub(super) struct CommandState {
+  /// The in-game command of the linking plugin, `{code}` is replaced with the code
+  pub(super) link_command: String,
+  pub(super) link_code_ttl: Duration,
+}
+
+impl CommandState {
+  pub(super) fn new(settings: &Settings) -> Self {
+    Self {
+      link_command: settings.link_command.clone(),
+      link_code_ttl: settings.link_code_ttl,
+    }
+  }
+
+  /// Issues a link code, replacing the user's previous one and forgetting expired ones. Returns
+  /// what the user has to run in-game
+  fn issue_link_code(&self, data: &mut StoreData, discord_id: u64, now: i64) -> String {
+    let code = generate_link_code();
+
+    data
+      .link_codes
+      .retain(|link_code| link_code.discord_id != discord_id && link_code.expires_at > now);
+    data.link_codes.push(LinkCode {
+      discord_id,
+      code: code.clone(),
+      expires_at: now + self.link_code_ttl.as_secs() as i64,
+    });
+
+    format!(
+      "Run `{}` in-game within {} minutes to link your account.",
+      self.link_command.replace("{code}", &code),
+      self.link_code_ttl.as_secs() / 60
+    )
+  }
+}
+
+/// How the registered slash commands differ from the ones the bot defines, by name
+#[derive(Debug, Default, PartialEq)]
+struct CommandChanges {
+  added: Vec<String>,
+  removed: Vec<String>,
+  updated: Vec<String>,
+}
+
+impl CommandChanges {
+  fn is_empty(&self) -> bool {
+    self.added.is_empty() && self.removed.is_empty() && self.updated.is_empty()
+  }
+}
+
+/// The fields of a registered command that Discord fills in itself. The bot doesn't set
+/// `dm_permission`, which guild commands ignore anyway
+const GENERATED_COMMAND_FIELDS: [&str; 8] = [
+  "id",
+  "application_id",
+  "guild_id",
+  "version",
+  "type",
+  "name_localized",
+  "description_localized",
+  "dm_permission",
+];
+
+/// Whether Discord treats the value like a field that isn't there
+fn is_unset(value: &serde_json::Value) -> bool {
+  match value {
+    serde_json::Value::Null => true,
+    serde_json::Value::Bool(value) => !value,
+    serde_json::Value::Array(values) => values.is_empty(),
+    serde_json::Value::Object(fields) => fields.is_empty(),
+    _ => false,
+  }
+}
+
+fn same_definition(defined: &serde_json::Value, registered: &serde_json::Value) -> bool {
+  use serde_json::Value;
+
+  match (defined, registered) {
+    (Value::Object(defined), Value::Object(registered)) => defined
+      .keys()
+      .chain(registered.keys())
+      .all(|key| match (defined.get(key), registered.get(key)) {
+        (Some(defined), Some(registered)) => same_definition(defined, registered),
+        (Some(value), None) | (None, Some(value)) => is_unset(value),
+        (None, None) => true,
+      }),
+    (Value::Array(defined), Value::Array(registered)) => {
+      defined.len() == registered.len()
+        && defined
+          .iter()
+          .zip(registered)
+          .all(|(defined, registered)| same_definition(defined, registered))
+    }
+    (defined, registered) => defined == registered || is_unset(defined) && is_unset(registered),
+  }
+}
+
+fn values_to_json(values: &[impl serde::Serialize]) -> serde_json::Result<Vec<serde_json::Value>> {
+  values.iter().map(serde_json::to_value).collect()
+}
+
+/// Compares the commands as JSON, since Discord sends back defaults the builders leave out and
+/// the other way around
+fn command_changes(
+  defined: &[serde_json::Value],
+  registered: &[serde_json::Value],
+) -> CommandChanges {
+  let name = |command: &serde_json::Value| command["name"].as_str().unwrap_or_default().to_string();
+  let mut changes = CommandChanges::default();
+
+  for command in defined {
+    match registered
+      .iter()
+      .find(|registered| name(registered) == name(command))
+    {
+      None => changes.added.push(name(command)),
+      Some(registered) => {
+        let mut registered = registered.clone();
+        if let Some(fields) = registered.as_object_mut() {
+          fields.retain(|key, _| !GENERATED_COMMAND_FIELDS.contains(&key.as_str()));
+        }
+        if !same_definition(command, &registered) {
+          changes.updated.push(name(command));
+        }
+      }
+    }
+  }
+  changes.removed = registered
+    .iter()
+    .map(name)
+    .filter(|registered| !defined.iter().any(|command| name(command) == *registered))
+    .collect();
+
+  changes
+}
+
+/// Whether the member who ran the command is a server administrator
+pub(super) fn is_admin(command: &CommandInteraction) -> bool {
+  command
+    .member
+    .as_ref()
+    .and_then(|member| member.permissions)
+    .is_some_and(|permissions| permissions.administrator())
+}
+
+/// Lets the user know their command failed, after the error was reported. The command may have
+/// been deferred already, then the deferred response is edited instead
+pub(super) async fn tell_something_went_wrong(ctx: &Context, command: &CommandInteraction) {
+  let message = "Something went wrong, please try again later or contact an admin.";
+
+  let responded = command
+    .create_response(
+      ctx,
+      CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+          .content(message)
+          .ephemeral(true),
+      ),
+    )
+    .await;
+
+  if responded.is_err() {
+    if let Err(error) = command
+      .edit_response(ctx, EditInteractionResponse::new().content(message))
+      .await
+    {
+      warn!(
+        "Couldn't tell the user /{} failed: {error}",
+        command.data.name
+      );
+    }
+  }
+}
+
+pub(super) fn can_manage_roles(command: &CommandInteraction) -> bool {
+  command
+    .member
+    .as_ref()
+    .and_then(|member| member.permissions)
+    .is_some_and(|permissions| permissions.manage_roles())
+}
+
+impl Handler {
+  /// When the player was last seen online by the status checks, and roughly how long they played
+  async fn last_seen(&self, command: &CommandInteraction) -> Reply {
+    let Some(CommandDataOptionValue::String(username)) =
+      command.data.options.first().map(|option| &option.value)
+    else {
+      return Reply::Text("There wasn't a username.".to_string());
+    };
+    let username = crate::commands::clean_username(username);
+
+    // The uuid finds the player under the name they were last seen with, after a rename
+    let uuid = self
+      .profiles()
+      .profile(username)
+      .await
+      .ok()
+      .map(|profile| profile.id);
+    let sighting = self
+      .store
+      .read(|data| data.sighting(username, uuid.as_deref()).cloned());
+
+    crate::commands::last_seen(
+      username,
+      sighting.as_ref(),
+      self.status.tick_interval(),
+      self.status.sightings_partial.load(Ordering::SeqCst),
+      &self.messages,
+    )
+  }
+
+  /// Whitelists someone outside the verification flow, like an operator who isn't in the Discord.
+  /// No link or role is created, the entry is only recorded as unmanaged
+  async fn whitelist_add(&self, ctx: &Context, command: &CommandInteraction) -> String {
+    if !is_admin(command) {
+      return "Only administrators can use this command.".to_string();
+    }
+
+    let Some(CommandDataOptionValue::String(username)) =
+      command.data.options.first().map(|option| &option.value)
+    else {
+      return "There wasn't a username.".to_string();
+    };
+    let username = crate::commands::clean_username(username);
+    if let Err(error) = self.validate_name(username) {
+      return error.message(username, &self.messages);
+    }
+
+    // Looked up like `/verify` does, so offline mode and Bedrock players work here too
+    let (uuid, name) = match self.profiles_for(username).profile(username).await {
+      Ok(MojangProfile { id, name }) => (id, name),
+      Err(error) => return mojang_failure_message(username, &error, &self.messages),
+    };
+
+    let output = match self
+      .run_rcon_command(&self.whitelist_add_command(&name))
+      .await
+    {
+      Ok(output) => output,
+      Err(err) => {
+        warn!("Couldn't whitelist '{name}': {err}");
+        return "Could not connect to the minecraft server. Probably because it is offline right now. Try again later".to_string();
+      }
+    };
+    let already_whitelisted = match parse_whitelist_add_output(&output) {
+      WhitelistResult::Added => false,
+      WhitelistResult::AlreadyWhitelisted => true,
+      WhitelistResult::PlayerNotFound => {
+        return format!("The server couldn't find '{name}', so it wasn't whitelisted.");
+      }
+      WhitelistResult::Unknown(output) => {
+        warn!("Unexpected answer to whitelisting '{name}', treating it as done: {output}");
+        false
+      }
+    };
+
+    self.store.write(|data| {
+      data.unmanaged.retain(|entry| entry.uuid != uuid);
+      data.unmanaged.push(UnmanagedEntry {
+        uuid,
+        minecraft_name: name.clone(),
+        added_by: command.user.id.get(),
+        added_at: Utc::now().timestamp(),
+      });
+    });
+
+    info!("{} whitelisted '{name}' without linking", command.user.name);
+    for observer in &self.observers {
+      observer
+        .on_admin_action(
+          ctx,
+          &command.user,
+          &format!("Whitelisted '{name}' (unmanaged)"),
+        )
+        .await;
+    }
+
+    if already_whitelisted {
+      format!("'{name}' was already on the whitelist, it's now recorded without a Discord link.")
+    } else {
+      format!("'{name}' was added to the whitelist, without a Discord link.")
+    }
+  }
+
+  /// Imports the whitelist from before the bot, so the players already on it get linked once they
+  /// verify. The names are looked up one at a time, with a progress message every few names
+  async fn import_whitelist(&self, ctx: &Context, command: &CommandInteraction) -> Reply {
+    if !is_admin(command) {
+      return Reply::Text("Only administrators can use this command.".to_string());
+    }
+
+    let server = self.server_by_name(
+      command
+        .data
+        .options
+        .first()
+        .and_then(|option| option.value.as_str()),
+    );
+    let server_key = self.server_key(server);
+
+    let whitelist = match self.run_rcon_command_on(server, "whitelist list").await {
+      Ok(output) => match parse_whitelist_output(&output) {
+        Some(whitelist) => whitelist,
+        None => {
+          warn!("Couldn't parse the whitelist to import it: {output}");
+          return Reply::Text(
+            "The server answered `whitelist list` with something unexpected, nothing was imported."
+              .to_string(),
+          );
+        }
+      },
+      Err(err) => {
+        warn!("Couldn't read the whitelist to import it: {err}");
+        return Reply::Text(
+          VerifyOutcome::connect_failure(&err).failure_message(&server.config.name, &self.messages),
+        );
+      }
+    };
+
+    let mut report = ImportReport {
+      whitelisted: whitelist.len(),
+      ..ImportReport::default()
+    };
+    // Running it again only looks up what's left
+    let names =
+      self.store.read(|data| {
+        let known =
+          |name: &str| {
+            data.links.iter().any(|link| {
+              link.server == server_key && link.minecraft_name.eq_ignore_ascii_case(name)
+            }) || data.unvalidated.iter().any(|verification| {
+              verification.server == server_key
+                && verification.minecraft_name.eq_ignore_ascii_case(name)
+            }) || data.unclaimed.iter().any(|entry| {
+              entry.server == server_key && entry.minecraft_name.eq_ignore_ascii_case(name)
+            }) || (server_key.is_none()
+              && data
+                .unmanaged
+                .iter()
+                .any(|entry| entry.minecraft_name.eq_ignore_ascii_case(name)))
+          };
+        crate::commands::names_to_import(whitelist, known)
+      });
+    info!(
+      "{} is importing {} whitelisted names of {}",
+      command.user.name,
+      names.len(),
+      server.config.name
+    );
+
+    for (index, name) in names.iter().enumerate() {
+      if index > 0 {
+        time::sleep(IMPORT_LOOKUP_DELAY).await;
+      }
+
+      match self.import_profile(name).await {
+        Ok(MojangProfile { id, name }) => {
+          self.store.write(|data| {
+            data
+              .unclaimed
+              .retain(|entry| entry.uuid != id || entry.server != server_key);
+            data.unclaimed.push(UnclaimedEntry {
+              uuid: id,
+              minecraft_name: name,
+              server: server_key.clone(),
+              imported_at: Utc::now().timestamp(),
+            });
+          });
+          report.imported += 1;
+        }
+        Err(error) => {
+          warn!("Couldn't look up '{name}' to import it: {error}");
+          report.failed.push((name.clone(), error.to_string()));
+        }
+      }
+
+      let done = index + 1;
+      if done % IMPORT_PROGRESS_INTERVAL == 0 && done < names.len() {
+        let progress = CreateInteractionResponseFollowup::new()
+          .content(format!("Looked up {done} of {} names...", names.len()))
+          .ephemeral(true);
+        if let Err(error) = command.create_followup(ctx, progress).await {
+          warn!("Couldn't send the progress of the whitelist import: {error}");
+        }
+      }
+    }
+
+    info!(
+      "Imported {} whitelisted names, {} couldn't be looked up",
+      report.imported,
+      report.failed.len()
+    );
+    for observer in &self.observers {
+      observer
+        .on_admin_action(
+          ctx,
+          &command.user,
+          &format!("Imported {} whitelisted names", report.imported),
+        )
+        .await;
+    }
+
+    Reply::Text(report.summary())
+  }
+
+  /// Looks an imported name up, waiting out rate limits longer than the lookup itself does
+  async fn import_profile(&self, name: &str) -> Result<MojangProfile, MojangError> {
+    let mut waits = 0;
+    loop {
+      match self.profiles_for(name).profile(name).await {
+        Err(MojangError::RateLimited { retry_after }) if waits < IMPORT_RATE_LIMIT_RETRIES => {
+          let wait = retry_after.unwrap_or(IMPORT_RATE_LIMIT_WAIT);
+          warn!(
+            "Mojang is rate limiting the whitelist import, waiting {}s",
+            wait.as_secs()
+          );
+          time::sleep(wait).await;
+          waits += 1;
+        }
+        result => return result,
+      }
+    }
+  }
+
+  /// Whether the name was imported with `/import-whitelist` and nobody verified it yet
+  pub(super) fn is_unclaimed(&self, name: &str, server_key: Option<&str>) -> bool {
+    self.store.read(|data| {
+      data.unclaimed.iter().any(|entry| {
+        entry.server.as_deref() == server_key && entry.minecraft_name.eq_ignore_ascii_case(name)
+      })
+    })
+  }
+
+  /// Takes the imported entry of the name, now that someone verified it. Returns whether there
+  /// was one
+  pub(super) fn claim_import(&self, name: &str, server_key: Option<&str>) -> bool {
+    if !self.is_unclaimed(name, server_key) {
+      return false;
+    }
+
+    self.store.write(|data| {
+      data.unclaimed.retain(|entry| {
+        entry.server.as_deref() != server_key || !entry.minecraft_name.eq_ignore_ascii_case(name)
+      })
+    });
+    true
+  }
+
+  /// Defers the response and edits it once the work is done. With a timeout, the user is told
+  /// when the work takes long. The work still finishes, since stopping it halfway could leave a
+  /// change half made
+  async fn respond_later(
+    &self,
+    ctx: &Context,
+    command: &CommandInteraction,
+    ephemeral: bool,
+    timeout: Option<Duration>,
+    work: impl Future<Output = Reply>,
+  ) -> Result<(), BotError> {
+    let name = &command.data.name;
+
+    if ephemeral {
+      command.defer_ephemeral(ctx).await?;
+    } else {
+      command.defer(ctx).await?;
+    }
+
+    let mut work = std::pin::pin!(work);
+    let reply = match timeout {
+      Some(timeout) => match time::timeout(timeout, &mut work).await {
+        Ok(reply) => reply,
+        Err(_) => {
+          warn!("/{name} is taking longer than {timeout:?}");
+          let notice = EditInteractionResponse::new().content(
+            "This is taking long, the server or Mojang might be struggling. The answer follows here once it's done.",
+          );
+          if let Err(error) = command.edit_response(ctx, notice).await {
+            warn!("Couldn't tell the user /{name} is taking long: {error}");
+          }
+
+          work.await
+        }
+      },
+      None => work.await,
+    };
+
+    command.edit_response(ctx, reply.into()).await?;
+    Ok(())
+  }
+
+  /// Verifications over time and this week's busiest day, from the store
+  async fn stats(&self, ctx: &Context, command: &CommandInteraction) -> Reply {
+    if !is_admin(command) {
+      return Reply::Text("Only administrators can use this command.".to_string());
+    }
+
+    let now = Utc::now();
+
+    let (total, last_day, last_week, peak) = self.store.read(|data| {
+      (
+        data.verifications.len(),
+        data.verifications_since(now.timestamp() - SECONDS_PER_DAY),
+        data.verifications_since(now.timestamp() - 7 * SECONDS_PER_DAY),
+        data
+          .peak_players_since(now.date_naive() - Days::new(6))
+          .cloned(),
+      )
+    });
+
+    let peak = peak.map_or_else(
+      || "no data yet".to_string(),
+      |peak| format!("{} on {}", peak.players, peak.day),
+    );
+
+    Reply::Embed(
+      ReplyEmbed::new("Verification stats", Colour::BLUE)
+        .field("Total verifications", total.to_string(), true)
+        .field("Last 24 hours", last_day.to_string(), true)
+        .field("Last 7 days", last_week.to_string(), true)
+        .field(
+          "Verified members",
+          self.verified_member_count(&ctx.http).await,
+          true,
+        )
+        .field("Peak players this week", peak, true),
+    )
+  }
+
+  /// Whitelist size, verified members and stored links side by side, to spot drift between them
+  async fn whitelist_count(&self, ctx: &Context, command: &CommandInteraction) -> Reply {
+    if !is_admin(command) {
+      return Reply::Text("Only administrators can use this command.".to_string());
+    }
+
+    let whitelist = match self.run_rcon_command("whitelist list").await {
+      Ok(output) => match parse_whitelist_output(&output) {
+        Some(names) => names.len().to_string(),
+        None => {
+          warn!("Couldn't parse the whitelist: {output}");
+          "unavailable (unexpected output)".to_string()
+        }
+      },
+      Err(_) => "unavailable (offline)".to_string(),
+    };
+
+    let verified = self.verified_member_count(&ctx.http).await;
+
+    let (linked, unmanaged, unclaimed) = self
+      .store
+      .read(|data| (data.links.len(), data.unmanaged.len(), data.unclaimed.len()));
+
+    Reply::Embed(
+      ReplyEmbed::new("Whitelist count", Colour::DARK_GREEN)
+        .field("Whitelist", whitelist, true)
+        .field("Verified members", verified, true)
+        .field("Linked accounts", linked.to_string(), true)
+        .field("Unmanaged entries", unmanaged.to_string(), true)
+        .field("Unclaimed imports", unclaimed.to_string(), true),
+    )
+  }
+
+  /// Lets a user retry verifying right away, after they ran into the attempt limit
+  async fn reset_cooldown(&self, command: &CommandInteraction) -> String {
+    if !is_admin(command) {
+      return "Only administrators can use this command.".to_string();
+    }
+
+    let Some(CommandDataOptionValue::User(user_id)) =
+      command.data.options.first().map(|option| &option.value)
+    else {
+      return "There wasn't a member.".to_string();
+    };
+
+    if self.verify_attempts.clear(*user_id) {
+      info!(
+        "{} reset the verification cooldown of {user_id}",
+        command.user.name
+      );
+      format!("Reset the verification cooldown of {}.", user_id.mention())
+    } else {
+      format!("{} has no active cooldown.", user_id.mention())
+    }
+  }
+
+  /// Issues a code for the server side linking plugin. A user has one code at a time, asking again
+  /// replaces it
+  fn link_discord(&self, command: &CommandInteraction) -> String {
+    let now = Utc::now().timestamp();
+
+    self.store.write(|data| {
+      self
+        .commands
+        .issue_link_code(data, command.user.id.get(), now)
+    })
+  }
+
+  /// The configuration the bot loaded, with the secrets redacted
+  fn show_config(&self, command: &CommandInteraction) -> CreateInteractionResponseMessage {
+    if !is_admin(command) {
+      return CreateInteractionResponseMessage::new()
+        .content("Only administrators can use this command.");
+    }
+
+    let unset = || "not set".to_string();
+    let redacted = |secret: bool| {
+      if secret {
+        "[redacted]".to_string()
+      } else {
+        unset()
+      }
+    };
+
+    let alert_thresholds = self.error_reporter.lock().unwrap().thresholds();
+    let settings = [
+      ("SERVER_NAME", self.main_server().config.name.clone()),
+      ("SERVER_ADDRESS", self.main_server().config.address.clone()),
+      ("RCON_PORT", self.main_server().config.rcon_port.to_string()),
+      (
+        "RCON_CONNECT_TIMEOUT_SECONDS",
+        self.console.connect_timeout.as_secs().to_string(),
+      ),
+      (
+        "RCON_COMMAND_TIMEOUT_SECONDS",
+        self.console.command_timeout.as_secs().to_string(),
+      ),
+      (
+        "QUERY_PORT",
+        self.main_server().config.query_port.map_or_else(
+          || "from the SRV record, or 25565".to_string(),
+          |port| port.to_string(),
+        ),
+      ),
+      (
+        "UDP_QUERY_PORT",
+        self
+          .main_server()
+          .config
+          .udp_query_port
+          .map_or_else(unset, |port| port.to_string()),
+      ),
+      (
+        "Extra servers",
+        if self.servers.len() > 1 {
+          self.servers[1..]
+            .iter()
+            .map(|server| format!("{} ({})", server.config.name, server.config.address))
+            .collect::<Vec<_>>()
+            .join(", ")
+        } else {
+          "none".to_string()
+        },
+      ),
+      (
+        "Other guilds",
+        if self.other_guilds.is_empty() {
+          "none".to_string()
+        } else {
+          self
+            .other_guilds
+            .keys()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+        },
+      ),
+      ("RCON_PASSWORD", redacted(true)),
+      ("DISCORD_TOKEN", redacted(true)),
+      (
+        "DISCORD_STATUS_CHANNEL_ID",
+        self
+          .status
+          .channel_id
+          .map_or_else(unset, |id| id.to_string()),
+      ),
+      (
+        "DISCORD_VERIFY_CHANNEL_ID",
+        self.verify_channel_id.to_string(),
+      ),
+      (
+        "STATUS_INTERVAL_SECONDS",
+        self.status.interval.as_secs().to_string(),
+      ),
+      ("STATUS_ONLINE_FORMAT", self.status.format.online.clone()),
+      ("STATUS_OFFLINE_FORMAT", self.status.format.offline.clone()),
+      (
+        "PRESENCE_INTERVAL_SECONDS",
+        self.status.presence_interval.as_secs().to_string(),
+      ),
+      ("VERIFIED_ROLE_NAME", self.verified_role_name.clone()),
+      (
+        "VERIFIED_ROLE_COLOR",
+        format!("#{:06x}", self.verified_role_colour.0),
+      ),
+      ("VERIFIED_ROLE_HOIST", self.verified_role_hoist.to_string()),
+      ("LOG_FORMAT", self.log_format.to_string()),
+      (
+        "BEDROCK_PORT",
+        self
+          .status
+          .bedrock_port
+          .map_or_else(unset, |port| port.to_string()),
+      ),
+      (
+        "VERIFY_HOURS",
+        self
+          .verify_hours
+          .as_ref()
+          .map_or_else(unset, VerifyHours::describe),
+      ),
+      (
+        "VERIFY_MAX_FAILED_ATTEMPTS",
+        self.verify_attempts.max_failures.to_string(),
+      ),
+      (
+        "VERIFY_ATTEMPT_WINDOW_MINUTES",
+        (self.verify_attempts.window.as_secs() / 60).to_string(),
+      ),
+      ("ROLE_RETRY_ATTEMPTS", self.role_retry_attempts.to_string()),
+      (
+        "STAFF_ROLE_NAME",
+        self
+          .staff_tier
+          .as_ref()
+          .map_or_else(unset, |staff_tier| staff_tier.role_name.clone()),
+      ),
+      (
+        "ROLE_COMMANDS",
+        format!("{} role(s)", self.role_commands.len()),
+      ),
+      (
+        "ROLE_COMMANDS_REPLACE_WHITELIST",
+        self.role_commands_replace_whitelist.to_string(),
+      ),
+      (
+        "PLAYTIME_COMMAND",
+        self
+          .console
+          .playtime
+          .as_ref()
+          .map_or_else(unset, |playtime| playtime.command.clone()),
+      ),
+      (
+        "NAME_HISTORY_URL",
+        self.name_history_url.clone().unwrap_or_else(unset),
+      ),
+      (
+        "POST_VERIFY_TELEPORT_COMMAND",
+        self.teleport_command.clone().unwrap_or_else(unset),
+      ),
+      (
+        "INGAME_ROLE_NAME",
+        self.console.ingame_role_name.clone().unwrap_or_else(unset),
+      ),
+      (
+        "CLEAN_VERIFY_CHANNEL",
+        self.clean_verify_channel.to_string(),
+      ),
+      (
+        "MAX_ACCOUNTS_PER_USER",
+        self.max_accounts_per_user.to_string(),
+      ),
+      ("BAN_ON_DISCORD_KICK", self.ban_on_discord_kick.to_string()),
+      (
+        "METRICS_ADDR",
+        self.metrics_addr.clone().unwrap_or_else(unset),
+      ),
+      ("SEND_WELCOME_DM", self.welcome_dm.is_some().to_string()),
+      ("FULL_SERVER_NOTE", self.full_server_note.to_string()),
+      (
+        "VERIFICATION_TTL_DAYS",
+        self
+          .verification_expiry
+          .as_ref()
+          .and_then(|expiry| expiry.default_ttl_days)
+          .map_or_else(unset, |ttl_days| ttl_days.to_string()),
+      ),
+      (
+        "MOJANG_OUTAGE_POLICY",
+        format!("{:?}", self.mojang_outage_policy).to_lowercase(),
+      ),
+      ("OFFLINE_MODE", self.offline_mode.to_string()),
+      (
+        "BEDROCK_VERIFY",
+        self.floodgate.as_ref().map_or_else(
+          || "false".to_string(),
+          |floodgate| {
+            format!(
+              "true, prefix '{}', added with {}",
+              floodgate.prefix,
+              floodgate.whitelist_command(&format!("{}<gamertag>", floodgate.prefix))
+            )
+          },
+        ),
+      ),
+      (
+        "LAST_SEEN_RETENTION_DAYS",
+        self.status.last_seen_retention_days.to_string(),
+      ),
+      ("UPDATE_PRESENCE", self.status.update_presence.to_string()),
+      (
+        "STATUS_CHANNEL_RENAME",
+        self.status.channel_rename.to_string(),
+      ),
+      ("STATUS_EMBED", self.status.embed.to_string()),
+      ("SYNC_NICKNAME", self.sync_nickname.to_string()),
+      (
+        "STATUS_SMOOTHING_SAMPLES",
+        self.status.smoother.lock().unwrap().samples.to_string(),
+      ),
+      ("REQUIRE_HUMAN_GATE", self.require_human_gate.to_string()),
+      ("VERIFY_CHALLENGE", self.verify_challenge.to_string()),
+      ("CHECK_BANLIST", self.check_banlist.to_string()),
+      ("RCON_BLOCKLIST", self.console.blocklist.join(",")),
+      (
+        "ALLOW_DANGEROUS_RCON",
+        self.console.allow_dangerous.to_string(),
+      ),
+      ("LINK_COMMAND", self.commands.link_command.clone()),
+      (
+        "ONLINE_COMMANDS",
+        format!("{} command(s)", self.console.online_commands.len()),
+      ),
+      (
+        "WEB_VERIFY_URL",
+        self
+          .web_verify
+          .as_ref()
+          .map_or_else(unset, |web_verify| web_verify.url.clone()),
+      ),
+      ("WEB_VERIFY_SECRET", redacted(self.web_verify.is_some())),
+      (
+        "CHAT_BRIDGE_CHANNEL_ID",
+        self
+          .chat_bridge
+          .as_ref()
+          .map_or_else(unset, |chat_bridge| chat_bridge.channel_id.to_string()),
+      ),
+      (
+        "CHAT_BRIDGE_WEBHOOK_URL",
+        redacted(
+          self
+            .chat_bridge
+            .as_ref()
+            .is_some_and(|chat_bridge| chat_bridge.webhook_url.is_some()),
+        ),
+      ),
+      (
+        "CHAT_BRIDGE_LOG_PATH",
+        self
+          .chat_bridge
+          .as_ref()
+          .and_then(|chat_bridge| chat_bridge.log_path.clone())
+          .unwrap_or_else(unset),
+      ),
+      (
+        "RECONCILE_CHANNEL_ID",
+        self
+          .reconcile
+          .channel_id
+          .map_or_else(unset, |id| id.to_string()),
+      ),
+      (
+        "RECONCILE_INTERVAL_HOURS",
+        (self.reconcile.interval.as_secs() / 3600).to_string(),
+      ),
+      ("RECONCILE_AUTO_FIX", self.reconcile.auto_fix.to_string()),
+      ("TIMEZONE", self.timezone.name()),
+      (
+        "DAILY_SUMMARY_TIME",
+        self
+          .daily_summary_time
+          .map_or_else(unset, |time| time.format("%H:%M").to_string()),
+      ),
+      (
+        "Schedules",
+        if self.schedules.is_empty() {
+          "none".to_string()
+        } else {
+          self
+            .schedules
+            .iter()
+            .map(|schedule| format!("`{}`", schedule.expression))
+            .collect::<Vec<_>>()
+            .join(", ")
+        },
+      ),
+      (
+        "ERROR_WEBHOOK_URL",
+        redacted(self.error_webhook_url.is_some()),
+      ),
+      (
+        "HTTP_TIMEOUT_SECONDS",
+        self.http_timeout.as_secs().to_string(),
+      ),
+      (
+        "Failure notices",
+        match self.alert_target {
+          Some(AlertTarget::User(user_id)) => format!("DM to {user_id}"),
+          Some(AlertTarget::Channel(channel_id)) => format!("posted in {channel_id}"),
+          None => "only logged".to_string(),
+        },
+      ),
+      (
+        "ALERT_FAILURE_THRESHOLD",
+        alert_thresholds.failures.to_string(),
+      ),
+      (
+        "ALERT_DOWNTIME_MINUTES",
+        (alert_thresholds.downtime.as_secs() / 60).to_string(),
+      ),
+      (
+        "ALERT_COOLDOWN_MINUTES",
+        (alert_thresholds.cooldown.as_secs() / 60).to_string(),
+      ),
+      (
+        "ALLOWED_GUILD_IDS",
+        self.allowed_guild_ids.as_ref().map_or_else(unset, |ids| {
+          ids
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+        }),
+      ),
+      (
+        "LEAVE_UNLISTED_GUILDS",
+        self.leave_unlisted_guilds.to_string(),
+      ),
+    ];
+
+    let description = settings
+      .iter()
+      .map(|(name, value)| format!("`{name}`: {value}"))
+      .collect::<Vec<_>>()
+      .join("\n");
+
+    // Embed descriptions are capped at 4096 characters
+    let description: String = description.chars().take(4096).collect();
+
+    CreateInteractionResponseMessage::new().embed(
+      CreateEmbed::new()
+        .title("Effective configuration")
+        .description(description)
+        .colour(Colour::BLUE),
+    )
+  }
+
+  /// Turns the server's whitelist on or off, e.g. to open the server up during events
+  async fn whitelist_toggle(&self, ctx: &Context, command: &CommandInteraction) -> String {
+    if !is_admin(command) {
+      return "Only administrators can use this command.".to_string();
+    }
+
+    let enabled = match command.data.options.first().map(|option| &option.value) {
+      Some(CommandDataOptionValue::String(state)) => state == "on",
+      _ => return "There wasn't a state.".to_string(),
+    };
+    let state = if enabled { "on" } else { "off" };
+
+    if let Err(err) = self.run_rcon_command(&format!("whitelist {state}")).await {
+      warn!("Couldn't turn the whitelist {state}: {err}");
+      return "Couldn't reach the server. Try again when it's online.".to_string();
+    }
+
+    info!("{} turned the whitelist {state}", command.user.name);
+    for observer in &self.observers {
+      observer
+        .on_admin_action(ctx, &command.user, &format!("Turned the whitelist {state}"))
+        .await;
+    }
+
+    if enabled {
+      "The whitelist is now on, only whitelisted players can join.".to_string()
+    } else {
+      "The whitelist is now off, anyone can join the server.".to_string()
+    }
+  }
+
+  /// Finds the Minecraft accounts a member linked, or the member who linked an account
+  async fn whois(&self, command: &CommandInteraction) -> Reply {
+    if !can_manage_roles(command) {
+      return Reply::Text("Only members who can manage roles can use this command.".to_string());
+    }
+
+    let mut user_id = None;
+    let mut username = None;
+    for option in &command.data.options {
+      match (option.name.as_str(), &option.value) {
+        ("user", CommandDataOptionValue::User(value)) => user_id = Some(*value),
+        ("username", CommandDataOptionValue::String(value)) => {
+          username = Some(crate::commands::clean_username(value))
+        }
+        _ => {}
+      }
+    }
+
+    let (title, links, current_profile) = match (user_id, username) {
+      (Some(user_id), None) => {
+        let links: Vec<Link> = self
+          .store
+          .read(|data| data.links.by_discord_id(user_id.get()).cloned().collect());
+        if links.is_empty() {
+          return Reply::Text(format!("<@{user_id}> hasn't linked a Minecraft account."));
+        }
+
+        ("Linked accounts".to_string(), links, None)
+      }
+      (None, Some(username)) => {
+        // The name may have moved to another account since it was linked, so the current owner
+        // of the name comes first
+        let profile = self.profiles().profile(username).await.ok();
+        let links: Vec<Link> = self.store.read(|data| {
+          let by_uuid: Vec<Link> = profile
+            .iter()
+            .flat_map(|profile| data.links.iter().filter(|link| link.uuid == profile.id))
+            .cloned()
+            .collect();
+          if !by_uuid.is_empty() {
+            return by_uuid;
+          }
+
+          data
+            .links
+            .iter()
+            .filter(|link| link.minecraft_name.eq_ignore_ascii_case(username))
+            .cloned()
+            .collect()
+        });
+        if links.is_empty() {
+          return Reply::Text(format!("'{username}' isn't linked to a Discord account."));
+        }
+
+        (format!("Who is {username}"), links, profile)
+      }
+      _ => {
+        return Reply::Text("Give either a member or a Minecraft username.".to_string());
+      }
+    };
+
+    let accounts: Vec<WhoisAccount> = links
+      .iter()
+      .map(|link| WhoisAccount {
+        link,
+        server_name: (self.servers.len() > 1).then(|| {
+          self
+            .server_by_name(link.server.as_deref())
+            .config
+            .name
+            .as_str()
+        }),
+        current_name: current_profile
+          .as_ref()
+          .filter(|profile| profile.id == link.uuid && profile.name != link.minecraft_name)
+          .map(|profile| profile.name.as_str()),
+      })
+      .collect();
+
+    crate::commands::whois(&title, &accounts)
+  }
+
+  pub(super) async fn handle_component(
+    &self,
+    ctx: &Context,
+    component: &ComponentInteraction,
+  ) -> Result<(), BotError> {
+    if let Some(page) = component.data.custom_id.strip_prefix("plugins:") {
+      let page = page.parse().unwrap_or_default();
+      self.plugins_page(ctx, component, page).await?;
+    } else if component.data.custom_id == "human-gate" {
+      self.pass_human_gate(ctx, component).await;
+    } else if let Some(server_name) = component.data.custom_id.strip_prefix("verify:") {
+      self.show_verify_form(ctx, component, server_name).await?;
+    }
+    Ok(())
+  }
+
+  /// Verifies the username typed into the form of the verify button, like `/verify` does. The
+  /// button was clicked by hand, so there's no human gate
+  pub(super) async fn handle_modal(
+    &self,
+    ctx: &Context,
+    modal: &ModalInteraction,
+  ) -> Result<(), BotError> {
+    let Some(server_name) = modal.data.custom_id.strip_prefix("verify-modal:") else {
+      return Ok(());
+    };
+    let username = modal
+      .data
+      .components
+      .iter()
+      .flat_map(|row| &row.components)
+      .find_map(|component| match component {
+        ActionRowComponent::InputText(input) if input.custom_id == "username" => {
+          input.value.clone()
+        }
+        _ => None,
+      })
+      .unwrap_or_default();
+    let Some(member) = modal.member.as_ref() else {
+      return Err(BotError::Config(
+        "The verify button was used outside of a guild".to_string(),
+      ));
+    };
+
+    modal.defer_ephemeral(ctx).await?;
+    let server = self.server_by_name(Some(server_name));
+    let content = self
+      .verify_typed_username(ctx, &modal.token, member, &username, server)
+      .await;
+    modal
+      .edit_response(ctx, EditInteractionResponse::new().content(content))
+      .await?;
+    Ok(())
+  }
+
+  pub(super) async fn handle_command(
+    &self,
+    ctx: &Context,
+    command: &CommandInteraction,
+  ) -> Result<(), BotError> {
+    if command.data.name == "verify" && self.web_verify.is_some() {
+      let response = CreateInteractionResponseMessage::new()
+        .content(self.web_verify_link(command.user.id))
+        .ephemeral(true);
+      command
+        .create_response(ctx, CreateInteractionResponse::Message(response))
+        .await?;
+      return Ok(());
+    }
+
+    if command.data.name == "verify" {
+      let mut username = None;
+      let mut server_name = None;
+      let mut bedrock = false;
+      for option in &command.data.options {
+        match (option.name.as_str(), &option.value) {
+          ("username", CommandDataOptionValue::String(value)) => username = Some(value.as_str()),
+          ("server", CommandDataOptionValue::String(value)) => server_name = Some(value.as_str()),
+          ("platform", CommandDataOptionValue::String(value)) => bedrock = value == "bedrock",
+          _ => {}
+        }
+      }
+      // Without extra servers, there is no server option
+      let server = self.server_by_name(server_name);
+
+      let Some(username) = username else {
+        return Err(BotError::Config(
+          "/verify is registered without a username option".to_string(),
+        ));
+      };
+      // Gamertags are told apart by the Floodgate prefix from here on
+      let prefixed;
+      let username = match &self.floodgate {
+        Some(floodgate) if bedrock && !floodgate.is_bedrock_name(username.trim()) => {
+          prefixed = format!("{}{}", floodgate.prefix, username.trim());
+          prefixed.as_str()
+        }
+        _ => username,
+      };
+      let Some(member) = command.member.as_ref() else {
+        return Err(BotError::Config(
+          "/verify is registered outside of a guild".to_string(),
+        ));
+      };
+
+      // A name that can't be right doesn't need the human gate first
+      let cleaned = crate::commands::clean_username(username);
+      if self.require_human_gate && self.validate_name(cleaned).is_ok() {
+        self.show_human_gate(ctx, command, cleaned, server).await;
+        return Ok(());
+      }
+
+      // Waiting in the verification queue can take a while, so only the verification itself is
+      // cut short, in verify_queued
+      self
+        .respond_later(ctx, command, true, None, async {
+          Reply::Text(
+            self
+              .verify_typed_username(ctx, &command.token, member, username, server)
+              .await,
+          )
+        })
+        .await?;
+      return Ok(());
+    }
+
+    if command.data.name == "confirm" {
+      let Some(CommandDataOptionValue::String(code)) =
+        command.data.options.first().map(|option| &option.value)
+      else {
+        return Err(BotError::Config(
+          "/confirm is registered without a code option".to_string(),
+        ));
+      };
+      let Some(member) = command.member.as_ref() else {
+        return Err(BotError::Config(
+          "/confirm is registered outside of a guild".to_string(),
+        ));
+      };
+
+      self
+        .respond_later(ctx, command, true, None, async {
+          Reply::Text(self.confirm_verification(ctx, command, member, code).await)
+        })
+        .await?;
+      return Ok(());
+    }
+
+    // Commands that talk to the server or Mojang respond later, so they can take longer than
+    // Discord's 3 seconds
+    match command.data.name.as_str() {
+      // Everyone should see who is on
+      "online" => {
+        self
+          .respond_later(ctx, command, false, Some(COMMAND_TIMEOUT), self.online(ctx))
+          .await?;
+        return Ok(());
+      }
+      "whitelist-toggle" => {
+        self
+          .respond_later(ctx, command, true, Some(COMMAND_TIMEOUT), async {
+            Reply::Text(self.whitelist_toggle(ctx, command).await)
+          })
+          .await?;
+        return Ok(());
+      }
+      "unverify" => {
+        self
+          .respond_later(ctx, command, true, Some(COMMAND_TIMEOUT), async {
+            Reply::Text(self.unverify(ctx, command).await)
+          })
+          .await?;
+        return Ok(());
+      }
+      "whois" => {
+        self
+          .respond_later(ctx, command, true, Some(COMMAND_TIMEOUT), async {
+            self.whois(command).await
+          })
+          .await?;
+        return Ok(());
+      }
+      "lastseen" => {
+        self
+          .respond_later(ctx, command, true, Some(COMMAND_TIMEOUT), async {
+            self.last_seen(command).await
+          })
+          .await?;
+        return Ok(());
+      }
+      "force-unverify" => {
+        self
+          .respond_later(ctx, command, true, Some(COMMAND_TIMEOUT), async {
+            Reply::Text(self.force_unverify(ctx, command).await)
+          })
+          .await?;
+        return Ok(());
+      }
+      "force-verify" => {
+        self
+          .respond_later(ctx, command, true, Some(COMMAND_TIMEOUT), async {
+            Reply::Text(self.force_verify(ctx, command).await)
+          })
+          .await?;
+        return Ok(());
+      }
+      "ingame" => {
+        self
+          .respond_later(ctx, command, true, Some(COMMAND_TIMEOUT), async {
+            Reply::Text(self.ingame(command).await)
+          })
+          .await?;
+        return Ok(());
+      }
+      "kick" | "ban" => {
+        let kind = if command.data.name == "kick" {
+          ModerationKind::Kick
+        } else {
+          ModerationKind::Ban
+        };
+        self
+          .respond_later(ctx, command, true, Some(COMMAND_TIMEOUT), async {
+            Reply::Text(self.moderate(command, kind).await)
+          })
+          .await?;
+        return Ok(());
+      }
+      "rcon" => {
+        self
+          .respond_later(ctx, command, true, Some(COMMAND_TIMEOUT), async {
+            Reply::Text(self.rcon_console(command).await)
+          })
+          .await?;
+        return Ok(());
+      }
+      "whitelist-add" => {
+        self
+          .respond_later(ctx, command, true, Some(COMMAND_TIMEOUT), async {
+            Reply::Text(self.whitelist_add(ctx, command).await)
+          })
+          .await?;
+        return Ok(());
+      }
+      "import-whitelist" => {
+        // A big whitelist takes minutes to look up, the progress is sent along the way
+        self
+          .respond_later(
+            ctx,
+            command,
+            true,
+            None,
+            self.import_whitelist(ctx, command),
+          )
+          .await?;
+        return Ok(());
+      }
+      "whitelist-count" => {
+        self
+          .respond_later(ctx, command, true, Some(COMMAND_TIMEOUT), async {
+            self.whitelist_count(ctx, command).await
+          })
+          .await?;
+        return Ok(());
+      }
+      "stats" => {
+        self
+          .respond_later(ctx, command, true, Some(COMMAND_TIMEOUT), async {
+            self.stats(ctx, command).await
+          })
+          .await?;
+        return Ok(());
+      }
+      "force-status" => {
+        self
+          .respond_later(ctx, command, true, Some(COMMAND_TIMEOUT), async {
+            Reply::Text(self.force_status(ctx, command).await)
+          })
+          .await?;
+        return Ok(());
+      }
+      "top-playtime" => {
+        self
+          .respond_later(ctx, command, true, Some(COMMAND_TIMEOUT), async {
+            self.top_playtime(command).await
+          })
+          .await?;
+        return Ok(());
+      }
+      "resync-discord" => {
+        self
+          .respond_later(ctx, command, true, Some(COMMAND_TIMEOUT), async {
+            Reply::Text(self.resync_discord(ctx, command).await)
+          })
+          .await?;
+        return Ok(());
+      }
+      "server-info" => {
+        self
+          .respond_later(
+            ctx,
+            command,
+            true,
+            Some(COMMAND_TIMEOUT),
+            self.server_info(),
+          )
+          .await?;
+        return Ok(());
+      }
+      _ => {}
+    }
+
+    let response = match command.data.name.as_str() {
+      "players" => self.players(ctx, command).await,
+      "showconfig" => self.show_config(command),
+      "link-discord" => CreateInteractionResponseMessage::new().content(self.link_discord(command)),
+      "status" => self.show_status().await,
+      "history" => self.history(command).await,
+      "reset-cooldown" => {
+        CreateInteractionResponseMessage::new().content(self.reset_cooldown(command).await)
+      }
+      "plugins" => self.plugins(command).await,
+      _ => CreateInteractionResponseMessage::new().content("Not a command"),
+    };
+
+    command
+      .create_response(
+        ctx,
+        CreateInteractionResponse::Message(response.ephemeral(true)),
+      )
+      .await?;
+    Ok(())
+  }
+
+  /// Replaces the guild's slash commands with the ones the bot defines, which also removes the
+  /// ones it no longer has. Nothing is sent when they're already up to date, so a crash loop
+  /// doesn't run into Discord's daily limit of command creations
+  pub(super) async fn register_commands(
+    &self,
+    ctx: &Context,
+    guild_id: GuildId,
+  ) -> Result<(), BotError> {
+    let commands = self.command_definitions();
+    let registered = guild_id.get_commands(ctx).await?;
+
+    let (defined, registered) = match (values_to_json(&commands), values_to_json(&registered)) {
+      (Ok(defined), Ok(registered)) => (defined, registered),
+      (Err(error), _) | (_, Err(error)) => {
+        return Err(BotError::Config(format!(
+          "Couldn't compare the slash commands: {error}"
+        )))
+      }
+    };
+
+    let changes = command_changes(&defined, &registered);
+    if changes.is_empty() {
+      info!("The slash commands are up to date");
+      return Ok(());
+    }
+
+    for (change, names) in [
+      ("Adding", &changes.added),
+      ("Removing", &changes.removed),
+      ("Updating", &changes.updated),
+    ] {
+      if !names.is_empty() {
+        info!("{change} the slash commands {}", names.join(", "));
+      }
+    }
+    guild_id.set_commands(ctx, commands).await?;
+
+    Ok(())
+  }
+
+  /// Every slash command the bot has with the current settings
+  fn command_definitions(&self) -> Vec<CreateCommand> {
+    // With web verification, the website asks for the username
+    let verify_command = if self.web_verify.is_some() {
+      CreateCommand::new("verify").description("Get a link to verify your Minecraft account.")
+    } else {
+      let verify_command = CreateCommand::new("verify")
+        .add_option(
+          CreateCommandOption::new(
+            CommandOptionType::String,
+            "username",
+            "Your Minecraft username",
+          )
+          .required(true),
+        )
+        .description("Verify a Minecraft username and add it to the whitelist.");
+
+      let verify_command = if self.servers.len() > 1 {
+        let server_option = self.servers.iter().fold(
+          CreateCommandOption::new(
+            CommandOptionType::String,
+            "server",
+            "The server to be whitelisted on",
+          )
+          .required(true),
+          |option, server| option.add_string_choice(&server.config.name, &server.config.name),
+        );
+        verify_command.add_option(server_option)
+      } else {
+        verify_command
+      };
+
+      // Optional options come after the required ones
+      if self.floodgate.is_some() {
+        verify_command.add_option(
+          CreateCommandOption::new(
+            CommandOptionType::String,
+            "platform",
+            "Bedrock to verify an Xbox gamertag, Java by default",
+          )
+          .add_string_choice("Java", "java")
+          .add_string_choice("Bedrock", "bedrock"),
+        )
+      } else {
+        verify_command
+      }
+    };
+
+    let mut commands = vec![verify_command];
+
+    commands.push(
+      CreateCommand::new("unverify")
+        .add_option(CreateCommandOption::new(
+          CommandOptionType::String,
+          "username",
+          "Only remove this account",
+        ))
+        .description("Remove your Minecraft account from the whitelist and unverify yourself."),
+    );
+
+    commands.push(
+      CreateCommand::new("force-unverify")
+        .add_option(
+          CreateCommandOption::new(CommandOptionType::User, "user", "The member to unverify")
+            .required(true),
+        )
+        .add_option(CreateCommandOption::new(
+          CommandOptionType::String,
+          "username",
+          "Only remove this account",
+        ))
+        .description("Remove someone's verified accounts from the whitelist.")
+        .default_member_permissions(Permissions::MANAGE_ROLES),
+    );
+
+    commands.push(
+      CreateCommand::new("whois")
+        .add_option(CreateCommandOption::new(
+          CommandOptionType::User,
+          "user",
+          "The member to find the Minecraft accounts of",
+        ))
+        .add_option(CreateCommandOption::new(
+          CommandOptionType::String,
+          "username",
+          "The Minecraft username to find the member of",
+        ))
+        .description("Find out who linked a Minecraft account, or which accounts a member linked.")
+        .default_member_permissions(Permissions::MANAGE_ROLES),
+    );
+
+    commands.push(
+      CreateCommand::new("force-verify")
+        .add_option(
+          CreateCommandOption::new(CommandOptionType::User, "user", "The member to verify")
+            .required(true),
+        )
+        .add_option(
+          CreateCommandOption::new(
+            CommandOptionType::String,
+            "username",
+            "The Minecraft username to verify them with",
+          )
+          .required(true),
+        )
+        .description("Verify an account for someone, replacing the ones they verified.")
+        .default_member_permissions(Permissions::MANAGE_ROLES),
+    );
+
+    commands
+      .push(CreateCommand::new("status").description("Show the status of the minecraft server."));
+
+    commands.push(
+      CreateCommand::new("server-info")
+        .description("Show the version, MOTD and latency of the minecraft server."),
+    );
+
+    commands.push(
+      CreateCommand::new("players")
+        .add_option(CreateCommandOption::new(
+          CommandOptionType::Boolean,
+          "fresh",
+          "Check the server right now instead of using the latest status",
+        ))
+        .description("List the players on the minecraft server."),
+    );
+
+    commands.push(
+      CreateCommand::new("lastseen")
+        .add_option(
+          CreateCommandOption::new(
+            CommandOptionType::String,
+            "username",
+            "The Minecraft username",
+          )
+          .required(true),
+        )
+        .description("Show when a player was last online, and roughly how long they played."),
+    );
+
+    commands.push(
+      CreateCommand::new("online")
+        .description("Show who is on the minecraft server right now, for everyone to see."),
+    );
+
+    if self.verify_challenge && self.web_verify.is_none() {
+      commands.push(
+        CreateCommand::new("confirm")
+          .add_option(
+            CreateCommandOption::new(
+              CommandOptionType::String,
+              "code",
+              "The code you got in-game",
+            )
+            .required(true),
+          )
+          .description("Confirm the code you got in-game to finish verifying."),
+      );
+    }
+
+    commands.push(
+      CreateCommand::new("link-discord")
+        .description("Get a code to link your Discord account in-game."),
+    );
+
+    commands.push(
+      CreateCommand::new("showconfig")
+        .description("Show the configuration the bot loaded, without secrets.")
+        .default_member_permissions(Permissions::ADMINISTRATOR),
+    );
+
+    commands.push(
+      CreateCommand::new("stats")
+        .description("Show verification stats and this week's peak player count.")
+        .default_member_permissions(Permissions::ADMINISTRATOR),
+    );
+
+    commands.push(
+      CreateCommand::new("whitelist-add")
+        .add_option(
+          CreateCommandOption::new(
+            CommandOptionType::String,
+            "username",
+            "The Minecraft username to whitelist",
+          )
+          .required(true),
+        )
+        .description("Whitelist someone without linking a Discord account.")
+        .default_member_permissions(Permissions::ADMINISTRATOR),
+    );
+
+    let import_command = CreateCommand::new("import-whitelist")
+      .description("Import the existing whitelist, so its players are linked once they verify.")
+      .default_member_permissions(Permissions::ADMINISTRATOR);
+    commands.push(if self.servers.len() > 1 {
+      import_command.add_option(self.servers.iter().fold(
+        CreateCommandOption::new(
+          CommandOptionType::String,
+          "server",
+          "The server whose whitelist is imported, the main one by default",
+        ),
+        |option, server| option.add_string_choice(&server.config.name, &server.config.name),
+      ))
+    } else {
+      import_command
+    });
+
+    commands.push(
+      CreateCommand::new("rcon")
+        .add_option(
+          CreateCommandOption::new(
+            CommandOptionType::String,
+            "command",
+            "The console command, like \"weather clear\"",
+          )
+          .required(true),
+        )
+        .description("Run a console command on the server.")
+        .default_member_permissions(Permissions::ADMINISTRATOR),
+    );
+
+    commands.push(
+      CreateCommand::new("whitelist-count")
+        .description("Compare the whitelist size with the verified members and linked accounts.")
+        .default_member_permissions(Permissions::ADMINISTRATOR),
+    );
+
+    commands.push(
+      CreateCommand::new("resync-discord")
+        .description("Re-apply the bot presence and status channel name right now.")
+        .default_member_permissions(Permissions::ADMINISTRATOR),
+    );
+
+    commands.push(
+      CreateCommand::new("ingame")
+        .add_option(
+          CreateCommandOption::new(
+            CommandOptionType::String,
+            "message",
+            "The message to show in the in-game chat",
+          )
+          .max_length(256)
+          .required(true),
+        )
+        .description("Send a message to the in-game chat."),
+    );
+
+    commands.push(
+      CreateCommand::new("force-status")
+        .add_option(
+          CreateCommandOption::new(
+            CommandOptionType::String,
+            "status",
+            "What the server status should be treated as",
+          )
+          .add_string_choice("offline", "offline")
+          .add_string_choice("online", "online")
+          .add_string_choice("auto", "auto")
+          .required(true),
+        )
+        .description("Override the server status, for testing.")
+        .default_member_permissions(Permissions::ADMINISTRATOR),
+    );
+
+    commands.push(
+      CreateCommand::new("whitelist-toggle")
+        .add_option(
+          CreateCommandOption::new(
+            CommandOptionType::String,
+            "state",
+            "Whether only whitelisted players can join",
+          )
+          .add_string_choice("on", "on")
+          .add_string_choice("off", "off")
+          .required(true),
+        )
+        .description("Turn the server's whitelist on or off.")
+        .default_member_permissions(Permissions::ADMINISTRATOR),
+    );
+
+    for (name, description) in [
+      ("kick", "Kick a player from the server."),
+      ("ban", "Ban a player from the server."),
+    ] {
+      commands.push(
+        CreateCommand::new(name)
+          .add_option(
+            CreateCommandOption::new(
+              CommandOptionType::String,
+              "username",
+              "The player's Minecraft username",
+            )
+            .required(true),
+          )
+          .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "reason",
+            "Why, shown to the player and in /history",
+          ))
+          .description(description)
+          .default_member_permissions(Permissions::ADMINISTRATOR),
+      );
+    }
+
+    commands.push(
+      CreateCommand::new("reset-cooldown")
+        .add_option(
+          CreateCommandOption::new(
+            CommandOptionType::User,
+            "member",
+            "The member who should be able to verify again right away",
+          )
+          .required(true),
+        )
+        .description("Clear a member's verification cooldown.")
+        .default_member_permissions(Permissions::ADMINISTRATOR),
+    );
+
+    commands.push(
+      CreateCommand::new("history")
+        .add_option(
+          CreateCommandOption::new(
+            CommandOptionType::String,
+            "username",
+            "The player's Minecraft username",
+          )
+          .required(true),
+        )
+        .description("Show the kicks and bans issued to a player through the bot.")
+        .default_member_permissions(Permissions::ADMINISTRATOR),
+    );
+
+    commands.push(
+      CreateCommand::new("plugins")
+        .description("List the plugins or mods installed on the server.")
+        .default_member_permissions(Permissions::ADMINISTRATOR),
+    );
+
+    commands.push(
+      CreateCommand::new("top-playtime")
+        .add_option(
+          CreateCommandOption::new(
+            CommandOptionType::Integer,
+            "count",
+            "How many players to show (default: 10)",
+          )
+          .min_int_value(1)
+          .max_int_value(25),
+        )
+        .description("Show the players with the most playtime.")
+        .default_member_permissions(Permissions::ADMINISTRATOR),
+    );
+
+    commands
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::bot::tests::test_handler;
+
+  #[test]
+  fn registered_commands_are_compared_with_the_definitions() {
+    let definition = |description: &str| {
+      serde_json::to_value(
+        CreateCommand::new("lastseen")
+          .add_option(
+            CreateCommandOption::new(CommandOptionType::String, "username", "The username")
+              .required(true),
+          )
+          .description(description),
+      )
+      .unwrap()
+    };
+    // Like Discord sends them back, with its own fields and without the defaults
+    let registered = |name: &str| {
+      serde_json::json!({
+        "id": "1",
+        "application_id": "2",
+        "guild_id": "3",
+        "version": "4",
+        "type": 1,
+        "name": name,
+        "name_localizations": null,
+        "description": "When a player was last online",
+        "description_localizations": null,
+        "default_member_permissions": null,
+        "nsfw": false,
+        "options": [
+          { "type": 3, "name": "username", "description": "The username", "required": true }
+        ],
+      })
+    };
+
+    assert_eq!(
+      command_changes(
+        &[definition("When a player was last online")],
+        &[registered("lastseen")]
+      ),
+      CommandChanges::default()
+    );
+    assert_eq!(
+      command_changes(
+        &[definition("Something else")],
+        &[registered("lastseen"), registered("old-name")]
+      ),
+      CommandChanges {
+        added: Vec::new(),
+        removed: vec!["old-name".to_string()],
+        updated: vec!["lastseen".to_string()],
+      }
+    );
+  }
+
+  #[tokio::test]
+  async fn a_new_link_code_replaces_the_old_one() {
+    let commands = test_handler(
+      "link_codes",
+      &[
+        ("LINK_COMMAND", "/discord link {code}"),
+        ("LINK_CODE_TTL_MINUTES", "5"),
+      ],
+    )
+    .await
+    .commands;
+    let mut data = StoreData::default();
+    let code = |discord_id, expires_at| LinkCode {
+      discord_id,
+      code: "ABCDEF".to_string(),
+      expires_at,
+    };
+    data.link_codes = vec![code(1, 2000), code(2, 999), code(3, 2000)];
+
+    let instructions = commands.issue_link_code(&mut data, 1, 1000);
+
+    let owners: Vec<_> = data.link_codes.iter().map(|code| code.discord_id).collect();
+    assert_eq!(owners, [3, 1]);
+    let issued = data.link_codes.last().unwrap();
+    assert_eq!(issued.expires_at, 1300);
+    assert_eq!(
+      instructions,
+      format!(
+        "Run `/discord link {}` in-game within 5 minutes to link your account.",
+        issued.code
+      )
+    );
+  }
+}
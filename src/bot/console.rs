@@ -0,0 +1,733 @@
+//! Talking to the server over RCON: the shared connections with their backoff, and the commands
+//! that run something on the console, like `/rcon`, `/ingame` and `/plugins`
+
+use super::*;
+
+/// How often connecting to RCON is tried after the server came back online
+const ONLINE_COMMANDS_CONNECT_ATTEMPTS: u32 = 6;
+
+const ONLINE_COMMANDS_CONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// The first and the longest wait before reconnecting after RCON couldn't be reached
+const RCON_RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+
+const RCON_MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Discord rejects messages longer than this
+const DISCORD_MESSAGE_LIMIT: usize = 2000;
+
+/// How many RCON authentication failures in a row mean the password is wrong
+const RCON_AUTH_FAILURE_THRESHOLD: u32 = 3;
+
+/// How long the bot stops authenticating after that, so the server doesn't lock it out
+const RCON_AUTH_PAUSE: Duration = Duration::from_secs(15 * 60);
+
+/// Plugins (or mods) the server has installed
+struct PluginList {
+  kind: &'static str,
+  names: Vec<String>,
+}
+
+const PLUGINS_PER_PAGE: usize = 20;
+
+impl PluginList {
+  /// Renders one page of the list, with buttons to move between pages
+  fn page(&self, page: usize) -> CreateInteractionResponseMessage {
+    if self.names.is_empty() {
+      return CreateInteractionResponseMessage::new().content("No plugins (vanilla server).");
+    }
+
+    let pages = self.names.len().div_ceil(PLUGINS_PER_PAGE);
+    let page = page.min(pages - 1);
+
+    let description = self
+      .names
+      .iter()
+      .skip(page * PLUGINS_PER_PAGE)
+      .take(PLUGINS_PER_PAGE)
+      .map(|name| format!("- {name}"))
+      .collect::<Vec<_>>()
+      .join("\n");
+
+    CreateInteractionResponseMessage::new()
+      .embed(
+        CreateEmbed::new()
+          .title(format!("{} ({})", self.kind, self.names.len()))
+          .description(description)
+          .footer(CreateEmbedFooter::new(format!(
+            "Page {}/{}",
+            page + 1,
+            pages
+          )))
+          .colour(Colour::DARK_GREEN),
+      )
+      .components(vec![CreateActionRow::Buttons(vec![
+        CreateButton::new(format!("plugins:{}", page.saturating_sub(1)))
+          .label("Previous")
+          .disabled(page == 0),
+        CreateButton::new(format!("plugins:{}", page + 1))
+          .label("Next")
+          .disabled(page + 1 >= pages),
+      ])])
+  }
+}
+
+/// Where to read players' playtime from
+pub(super) struct PlaytimeSource {
+  pub(super) command: String,
+  /// Has to have a `name` and a `value` capture group
+  pub(super) pattern: Regex,
+}
+
+impl PlaytimeSource {
+  /// The players with the highest playtime in the command's output
+  fn top(&self, output: &str, count: usize) -> Vec<(String, u64)> {
+    let mut playtimes: Vec<(String, u64)> = self
+      .pattern
+      .captures_iter(output)
+      .filter_map(|captures| {
+        let name = captures.name("name")?.as_str().to_string();
+        let value = captures.name("value")?.as_str().parse().ok()?;
+        Some((name, value))
+      })
+      .collect();
+
+    playtimes.sort_by(|(_, a), (_, b)| b.cmp(a));
+    playtimes.truncate(count);
+    playtimes
+  }
+}
+
+/// What the bot runs on the server console, and who can run what
+pub(super) struct Console {
+  /// Console commands `/rcon` refuses, lowercase
+  pub(super) blocklist: Vec<String>,
+  pub(super) allow_dangerous: bool,
+  pub(super) playtime: Option<PlaytimeSource>,
+  /// Members with this role can use `/ingame`, besides administrators
+  pub(super) ingame_role_name: Option<String>,
+  /// RCON commands to run when the server comes back online
+  pub(super) online_commands: Vec<String>,
+  /// How long connecting and logging in over RCON can take
+  pub(super) connect_timeout: Duration,
+  /// How long an RCON command can take to answer
+  pub(super) command_timeout: Duration,
+}
+
+impl Console {
+  pub(super) fn new(settings: &Settings) -> Self {
+    Self {
+      blocklist: settings
+        .rcon_blocklist
+        .iter()
+        .map(|command| console_command_name(command))
+        .filter(|name| !name.is_empty())
+        .collect(),
+      allow_dangerous: settings.allow_dangerous_rcon,
+      playtime: settings
+        .playtime_command
+        .clone()
+        .map(|command| PlaytimeSource {
+          command,
+          pattern: settings.playtime_pattern.clone(),
+        }),
+      ingame_role_name: settings.ingame_role_name.clone(),
+      online_commands: settings.online_commands.clone(),
+      connect_timeout: settings.rcon_connect_timeout,
+      command_timeout: settings.rcon_command_timeout,
+    }
+  }
+
+  /// The first command on the blocklist the console command would run, `None` if it can run
+  pub(super) fn blocked_command(&self, console_command: &str) -> Option<String> {
+    if self.allow_dangerous {
+      return None;
+    }
+    console_command_names(console_command)
+      .into_iter()
+      .find(|name| self.blocklist.contains(name))
+  }
+}
+
+/// The name of a console command, like "stop" for "/minecraft:stop now"
+pub(super) fn console_command_name(command: &str) -> String {
+  let name = command
+    .trim()
+    .trim_start_matches('/')
+    .split_whitespace()
+    .next()
+    .unwrap_or_default();
+  let name = name.rsplit_once(':').map_or(name, |(_, name)| name);
+
+  name.to_lowercase()
+}
+
+/// The names of every command a console command runs, like "execute" and "stop" for
+/// "execute as @a run stop". `execute` runs the command after each `run` in its chain
+fn console_command_names(command: &str) -> Vec<String> {
+  let mut names = Vec::new();
+  let mut expects_command = true;
+  let mut in_execute = false;
+
+  for word in command.split_whitespace() {
+    if expects_command {
+      let name = console_command_name(word);
+      in_execute = name == "execute";
+      names.push(name);
+      expects_command = false;
+    } else if in_execute && word.eq_ignore_ascii_case("run") {
+      expects_command = true;
+    }
+  }
+
+  names
+}
+
+/// Puts the RCON output in a code block, cut off to fit in a Discord message
+fn rcon_output_reply(output: &str) -> String {
+  let output = if output.trim().is_empty() {
+    "(no output)".to_string()
+  } else {
+    // Backticks in the output would end the code block early
+    output.replace("```", "`\u{200b}``")
+  };
+
+  let fences = "```\n\n```".len();
+  if output.len() + fences <= DISCORD_MESSAGE_LIMIT {
+    return format!("```\n{output}\n```");
+  }
+
+  let note = "\n(The output was cut off)";
+  let mut end = DISCORD_MESSAGE_LIMIT - fences - note.len();
+  while !output.is_char_boundary(end) {
+    end -= 1;
+  }
+
+  format!("```\n{}\n```{note}", &output[..end])
+}
+
+/// Builds a `tellraw` command showing a Discord message in the in-game chat
+pub(super) fn tellraw_command(author: &str, message: &str) -> String {
+  let component = serde_json::json!([
+    "",
+    { "text": "[Discord] ", "color": "blue" },
+    { "text": author, "color": "white" },
+    { "text": format!(": {message}"), "color": "white" },
+  ]);
+
+  // RCON only allows ASCII, so everything else has to be escaped inside the JSON strings
+  let mut command = "tellraw @a ".to_string();
+  for char in component.to_string().chars() {
+    if char.is_ascii() {
+      command.push(char);
+    } else {
+      for unit in char.encode_utf16(&mut [0; 2]) {
+        command.push_str(&format!("\\u{unit:04x}"));
+      }
+    }
+  }
+
+  command
+}
+
+/// Consecutive RCON authentication failures. Connection failures don't count, only a rejected password
+#[derive(Default)]
+pub(super) struct RconAuthFailures {
+  consecutive: u32,
+  paused_until: Option<Instant>,
+}
+
+/// How long to wait before connecting to RCON again, after the server couldn't be reached
+#[derive(Default)]
+pub(super) struct RconBackoff {
+  delay: Duration,
+  retry_at: Option<Instant>,
+}
+
+impl RconBackoff {
+  fn failed(&mut self) {
+    self.delay = if self.delay.is_zero() {
+      RCON_RECONNECT_BACKOFF
+    } else {
+      (self.delay * 2).min(RCON_MAX_RECONNECT_BACKOFF)
+    };
+    self.retry_at = Some(Instant::now() + self.delay);
+  }
+}
+
+/// Runs the commands over a server's shared connection
+struct SharedRcon<'a> {
+  handler: &'a Handler,
+  server: &'a Server,
+}
+
+#[async_trait]
+impl Rcon for SharedRcon<'_> {
+  async fn run_command(&mut self, command: &str) -> io::Result<String> {
+    self.handler.run_rcon_command_on(self.server, command).await
+  }
+}
+
+/// Connects to one of the handler's servers
+pub(super) struct ServerConnector<'a> {
+  pub(super) handler: &'a Handler,
+  pub(super) server: &'a Server,
+}
+
+#[async_trait]
+impl RconConnector for ServerConnector<'_> {
+  async fn connect(&self) -> io::Result<Box<dyn Rcon + '_>> {
+    self.handler.connect_to(self.server).await
+  }
+}
+
+impl Handler {
+  /// Runs ONLINE_COMMANDS after the server came back online. RCON can come up a bit after the
+  /// server answers pings, so connecting is retried a few times
+  pub(super) async fn run_online_commands(&self) {
+    if self.console.online_commands.is_empty() {
+      return;
+    }
+
+    let mut rcon_client = None;
+    for attempt in 1..=ONLINE_COMMANDS_CONNECT_ATTEMPTS {
+      match self.connect_to(self.main_server()).await {
+        Ok(client) => {
+          rcon_client = Some(client);
+          break;
+        }
+        Err(err) if attempt < ONLINE_COMMANDS_CONNECT_ATTEMPTS => {
+          warn!("RCON isn't reachable yet after the server came online: {err}");
+          time::sleep(ONLINE_COMMANDS_CONNECT_DELAY).await;
+        }
+        Err(_) => {}
+      }
+    }
+
+    let Some(mut rcon_client) = rcon_client else {
+      self
+        .report_error("The server came back online, but RCON never became reachable, so ONLINE_COMMANDS didn't run")
+        .await;
+      return;
+    };
+
+    for online_command in &self.console.online_commands {
+      match rcon_client.run_command(online_command).await {
+        Ok(_) => info!("Ran an online command: {online_command}"),
+        Err(err) => {
+          self
+            .report_error(&format!(
+              "Couldn't run the online command `{online_command}`: {err}"
+            ))
+            .await
+        }
+      }
+    }
+  }
+
+  /// Connects and authenticates. After repeated authentication failures it stops trying for a
+  /// while, and after the server couldn't be reached it waits a bit longer every time
+  async fn create_rcon_client(&self, server: &Server) -> io::Result<RconClient> {
+    if let Some(paused_until) = server.rcon_auth_failures.lock().unwrap().paused_until {
+      if Instant::now() < paused_until {
+        return Err(io::Error::new(
+          io::ErrorKind::PermissionDenied,
+          "rcon authentication is paused after repeated failures",
+        ));
+      }
+    }
+
+    if let Some(retry_at) = server.rcon_backoff.lock().unwrap().retry_at {
+      let wait = retry_at.saturating_duration_since(Instant::now());
+      if !wait.is_zero() {
+        return Err(io::Error::new(
+          io::ErrorKind::ConnectionRefused,
+          format!(
+            "rcon was unreachable, reconnecting in {} seconds",
+            wait.as_secs().max(1)
+          ),
+        ));
+      }
+    }
+
+    let connected = rcon::connect(
+      &server.config.address,
+      server.config.rcon_port,
+      self.console.connect_timeout,
+    )
+    .await;
+    let operation = Operation::RconConnect(server.config.name.clone());
+
+    let mut rcon_client = match connected {
+      Ok(rcon_client) => {
+        *server.rcon_backoff.lock().unwrap() = RconBackoff::default();
+        rcon_client
+      }
+      Err(err) => {
+        server.rcon_backoff.lock().unwrap().failed();
+        Metrics::count(&METRICS.rcon_connection_failures);
+        self.operation_failed(operation, &err);
+        return Err(err);
+      }
+    };
+
+    match rcon::authenticate(
+      &mut rcon_client,
+      &server.config.rcon_password,
+      self.console.connect_timeout,
+    )
+    .await
+    {
+      Ok(()) => {
+        *server.rcon_auth_failures.lock().unwrap() = RconAuthFailures::default();
+        self.operation_succeeded(&operation);
+        Ok(rcon_client)
+      }
+      Err(err) => {
+        Metrics::count(&METRICS.rcon_connection_failures);
+        self.operation_failed(operation, &err);
+        if is_rcon_auth_failure(&err) {
+          self.record_rcon_auth_failure(server).await;
+        }
+        Err(err)
+      }
+    }
+  }
+
+  async fn record_rcon_auth_failure(&self, server: &Server) {
+    let paused = {
+      let mut failures = server.rcon_auth_failures.lock().unwrap();
+      failures.consecutive += 1;
+
+      if failures.consecutive < RCON_AUTH_FAILURE_THRESHOLD {
+        false
+      } else {
+        failures.consecutive = 0;
+        failures.paused_until = Some(Instant::now() + RCON_AUTH_PAUSE);
+        true
+      }
+    };
+
+    if paused {
+      self
+        .report_error(&format!(
+          "RCON authentication to the {} server failed {RCON_AUTH_FAILURE_THRESHOLD} times in a row, its RCON password is likely wrong. Not trying again for {} minutes",
+          server.config.name,
+          RCON_AUTH_PAUSE.as_secs() / 60
+        ))
+        .await;
+    }
+  }
+
+  /// Makes sure there is a connection, so an unreachable server is noticed right away
+  pub(super) async fn connect_to<'a>(
+    &'a self,
+    server: &'a Server,
+  ) -> io::Result<Box<dyn Rcon + 'a>> {
+    let mut connection = server.rcon_connection.lock().await;
+    if connection.is_none() {
+      *connection = Some(self.create_rcon_client(server).await?);
+    }
+
+    Ok(Box::new(SharedRcon {
+      handler: self,
+      server,
+    }))
+  }
+
+  /// Runs a command on the main server
+  pub(super) async fn run_rcon_command(&self, command: &str) -> io::Result<String> {
+    self.run_rcon_command_on(self.main_server(), command).await
+  }
+
+  /// Runs a command over the server's shared connection
+  pub(super) async fn run_rcon_command_on(
+    &self,
+    server: &Server,
+    command: &str,
+  ) -> io::Result<String> {
+    let mut connection = server.rcon_connection.lock().await;
+    rcon::run_on_connection(
+      &mut connection,
+      command,
+      self.console.command_timeout,
+      || self.create_rcon_client(server),
+    )
+    .await
+  }
+
+  pub(super) async fn top_playtime(&self, command: &CommandInteraction) -> Reply {
+    if !is_admin(command) {
+      return Reply::Text("Only administrators can use this command.".to_string());
+    }
+
+    let Some(playtime) = &self.console.playtime else {
+      return Reply::Text(
+        "Playtime isn't configured. Set PLAYTIME_COMMAND to enable it.".to_string(),
+      );
+    };
+
+    let count = command
+      .data
+      .options
+      .first()
+      .and_then(|option| option.value.as_i64())
+      .unwrap_or(10) as usize;
+
+    let output = match self.run_rcon_command(&playtime.command).await {
+      Ok(output) => strip_color_codes(&output),
+      Err(_) => {
+        return Reply::Text(
+          "Could not connect to the minecraft server. Probably because it is offline right now. Try again later".to_string(),
+        )
+      }
+    };
+
+    let top = playtime.top(&output, count);
+
+    // The output didn't match the configured format, so at least show what the server said
+    if top.is_empty() {
+      let raw_output: String = output.trim().chars().take(1900).collect();
+      return Reply::Text(format!(
+        "Couldn't read any playtimes from the server response:\n```\n{raw_output}\n```"
+      ));
+    }
+
+    let description = top
+      .iter()
+      .enumerate()
+      .map(|(place, (name, value))| format!("{}. **{name}** - {value}", place + 1))
+      .collect::<Vec<_>>()
+      .join("\n");
+
+    let mut embed = ReplyEmbed::new("Top players by playtime", Colour::GOLD);
+    embed.description = Some(description);
+    Reply::Embed(embed)
+  }
+
+  /// Sends a message to the in-game chat, with the author's name in front
+  pub(super) async fn ingame(&self, command: &CommandInteraction) -> String {
+    let can_use = is_admin(command)
+      || self
+        .console
+        .ingame_role_name
+        .as_ref()
+        .is_some_and(|role_name| {
+          let role_id = self
+            .guild_snapshot()
+            .and_then(|guild| guild.role_by_name(role_name).map(|role| role.id));
+
+          command
+            .member
+            .as_ref()
+            .zip(role_id)
+            .is_some_and(|(member, role_id)| member.roles.contains(&role_id))
+        });
+
+    if !can_use {
+      return "You don't have permission to use this command.".to_string();
+    }
+
+    let Some(CommandDataOptionValue::String(message)) =
+      command.data.options.first().map(|option| &option.value)
+    else {
+      return "There wasn't a message to send.".to_string();
+    };
+
+    let author = command
+      .member
+      .as_ref()
+      .map(|member| member.display_name().to_string())
+      .unwrap_or_else(|| command.user.name.clone());
+
+    match self.run_rcon_command(&tellraw_command(&author, message)).await {
+      Ok(_) => {
+        info!("{} sent a message in-game: {message}", command.user.name);
+        "Sent your message to the server.".to_string()
+      }
+      Err(_) => "Could not connect to the minecraft server. Probably because it is offline right now. Try again later".to_string(),
+    }
+  }
+
+  /// Runs a console command for an admin and shows what the server answered
+  pub(super) async fn rcon_console(&self, command: &CommandInteraction) -> String {
+    // The command is hidden from non-admins by default, but that can be changed in the server
+    // settings
+    if !is_admin(command) {
+      return "You don't have permission to use this command.".to_string();
+    }
+
+    let Some(CommandDataOptionValue::String(console_command)) =
+      command.data.options.first().map(|option| &option.value)
+    else {
+      return "There wasn't a command to run.".to_string();
+    };
+    let console_command = console_command.trim().trim_start_matches('/');
+
+    if let Some(name) = self.console.blocked_command(console_command) {
+      return format!(
+        "`{name}` is blocked because it can take the server down or hand out permissions. Run it from the server console, or set ALLOW_DANGEROUS_RCON to allow it here."
+      );
+    }
+
+    info!(
+      "{} ran a console command: {console_command}",
+      command.user.name
+    );
+
+    match self.run_rcon_command(console_command).await {
+      Ok(output) => rcon_output_reply(&output),
+      Err(_) => "Could not connect to the minecraft server. Probably because it is offline right now. Try again later".to_string(),
+    }
+  }
+
+  /// Gets the plugins over RCON, falling back to the mods modded servers list in their status
+  async fn fetch_plugin_list(&self) -> Result<PluginList, String> {
+    let rcon_output = self.run_rcon_command("plugins").await.ok();
+
+    if let Some(names) = rcon_output.as_deref().and_then(parse_plugins_output) {
+      return Ok(PluginList {
+        kind: "Plugins",
+        names,
+      });
+    }
+
+    let (query_host, query_port) = self.main_server().query_address().await;
+    match ping::status_json(&query_host, query_port).await {
+      Ok(status) => Ok(PluginList {
+        kind: "Mods",
+        names: ping::mod_list(&status)
+          .iter()
+          .map(|name| strip_color_codes(name))
+          .collect(),
+      }),
+      Err(_) if rcon_output.is_some() => Ok(PluginList {
+        kind: "Plugins",
+        names: Vec::new(),
+      }),
+      Err(error) => {
+        debug!("Couldn't get status. Reason: {error}");
+        Err("Could not connect to the minecraft server. Probably because it is offline right now. Try again later".to_string())
+      }
+    }
+  }
+
+  pub(super) async fn plugins(
+    &self,
+    command: &CommandInteraction,
+  ) -> CreateInteractionResponseMessage {
+    if !is_admin(command) {
+      return CreateInteractionResponseMessage::new()
+        .content("Only administrators can use this command.");
+    }
+
+    match self.fetch_plugin_list().await {
+      Ok(plugin_list) => plugin_list.page(0),
+      Err(message) => CreateInteractionResponseMessage::new().content(message),
+    }
+  }
+
+  /// Handles the page buttons of the `/plugins` list
+  pub(super) async fn plugins_page(
+    &self,
+    ctx: &Context,
+    component: &ComponentInteraction,
+    page: usize,
+  ) -> Result<(), BotError> {
+    let response = match self.fetch_plugin_list().await {
+      Ok(plugin_list) => plugin_list.page(page),
+      Err(message) => CreateInteractionResponseMessage::new()
+        .content(message)
+        .embeds(Vec::new())
+        .components(Vec::new()),
+    };
+
+    component
+      .create_response(ctx, CreateInteractionResponse::UpdateMessage(response))
+      .await?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::bot::tests::test_handler;
+
+  #[test]
+  fn console_command_names_are_normalized() {
+    assert_eq!(console_command_name("/minecraft:STOP now"), "stop");
+    assert_eq!(console_command_name(" op Alice"), "op");
+    assert_eq!(console_command_name(""), "");
+  }
+
+  #[test]
+  fn commands_run_by_execute_are_found() {
+    assert_eq!(
+      console_command_names("execute run stop"),
+      ["execute", "stop"]
+    );
+    assert_eq!(
+      console_command_names("execute as @a run minecraft:op Alice"),
+      ["execute", "op"]
+    );
+    assert_eq!(
+      console_command_names("execute as @a at @s RUN execute if entity @s run deop Bob"),
+      ["execute", "execute", "deop"]
+    );
+    // Only `execute` runs the word after "run"
+    assert_eq!(console_command_names("say run stop"), ["say"]);
+    assert!(console_command_names("").is_empty());
+  }
+
+  #[test]
+  fn long_rcon_output_is_cut_off() {
+    let reply = rcon_output_reply(&"é".repeat(DISCORD_MESSAGE_LIMIT));
+
+    assert!(reply.len() <= DISCORD_MESSAGE_LIMIT);
+    assert!(reply.ends_with("(The output was cut off)"));
+    assert_eq!(rcon_output_reply(""), "```\n(no output)\n```");
+  }
+
+  #[test]
+  fn rcon_backoff_doubles_up_to_the_cap() {
+    let mut backoff = RconBackoff::default();
+
+    backoff.failed();
+    assert_eq!(backoff.delay, RCON_RECONNECT_BACKOFF);
+    backoff.failed();
+    assert_eq!(backoff.delay, RCON_RECONNECT_BACKOFF * 2);
+
+    for _ in 0..10 {
+      backoff.failed();
+    }
+    assert_eq!(backoff.delay, RCON_MAX_RECONNECT_BACKOFF);
+  }
+
+  #[tokio::test]
+  async fn blocked_commands_are_found_unless_allowed() {
+    let console = test_handler(
+      "rcon_blocklist",
+      &[("RCON_BLOCKLIST", "/Stop, minecraft:op,")],
+    )
+    .await
+    .console;
+
+    assert_eq!(console.blocklist, ["stop", "op"]);
+    assert_eq!(
+      console
+        .blocked_command("execute as @a run OP Alice")
+        .as_deref(),
+      Some("op")
+    );
+    assert_eq!(console.blocked_command("say stop"), None);
+
+    let console = test_handler(
+      "rcon_allow_dangerous",
+      &[("RCON_BLOCKLIST", "stop"), ("ALLOW_DANGEROUS_RCON", "true")],
+    )
+    .await
+    .console;
+    assert_eq!(console.blocked_command("stop"), None);
+  }
+}
@@ -0,0 +1,1270 @@
+//! Taking accounts away: `/kick`, `/ban`, unverifying, and following Discord bans, kicks and
+//! Verified role changes on the server
+
+use super::*;
+
+/// The reason given to the server when an account is banned because of a Discord kick
+const DISCORD_KICK_REASON: &str = "Kicked from Discord";
+
+/// How long a role change of the bot is expected to take to come back as a member update
+const OWN_ROLE_CHANGE_WINDOW: Duration = Duration::from_secs(30);
+
+/// How many moderation actions `/history` shows
+const HISTORY_LENGTH: usize = 10;
+
+/// What leaving the guild means for the member's accounts
+#[derive(Debug, PartialEq)]
+enum Departure {
+  /// Banned from Discord, `member_banned` bans the accounts and keeps them for a pardon
+  Banned,
+  /// Kicked with `BAN_ON_DISCORD_KICK` set, the accounts are banned right away
+  Kicked,
+  /// The accounts are taken off the whitelist and forgotten
+  Left,
+}
+
+/// Decides what happens to the accounts of a member who left the guild. A ban always wins over a
+/// kick, so the ban path is the one that keeps the accounts for a pardon
+fn departure(
+  already_banned: bool,
+  ban_entry: bool,
+  kick_entry: bool,
+  ban_on_kick: bool,
+) -> Departure {
+  if already_banned || ban_entry {
+    Departure::Banned
+  } else if ban_on_kick && kick_entry {
+    Departure::Kicked
+  } else {
+    Departure::Left
+  }
+}
+
+/// What the Verified role going away or coming back does to the member's accounts
+#[derive(Debug, PartialEq)]
+enum VerifiedRoleChange {
+  Suspend,
+  Restore,
+}
+
+/// `had_role` is `None` when the member wasn't cached before the update, then the accounts are
+/// brought in line with the roles the member has now
+fn verified_role_change(had_role: Option<bool>, has_role: bool) -> Option<VerifiedRoleChange> {
+  if had_role == Some(has_role) {
+    None
+  } else if has_role {
+    Some(VerifiedRoleChange::Restore)
+  } else {
+    Some(VerifiedRoleChange::Suspend)
+  }
+}
+
+/// The links and unvalidated verifications of a member's accounts
+type MemberAccounts = (Vec<Link>, Vec<UnvalidatedVerification>);
+
+/// The accounts forgotten when a member left the guild. Discord can send the removal of a banned
+/// member before the ban, and before the ban shows up in the audit log, so the ban path takes
+/// them back to ban them anyway
+pub(super) struct DepartedAccounts {
+  window: Duration,
+  departed: Mutex<HashMap<UserId, (Instant, MemberAccounts)>>,
+}
+
+impl DepartedAccounts {
+  pub(super) fn new(window: Duration) -> Self {
+    Self {
+      window,
+      departed: Mutex::new(HashMap::new()),
+    }
+  }
+
+  fn record(&self, user_id: UserId, accounts: MemberAccounts) {
+    self.record_at(user_id, accounts, Instant::now());
+  }
+
+  fn record_at(&self, user_id: UserId, accounts: MemberAccounts, now: Instant) {
+    let mut departed = self.departed.lock().unwrap();
+    departed.retain(|_, (left_at, _)| now.duration_since(*left_at) < self.window);
+    departed.insert(user_id, (now, accounts));
+  }
+
+  /// The accounts the user had if they left within the window. They're handed out only once
+  fn take(&self, user_id: UserId) -> Option<MemberAccounts> {
+    self.take_at(user_id, Instant::now())
+  }
+
+  fn take_at(&self, user_id: UserId, now: Instant) -> Option<MemberAccounts> {
+    let (left_at, accounts) = self.departed.lock().unwrap().remove(&user_id)?;
+    (now.duration_since(left_at) < self.window).then_some(accounts)
+  }
+}
+
+/// Forgets the user's links and unvalidated verifications of the named accounts
+pub(super) fn forget_accounts(data: &mut StoreData, discord_id: u64, names: &[String]) {
+  let links: Vec<(String, Option<String>)> = data
+    .links
+    .by_discord_id(discord_id)
+    .filter(|link| names.contains(&link.minecraft_name))
+    .map(|link| (link.uuid.clone(), link.server.clone()))
+    .collect();
+  for (uuid, server) in links {
+    data.links.remove(&uuid, server.as_deref());
+  }
+  data.unvalidated.retain(|verification| {
+    verification.discord_id != discord_id || !names.contains(&verification.minecraft_name)
+  });
+}
+
+impl Handler {
+  pub(super) fn mark_own_role_change(&self, user_id: UserId) {
+    let mut own_role_changes = self.own_role_changes.lock().unwrap();
+    // A change that didn't change anything never shows up, so its mark is dropped eventually
+    own_role_changes.retain(|_, marked_at| marked_at.elapsed() < OWN_ROLE_CHANGE_WINDOW);
+    own_role_changes.insert(user_id, Instant::now());
+  }
+
+  /// Whether the bot changed the member's roles itself. The mark is used up
+  pub(super) fn take_own_role_change(&self, user_id: UserId) -> bool {
+    self
+      .own_role_changes
+      .lock()
+      .unwrap()
+      .remove(&user_id)
+      .is_some_and(|marked_at| marked_at.elapsed() < OWN_ROLE_CHANGE_WINDOW)
+  }
+
+  /// The named accounts of the user with their uuids, `None` for the unvalidated ones. Read before
+  /// the accounts are forgotten, for the audit channel
+  fn account_uuids(&self, user_id: UserId, names: &[String]) -> Vec<(String, Option<String>)> {
+    self.store.read(|data| {
+      names
+        .iter()
+        .map(|name| {
+          let uuid = data
+            .links
+            .by_discord_id(user_id.get())
+            .find(|link| link.minecraft_name == *name)
+            .map(|link| link.uuid.clone());
+          (name.clone(), uuid)
+        })
+        .collect()
+    })
+  }
+
+  /// Kicks or bans a player over RCON and records it for `/history`
+  pub(super) async fn moderate(
+    &self,
+    command: &CommandInteraction,
+    kind: ModerationKind,
+  ) -> String {
+    if !is_admin(command) {
+      return "Only administrators can use this command.".to_string();
+    }
+
+    let mut username = None;
+    let mut reason = None;
+    for option in &command.data.options {
+      match (option.name.as_str(), &option.value) {
+        ("username", CommandDataOptionValue::String(value)) => username = Some(value.clone()),
+        ("reason", CommandDataOptionValue::String(value)) => reason = Some(value.clone()),
+        _ => {}
+      }
+    }
+
+    let Some(username) = username else {
+      return "There wasn't a username.".to_string();
+    };
+    // Only the name and the reason can reach the console, not a selector or a second command
+    let username = username.trim();
+    let floodgate_prefix = self
+      .floodgate
+      .as_ref()
+      .map(|floodgate| floodgate.prefix.as_str());
+    if let Err(error) = crate::commands::validate_player_name(username, floodgate_prefix) {
+      return error.message(username, &self.messages);
+    }
+    let reason = reason
+      .map(|reason| crate::commands::console_text(&reason))
+      .filter(|reason| !reason.is_empty());
+
+    let (verb, past_tense) = match kind {
+      ModerationKind::Kick => ("kick", "kicked"),
+      ModerationKind::Ban => ("ban", "banned"),
+    };
+
+    let rcon_command = match &reason {
+      Some(reason) => format!("{verb} {username} {reason}"),
+      None => format!("{verb} {username}"),
+    };
+
+    let output = match self.run_rcon_command(&rcon_command).await {
+      Ok(output) => strip_color_codes(&output),
+      Err(_) => return "Could not connect to the minecraft server. Probably because it is offline right now. Try again later".to_string(),
+    };
+
+    // Only what the server did goes into the history
+    match parse_moderation_output(&output) {
+      ModerationResult::Done => {}
+      ModerationResult::PlayerNotFound => {
+        return format!("Couldn't {verb} `{username}`, the server doesn't know the player.")
+      }
+      ModerationResult::NothingChanged => {
+        return format!("`{username}` is already {past_tense}, nothing changed.")
+      }
+      ModerationResult::Unknown(output) => {
+        return format!(
+          "Ran `{rcon_command}`, but the server's answer wasn't understood, so it's not in /history. The server said: {output}"
+        )
+      }
+    }
+
+    self.store.write(|data| {
+      data.moderation.push(ModerationAction {
+        minecraft_name: username.to_string(),
+        kind,
+        reason,
+        moderator_id: command.user.id.get(),
+        moderator_name: command.user.name.clone(),
+        issued_at: Utc::now().timestamp(),
+      })
+    });
+
+    info!("{} {past_tense} '{username}'", command.user.name);
+    format!("Ran `{rcon_command}`. The server said: {}", output.trim())
+  }
+
+  /// Shows the kicks and bans recorded for a player
+  pub(super) async fn history(
+    &self,
+    command: &CommandInteraction,
+  ) -> CreateInteractionResponseMessage {
+    if !is_admin(command) {
+      return CreateInteractionResponseMessage::new()
+        .content("Only administrators can use this command.");
+    }
+
+    let Some(CommandDataOptionValue::String(username)) =
+      command.data.options.first().map(|option| &option.value)
+    else {
+      return CreateInteractionResponseMessage::new().content("There wasn't a username.");
+    };
+
+    let actions: Vec<ModerationAction> = self.store.read(|data| {
+      data
+        .moderation
+        .iter()
+        .rev()
+        .filter(|action| action.minecraft_name.eq_ignore_ascii_case(username))
+        .take(HISTORY_LENGTH)
+        .cloned()
+        .collect()
+    });
+
+    if actions.is_empty() {
+      return CreateInteractionResponseMessage::new()
+        .content(format!("'{username}' has no recorded kicks or bans."));
+    }
+
+    let description = actions
+      .iter()
+      .map(|action| {
+        let kind = match action.kind {
+          ModerationKind::Kick => "Kicked",
+          ModerationKind::Ban => "Banned",
+        };
+        let reason = action.reason.as_deref().unwrap_or("no reason given");
+
+        format!(
+          "<t:{}:f> **{kind}** by <@{}> ({}): {reason}",
+          action.issued_at, action.moderator_id, action.moderator_name
+        )
+      })
+      .collect::<Vec<_>>()
+      .join("\n");
+
+    CreateInteractionResponseMessage::new().embed(
+      CreateEmbed::new()
+        .title(format!("Moderation history of {username}"))
+        .description(description)
+        .footer(CreateEmbedFooter::new(format!(
+          "Showing up to the {HISTORY_LENGTH} most recent actions"
+        )))
+        .colour(Colour::ORANGE),
+    )
+  }
+
+  /// The names of the accounts the user verified, optionally only the given one
+  fn verified_names(&self, user_id: UserId, only: Option<&str>) -> Vec<String> {
+    let matches = |name: &str| only.is_none_or(|only| name.eq_ignore_ascii_case(only));
+
+    self.store.read(|data| {
+      data
+        .links
+        .by_discord_id(user_id.get())
+        .map(|link| link.minecraft_name.clone())
+        .chain(
+          data
+            .unvalidated
+            .iter()
+            .filter(|verification| verification.discord_id == user_id.get())
+            .map(|verification| verification.minecraft_name.clone()),
+        )
+        .filter(|name| matches(name))
+        .collect()
+    })
+  }
+
+  /// Takes the accounts off the whitelist and forgets them. Nothing is forgotten when the server
+  /// can't be reached, so the store keeps matching the whitelist
+  async fn remove_verified_accounts(&self, user_id: UserId, names: &[String]) -> io::Result<()> {
+    let accounts = self.accounts_of(user_id, names);
+
+    for server in &self.servers {
+      let key = self.server_key(server);
+      let server_names: Vec<&String> = accounts
+        .iter()
+        .filter(|(_, server)| *server == key)
+        .map(|(name, _)| name)
+        .collect();
+      if server_names.is_empty() {
+        continue;
+      }
+
+      let mut rcon_client = self.connect_to(server).await?;
+      for name in server_names {
+        rcon_client
+          .run_command(&format!("whitelist remove {name}"))
+          .await
+          .inspect_err(|err| warn!("Couldn't remove '{name}' from the whitelist: {err}"))?;
+      }
+    }
+
+    self
+      .store
+      .write(|data| forget_accounts(data, user_id.get(), names));
+
+    Ok(())
+  }
+
+  /// The named accounts of the user, linked or unvalidated, with the server each is whitelisted on
+  fn accounts_of(&self, user_id: UserId, names: &[String]) -> Vec<(String, Option<String>)> {
+    // Each account is only on the whitelist of the server it was verified for
+    self.store.read(|data| {
+      data
+        .links
+        .by_discord_id(user_id.get())
+        .map(|link| (link.minecraft_name.clone(), link.server.clone()))
+        .chain(
+          data
+            .unvalidated
+            .iter()
+            .filter(|verification| verification.discord_id == user_id.get())
+            .map(|verification| {
+              (
+                verification.minecraft_name.clone(),
+                verification.server.clone(),
+              )
+            }),
+        )
+        .filter(|(name, _)| names.contains(name))
+        .collect()
+    })
+  }
+
+  /// Removes the Verified role in every guild, unless the user still has a verified account. A
+  /// user who left a guild has no role to remove there
+  pub(super) async fn remove_verified_role(
+    &self,
+    ctx: &Context,
+    user_id: UserId,
+    reason: &str,
+  ) -> Result<(), SerenityError> {
+    if !self.verified_names(user_id, None).is_empty() {
+      return Ok(());
+    }
+
+    let guilds = self.guild_snapshot().into_iter().chain(
+      self
+        .other_guilds
+        .values()
+        .filter_map(|other_guild| other_guild.snapshot.read().unwrap().clone()),
+    );
+    let verified_roles: Vec<(GuildId, RoleId)> = guilds
+      .filter_map(|guild| Some((guild.id, self.verified_role(&guild)?.id)))
+      .collect();
+
+    for (guild_id, role_id) in verified_roles {
+      let own_role_change = self.is_verify_guild(guild_id);
+      if own_role_change {
+        self.mark_own_role_change(user_id);
+      }
+      let result = ctx
+        .http
+        .remove_member_role(guild_id, user_id, role_id, Some(reason))
+        .await;
+      if result.is_err() && own_role_change {
+        self.take_own_role_change(user_id);
+      }
+
+      match result {
+        Err(error) if is_unknown_member(&error) => {}
+        result => result?,
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Takes the accounts of a member whose Verified role was removed by hand off the whitelist. The
+  /// links are kept as suspended, so giving the role back restores them
+  async fn suspend_links(&self, ctx: &Context, user_id: UserId) {
+    self.set_links_suspended(ctx, user_id, true).await;
+  }
+
+  /// Puts the suspended accounts of a member who was given the Verified role back on the whitelist
+  async fn restore_links(&self, ctx: &Context, user_id: UserId) {
+    self.set_links_suspended(ctx, user_id, false).await;
+  }
+
+  async fn set_links_suspended(&self, ctx: &Context, user_id: UserId, suspended: bool) {
+    let links: Vec<Link> = self.store.read(|data| {
+      data
+        .links
+        .by_discord_id(user_id.get())
+        .filter(|link| !link.banned && link.suspended != suspended)
+        .cloned()
+        .collect()
+    });
+
+    let (action, kind) = if suspended {
+      ("remove", OperationKind::WhitelistRemove)
+    } else {
+      ("add", OperationKind::WhitelistAdd)
+    };
+    for link in links {
+      let name = &link.minecraft_name;
+
+      // With the role commands replacing the whitelist, there is no whitelist entry to change
+      if !self.role_commands_replace_whitelist {
+        let server = self.server_by_name(link.server.as_deref());
+        let outcome = match self
+          .run_rcon_command_on(server, &format!("whitelist {action} {name}"))
+          .await
+        {
+          Ok(_) => AccountOutcome::Done,
+          Err(err) => {
+            warn!("Couldn't {action} '{name}' on the whitelist of {user_id}'s changed roles, queued for when the server is online: {err}");
+            self.queue_operation(kind, user_id.get(), name, link.server.clone(), false);
+            AccountOutcome::Queued
+          }
+        };
+
+        if suspended {
+          self
+            .notify_account_event(
+              ctx,
+              AccountEvent {
+                action: AccountAction::AutomaticRemoval,
+                discord_id: user_id,
+                minecraft_name: name,
+                uuid: Some(&link.uuid),
+                outcome,
+                admin: None,
+                reason: Some("The Verified role was removed"),
+              },
+            )
+            .await;
+        }
+      }
+
+      self.store.write(|data| {
+        if let Some(link) = data.links.by_uuid_mut(&link.uuid, link.server.as_deref()) {
+          link.suspended = suspended;
+        }
+      });
+      if suspended {
+        info!("Suspended '{name}' of {user_id}, their Verified role was removed");
+      } else {
+        info!("Restored '{name}' of {user_id}, their Verified role was given back");
+      }
+    }
+  }
+
+  /// The moderator and the reason of a ban or kick of the user that just happened, from the audit
+  /// log. `None` when there is none, or the bot can't view the audit log
+  async fn recent_audit_entry(
+    &self,
+    ctx: &Context,
+    guild_id: GuildId,
+    action: MemberAction,
+    user_id: UserId,
+  ) -> Option<(Option<User>, Option<String>)> {
+    let audit_logs = ctx
+      .http
+      .get_audit_logs(
+        guild_id,
+        Some(audit_log::Action::Member(action)),
+        None,
+        None,
+        Some(10),
+      )
+      .await
+      .inspect_err(|error| warn!("Couldn't read the audit log: {error}"))
+      .ok()?;
+
+    let entry = audit_logs.entries.into_iter().find(|entry| {
+      let age = Utc::now() - *entry.id.created_at();
+      entry.target_id.map(|target| target.get()) == Some(user_id.get())
+        && age.to_std().is_ok_and(|age| age < AUDIT_LOG_MAX_AGE)
+    })?;
+
+    Some((audit_logs.users.get(&entry.user_id).cloned(), entry.reason))
+  }
+
+  /// The links and unvalidated verifications of the named accounts, before they're forgotten
+  fn accounts_to_forget(&self, user_id: UserId, names: &[String]) -> MemberAccounts {
+    self.store.read(|data| {
+      let links = data
+        .links
+        .by_discord_id(user_id.get())
+        .filter(|link| names.contains(&link.minecraft_name))
+        .cloned()
+        .collect();
+      let unvalidated = data
+        .unvalidated
+        .iter()
+        .filter(|verification| {
+          verification.discord_id == user_id.get() && names.contains(&verification.minecraft_name)
+        })
+        .cloned()
+        .collect();
+      (links, unvalidated)
+    })
+  }
+
+  /// Brings back the accounts of a banned member that were forgotten when the removal arrived
+  /// before the ban, and drops the removals still queued for them, which would forget them again
+  fn take_back_departed_accounts(&self, user_id: UserId) {
+    let departed = self.departed_accounts.take(user_id);
+    self.store.write(|data| {
+      data
+        .pending_operations
+        .retain(|operation| !(operation.forget_link && operation.discord_id == user_id.get()));
+      if let Some((links, unvalidated)) = departed {
+        for link in links {
+          data.links.upsert(link);
+        }
+        data.unvalidated.extend(unvalidated);
+      }
+    });
+  }
+
+  fn has_banned_links(&self, user_id: UserId) -> bool {
+    self.store.read(|data| {
+      data
+        .links
+        .by_discord_id(user_id.get())
+        .any(|link| link.banned)
+    })
+  }
+
+  /// Bans the user's accounts on the server and takes them off the whitelist. The links are kept
+  /// and marked as banned, and the bans are recorded like the `/ban` ones. Bans that can't run
+  /// while the server is offline are queued
+  async fn ban_linked_accounts(
+    &self,
+    ctx: &Context,
+    user: &User,
+    moderator: Option<User>,
+    reason: Option<String>,
+    server_reason: &str,
+  ) {
+    let user_id = user.id.get();
+    // Unvalidated verifications are whitelisted too, but have no link to keep
+    let accounts: Vec<(String, Option<String>)> = self.store.read(|data| {
+      data
+        .links
+        .by_discord_id(user_id)
+        .filter(|link| !link.banned)
+        .map(|link| (link.minecraft_name.clone(), link.server.clone()))
+        .chain(
+          data
+            .unvalidated
+            .iter()
+            .filter(|verification| verification.discord_id == user_id)
+            .map(|verification| {
+              (
+                verification.minecraft_name.clone(),
+                verification.server.clone(),
+              )
+            }),
+        )
+        .collect()
+    });
+
+    let (moderator_id, moderator_name) = moderator
+      .map_or((0, "Discord".to_string()), |moderator| {
+        (moderator.id.get(), moderator.name)
+      });
+
+    for (name, server_key) in &accounts {
+      let server = self.server_by_name(server_key.as_deref());
+      let banned = async {
+        self
+          .run_rcon_command_on(server, &format!("ban {name} {server_reason}"))
+          .await?;
+        self
+          .run_rcon_command_on(server, &format!("whitelist remove {name}"))
+          .await
+      };
+
+      let outcome = match banned.await {
+        Ok(_) => {
+          info!(
+            "Banned '{name}' of {} ({user_id}), {moderator_name} removed them from Discord: {}",
+            user.tag(),
+            reason.as_deref().unwrap_or("no reason given")
+          );
+          AccountOutcome::Done
+        }
+        Err(err) => {
+          warn!(
+            "Couldn't ban '{name}' of {} ({user_id}), banning them once the server is online: {err}",
+            user.tag()
+          );
+          self.queue_operation(OperationKind::Ban, user_id, name, server_key.clone(), false);
+          AccountOutcome::Queued
+        }
+      };
+      let uuid = self
+        .account_uuids(user.id, std::slice::from_ref(name))
+        .remove(0)
+        .1;
+      self
+        .notify_account_event(
+          ctx,
+          AccountEvent {
+            action: AccountAction::AutomaticRemoval,
+            discord_id: user.id,
+            minecraft_name: name,
+            uuid: uuid.as_deref(),
+            outcome,
+            admin: None,
+            reason: Some(server_reason),
+          },
+        )
+        .await;
+
+      self.store.write(|data| {
+        data.moderation.push(ModerationAction {
+          minecraft_name: name.clone(),
+          kind: ModerationKind::Ban,
+          reason: Some(reason.clone().unwrap_or_else(|| server_reason.to_string())),
+          moderator_id,
+          moderator_name: moderator_name.clone(),
+          issued_at: Utc::now().timestamp(),
+        })
+      });
+    }
+
+    self.store.write(|data| {
+      let links: Vec<(String, Option<String>)> = data
+        .links
+        .by_discord_id(user_id)
+        .map(|link| (link.uuid.clone(), link.server.clone()))
+        .collect();
+      for (uuid, server) in links {
+        if let Some(link) = data.links.by_uuid_mut(&uuid, server.as_deref()) {
+          link.banned = true;
+        }
+      }
+      data
+        .unvalidated
+        .retain(|verification| verification.discord_id != user_id);
+    });
+  }
+
+  /// Pardons the accounts of a user whose Discord ban was lifted and forgets them. They aren't
+  /// whitelisted again, the user has to verify again after rejoining
+  async fn pardon_linked_accounts(&self, user: &User) {
+    let user_id = user.id.get();
+    let links: Vec<Link> = self.store.read(|data| {
+      data
+        .links
+        .by_discord_id(user_id)
+        .filter(|link| link.banned)
+        .cloned()
+        .collect()
+    });
+
+    for link in links {
+      let name = &link.minecraft_name;
+      self
+        .store
+        .write(|data| data.links.remove(&link.uuid, link.server.as_deref()));
+
+      // A ban that never reached the server doesn't need a pardon
+      let was_queued = self.store.write(|data| {
+        let queued = data.pending_operations.len();
+        data.pending_operations.retain(|operation| {
+          !(operation.kind == OperationKind::Ban
+            && operation.discord_id == user_id
+            && operation.minecraft_name == *name
+            && operation.server == link.server)
+        });
+        data.pending_operations.len() != queued
+      });
+      if was_queued {
+        info!(
+          "Dropped the queued ban of '{name}', {} was unbanned",
+          user.tag()
+        );
+        continue;
+      }
+
+      let server = self.server_by_name(link.server.as_deref());
+      match self
+        .run_rcon_command_on(server, &format!("pardon {name}"))
+        .await
+      {
+        Ok(_) => info!("Pardoned '{name}', {} ({user_id}) was unbanned", user.tag()),
+        Err(err) => {
+          warn!(
+            "Couldn't pardon '{name}' of {} ({user_id}), pardoning them once the server is online: {err}",
+            user.tag()
+          );
+          self.queue_operation(
+            OperationKind::Pardon,
+            user_id,
+            name,
+            link.server.clone(),
+            false,
+          );
+        }
+      }
+    }
+  }
+
+  /// Takes the user's accounts off the whitelist and removes their Verified role. The role stays
+  /// when the server can't be reached, so the whitelist and the role don't drift apart
+  pub(super) async fn unverify(&self, ctx: &Context, command: &CommandInteraction) -> String {
+    let user_id = command.user.id;
+    let username = command
+      .data
+      .options
+      .iter()
+      .find(|option| option.name == "username")
+      .and_then(|option| option.value.as_str());
+    let names = self.verified_names(user_id, username);
+
+    if names.is_empty() {
+      return match username {
+        Some(username) => self.messages.render(
+          MessageKey::UnverifyNotVerifiedName,
+          &[("username", &username)],
+        ),
+        None => self.messages.text(MessageKey::UnverifyNotVerified),
+      };
+    }
+
+    let accounts = self.account_uuids(user_id, &names);
+    let removed = self.remove_verified_accounts(user_id, &names).await;
+    let outcome = match &removed {
+      Ok(()) => AccountOutcome::Done,
+      Err(err) => AccountOutcome::Failed(format!("Couldn't reach the server: {err}")),
+    };
+    for (name, uuid) in &accounts {
+      self
+        .notify_account_event(
+          ctx,
+          AccountEvent {
+            action: AccountAction::Unverify,
+            discord_id: user_id,
+            minecraft_name: name,
+            uuid: uuid.as_deref(),
+            outcome: outcome.clone(),
+            admin: None,
+            reason: None,
+          },
+        )
+        .await;
+    }
+    if removed.is_err() {
+      return self.messages.text(MessageKey::UnverifyServerUnreachable);
+    }
+
+    let names = names.join("', '");
+    info!("{} unverified '{names}'", command.user.name);
+
+    // The role stays while other accounts are still verified
+    if !self.verified_names(user_id, None).is_empty() {
+      return self
+        .messages
+        .render(MessageKey::UnverifyRemoved, &[("names", &names)]);
+    }
+
+    match self.remove_verified_role(ctx, user_id, "Unverified").await {
+      Ok(()) => self
+        .messages
+        .render(MessageKey::UnverifyRemovedLast, &[("names", &names)]),
+      Err(error) => {
+        warn!("Couldn't remove the Verified role from {user_id}: {error}");
+        self
+          .messages
+          .render(MessageKey::UnverifyRoleFailed, &[("names", &names)])
+      }
+    }
+  }
+
+  /// Removes someone else's verification, all of it or just one account
+  pub(super) async fn force_unverify(&self, ctx: &Context, command: &CommandInteraction) -> String {
+    if !can_manage_roles(command) {
+      return "Only members who can manage roles can use this command.".to_string();
+    }
+
+    let mut user_id = None;
+    let mut username = None;
+    for option in &command.data.options {
+      match (option.name.as_str(), &option.value) {
+        ("user", CommandDataOptionValue::User(value)) => user_id = Some(*value),
+        ("username", CommandDataOptionValue::String(value)) => username = Some(value.as_str()),
+        _ => {}
+      }
+    }
+
+    let Some(user_id) = user_id else {
+      return "There wasn't a user.".to_string();
+    };
+
+    let names = self.verified_names(user_id, username);
+    if names.is_empty() {
+      return match username {
+        Some(username) => format!("<@{user_id}> hasn't verified '{username}'."),
+        None => format!("<@{user_id}> hasn't verified a Minecraft account."),
+      };
+    }
+
+    let accounts = self.account_uuids(user_id, &names);
+    let removed = self.remove_verified_accounts(user_id, &names).await;
+    let outcome = match &removed {
+      Ok(()) => AccountOutcome::Done,
+      Err(err) => AccountOutcome::Failed(format!("Couldn't reach the server: {err}")),
+    };
+    for (name, uuid) in &accounts {
+      self
+        .notify_account_event(
+          ctx,
+          AccountEvent {
+            action: AccountAction::ForceUnverify,
+            discord_id: user_id,
+            minecraft_name: name,
+            uuid: uuid.as_deref(),
+            outcome: outcome.clone(),
+            admin: Some(&command.user),
+            reason: None,
+          },
+        )
+        .await;
+    }
+    if removed.is_err() {
+      return self.messages.text(MessageKey::UnverifyServerUnreachable);
+    }
+
+    let names = names.join("', '");
+    info!("{} unverified '{names}' of {user_id}", command.user.name);
+
+    match self
+      .remove_verified_role(ctx, user_id, "Unverified by an admin")
+      .await
+    {
+      Ok(()) => format!("Removed '{names}' of <@{user_id}> from the whitelist."),
+      Err(error) => {
+        warn!("Couldn't remove the Verified role from {user_id}: {error}");
+        format!("Removed '{names}' of <@{user_id}> from the whitelist, but couldn't remove their Verified role.")
+      }
+    }
+  }
+
+  /// Verifies an account for someone else, replacing the accounts they verified before. Meant
+  /// for fixing typos, so the usual checks like the banlist are skipped
+  pub(super) async fn force_verify(&self, ctx: &Context, command: &CommandInteraction) -> String {
+    if !can_manage_roles(command) {
+      return "Only members who can manage roles can use this command.".to_string();
+    }
+
+    let mut user_id = None;
+    let mut username = None;
+    for option in &command.data.options {
+      match (option.name.as_str(), &option.value) {
+        ("user", CommandDataOptionValue::User(value)) => user_id = Some(*value),
+        ("username", CommandDataOptionValue::String(value)) => username = Some(value.clone()),
+        _ => {}
+      }
+    }
+
+    let (Some(user_id), Some(username)) = (user_id, username) else {
+      return "There wasn't a user or a username.".to_string();
+    };
+
+    let (uuid, name) = match self.profiles().profile(&username).await {
+      Ok(MojangProfile { id, name }) => (id, name),
+      Err(error) => return mojang_failure_message(&username, &error, &self.messages),
+    };
+
+    if let Some(link) = self.store.read(|data| data.links.by_uuid(&uuid).cloned()) {
+      if link.discord_id != user_id.get() {
+        return format!(
+          "'{name}' is already verified by <@{}>. Unverify it first.",
+          link.discord_id
+        );
+      }
+    }
+
+    // Re-verifying the same account mustn't take it off the whitelist again
+    let old_names: Vec<String> = self
+      .verified_names(user_id, None)
+      .into_iter()
+      .filter(|old_name| !old_name.eq_ignore_ascii_case(&name))
+      .collect();
+    // The new account goes on first, so a failure doesn't leave the user without any
+    let whitelisted = self
+      .run_rcon_command(&format!("whitelist add {name}"))
+      .await;
+    let mut event = AccountEvent {
+      action: AccountAction::ForceVerify,
+      discord_id: user_id,
+      minecraft_name: &name,
+      uuid: Some(&uuid),
+      outcome: AccountOutcome::Done,
+      admin: Some(&command.user),
+      reason: None,
+    };
+    let output = match whitelisted {
+      Ok(output) => output,
+      Err(err) => {
+        event.outcome = AccountOutcome::Failed(format!("Couldn't reach the server: {err}"));
+        self.notify_account_event(ctx, event).await;
+        return "Couldn't reach the server. Try again when it's online.".to_string();
+      }
+    };
+    if parse_whitelist_add_output(&output) == WhitelistResult::PlayerNotFound {
+      event.outcome = AccountOutcome::Failed("The server doesn't know the account".to_string());
+      self.notify_account_event(ctx, event).await;
+      return format!("The server couldn't find '{name}', so it wasn't whitelisted.");
+    }
+
+    if !self.link_account(ctx, user_id, &uuid, &name, None).await {
+      event.outcome = AccountOutcome::Failed("The account is linked to someone else".to_string());
+      self.notify_account_event(ctx, event).await;
+      return format!(
+        "'{name}' was whitelisted, but it's linked to someone else, so nothing else changed. Check the link by hand."
+      );
+    }
+    let removed_old = old_names.is_empty()
+      || self
+        .remove_verified_accounts(user_id, &old_names)
+        .await
+        .is_ok();
+
+    let changes = if old_names.is_empty() {
+      format!("Added '{name}'")
+    } else if removed_old {
+      format!("Replaced '{}' with '{name}'", old_names.join("', '"))
+    } else {
+      format!(
+        "Added '{name}', but couldn't remove '{}'",
+        old_names.join("', '")
+      )
+    };
+    event.reason = Some(&changes);
+    self.notify_account_event(ctx, event).await;
+
+    info!(
+      "{} force verified '{name}' for {user_id}",
+      command.user.name
+    );
+
+    let role_note = match self.guild_snapshot() {
+      Some(guild) => match ctx.http.get_member(guild.id, user_id).await {
+        Ok(member) => match self.verified_role(&guild) {
+          Some(role) => match self.add_role_with_retry(ctx, &member, role.id).await {
+            Ok(()) => "",
+            Err(error) => {
+              warn!("Couldn't add the Verified role to {user_id}: {error}");
+              " Couldn't give them the Verified role."
+            }
+          },
+          None => "",
+        },
+        Err(error) if is_unknown_member(&error) => {
+          " They aren't in the guild, so no role was given."
+        }
+        Err(error) => {
+          warn!("Couldn't fetch the member {user_id}: {error}");
+          " Couldn't give them the Verified role."
+        }
+      },
+      None => "",
+    };
+
+    format!("{changes} on the whitelist for <@{user_id}>.{role_note}")
+  }
+
+  /// Bans the accounts of a member banned from Discord on the server too
+  pub(super) async fn member_banned(&self, ctx: &Context, guild_id: GuildId, banned_user: User) {
+    if !self.is_verify_guild(guild_id) {
+      return;
+    }
+
+    let (moderator, reason) = self
+      .recent_audit_entry(ctx, guild_id, MemberAction::BanAdd, banned_user.id)
+      .await
+      .unwrap_or_default();
+    self.take_back_departed_accounts(banned_user.id);
+    self
+      .ban_linked_accounts(ctx, &banned_user, moderator, reason, DISCORD_BAN_REASON)
+      .await;
+  }
+
+  /// Pardons the accounts of a member whose Discord ban was lifted, without whitelisting them
+  pub(super) async fn member_unbanned(&self, guild_id: GuildId, unbanned_user: User) {
+    if !self.is_verify_guild(guild_id) {
+      return;
+    }
+
+    self.pardon_linked_accounts(&unbanned_user).await;
+  }
+
+  /// A moderator taking the Verified role away suspends the member's accounts, giving it back
+  /// restores them. The bot's own role changes are skipped. Big guilds don't have most members
+  /// cached, then there's no member from before and the accounts are brought in line with the
+  /// roles the member has now, which only changes something when they don't match
+  pub(super) async fn member_roles_changed(
+    &self,
+    ctx: &Context,
+    old_if_available: Option<Member>,
+    event: GuildMemberUpdateEvent,
+  ) {
+    if !self.is_guild_allowed(Some(event.guild_id)) || !self.is_verify_guild(event.guild_id) {
+      return;
+    }
+    let Some(role_id) = self
+      .guild_snapshot()
+      .and_then(|guild| Some(self.verified_role(&guild)?.id))
+    else {
+      return;
+    };
+
+    let user_id = event.user.id;
+    let has_role = event.roles.contains(&role_id);
+    let had_role = old_if_available.map(|old| old.roles.contains(&role_id));
+    let Some(change) = verified_role_change(had_role, has_role) else {
+      return;
+    };
+    if self.take_own_role_change(user_id) {
+      return;
+    }
+
+    match change {
+      VerifiedRoleChange::Restore => self.restore_links(ctx, user_id).await,
+      VerifiedRoleChange::Suspend => self.suspend_links(ctx, user_id).await,
+    }
+  }
+
+  /// A member who came back before the server was online keeps their whitelist entries
+  pub(super) fn member_rejoined(&self, member: &Member) {
+    let user_id = member.user.id.get();
+    let had_pending_removal = self.store.write(|data| {
+      let queued = data.pending_operations.len();
+      data
+        .pending_operations
+        .retain(|operation| !(operation.forget_link && operation.discord_id == user_id));
+      data.pending_operations.len() != queued
+    });
+
+    if had_pending_removal {
+      info!(
+        "{} rejoined the guild, keeping their whitelist entries",
+        member.user.tag()
+      );
+    }
+  }
+
+  /// Takes the accounts of a member who left off the whitelist and forgets them. Kicked members'
+  /// accounts are banned with BAN_ON_DISCORD_KICK, banned members are left to `member_banned`
+  pub(super) async fn member_left(&self, ctx: &Context, guild_id: GuildId, user: User) {
+    if !self.is_verify_guild(guild_id) {
+      return;
+    }
+
+    let names = self.verified_names(user.id, None);
+    if names.is_empty() {
+      return;
+    }
+
+    let already_banned = self.has_banned_links(user.id);
+    let ban_entry = if already_banned {
+      None
+    } else {
+      self
+        .recent_audit_entry(ctx, guild_id, MemberAction::BanAdd, user.id)
+        .await
+    };
+    let kick_entry = if self.ban_on_discord_kick && !already_banned && ban_entry.is_none() {
+      self
+        .recent_audit_entry(ctx, guild_id, MemberAction::Kick, user.id)
+        .await
+    } else {
+      None
+    };
+
+    match departure(
+      already_banned,
+      ban_entry.is_some(),
+      kick_entry.is_some(),
+      self.ban_on_discord_kick,
+    ) {
+      // member_banned bans the accounts and keeps them for a pardon
+      Departure::Banned => return,
+      Departure::Kicked => {
+        let (moderator, reason) = kick_entry.unwrap_or_default();
+        self
+          .ban_linked_accounts(ctx, &user, moderator, reason, DISCORD_KICK_REASON)
+          .await;
+        return;
+      }
+      Departure::Left => {}
+    }
+
+    let tag = user.tag();
+    let accounts = self.account_uuids(user.id, &names);
+    let forgotten = self.accounts_to_forget(user.id, &names);
+    if self.remove_verified_accounts(user.id, &names).await.is_ok() {
+      self.departed_accounts.record(user.id, forgotten);
+      info!(
+        "{tag} ({}) left the guild, removed '{}' from the whitelist",
+        user.id,
+        names.join("', '")
+      );
+      for (name, uuid) in &accounts {
+        self
+          .notify_account_event(
+            ctx,
+            AccountEvent {
+              action: AccountAction::AutomaticRemoval,
+              discord_id: user.id,
+              minecraft_name: name,
+              uuid: uuid.as_deref(),
+              outcome: AccountOutcome::Done,
+              admin: None,
+              reason: Some("Left the Discord"),
+            },
+          )
+          .await;
+      }
+      return;
+    }
+
+    info!(
+      "{tag} ({}) left the guild, removing '{}' from the whitelist once the server is online",
+      user.id,
+      names.join("', '")
+    );
+    for (name, server) in self.accounts_of(user.id, &names) {
+      self.queue_operation(
+        OperationKind::WhitelistRemove,
+        user.id.get(),
+        &name,
+        server,
+        true,
+      );
+    }
+    for (name, uuid) in &accounts {
+      self
+        .notify_account_event(
+          ctx,
+          AccountEvent {
+            action: AccountAction::AutomaticRemoval,
+            discord_id: user.id,
+            minecraft_name: name,
+            uuid: uuid.as_deref(),
+            outcome: AccountOutcome::Queued,
+            admin: None,
+            reason: Some("Left the Discord"),
+          },
+        )
+        .await;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn a_ban_wins_over_a_kick() {
+    assert_eq!(departure(true, false, false, false), Departure::Banned);
+    assert_eq!(departure(false, true, true, true), Departure::Banned);
+    assert_eq!(departure(false, false, true, true), Departure::Kicked);
+    // Kicks only ban the accounts when BAN_ON_DISCORD_KICK is set
+    assert_eq!(departure(false, false, true, false), Departure::Left);
+    assert_eq!(departure(false, false, false, true), Departure::Left);
+  }
+
+  #[test]
+  fn only_a_changed_verified_role_does_something() {
+    assert_eq!(verified_role_change(Some(true), true), None);
+    assert_eq!(verified_role_change(Some(false), false), None);
+    assert_eq!(
+      verified_role_change(Some(true), false),
+      Some(VerifiedRoleChange::Suspend)
+    );
+    assert_eq!(
+      verified_role_change(Some(false), true),
+      Some(VerifiedRoleChange::Restore)
+    );
+    // Without the member from before, the accounts follow the roles they have now
+    assert_eq!(
+      verified_role_change(None, false),
+      Some(VerifiedRoleChange::Suspend)
+    );
+    assert_eq!(
+      verified_role_change(None, true),
+      Some(VerifiedRoleChange::Restore)
+    );
+  }
+
+  #[test]
+  fn departed_accounts_are_taken_back_once_within_the_window() {
+    let departed = DepartedAccounts::new(Duration::from_secs(30));
+    let user_id = UserId::new(1);
+    let start = Instant::now();
+    let link = Link {
+      discord_id: 1,
+      uuid: "069a79f444e94726a5befca90e38aaf5".to_string(),
+      minecraft_name: "Notch".to_string(),
+      linked_at: 0,
+      expiry_warned: false,
+      suspended: false,
+      banned: false,
+      server: None,
+      platform: Platform::Java,
+      xuid: None,
+    };
+
+    departed.record_at(user_id, (vec![link], Vec::new()), start);
+    let (links, _) = departed
+      .take_at(user_id, start + Duration::from_secs(5))
+      .unwrap();
+    assert_eq!(links[0].minecraft_name, "Notch");
+    assert!(departed
+      .take_at(user_id, start + Duration::from_secs(5))
+      .is_none());
+
+    departed.record_at(user_id, (Vec::new(), Vec::new()), start);
+    assert!(departed
+      .take_at(user_id, start + Duration::from_secs(30))
+      .is_none());
+  }
+}
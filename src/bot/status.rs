@@ -0,0 +1,1145 @@
+//! The server status: polling the server, the presence, the status channel name and embed, and the
+//! commands that show or override the status
+
+use super::*;
+
+/// The query port when neither the config nor an SRV record has one
+const DEFAULT_QUERY_PORT: u16 = 25565;
+
+/// How many failed status checks in a row make the query address be resolved again, in case DNS
+/// changed
+const QUERY_RESOLVE_AFTER_FAILURES: u32 = 3;
+
+/// How long each way of checking the server's status has, so a status check with every fallback
+/// stays under 15 seconds
+const STATUS_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How the status is shown, and what the status checks last found
+pub(super) struct StatusMonitor {
+  /// How often the status channel is renamed at most
+  pub(super) interval: Duration,
+  /// Without a status channel, the status is only shown in the presence and the commands
+  pub(super) channel_id: Option<u64>,
+  pub(super) update_presence: bool,
+  /// How often the server is pinged for the presence. The channel is renamed at most once per
+  /// status interval, Discord rate limits renames much more than presence updates
+  pub(super) presence_interval: Duration,
+  /// Also ping this port as a Bedrock server, for Geyser setups
+  pub(super) bedrock_port: Option<u16>,
+  pub(super) format: StatusFormat,
+  /// Whether the status channel is renamed to the server status
+  pub(super) channel_rename: bool,
+  /// Whether a pinned embed in the status channel shows the full server status
+  pub(super) embed: bool,
+  /// Players unseen for longer are forgotten by `/lastseen`
+  pub(super) last_seen_retention_days: i64,
+  /// When the status channel was last renamed
+  pub(super) last_rename: Mutex<Option<Instant>>,
+  /// When the server stopped answering, for the status embed
+  pub(super) offline_since: Mutex<Option<DateTime<Utc>>>,
+  /// When the status checks last reached the server, for `/server-info`
+  pub(super) last_contact: Mutex<Option<DateTime<Utc>>>,
+  pub(super) latest_snapshot: Mutex<Option<ServerSnapshot>>,
+  pub(super) smoother: Mutex<PlayerCountSmoother>,
+  pub(super) transitions: Mutex<StatusTransitions>,
+  /// Set by `/force-status`, overrides whether the server is online
+  pub(super) forced: Mutex<Option<bool>>,
+  /// Set at startup when the status channel can't be renamed at all, like a category
+  pub(super) renames_disabled: AtomicBool,
+  /// Set when renaming the status channel failed because of missing permissions
+  pub(super) renames_paused: AtomicBool,
+  /// When the status loop last finished a tick, for `/healthz`
+  pub(super) last_tick: Mutex<Option<Instant>>,
+  /// Whether the last status check saw fewer players than were online
+  pub(super) sightings_partial: AtomicBool,
+}
+
+impl StatusMonitor {
+  /// The channel name formats come from the settings, or else the messages file
+  pub(super) fn new(
+    interval: Duration,
+    channel_id: Option<u64>,
+    settings: &Settings,
+    messages: &Messages,
+  ) -> Self {
+    let default_online_format = if settings.bedrock_port.is_some() {
+      MessageKey::StatusChannelEditions
+    } else {
+      MessageKey::StatusChannelOnline
+    };
+    let format = StatusFormat {
+      online: settings
+        .status_online_format
+        .clone()
+        .unwrap_or_else(|| messages.template(default_online_format).to_string()),
+      offline: settings.status_offline_format.clone().unwrap_or_else(|| {
+        messages
+          .template(MessageKey::StatusChannelOffline)
+          .to_string()
+      }),
+    };
+    // The counts are at most a few digits, so a name that's too long is the template's fault
+    let longest_online_name = format
+      .online
+      .replace("{online}", "99999")
+      .replace("{max}", "99999")
+      .replace("{editions}", "Java: 99999, Bedrock: 99999");
+    for (setting, name) in [
+      ("STATUS_ONLINE_FORMAT", &longest_online_name),
+      ("STATUS_OFFLINE_FORMAT", &format.offline),
+    ] {
+      if name.chars().count() > CHANNEL_NAME_LIMIT {
+        warn!("{setting} can be longer than Discord's {CHANNEL_NAME_LIMIT} character limit for channel names, it will be cut off");
+      }
+    }
+    if interval < CHANNEL_RENAME_WINDOW / CHANNEL_RENAMES_PER_WINDOW as u32 {
+      warn!(
+        "STATUS_INTERVAL_SECONDS is {}, but Discord only allows {CHANNEL_RENAMES_PER_WINDOW} channel renames per 10 minutes. Renames in between are skipped",
+        interval.as_secs()
+      );
+    }
+
+    Self {
+      interval,
+      channel_id,
+      update_presence: settings.update_presence,
+      presence_interval: settings.presence_interval,
+      bedrock_port: settings.bedrock_port,
+      format,
+      channel_rename: settings.status_channel_rename,
+      embed: settings.status_embed,
+      last_seen_retention_days: settings.last_seen_retention_days,
+      last_rename: Mutex::new(None),
+      offline_since: Mutex::new(None),
+      last_contact: Mutex::new(None),
+      latest_snapshot: Mutex::new(None),
+      smoother: Mutex::new(PlayerCountSmoother::new(settings.status_smoothing_samples)),
+      transitions: Mutex::new(StatusTransitions::default()),
+      forced: Mutex::new(None),
+      renames_disabled: AtomicBool::new(false),
+      renames_paused: AtomicBool::new(false),
+      last_tick: Mutex::new(None),
+      sightings_partial: AtomicBool::new(false),
+    }
+  }
+
+  /// The presence can be updated more often than the channel name, which apply_snapshot keeps to
+  /// the status interval
+  pub(super) fn tick_interval(&self) -> Duration {
+    if self.update_presence {
+      self.presence_interval.min(self.interval)
+    } else {
+      self.interval
+    }
+  }
+
+  pub(super) fn is_forced(&self) -> bool {
+    self.forced.lock().unwrap().is_some()
+  }
+
+  /// The latest status the checks found
+  pub(super) fn latest(&self) -> Option<ServerSnapshot> {
+    self.latest_snapshot.lock().unwrap().clone()
+  }
+
+  /// The status `/force-status` set, keeping the last known player limit. `None` if it isn't
+  /// forced
+  fn forced_snapshot(&self) -> Option<ServerSnapshot> {
+    let online = (*self.forced.lock().unwrap())?;
+    let players_max = self
+      .latest_snapshot
+      .lock()
+      .unwrap()
+      .as_ref()
+      .map_or(0, |snapshot| snapshot.players_max);
+
+    Some(ServerSnapshot {
+      online,
+      players_max,
+      ..ServerSnapshot::offline()
+    })
+  }
+
+  /// Keeps the snapshot as the latest status. Returns whether the server was online before, and
+  /// the snapshot to display. Only what's displayed is smoothed, the commands keep showing the
+  /// latest sample
+  fn record(&self, snapshot: &ServerSnapshot) -> (Option<bool>, ServerSnapshot) {
+    let was_online = self
+      .latest_snapshot
+      .lock()
+      .unwrap()
+      .replace(snapshot.clone())
+      .map(|previous| previous.online);
+
+    let players_online = self
+      .smoother
+      .lock()
+      .unwrap()
+      .smooth(snapshot.online, snapshot.players_online);
+    let displayed = ServerSnapshot {
+      players_online,
+      ..snapshot.clone()
+    };
+    (was_online, displayed)
+  }
+
+  /// Since when the server is offline, `None` while it's online
+  fn offline_since(&self, online: bool, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let mut offline_since = self.offline_since.lock().unwrap();
+    if online {
+      *offline_since = None;
+    } else if offline_since.is_none() {
+      *offline_since = Some(now);
+    }
+    *offline_since
+  }
+
+  /// Whether the status interval passed since the channel was last renamed
+  fn rename_due(&self) -> bool {
+    self
+      .last_rename
+      .lock()
+      .unwrap()
+      .is_none_or(|renamed_at| renamed_at.elapsed() >= self.interval)
+  }
+
+  /// The status channel, if it gets renamed
+  fn renamed_channel_id(&self) -> Option<u64> {
+    self
+      .channel_id
+      .filter(|_| self.channel_rename && !self.renames_disabled.load(Ordering::SeqCst))
+  }
+}
+
+/// The plain text of a chat object like the MOTD, without its formatting
+fn motd_text(motd: &ChatObject) -> String {
+  fn collect(chat: &ChatObject, text: &mut String) {
+    match chat {
+      ChatObject::Object(component) => {
+        text.push_str(component.text.as_deref().unwrap_or_default());
+        for extra in component.extra.iter().flatten() {
+          collect(extra, text);
+        }
+      }
+      ChatObject::Array(components) => {
+        for component in components {
+          collect(component, text);
+        }
+      }
+      ChatObject::JsonPrimitive(value) => text.push_str(value.as_str().unwrap_or_default()),
+    }
+  }
+
+  let mut text = String::new();
+  collect(motd, &mut text);
+  plain_motd(&text)
+}
+
+/// Where the server's status is read from
+#[derive(Default)]
+pub(super) struct QueryAddress {
+  resolved: Option<(String, u16)>,
+  /// Failed status checks in a row
+  failures: u32,
+}
+
+impl Server {
+  /// The host and port the status is read from. Without a configured port, that's where the
+  /// server's SRV record points to, like for the Minecraft client. It's resolved again after the
+  /// server couldn't be reached a few times
+  pub(super) async fn query_address(&self) -> (String, u16) {
+    {
+      let query_address = self.query_address.lock().unwrap();
+      if let Some(resolved) = &query_address.resolved {
+        if query_address.failures < QUERY_RESOLVE_AFTER_FAILURES {
+          return resolved.clone();
+        }
+      }
+    }
+
+    let host = &self.config.address;
+    let resolved = match self.config.query_port {
+      Some(query_port) => (host.clone(), query_port),
+      None if host.parse::<std::net::IpAddr>().is_ok() => (host.clone(), DEFAULT_QUERY_PORT),
+      None => match address::lookup_minecraft_srv(host).await {
+        Ok(Some(record)) => (record.target, record.port),
+        Ok(None) => (host.clone(), DEFAULT_QUERY_PORT),
+        Err(error) => {
+          warn!("Couldn't look up the SRV record of {host}: {error}");
+          (host.clone(), DEFAULT_QUERY_PORT)
+        }
+      },
+    };
+
+    let mut query_address = self.query_address.lock().unwrap();
+    if query_address.resolved.as_ref() != Some(&resolved) {
+      info!(
+        "Reading the status of {} from {}:{}",
+        self.config.name, resolved.0, resolved.1
+      );
+    }
+    query_address.resolved = Some(resolved.clone());
+    query_address.failures = 0;
+
+    resolved
+  }
+
+  fn query_checked(&self, reached: bool) {
+    let mut query_address = self.query_address.lock().unwrap();
+    if reached {
+      query_address.failures = 0;
+    } else {
+      query_address.failures += 1;
+    }
+  }
+}
+
+impl Handler {
+  /// Disables status renames if the status channel isn't a kind of channel that gets renamed
+  pub(super) async fn check_status_channel(&self, http: &Http) {
+    let Some(status_channel_id) = self.status.channel_id else {
+      return;
+    };
+
+    let kind = match http.get_channel(ChannelId::new(status_channel_id)).await {
+      Ok(Channel::Guild(channel)) => channel.kind,
+      Ok(_) => ChannelType::Private,
+      Err(error) => {
+        warn!("Couldn't check the status channel {status_channel_id}: {error}");
+        return;
+      }
+    };
+
+    if !matches!(
+      kind,
+      ChannelType::Text | ChannelType::Voice | ChannelType::Stage
+    ) {
+      self.status.renames_disabled.store(true, Ordering::SeqCst);
+      error!(
+        "The status channel {status_channel_id} is a {} channel, which can't show the status. Use a text, voice or stage channel. Status renames are disabled",
+        kind.name()
+      );
+    }
+  }
+
+  /// Pings the server (unless its status is forced) and applies the result
+  pub(super) async fn poll_server(&self, ctx: &Context) {
+    let forced_snapshot = self.status.forced_snapshot();
+    let forced = forced_snapshot.is_some();
+
+    let snapshot = match forced_snapshot {
+      Some(snapshot) => snapshot,
+      None => {
+        self
+          .fetch_server_snapshot(self.main_server(), self.status.bedrock_port)
+          .await
+      }
+    };
+
+    if !forced {
+      METRICS.record_server(snapshot.online, snapshot.players_online);
+    }
+    if snapshot.online && !forced {
+      self
+        .store
+        .write(|data| data.record_players(Utc::now().date_naive(), snapshot.players_online));
+      *self.status.last_contact.lock().unwrap() = Some(Utc::now());
+    }
+
+    let (was_online, displayed_snapshot) = self.status.record(&snapshot);
+    self
+      .apply_snapshot(ctx, &displayed_snapshot, false, None)
+      .await;
+    self.update_status_embed(ctx, &displayed_snapshot).await;
+
+    if !forced && was_online == Some(false) && snapshot.online {
+      self.run_online_commands().await;
+    }
+
+    // A forced status isn't what the server is really doing, so it isn't announced
+    if !forced {
+      let transition = self
+        .status
+        .transitions
+        .lock()
+        .unwrap()
+        .observe(snapshot.online);
+      if transition.is_some() {
+        self.announce_transition(ctx, &snapshot).await;
+      }
+    }
+  }
+
+  /// Edits the pinned status embed, or posts and pins one if there isn't one yet. The message id is
+  /// stored, so restarts keep editing the same message
+  async fn update_status_embed(&self, ctx: &Context, snapshot: &ServerSnapshot) {
+    let offline_since = self.status.offline_since(snapshot.online, Utc::now());
+
+    let Some(status_channel_id) = self.status.channel_id.filter(|_| self.status.embed) else {
+      return;
+    };
+    let channel_id = ChannelId::new(status_channel_id);
+
+    let embed = if snapshot.online {
+      let names = if snapshot.names.is_empty() {
+        "-".to_string()
+      } else {
+        snapshot.names.join(", ")
+      };
+
+      CreateEmbed::new()
+        .title("Server is online")
+        .description(snapshot.motd.as_deref().unwrap_or_default())
+        .field(
+          "Players",
+          format!("{}/{}", snapshot.players_online, snapshot.players_max),
+          true,
+        )
+        .field(
+          "Version",
+          snapshot.version.as_deref().unwrap_or("unknown"),
+          true,
+        )
+        .field("Online now", names, false)
+        .colour(Colour::DARK_GREEN)
+    } else {
+      let description = match offline_since {
+        Some(offline_since) => format!("Unreachable since <t:{}:R>", offline_since.timestamp()),
+        None => "Unreachable".to_string(),
+      };
+
+      CreateEmbed::new()
+        .title("Server is offline")
+        .description(description)
+        .colour(Colour::RED)
+    }
+    .footer(CreateEmbedFooter::new("Last updated"))
+    .timestamp(Timestamp::now());
+
+    let stored_message_id = self.store.read(|data| data.status_message_id);
+    let message_id = match stored_message_id {
+      Some(message_id) => Some(MessageId::new(message_id)),
+      // The store might be new, the bot's pinned status from before is reused
+      None => channel_id
+        .pins(ctx)
+        .await
+        .inspect_err(|error| warn!("Couldn't read the pins of the status channel: {error}"))
+        .ok()
+        .and_then(|pins| {
+          let bot_id = ctx.cache.current_user().id;
+          pins
+            .into_iter()
+            .find(|pin| pin.author.id == bot_id && !pin.embeds.is_empty())
+            .map(|pin| pin.id)
+        }),
+    };
+
+    if let Some(message_id) = message_id {
+      match channel_id
+        .edit_message(ctx, message_id, EditMessage::new().embed(embed.clone()))
+        .await
+      {
+        Ok(_) => {
+          if stored_message_id != Some(message_id.get()) {
+            self
+              .store
+              .write(|data| data.status_message_id = Some(message_id.get()));
+          }
+          return;
+        }
+        // Deleted by someone, a new one is posted
+        Err(error) if is_unknown_message(&error) => {}
+        Err(error) => {
+          warn!("Couldn't update the status embed: {error}");
+          return;
+        }
+      }
+    }
+
+    let message = match channel_id
+      .send_message(ctx, CreateMessage::new().embed(embed))
+      .await
+    {
+      Ok(message) => message,
+      Err(error) => {
+        warn!("Couldn't post the status embed: {error}");
+        return;
+      }
+    };
+    if let Err(error) = message.pin(ctx).await {
+      warn!("Couldn't pin the status embed: {error}");
+    }
+    self
+      .store
+      .write(|data| data.status_message_id = Some(message.id.get()));
+  }
+
+  /// Posts to the status channel that the server went offline or came back
+  async fn announce_transition(&self, ctx: &Context, snapshot: &ServerSnapshot) {
+    let Some(status_channel_id) = self.status.channel_id else {
+      return;
+    };
+
+    let embed = if snapshot.online {
+      CreateEmbed::new()
+        .title("Server is back online")
+        .field(
+          "Version",
+          snapshot.version.as_deref().unwrap_or("unknown"),
+          true,
+        )
+        .field(
+          "Players",
+          format!("{}/{}", snapshot.players_online, snapshot.players_max),
+          true,
+        )
+        .colour(Colour::DARK_GREEN)
+    } else {
+      CreateEmbed::new()
+        .title("Server went offline")
+        .colour(Colour::RED)
+    }
+    .timestamp(Timestamp::now());
+
+    if let Err(error) = ChannelId::new(status_channel_id)
+      .send_message(ctx, CreateMessage::new().embed(embed))
+      .await
+    {
+      warn!("Couldn't announce the server status change: {error}");
+    }
+  }
+
+  /// Counts the status check towards today's activity, for the daily summary
+  pub(super) fn record_activity(&self) {
+    if self.status.is_forced() {
+      return;
+    }
+    let Some(snapshot) = self.status.latest() else {
+      return;
+    };
+
+    let players: Vec<(String, Option<String>)> = snapshot
+      .names
+      .iter()
+      .map(|name| (name.clone(), snapshot.uuids.get(name).cloned()))
+      .collect();
+    let today = self.today();
+    let interval = self.status.tick_interval().as_secs();
+    self.store.write(|data| {
+      data.record_activity(
+        today,
+        Utc::now().timestamp(),
+        interval,
+        snapshot.online,
+        snapshot.players_online,
+        &players,
+      )
+    });
+  }
+
+  /// Remembers who the status check saw online, for `/lastseen`. When the server lists only some
+  /// of the players, the rest are asked over RCON
+  pub(super) async fn record_sightings(&self) {
+    if self.status.is_forced() {
+      return;
+    }
+    let Some(snapshot) = self.status.latest().filter(|snapshot| snapshot.online) else {
+      return;
+    };
+
+    let mut players: Vec<(String, Option<String>)> = snapshot
+      .names
+      .iter()
+      .map(|name| (name.clone(), snapshot.uuids.get(name).cloned()))
+      .collect();
+    if players.len() < snapshot.players_online as usize {
+      match self.run_rcon_command("list").await {
+        Ok(output) => {
+          for name in parse_list_output(&output).map_or_else(Vec::new, |list| list.names) {
+            if !players.iter().any(|(listed, _)| *listed == name) {
+              players.push((name, None));
+            }
+          }
+        }
+        Err(error) => debug!("Couldn't list the players who are online: {error}"),
+      }
+    }
+    self.status.sightings_partial.store(
+      players.len() < snapshot.players_online as usize,
+      Ordering::SeqCst,
+    );
+
+    // The server derives the uuids from the names, so they're known without asking
+    if self.offline_mode {
+      for (name, uuid) in &mut players {
+        uuid.get_or_insert_with(|| offline_uuid(name));
+      }
+    }
+
+    let now = Utc::now().timestamp();
+    let interval = self.status.tick_interval().as_secs();
+    self.store.write(|data| {
+      data.record_sightings(&players, now, interval);
+      data.prune_sightings(now - self.status.last_seen_retention_days * SECONDS_PER_DAY);
+    });
+  }
+
+  /// Updates the bot presence and the status channel name to match the snapshot.
+  /// Normally only what changed since the last applied state is touched, passing the status
+  /// channel forces re-applying everything to it
+  async fn apply_snapshot(
+    &self,
+    ctx: &Context,
+    snapshot: &ServerSnapshot,
+    force: bool,
+    status_channel: Option<GuildChannel>,
+  ) {
+    let new_state = StatusState {
+      channel_name: snapshot.channel_name(&self.status.format),
+      presence: snapshot.presence_text(),
+      online: snapshot.online,
+    };
+    let old_state = self.store.read(|data| data.status.clone());
+
+    if !force && old_state.as_ref() == Some(&new_state) {
+      return;
+    }
+
+    if self.status.update_presence {
+      let (activity, online_status) = status_presence(&new_state);
+      ctx.set_presence(Some(activity), online_status);
+    }
+
+    // Not saving the state makes a later tick rename the channel
+    if !force && !self.status.rename_due() {
+      return;
+    }
+
+    let status_channel_id = self.status.renamed_channel_id();
+
+    if let Some(status_channel_id) = status_channel_id {
+      let status_channel = match status_channel {
+        Some(status_channel) => Some(status_channel),
+        None => ChannelId::new(status_channel_id)
+          .to_channel(ctx)
+          .await
+          .inspect_err(|error| warn!("Couldn't fetch the status channel: {error}"))
+          .ok()
+          .and_then(Channel::guild),
+      };
+
+      let Some(status_channel) = status_channel else {
+        return;
+      };
+
+      // Not saving the state makes the next tick try again
+      if !self
+        .rename_status_channel(ctx, status_channel, &new_state.channel_name)
+        .await
+      {
+        return;
+      }
+    }
+
+    if old_state.as_ref() != Some(&new_state) {
+      self.store.write(|data| data.status = Some(new_state));
+    }
+  }
+
+  /// Renames the status channel, if it has a different name. Returns whether it has the new name
+  async fn rename_status_channel(
+    &self,
+    ctx: &Context,
+    status_channel: GuildChannel,
+    new_channel_name: &str,
+  ) -> bool {
+    let old_channel_name = status_channel.name.clone();
+
+    // Renames stay paused after Discord said we're missing permissions, until we have them again
+    if self.status.renames_paused.load(Ordering::SeqCst) {
+      let bot_id = ctx.cache.current_user().id;
+      let can_rename = permissions_in(ctx, &status_channel, bot_id)
+        .await
+        .is_ok_and(|permissions| permissions.manage_channels());
+
+      if !can_rename {
+        return false;
+      }
+
+      self.status.renames_paused.store(false, Ordering::SeqCst);
+      info!("Got the permission to rename the status channel back, resuming renames");
+    }
+
+    // Only change the channel name if the the new channel name will be different
+    if old_channel_name == new_channel_name {
+      return true;
+    }
+
+    info!("Changing channel name...");
+    match rename_channel(
+      ctx,
+      &self.main_server().rename_limiter,
+      status_channel.id,
+      new_channel_name,
+    )
+    .await
+    {
+      Ok(()) => {
+        info!("Channel name changed from '{old_channel_name}' to '{new_channel_name}'");
+        *self.status.last_rename.lock().unwrap() = Some(Instant::now());
+        self.operation_succeeded(&Operation::ChannelRename);
+        true
+      }
+      Err(RenameError::Discord(error)) if is_missing_permissions(&error) => {
+        self.status.renames_paused.store(true, Ordering::SeqCst);
+        warn!("Missing permissions to rename the status channel, pausing renames until the bot can manage the channel again");
+        self.operation_failed(Operation::ChannelRename, &error);
+        false
+      }
+      // Waiting for the limit isn't a failure
+      Err(error @ RenameError::TooSoon(_)) => {
+        warn!("Couldn't change the name of the channel: {error}");
+        false
+      }
+      Err(error) => {
+        warn!("Couldn't change the name of the channel: {error}");
+        self.operation_failed(Operation::ChannelRename, &error);
+        false
+      }
+    }
+  }
+
+  /// Pings the minecraft server, and its Bedrock port if there is one. When the status ping fails,
+  /// the query protocol (with a UDP query port set) and then RCON `list` are tried before the Java
+  /// server counts as offline
+  pub(super) async fn fetch_server_snapshot(
+    &self,
+    server: &Server,
+    bedrock_port: Option<u16>,
+  ) -> ServerSnapshot {
+    let bedrock_status = async {
+      match bedrock_port {
+        Some(bedrock_port) => Some(bedrock::status(&server.config.address, bedrock_port).await),
+        None => None,
+      }
+    };
+    let (java_snapshot, bedrock_status) =
+      tokio::join!(self.probe_java_server(server), bedrock_status);
+
+    let bedrock_status = bedrock_status.map(|bedrock_status| {
+      bedrock_status
+        .inspect_err(|error| debug!("Couldn't get Bedrock status. Reason: {}", error))
+        .ok()
+    });
+
+    let java_online = java_snapshot
+      .as_ref()
+      .map(|snapshot| snapshot.players_online);
+    let mut snapshot = java_snapshot.unwrap_or_else(ServerSnapshot::offline);
+
+    if let Some(bedrock_status) = bedrock_status {
+      let bedrock_online = bedrock_status.as_ref().map(|status| status.players_online);
+
+      if let Some(status) = &bedrock_status {
+        snapshot.online = true;
+        snapshot.players_online += status.players_online;
+        // Both editions usually share the same slots, so only fall back to Bedrock's max
+        if java_online.is_none() {
+          snapshot.players_max = status.players_max;
+        }
+      }
+
+      snapshot.editions = Some(EditionCounts {
+        java: java_online,
+        bedrock: bedrock_online,
+      });
+    }
+
+    snapshot
+  }
+
+  /// The Java server's status from the first probe that answers, `None` if none did
+  async fn probe_java_server(&self, server: &Server) -> Option<ServerSnapshot> {
+    let (query_host, query_port) = server.query_address().await;
+    let name = &server.config.name;
+
+    let status = time::timeout(
+      STATUS_PROBE_TIMEOUT,
+      mc_query::status(&query_host, query_port),
+    )
+    .await
+    .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "timed out")));
+    server.query_checked(status.is_ok());
+
+    let (probe, snapshot) = match status {
+      Ok(status) => (
+        StatusProbe::Ping,
+        ServerSnapshot {
+          online: true,
+          players_online: status.players.online,
+          players_max: status.players.max,
+          names: status
+            .players
+            .sample
+            .iter()
+            .flatten()
+            .map(|sample| sample.name.clone())
+            .collect(),
+          uuids: status
+            .players
+            .sample
+            .iter()
+            .flatten()
+            .map(|sample| (sample.name.clone(), sample.id.clone()))
+            .collect(),
+          editions: None,
+          version: Some(status.version.name.clone()),
+          motd: Some(motd_text(&status.motd)),
+          fetched_at: Instant::now(),
+        },
+      ),
+      Err(error) => {
+        debug!("Couldn't get status of {name}. Reason: {}", error);
+        let fallback = match self.query_java_server(server, &query_host).await {
+          Some(snapshot) => Some((StatusProbe::Query, snapshot)),
+          None => self
+            .list_java_players(server)
+            .await
+            .map(|snapshot| (StatusProbe::RconList, snapshot)),
+        };
+        fallback?
+      }
+    };
+
+    if probe == StatusProbe::Ping {
+      debug!("{name} answered the {probe}");
+    } else {
+      info!("{name} didn't answer the status ping, but the {probe}");
+    }
+    Some(snapshot)
+  }
+
+  /// The full stat of the query protocol, if the server has a UDP query port set
+  async fn query_java_server(&self, server: &Server, host: &str) -> Option<ServerSnapshot> {
+    let udp_query_port = server.config.udp_query_port?;
+
+    let stat = time::timeout(
+      STATUS_PROBE_TIMEOUT,
+      mc_query::query::stat_full(host, udp_query_port),
+    )
+    .await
+    .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "timed out")))
+    .inspect_err(|error| debug!("Couldn't query {}. Reason: {error}", server.config.name))
+    .ok()?;
+
+    Some(ServerSnapshot {
+      online: true,
+      players_online: stat.num_players as u32,
+      players_max: stat.max_players as u32,
+      names: stat.players,
+      uuids: HashMap::new(),
+      editions: None,
+      version: Some(stat.version),
+      motd: Some(plain_motd(&stat.motd)),
+      fetched_at: Instant::now(),
+    })
+  }
+
+  /// The players from RCON `list`, for servers that answer neither the ping nor the query
+  async fn list_java_players(&self, server: &Server) -> Option<ServerSnapshot> {
+    let output = time::timeout(
+      STATUS_PROBE_TIMEOUT,
+      self.run_rcon_command_on(server, "list"),
+    )
+    .await
+    .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "timed out")))
+    .inspect_err(|error| {
+      debug!(
+        "Couldn't list the players of {}. Reason: {error}",
+        server.config.name
+      )
+    })
+    .ok()?;
+    let player_list = parse_list_output(&output)?;
+
+    Some(ServerSnapshot {
+      online: true,
+      players_online: player_list.online,
+      players_max: player_list.max,
+      names: player_list.names,
+      ..ServerSnapshot::offline()
+    })
+  }
+
+  /// Shows the latest status of the server
+  pub(super) async fn show_status(&self) -> CreateInteractionResponseMessage {
+    crate::commands::status(
+      self.status.latest_snapshot.lock().unwrap().as_ref(),
+      &self.messages,
+    )
+    .into()
+  }
+
+  /// Pings the main server right now, timing how long it takes to answer
+  pub(super) async fn server_info(&self) -> Reply {
+    let server = self.main_server();
+    let (host, port) = server.query_address().await;
+
+    let started_at = Instant::now();
+    let status = time::timeout(STATUS_PROBE_TIMEOUT, mc_query::status(&host, port))
+      .await
+      .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "timed out")));
+    let latency = started_at.elapsed();
+
+    let info = match status {
+      Ok(status) => Some(ServerInfo {
+        version: status.version.name.clone(),
+        protocol: status.version.protocol,
+        motd: motd_text(&status.motd),
+        players_online: status.players.online,
+        players_max: status.players.max,
+        latency,
+        favicon: status.favicon.as_deref().and_then(decode_favicon),
+      }),
+      Err(error) => {
+        debug!(
+          "Couldn't ping {} for /server-info: {error}",
+          server.config.name
+        );
+        None
+      }
+    };
+    let last_contact = self
+      .status
+      .last_contact
+      .lock()
+      .unwrap()
+      .map(|last_contact| last_contact.timestamp());
+
+    crate::commands::server_info(&server.config.address, info, last_contact, &self.messages)
+  }
+
+  /// Lists the online players from the latest status, or a fresh one if asked for
+  pub(super) async fn players(
+    &self,
+    ctx: &Context,
+    command: &CommandInteraction,
+  ) -> CreateInteractionResponseMessage {
+    let fresh = command
+      .data
+      .options
+      .first()
+      .and_then(|option| option.value.as_bool())
+      .unwrap_or(false);
+
+    if fresh {
+      self.poll_server(ctx).await;
+    }
+
+    crate::commands::players(
+      self.status.latest_snapshot.lock().unwrap().as_ref(),
+      &self.messages,
+    )
+    .into()
+  }
+
+  /// Checks the server right now. Servers that hide their player sample are asked over RCON
+  pub(super) async fn online(&self, ctx: &Context) -> Reply {
+    self.poll_server(ctx).await;
+    let mut snapshot = self.status.latest();
+
+    if let Some(snapshot) = snapshot
+      .as_mut()
+      .filter(|snapshot| snapshot.online && snapshot.names.len() < snapshot.players_online as usize)
+    {
+      match self.run_rcon_command("list").await {
+        Ok(output) => {
+          if let Some(player_list) = parse_list_output(&output) {
+            snapshot.names = player_list.names;
+          }
+        }
+        Err(err) => warn!("Couldn't list the online players: {err}"),
+      }
+    }
+
+    crate::commands::players(snapshot.as_ref(), &self.messages)
+  }
+
+  /// Overrides what the status loop thinks the server status is, for testing
+  pub(super) async fn force_status(&self, ctx: &Context, command: &CommandInteraction) -> String {
+    if !is_admin(command) {
+      return "Only administrators can use this command.".to_string();
+    }
+
+    let forced_status = match command.data.options.first().map(|option| &option.value) {
+      Some(CommandDataOptionValue::String(status)) if status == "online" => Some(true),
+      Some(CommandDataOptionValue::String(status)) if status == "offline" => Some(false),
+      _ => None,
+    };
+
+    *self.status.forced.lock().unwrap() = forced_status;
+    info!(
+      "{} forced the server status to {forced_status:?}",
+      command.user.name
+    );
+
+    self.poll_server(ctx).await;
+
+    match forced_status {
+      Some(true) => "The server is now treated as online until you run `/force-status auto`.",
+      Some(false) => "The server is now treated as offline until you run `/force-status auto`.",
+      None => "The server status is polled for real again.",
+    }
+    .to_string()
+  }
+
+  /// Re-applies the presence and the status channel name from the latest known status,
+  /// without waiting for the next tick
+  pub(super) async fn resync_discord(&self, ctx: &Context, command: &CommandInteraction) -> String {
+    if !is_admin(command) {
+      return "Only administrators can use this command.".to_string();
+    }
+
+    let Some(snapshot) = self.status.latest() else {
+      return "The server status hasn't been checked yet. Try again in a moment.".to_string();
+    };
+
+    let status_channel = match self.status.channel_id {
+      // Skip the cache, it might be just as stale as the channel name
+      Some(status_channel_id) => match ctx
+        .http
+        .get_channel(ChannelId::new(status_channel_id))
+        .await
+      {
+        Ok(channel) => channel.guild(),
+        Err(error) => {
+          warn!("Couldn't fetch the status channel: {error}");
+          None
+        }
+      },
+      None => None,
+    };
+
+    if self.status.channel_id.is_some() && status_channel.is_none() {
+      return "Couldn't fetch the status channel from Discord. Try again later.".to_string();
+    }
+
+    self
+      .apply_snapshot(ctx, &snapshot, true, status_channel)
+      .await;
+    info!(
+      "{} resynced the presence and status channel",
+      command.user.name
+    );
+
+    format!(
+      "Re-applied the presence and channel name: {}",
+      snapshot.channel_name(&self.status.format)
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::bot::tests::test_handler;
+
+  #[test]
+  fn motd_text_joins_the_components() {
+    let motd: ChatObject =
+      serde_json::from_str(r#"{"text": "§aWelcome ", "extra": [{"text": "to the "}, "server"]}"#)
+        .unwrap();
+
+    assert_eq!(motd_text(&motd), "Welcome to the server");
+  }
+
+  fn snapshot(online: bool, players_online: u32) -> ServerSnapshot {
+    ServerSnapshot {
+      online,
+      players_online,
+      players_max: 20,
+      ..ServerSnapshot::offline()
+    }
+  }
+
+  #[tokio::test]
+  async fn the_tick_follows_the_presence_interval() {
+    let vars = [
+      ("STATUS_INTERVAL_SECONDS", "300"),
+      ("PRESENCE_INTERVAL_SECONDS", "60"),
+    ];
+    let status = test_handler("tick_interval", &vars).await.status;
+    assert_eq!(status.tick_interval(), Duration::from_secs(60));
+
+    let vars = [
+      ("STATUS_INTERVAL_SECONDS", "300"),
+      ("PRESENCE_INTERVAL_SECONDS", "60"),
+      ("UPDATE_PRESENCE", "false"),
+    ];
+    let status = test_handler("tick_interval_no_presence", &vars)
+      .await
+      .status;
+    assert_eq!(status.tick_interval(), Duration::from_secs(300));
+  }
+
+  #[tokio::test]
+  async fn a_forced_status_keeps_the_player_limit() {
+    let status = test_handler("forced_status", &[]).await.status;
+    assert!(status.forced_snapshot().is_none());
+
+    status.record(&snapshot(true, 3));
+    *status.forced.lock().unwrap() = Some(false);
+
+    let forced = status.forced_snapshot().unwrap();
+    assert!(!forced.online);
+    assert_eq!(forced.players_online, 0);
+    assert_eq!(forced.players_max, 20);
+    assert!(status.is_forced());
+  }
+
+  #[tokio::test]
+  async fn only_the_displayed_count_is_smoothed() {
+    let status = test_handler("smoothing", &[("STATUS_SMOOTHING_SAMPLES", "3")])
+      .await
+      .status;
+
+    assert_eq!(status.record(&snapshot(true, 5)).0, None);
+    status.record(&snapshot(true, 6));
+    let (was_online, displayed) = status.record(&snapshot(true, 30));
+
+    assert_eq!(was_online, Some(true));
+    assert_eq!(displayed.players_online, 6);
+    assert_eq!(status.latest().unwrap().players_online, 30);
+    assert_eq!(status.record(&snapshot(false, 0)).0, Some(true));
+  }
+
+  #[tokio::test]
+  async fn offline_since_is_kept_until_the_server_is_back() {
+    let status = test_handler("offline_since", &[]).await.status;
+    let went_down = Utc::now();
+
+    assert_eq!(status.offline_since(true, went_down), None);
+    assert_eq!(status.offline_since(false, went_down), Some(went_down));
+    assert_eq!(
+      status.offline_since(false, went_down + chrono::TimeDelta::new(300, 0).unwrap()),
+      Some(went_down)
+    );
+    assert_eq!(status.offline_since(true, went_down), None);
+  }
+
+  #[tokio::test]
+  async fn the_channel_is_renamed_once_per_interval() {
+    let status = test_handler("rename_due", &[("DISCORD_STATUS_CHANNEL_ID", "2")])
+      .await
+      .status;
+
+    assert!(status.rename_due());
+    assert_eq!(status.renamed_channel_id(), Some(2));
+
+    *status.last_rename.lock().unwrap() = Some(Instant::now());
+    assert!(!status.rename_due());
+
+    status.renames_disabled.store(true, Ordering::SeqCst);
+    assert_eq!(status.renamed_channel_id(), None);
+  }
+}
@@ -0,0 +1,1467 @@
+//! Verifying members: `/verify` and its challenge, the human gate, web verification and what
+//! happens after an account is whitelisted, like role commands and the welcome DM
+
+use super::*;
+
+/// How long a verification can wait for a permit before the user is told they're queued
+const VERIFY_QUEUE_NOTICE_AFTER: Duration = Duration::from_secs(1);
+
+const LINK_CODE_LENGTH: usize = 6;
+
+/// How long a web verification link stays valid
+const WEB_VERIFY_TOKEN_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// How long the user has to click the human gate button
+const HUMAN_GATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// How long an in-game verification code can be confirmed
+const VERIFY_CHALLENGE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// The part of the day during which `/verify` is allowed
+#[derive(Debug)]
+pub struct VerifyHours {
+  start: NaiveTime,
+  end: NaiveTime,
+  offset: FixedOffset,
+}
+
+impl VerifyHours {
+  /// Parses a range like "09:00-23:00". The end can be before the start to span midnight
+  pub fn parse(hours: &str, offset: FixedOffset) -> Option<Self> {
+    let (start, end) = hours.split_once('-')?;
+
+    Some(Self {
+      start: NaiveTime::parse_from_str(start.trim(), "%H:%M").ok()?,
+      end: NaiveTime::parse_from_str(end.trim(), "%H:%M").ok()?,
+      offset,
+    })
+  }
+
+  fn contains(&self, now: DateTime<Utc>) -> bool {
+    let time = now.with_timezone(&self.offset).time();
+
+    if self.start <= self.end {
+      self.start <= time && time < self.end
+    } else {
+      self.start <= time || time < self.end
+    }
+  }
+
+  /// Formats a time like "9am" or "9:30pm"
+  fn format_time(time: NaiveTime) -> String {
+    let (is_pm, hour) = time.hour12();
+    let suffix = if is_pm { "pm" } else { "am" };
+
+    match time.minute() {
+      0 => format!("{hour}{suffix}"),
+      minute => format!("{hour}:{minute:02}{suffix}"),
+    }
+  }
+
+  pub(super) fn describe(&self) -> String {
+    format!(
+      "between {} and {}",
+      Self::format_time(self.start),
+      Self::format_time(self.end)
+    )
+  }
+}
+
+/// A code sent to the player in-game, which `/confirm` has to repeat before they're verified
+pub(super) struct VerifyChallenge {
+  pub(super) username: String,
+  pub(super) server_name: String,
+  pub(super) code: String,
+  pub(super) expires_at: Instant,
+}
+
+/// Tracks failed `/verify` attempts, so a user can't spam RCON and the Mojang API
+pub(super) struct AttemptLimiter {
+  /// At least 1, the config rejects 0
+  pub(super) max_failures: usize,
+  pub(super) window: Duration,
+  failures: Mutex<HashMap<UserId, Vec<Instant>>>,
+}
+
+impl AttemptLimiter {
+  pub(super) fn new(max_failures: usize, window: Duration) -> Self {
+    Self {
+      max_failures,
+      window,
+      failures: Mutex::new(HashMap::new()),
+    }
+  }
+
+  /// How long the user has to wait before trying again, `None` if they can try right away
+  fn retry_after(&self, user_id: UserId) -> Option<Duration> {
+    self.retry_after_at(user_id, Instant::now())
+  }
+
+  fn retry_after_at(&self, user_id: UserId, now: Instant) -> Option<Duration> {
+    let failures = self.failures.lock().unwrap();
+    let recent: Vec<&Instant> = failures
+      .get(&user_id)?
+      .iter()
+      .filter(|failed_at| now.duration_since(**failed_at) < self.window)
+      .collect();
+
+    if recent.len() < self.max_failures {
+      return None;
+    }
+
+    // The user can try again once the oldest failure that still counts leaves the window
+    let oldest = recent[recent.len() - self.max_failures];
+    Some(self.window - now.duration_since(*oldest))
+  }
+
+  fn record_failure(&self, user_id: UserId) {
+    self.record_failure_at(user_id, Instant::now());
+  }
+
+  fn record_failure_at(&self, user_id: UserId, now: Instant) {
+    let mut failures = self.failures.lock().unwrap();
+    let user_failures = failures.entry(user_id).or_default();
+
+    user_failures.retain(|failed_at| now.duration_since(*failed_at) < self.window);
+    user_failures.push(now);
+  }
+
+  /// Forgets the user's failures. Returns whether any of them still counted
+  pub(super) fn clear(&self, user_id: UserId) -> bool {
+    self
+      .failures
+      .lock()
+      .unwrap()
+      .remove(&user_id)
+      .is_some_and(|failures| {
+        failures
+          .iter()
+          .any(|failed_at| failed_at.elapsed() < self.window)
+      })
+  }
+
+  /// Forgets the failures that left the window, and the users without any left
+  pub(super) fn prune(&self, now: Instant) {
+    self.failures.lock().unwrap().retain(|_, user_failures| {
+      user_failures.retain(|failed_at| now.duration_since(*failed_at) < self.window);
+      !user_failures.is_empty()
+    });
+  }
+}
+
+/// A random code like "K7Q2XM", without characters that are easy to mix up
+pub(super) fn generate_link_code() -> String {
+  const ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+  let mut rng = rand::thread_rng();
+  (0..LINK_CODE_LENGTH)
+    .map(|_| *ALPHABET.choose(&mut rng).unwrap() as char)
+    .collect()
+}
+
+/// A random token for web verification links, long enough that it can't be guessed
+fn generate_web_verify_token() -> String {
+  let bytes: [u8; 32] = rand::thread_rng().gen();
+  bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Compares secrets in constant time, so the time it takes doesn't tell how much of a guess was
+/// right
+fn same_secret(guess: &str, secret: &str) -> bool {
+  guess.as_bytes().ct_eq(secret.as_bytes()).into()
+}
+
+/// An RCON command to run when a member with the role verifies
+#[derive(Debug)]
+pub struct RoleCommand {
+  role_name: String,
+  /// `{name}` is replaced with the minecraft username
+  command: String,
+}
+
+/// Parses role commands like "Donor=lp user {name} parent add donor;VIP=lp user {name} parent add vip"
+pub fn parse_role_commands(config: &str) -> Option<Vec<RoleCommand>> {
+  config
+    .split(';')
+    .filter(|entry| !entry.trim().is_empty())
+    .map(|entry| {
+      let (role_name, command) = entry.split_once('=')?;
+
+      Some(RoleCommand {
+        role_name: role_name.trim().to_string(),
+        command: command.trim().to_string(),
+      })
+    })
+    .collect()
+}
+
+/// DM sent to users after they verify
+pub(super) struct WelcomeDm {
+  pub(super) text: String,
+  pub(super) join_address: String,
+}
+
+/// Extra RCON command to run when a member with the staff role verifies
+pub(super) struct StaffTier {
+  pub(super) role_name: String,
+  /// `{name}` is replaced with the minecraft username
+  pub(super) command: String,
+}
+
+/// What `/verify` does when the Mojang API can't be reached at all
+#[derive(Debug, PartialEq)]
+pub enum MojangOutagePolicy {
+  /// Tell the user to try again
+  Reject,
+  /// Keep the verification and retry it once Mojang is back
+  Queue,
+  /// Whitelist the typed name anyway, and check it once Mojang is back
+  Trust,
+}
+
+impl MojangOutagePolicy {
+  pub fn parse(policy: &str) -> Option<Self> {
+    match policy {
+      "reject" => Some(Self::Reject),
+      "queue" => Some(Self::Queue),
+      "trust" => Some(Self::Trust),
+      _ => None,
+    }
+  }
+}
+
+impl Handler {
+  /// Forgets the codes and human gates that ran out at `now`, and the failed attempts that don't
+  /// count anymore
+  pub(super) fn forget_expired_verifications(&self, now: Instant) {
+    self
+      .verify_challenges
+      .lock()
+      .unwrap()
+      .retain(|_, challenge| challenge.expires_at > now);
+    self
+      .human_gates
+      .lock()
+      .unwrap()
+      .retain(|_, (_, _, shown_at)| now.duration_since(*shown_at) < HUMAN_GATE_WINDOW);
+    self.verify_attempts.prune(now);
+  }
+
+  /// Warns the user when the account only took the name recently, since then the name most likely
+  /// changed hands and the user might be whitelisting someone else's account
+  async fn name_history_note(&self, uuid: &str, name: &str) -> Option<String> {
+    let url_template = self
+      .name_history_url
+      .as_ref()
+      .filter(|_| !self.offline_mode)?;
+
+    let Some(history) = get_name_history(&self.http, url_template, uuid).await else {
+      warn!("Couldn't fetch the name history of '{name}', skipping the check");
+      return None;
+    };
+
+    let changed_to_at = history
+      .iter()
+      .filter(|entry| entry.name.eq_ignore_ascii_case(name))
+      .filter_map(|entry| entry.changed_to_at)
+      .max()?;
+
+    let days_ago = (Utc::now() - DateTime::from_timestamp_millis(changed_to_at)?).num_days();
+
+    if days_ago > self.name_recently_freed_days {
+      return None;
+    }
+
+    Some(self.messages.render(
+      MessageKey::NameRecentlyChanged,
+      &[("name", &name), ("days", &days_ago)],
+    ))
+  }
+
+  /// The role commands for the roles the member has, ready to run
+  fn matching_role_commands(
+    &self,
+    guild: &GuildSnapshot,
+    member: &Member,
+    name: &str,
+  ) -> Vec<String> {
+    self
+      .role_commands
+      .iter()
+      .filter(|role_command| {
+        guild
+          .role_by_name(&role_command.role_name)
+          .is_some_and(|role| member.roles.contains(&role.id))
+      })
+      .map(|role_command| role_command.command.replace("{name}", name))
+      .collect()
+  }
+
+  /// Runs the role commands on top of the whitelist. This is best-effort, a failure doesn't undo
+  /// the verification. Returns a note for the response if there was anything to run
+  async fn run_role_commands(
+    &self,
+    rcon_client: &mut dyn Rcon,
+    role_commands: &[String],
+    name: &str,
+  ) -> Option<String> {
+    if role_commands.is_empty() {
+      return None;
+    }
+
+    let mut failed = false;
+    for role_command in role_commands {
+      match rcon_client.run_command(role_command).await {
+        Ok(_) => info!("Ran a role command for '{name}': {role_command}"),
+        Err(error) => {
+          warn!("Couldn't run a role command for '{name}' ({role_command}): {error}");
+          failed = true;
+        }
+      }
+    }
+
+    Some(if failed {
+      self.messages.text(MessageKey::RolePerksFailed)
+    } else {
+      self.messages.text(MessageKey::RolePerksDone)
+    })
+  }
+
+  /// DMs the welcome message with the rules and the join address.
+  /// Returns a note for the response if the DM couldn't be sent
+  async fn send_welcome_dm(&self, ctx: &Context, user: &User, name: &str) -> Option<String> {
+    let welcome_dm = self.welcome_dm.as_ref()?;
+
+    let embed = CreateEmbed::new()
+      .title(
+        self
+          .messages
+          .render(MessageKey::WelcomeTitle, &[("name", &name)]),
+      )
+      .description(&welcome_dm.text)
+      .field(
+        self.messages.text(MessageKey::WelcomeAddressField),
+        &welcome_dm.join_address,
+        false,
+      )
+      .colour(Colour::DARK_GREEN);
+
+    match user
+      .direct_message(ctx, CreateMessage::new().embed(embed))
+      .await
+    {
+      Ok(_) => None,
+      Err(error) => {
+        warn!("Couldn't send the welcome DM to {}: {error}", user.name);
+        Some(self.messages.render(
+          MessageKey::WelcomeDmFailed,
+          &[("address", &welcome_dm.join_address)],
+        ))
+      }
+    }
+  }
+
+  /// Warns the user that they won't be able to join right away, if the server was full at the
+  /// last status check
+  fn full_server_note(&self) -> Option<String> {
+    if !self.full_server_note {
+      return None;
+    }
+
+    let is_full = self
+      .status
+      .latest_snapshot
+      .lock()
+      .unwrap()
+      .as_ref()
+      .is_some_and(|snapshot| {
+        snapshot.online
+          && snapshot.players_max > 0
+          && snapshot.players_online >= snapshot.players_max
+      });
+
+    is_full.then(|| self.messages.text(MessageKey::FullServer))
+  }
+
+  /// Runs the teleport command if the player is online right now.
+  /// Returns a note for the response if it's configured
+  async fn teleport_after_verify(&self, rcon_client: &mut dyn Rcon, name: &str) -> Option<String> {
+    let teleport_command = self.teleport_command.as_ref()?;
+
+    let player_list = match rcon_client.run_command("list").await {
+      Ok(output) => parse_list_output(&output),
+      Err(error) => {
+        warn!("Couldn't list the online players: {error}");
+        None
+      }
+    };
+
+    let is_online = player_list.is_some_and(|player_list| {
+      player_list
+        .names
+        .iter()
+        .any(|online_name| online_name.eq_ignore_ascii_case(name))
+    });
+
+    if !is_online {
+      return Some(self.messages.text(MessageKey::TeleportNotOnline));
+    }
+
+    let teleport_command = teleport_command.replace("{name}", name);
+
+    match rcon_client.run_command(&teleport_command).await {
+      Ok(_) => {
+        info!("Teleported '{name}' after verifying: {teleport_command}");
+        Some(self.messages.text(MessageKey::TeleportDone))
+      }
+      Err(error) => {
+        warn!("Couldn't teleport '{name}' after verifying: {error}");
+        Some(self.messages.text(MessageKey::TeleportFailed))
+      }
+    }
+  }
+
+  /// Runs the staff command for members with the staff role. This is best-effort, a failure
+  /// doesn't undo the verification. Returns a note for the response if anything was attempted
+  async fn apply_staff_tier(
+    &self,
+    rcon_client: &mut dyn Rcon,
+    guild: &GuildSnapshot,
+    member: &Member,
+    name: &str,
+  ) -> Option<String> {
+    let staff_tier = self.staff_tier.as_ref()?;
+    let staff_role = guild.role_by_name(&staff_tier.role_name)?;
+
+    if !member.roles.contains(&staff_role.id) {
+      return None;
+    }
+
+    let staff_command = staff_tier.command.replace("{name}", name);
+
+    match rcon_client.run_command(&staff_command).await {
+      Ok(_) => {
+        info!("Ran the staff command for '{name}': {staff_command}");
+        Some(self.messages.text(MessageKey::StaffPermissionsDone))
+      }
+      Err(error) => {
+        warn!("Couldn't run the staff command for '{name}': {error}");
+        Some(self.messages.text(MessageKey::StaffPermissionsFailed))
+      }
+    }
+  }
+
+  /// Waits for a verification permit before verifying, telling the user if it takes a while.
+  /// `token` is the interaction's, to tell the user they're queued
+  async fn verify_queued(
+    &self,
+    ctx: &Context,
+    token: &str,
+    member: &Member,
+    username: &str,
+    server: &Server,
+  ) -> String {
+    if let Some(message) = self.too_many_attempts(member.user.id) {
+      return message;
+    }
+
+    let queued_at = Instant::now();
+
+    let permit =
+      match time::timeout(VERIFY_QUEUE_NOTICE_AFTER, self.verify_permits.acquire()).await {
+        Ok(permit) => permit,
+        Err(_) => {
+          let edit_response = |content| {
+            EditInteractionResponse::new()
+              .content(content)
+              .execute(ctx, token)
+          };
+
+          if let Err(error) = edit_response(self.messages.text(MessageKey::VerifyInQueue)).await {
+            warn!("Couldn't tell a user they're queued: {error}");
+          }
+
+          let permit = self.verify_permits.acquire().await;
+
+          info!(
+            "{} waited {:?} in the verification queue",
+            member.user.name,
+            queued_at.elapsed()
+          );
+          if let Err(error) = edit_response(self.messages.text(MessageKey::VerifyQueueTurn)).await {
+            warn!("Couldn't tell a user they're no longer queued: {error}");
+          }
+
+          permit
+        }
+      }
+      .expect("The verification semaphore is never closed");
+
+    // A verification that's cut short could leave the account whitelisted but not linked, so it
+    // always finishes. The user is only told that it takes a while
+    let mut verification = std::pin::pin!(self.verify(ctx, member, username, server));
+    let content = match time::timeout(COMMAND_TIMEOUT, &mut verification).await {
+      Ok(content) => content,
+      Err(_) => {
+        warn!(
+          "Verifying '{username}' for {} is taking longer than {COMMAND_TIMEOUT:?}",
+          member.user.name
+        );
+        let notice = if self.offline_mode {
+          self.messages.text(MessageKey::VerifyTimedOutOfflineMode)
+        } else {
+          self.messages.text(MessageKey::VerifyTimedOut)
+        };
+        if let Err(error) = EditInteractionResponse::new()
+          .content(notice)
+          .execute(ctx, token)
+          .await
+        {
+          warn!("Couldn't tell a user their verification is taking long: {error}");
+        }
+
+        verification.await
+      }
+    };
+    drop(permit);
+
+    content
+  }
+
+  /// The answer for a user who failed too often recently, `None` if they can try again
+  fn too_many_attempts(&self, user_id: UserId) -> Option<String> {
+    let retry_after = self.verify_attempts.retry_after(user_id)?;
+    Some(self.messages.render(
+      MessageKey::VerifyTooManyAttempts,
+      &[("minutes", &retry_after.as_secs().div_ceil(60))],
+    ))
+  }
+
+  async fn verify(
+    &self,
+    ctx: &Context,
+    member: &Member,
+    username: &str,
+    server: &Server,
+  ) -> String {
+    if let Some(verify_hours) = &self.verify_hours {
+      if !verify_hours.contains(Utc::now()) {
+        return self.messages.render(
+          MessageKey::VerifyHours,
+          &[("hours", &verify_hours.describe())],
+        );
+      }
+    }
+
+    self.verify_member(ctx, member, username, server).await
+  }
+
+  /// Verifies the member's minecraft account, for `/verify` and for verifications queued during a
+  /// Mojang outage. Returns the response for the member
+  async fn verify_member(
+    &self,
+    ctx: &Context,
+    member: &Member,
+    username: &str,
+    server: &Server,
+  ) -> String {
+    let Some(guild) = self.guild_snapshot_of(member.guild_id) else {
+      return self.messages.text(MessageKey::BotStarting);
+    };
+
+    let verified_role = match self.verified_role(&guild).cloned() {
+      Some(verified_role) => verified_role,
+      // Deleted while the bot was running
+      None => match self.create_verified_role(ctx, &guild).await {
+        Ok(verified_role) => verified_role,
+        Err(error) => {
+          self
+            .report_error(&format!(
+              "Can't verify anyone, the Verified role is missing and couldn't be created: {error}"
+            ))
+            .await;
+          return self.messages.text(MessageKey::VerifiedRoleMissing);
+        }
+      },
+    };
+
+    let server_key = self.server_key(server);
+    let request = VerifyRequest {
+      username,
+      discord_id: member.user.id.get(),
+      // The role can be handed out or removed by hand, the links are what the bot verified itself
+      linked_accounts: self.store.read(|data| {
+        data
+          .links
+          .by_discord_id(member.user.id.get())
+          .filter(|link| link.server == server_key)
+          .count()
+          + data
+            .unvalidated
+            .iter()
+            .filter(|verification| {
+              verification.discord_id == member.user.id.get() && verification.server == server_key
+            })
+            .count()
+      }),
+      max_accounts: self.max_accounts_per_user,
+      check_banlist: self.check_banlist,
+      // Trusted names are checked with Mojang later, which doesn't know gamertags
+      trust_on_mojang_outage: self.mojang_outage_policy == MojangOutagePolicy::Trust
+        && !self.is_bedrock_name(username),
+    };
+
+    let replace_whitelist = self.role_commands_replace_whitelist;
+    Metrics::count(&METRICS.verifications_attempted);
+    let connector = ServerConnector {
+      handler: self,
+      server,
+    };
+    let outcome = crate::commands::verify_account(
+      request,
+      self.profiles_for(username),
+      &connector,
+      |uuid| {
+        self
+          .store
+          .read(|data| data.links.by_uuid(uuid).map(|link| link.discord_id))
+      },
+      |name| {
+        // The role commands either grant access themselves, or come on top of the whitelist
+        let role_commands = self.matching_role_commands(&guild, member, name);
+        if replace_whitelist && !role_commands.is_empty() {
+          role_commands
+        } else if self.is_unclaimed(name, server_key.as_deref()) {
+          // Imported from the whitelist, so it's on it already
+          Vec::new()
+        } else {
+          vec![self.whitelist_add_command(name)]
+        }
+      },
+    )
+    .await;
+    match outcome {
+      // The Geyser API being down isn't Mojang's
+      _ if self.is_bedrock_name(username) => {}
+      VerifyOutcome::MojangUnavailable => self.operation_failed(
+        Operation::Mojang,
+        &"Mojang couldn't be reached to verify someone",
+      ),
+      VerifyOutcome::Whitelisted { .. } => self.operation_succeeded(&Operation::Mojang),
+      _ => {}
+    }
+
+    let VerifyOutcome::Whitelisted {
+      uuid: id,
+      name,
+      already_whitelisted,
+      mut rcon,
+    } = outcome
+    else {
+      let mut event = AccountEvent {
+        action: AccountAction::Verify,
+        discord_id: member.user.id,
+        minecraft_name: username,
+        uuid: None,
+        outcome: AccountOutcome::Queued,
+        admin: None,
+        reason: None,
+      };
+      if matches!(outcome, VerifyOutcome::MojangUnavailable)
+        && self.mojang_outage_policy == MojangOutagePolicy::Queue
+      {
+        self.notify_account_event(ctx, event).await;
+        return self.queue_verification(member.user.id, username, server_key);
+      }
+
+      Metrics::count(&METRICS.verifications_failed);
+      if outcome.is_failed_attempt() {
+        self.verify_attempts.record_failure(member.user.id);
+      }
+      let failure_message = outcome.failure_message(username, &self.messages);
+      event.outcome = AccountOutcome::Failed(failure_message.clone());
+      self.notify_account_event(ctx, event).await;
+      return failure_message;
+    };
+
+    let role_commands = self.matching_role_commands(&guild, member, &name);
+    let role_commands_note = if replace_whitelist && !role_commands.is_empty() {
+      None
+    } else {
+      self
+        .run_role_commands(rcon.as_mut(), &role_commands, &name)
+        .await
+    };
+
+    let staff_note = self
+      .apply_staff_tier(rcon.as_mut(), &guild, member, &name)
+      .await;
+    let teleport_note = self.teleport_after_verify(rcon.as_mut(), &name).await;
+    let name_history_note = match &id {
+      Some(id) if !self.is_bedrock_name(&name) => self.name_history_note(id, &name).await,
+      _ => None,
+    };
+
+    let claimed = self.claim_import(&name, server_key.as_deref());
+    if claimed {
+      info!("{} claimed the imported '{name}'", member.user.name);
+    }
+
+    let response = match self
+      .add_role_with_retry(ctx, member, verified_role.id)
+      .await
+    {
+      Ok(()) if already_whitelisted || claimed => self
+        .messages
+        .render(MessageKey::VerifyAlreadyWhitelisted, &[("name", &name)]),
+      Ok(()) => self
+        .messages
+        .render(MessageKey::VerifySuccess, &[("name", &name)]),
+      Err(error) if is_missing_permissions(&error) => {
+        warn!("Couldn't add the Verified role, missing permissions: {error}");
+        self
+          .messages
+          .render(MessageKey::VerifyRoleMissingPermission, &[("name", &name)])
+      }
+      Err(error) => {
+        warn!("Couldn't add the Verified role: {error}");
+        self
+          .messages
+          .render(MessageKey::VerifyRoleFailed, &[("name", &name)])
+      }
+    };
+
+    // A name typed during a Mojang outage might not have the right capitalization
+    let nickname_note = match &id {
+      Some(_) => {
+        self
+          .sync_member_nickname(ctx, guild.id, member.user.id, &name)
+          .await
+      }
+      None => None,
+    };
+
+    self.verify_attempts.clear(member.user.id);
+    let today = self.today();
+    self.store.write(|data| {
+      data.verifications.push(Utc::now().timestamp());
+      data.record_verification(today);
+    });
+    Metrics::count(&METRICS.verifications_succeeded);
+
+    let unvalidated_note = match &id {
+      Some(id) => {
+        self
+          .link_account(ctx, member.user.id, id, &name, server_key.clone())
+          .await;
+        None
+      }
+      None => {
+        info!("Whitelisted '{name}' without checking it, the Mojang API is down");
+        self.store.write(|data| {
+          data.unvalidated.push(UnvalidatedVerification {
+            discord_id: member.user.id.get(),
+            minecraft_name: name.clone(),
+            verified_at: Utc::now().timestamp(),
+            server: server_key.clone(),
+          })
+        });
+        Some(self.messages.text(MessageKey::VerifyUnvalidated))
+      }
+    };
+    self
+      .notify_account_event(
+        ctx,
+        AccountEvent {
+          action: AccountAction::Verify,
+          discord_id: member.user.id,
+          minecraft_name: &name,
+          uuid: id.as_deref(),
+          outcome: AccountOutcome::Done,
+          admin: None,
+          reason: None,
+        },
+      )
+      .await;
+
+    [
+      Some(response),
+      nickname_note,
+      unvalidated_note,
+      role_commands_note,
+      staff_note,
+      teleport_note,
+      name_history_note,
+      self.full_server_note(),
+      self.send_welcome_dm(ctx, &member.user, &name).await,
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>()
+    .join("\n")
+  }
+
+  /// Verifies right away, or sends a code in-game first if VERIFY_CHALLENGE is on
+  async fn begin_verification(
+    &self,
+    ctx: &Context,
+    token: &str,
+    member: &Member,
+    username: &str,
+    server: &Server,
+  ) -> String {
+    if self.verify_challenge {
+      self.send_verify_challenge(member, username, server).await
+    } else {
+      self
+        .verify_queued(ctx, token, member, username, server)
+        .await
+    }
+  }
+
+  /// Checks the username as the member typed it and verifies it, for `/verify` and the form of
+  /// the verify button. `token` is the interaction's
+  pub(super) async fn verify_typed_username(
+    &self,
+    ctx: &Context,
+    token: &str,
+    member: &Member,
+    username: &str,
+    server: &Server,
+  ) -> String {
+    let username = crate::commands::clean_username(username);
+    if let Err(error) = self.validate_name(username) {
+      return error.message(username, &self.messages);
+    }
+
+    self
+      .begin_verification(ctx, token, member, username, server)
+      .await
+  }
+
+  /// Messages a code to the player, so only someone who can play on the account can verify it. A
+  /// user has one code at a time, asking again replaces it
+  async fn send_verify_challenge(
+    &self,
+    member: &Member,
+    username: &str,
+    server: &Server,
+  ) -> String {
+    if let Some(retry_after) = self.verify_attempts.retry_after(member.user.id) {
+      return self.messages.render(
+        MessageKey::VerifyTooManyAttempts,
+        &[("minutes", &retry_after.as_secs().div_ceil(60))],
+      );
+    }
+
+    let player_list = match self.run_rcon_command_on(server, "list").await {
+      Ok(output) => parse_list_output(&output),
+      Err(err) => {
+        warn!("Couldn't list the players to send a verification code: {err}");
+        return VerifyOutcome::connect_failure(&err).failure_message(username, &self.messages);
+      }
+    };
+    let Some(name) = player_list.and_then(|player_list| {
+      player_list
+        .names
+        .into_iter()
+        .find(|name| name.eq_ignore_ascii_case(username))
+    }) else {
+      return self
+        .messages
+        .render(MessageKey::ChallengeNotOnline, &[("username", &username)]);
+    };
+
+    let code = generate_link_code();
+    let message = self.messages.render(
+      MessageKey::ChallengeInGame,
+      &[
+        ("code", &code),
+        ("minutes", &(VERIFY_CHALLENGE_TTL.as_secs() / 60)),
+      ],
+    );
+    if let Err(err) = self
+      .run_rcon_command_on(server, &format!("msg {name} {message}"))
+      .await
+    {
+      warn!("Couldn't send the verification code to '{name}': {err}");
+      return VerifyOutcome::connect_failure(&err).failure_message(username, &self.messages);
+    }
+
+    self.verify_challenges.lock().unwrap().insert(
+      member.user.id,
+      VerifyChallenge {
+        username: name.clone(),
+        server_name: server.config.name.clone(),
+        code,
+        expires_at: Instant::now() + VERIFY_CHALLENGE_TTL,
+      },
+    );
+    info!(
+      "Sent a verification code to '{name}' for {}",
+      member.user.name
+    );
+
+    self.messages.render(
+      MessageKey::ChallengeSent,
+      &[
+        ("name", &name),
+        ("minutes", &(VERIFY_CHALLENGE_TTL.as_secs() / 60)),
+      ],
+    )
+  }
+
+  /// Finishes the verification the code was sent for. Wrong codes count as failed attempts, so
+  /// the code can't be guessed
+  pub(super) async fn confirm_verification(
+    &self,
+    ctx: &Context,
+    command: &CommandInteraction,
+    member: &Member,
+    code: &str,
+  ) -> String {
+    if let Some(retry_after) = self.verify_attempts.retry_after(member.user.id) {
+      return self.messages.render(
+        MessageKey::VerifyTooManyAttempts,
+        &[("minutes", &retry_after.as_secs().div_ceil(60))],
+      );
+    }
+
+    let challenge = {
+      let mut challenges = self.verify_challenges.lock().unwrap();
+      match challenges.get(&member.user.id) {
+        None => return self.messages.text(MessageKey::ConfirmNoCode),
+        Some(challenge) if Instant::now() >= challenge.expires_at => {
+          challenges.remove(&member.user.id);
+          return self.messages.text(MessageKey::ConfirmExpired);
+        }
+        Some(challenge) if !challenge.code.eq_ignore_ascii_case(code.trim()) => {
+          self.verify_attempts.record_failure(member.user.id);
+          return self.messages.text(MessageKey::ConfirmWrongCode);
+        }
+        Some(_) => challenges
+          .remove(&member.user.id)
+          .expect("The challenge was just found"),
+      }
+    };
+
+    let server = self.server_by_name(Some(&challenge.server_name));
+    self
+      .verify_queued(ctx, &command.token, member, &challenge.username, server)
+      .await
+  }
+
+  /// Asks the user to click a button before verifying, which scripts calling the command don't do
+  pub(super) async fn show_human_gate(
+    &self,
+    ctx: &Context,
+    command: &CommandInteraction,
+    username: &str,
+    server: &Server,
+  ) {
+    self.human_gates.lock().unwrap().insert(
+      command.user.id,
+      (
+        username.to_string(),
+        server.config.name.clone(),
+        Instant::now(),
+      ),
+    );
+
+    let message = CreateInteractionResponseMessage::new()
+      .content(self.messages.render(
+        MessageKey::HumanGate,
+        &[
+          ("seconds", &HUMAN_GATE_WINDOW.as_secs()),
+          ("username", &username),
+        ],
+      ))
+      .button(
+        CreateButton::new("human-gate").label(self.messages.text(MessageKey::HumanGateButton)),
+      )
+      .ephemeral(true);
+
+    if let Err(error) = command
+      .create_response(ctx, CreateInteractionResponse::Message(message))
+      .await
+    {
+      warn!("Couldn't show the human gate: {error}");
+    }
+  }
+
+  /// Continues the verification the button was shown for, if it was clicked in time
+  pub(super) async fn pass_human_gate(&self, ctx: &Context, component: &ComponentInteraction) {
+    let gate = self.human_gates.lock().unwrap().remove(&component.user.id);
+    let gate = gate.filter(|(_, _, shown_at)| shown_at.elapsed() < HUMAN_GATE_WINDOW);
+
+    let content = if gate.is_some() {
+      self.messages.text(MessageKey::HumanGateVerifying)
+    } else {
+      self.messages.text(MessageKey::HumanGateExpired)
+    };
+    let update = CreateInteractionResponseMessage::new()
+      .content(content)
+      .components(Vec::new());
+
+    if let Err(error) = component
+      .create_response(ctx, CreateInteractionResponse::UpdateMessage(update))
+      .await
+    {
+      warn!("Couldn't answer the human gate button: {error}");
+      return;
+    }
+
+    let (Some((username, server_name, _)), Some(member)) = (gate, &component.member) else {
+      return;
+    };
+
+    let server = self.server_by_name(Some(&server_name));
+    let content = self
+      .begin_verification(ctx, &component.token, member, &username, server)
+      .await;
+
+    if let Err(error) = component
+      .edit_response(ctx, EditInteractionResponse::new().content(content))
+      .await
+    {
+      warn!("Couldn't respond to a verification: {error}");
+    }
+  }
+
+  /// Issues a one-time token and returns the link to the verification page with it
+  pub(super) fn web_verify_link(&self, user_id: UserId) -> String {
+    let Some(web_verify) = &self.web_verify else {
+      return "Web verification isn't configured.".to_string();
+    };
+
+    let token = generate_web_verify_token();
+    let now = Utc::now().timestamp();
+
+    self.store.write(|data| {
+      data
+        .web_verify_tokens
+        .retain(|issued| issued.discord_id != user_id.get() && issued.expires_at > now);
+      data.web_verify_tokens.push(WebVerifyToken {
+        discord_id: user_id.get(),
+        token: token.clone(),
+        expires_at: now + WEB_VERIFY_TOKEN_TTL.as_secs() as i64,
+      });
+    });
+
+    format!(
+      "Verify your Minecraft account here within {} minutes: {}",
+      WEB_VERIFY_TOKEN_TTL.as_secs() / 60,
+      web_verify.url.replace("{token}", &token)
+    )
+  }
+
+  /// Handles `GET /callback?token=...&username=...`, authenticated with the shared secret in a
+  /// `Authorization: Bearer ...` header
+  pub(super) async fn web_verify_callback(
+    &self,
+    ctx: &Context,
+    web_verify: &WebVerifyConfig,
+    request: &web::Request,
+  ) -> (u16, String) {
+    let (user_id, username) = match self.web_verify_request(web_verify, request) {
+      Ok(verification) => verification,
+      Err(rejection) => return rejection,
+    };
+
+    if let Some(message) = self.too_many_attempts(user_id) {
+      return (429, message);
+    }
+
+    let Some(guild) = self.guild_snapshot() else {
+      return (400, "The bot is still starting up".to_string());
+    };
+
+    let member = match ctx.http.get_member(guild.id, user_id).await {
+      Ok(member) => member,
+      Err(error) => {
+        warn!("Couldn't fetch {user_id} for a web verification: {error}");
+        return (
+          400,
+          "The Discord user isn't in the server anymore".to_string(),
+        );
+      }
+    };
+
+    // Counts towards VERIFY_CONCURRENCY and holds off the shutdown, like `/verify`
+    let permit = self
+      .verify_permits
+      .acquire()
+      .await
+      .expect("The verification semaphore is never closed");
+    let response = self
+      .verify(ctx, &member, username, self.main_server())
+      .await;
+    drop(permit);
+
+    if let Err(error) = direct_message(ctx, user_id, CreateMessage::new().content(&response)).await
+    {
+      warn!("Couldn't DM {user_id} the result of their web verification: {error}");
+    }
+
+    (200, response)
+  }
+
+  /// Checks the callback and the username, then uses up the token. The username is checked like a
+  /// typed one before anything reaches the server, and before the token is used up, so the link
+  /// still works with a corrected name. Returns the user and the cleaned username
+  fn web_verify_request<'a>(
+    &self,
+    web_verify: &WebVerifyConfig,
+    request: &'a web::Request,
+  ) -> Result<(UserId, &'a str), (u16, String)> {
+    if request.path != "/callback" {
+      return Err((404, "Not found".to_string()));
+    }
+    if request.method != "GET" {
+      return Err((405, "Only GET is supported".to_string()));
+    }
+
+    let authorized = request
+      .headers
+      .get("authorization")
+      .and_then(|authorization| authorization.strip_prefix("Bearer "))
+      .is_some_and(|secret| same_secret(secret, &web_verify.secret));
+    if !authorized {
+      return Err((401, "Wrong or missing secret".to_string()));
+    }
+
+    let (Some(token), Some(username)) = (request.query.get("token"), request.query.get("username"))
+    else {
+      return Err((400, "Missing token or username".to_string()));
+    };
+
+    let username = crate::commands::clean_username(username);
+    if let Err(error) = self.validate_name(username) {
+      return Err((400, error.message(username, &self.messages)));
+    }
+
+    let now = Utc::now().timestamp();
+    let issued = self.store.write(|data| {
+      let index = data
+        .web_verify_tokens
+        .iter()
+        .position(|issued| same_secret(token, &issued.token))?;
+      Some(data.web_verify_tokens.remove(index)).filter(|issued| issued.expires_at > now)
+    });
+    let Some(issued) = issued else {
+      return Err((400, "Unknown or expired token".to_string()));
+    };
+
+    Ok((UserId::new(issued.discord_id), username))
+  }
+
+  /// Stores the link between the Discord user and the minecraft account. An account linked to
+  /// another Discord user isn't taken over, staff are told to sort it out instead
+  pub(super) async fn link_account(
+    &self,
+    ctx: &Context,
+    user_id: UserId,
+    uuid: &str,
+    name: &str,
+    server: Option<String>,
+  ) -> bool {
+    let xuid = floodgate_xuid(uuid);
+    let linked = self.store.write(|data| {
+      data.links.link(Link {
+        discord_id: user_id.get(),
+        uuid: uuid.to_string(),
+        minecraft_name: name.to_string(),
+        linked_at: Utc::now().timestamp(),
+        expiry_warned: false,
+        suspended: false,
+        banned: false,
+        server,
+        platform: if xuid.is_some() {
+          Platform::Bedrock
+        } else {
+          Platform::Java
+        },
+        xuid,
+      })
+    });
+    if let Err(linked_to) = linked {
+      self
+        .report_error(&format!(
+          "'{name}' was whitelisted for {user_id}, but it's already linked to <@{linked_to}>, so the link was kept. Check it by hand"
+        ))
+        .await;
+      return false;
+    }
+
+    for observer in &self.observers {
+      observer.on_verified(ctx, user_id, name, uuid).await;
+    }
+    true
+  }
+
+  /// Keeps the verification for when the Mojang API is back. A user only has one queued
+  /// verification, a newer one replaces it
+  fn queue_verification(&self, user_id: UserId, username: &str, server: Option<String>) -> String {
+    self.store.write(|data| {
+      data
+        .pending_verifications
+        .retain(|pending| pending.discord_id != user_id.get());
+      data.pending_verifications.push(PendingVerification {
+        discord_id: user_id.get(),
+        username: username.to_string(),
+        requested_at: Utc::now().timestamp(),
+        server,
+      });
+    });
+    info!("Queued the verification of '{username}', the Mojang API is down");
+
+    self.messages.text(MessageKey::VerifyQueuedForMojang)
+  }
+
+  pub(super) async fn retry_queued_verifications(&self, ctx: &Context) {
+    let pending_verifications = self.store.read(|data| data.pending_verifications.clone());
+    let Some(guild) = self.guild_snapshot() else {
+      return;
+    };
+
+    for pending in pending_verifications {
+      // Still down, the rest would fail too
+      match get_mojang_profile(&self.http, &pending.username).await {
+        Err(error) if error.is_transient() => {
+          self.operation_failed(Operation::Mojang, &error);
+          return;
+        }
+        _ => self.operation_succeeded(&Operation::Mojang),
+      }
+
+      self.store.write(|data| {
+        data
+          .pending_verifications
+          .retain(|queued| queued.discord_id != pending.discord_id)
+      });
+
+      let user_id = UserId::new(pending.discord_id);
+      let member = match ctx.http.get_member(guild.id, user_id).await {
+        Ok(member) => member,
+        Err(error) => {
+          warn!("Dropped the queued verification of {user_id}, couldn't fetch them: {error}");
+          continue;
+        }
+      };
+
+      let server = self.server_by_name(pending.server.as_deref());
+      let response = self
+        .verify_member(ctx, &member, &pending.username, server)
+        .await;
+
+      if let Err(error) = direct_message(ctx, user_id, CreateMessage::new().content(response)).await
+      {
+        warn!("Couldn't DM {user_id} the result of their queued verification: {error}");
+      }
+    }
+  }
+
+  pub(super) async fn validate_trusted_verifications(&self, ctx: &Context) {
+    let unvalidated = self.store.read(|data| data.unvalidated.clone());
+
+    for verification in unvalidated {
+      let name = &verification.minecraft_name;
+      let profile = match get_mojang_profile(&self.http, name).await {
+        Err(error) if error.is_transient() => {
+          self.operation_failed(Operation::Mojang, &error);
+          return;
+        }
+        profile => profile,
+      };
+      self.operation_succeeded(&Operation::Mojang);
+
+      self.store.write(|data| {
+        data.unvalidated.retain(|unvalidated| {
+          !(unvalidated.discord_id == verification.discord_id
+            && unvalidated.minecraft_name == *name)
+        })
+      });
+
+      let user_id = UserId::new(verification.discord_id);
+      match profile {
+        Ok(MojangProfile { id, name }) => {
+          if self
+            .link_account(ctx, user_id, &id, &name, verification.server.clone())
+            .await
+          {
+            info!("Validated '{name}', which was whitelisted during a Mojang outage");
+          }
+        }
+        Err(error) => {
+          self
+            .report_error(&format!(
+              "'{name}' was whitelisted for {user_id} during a Mojang outage, but Mojang doesn't know the account ({error}). Check it by hand"
+            ))
+            .await;
+        }
+      }
+    }
+  }
+
+  /// Asks for the username in a form, for members who don't use slash commands. With web
+  /// verification, the button gives the link instead
+  pub(super) async fn show_verify_form(
+    &self,
+    ctx: &Context,
+    component: &ComponentInteraction,
+    server_name: &str,
+  ) -> Result<(), BotError> {
+    let response = if self.web_verify.is_some() {
+      CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+          .content(self.web_verify_link(component.user.id))
+          .ephemeral(true),
+      )
+    } else {
+      let username = CreateInputText::new(
+        InputTextStyle::Short,
+        self.messages.text(MessageKey::VerifyFormUsername),
+        "username",
+      )
+      .required(true);
+      CreateInteractionResponse::Modal(
+        CreateModal::new(
+          format!("verify-modal:{server_name}"),
+          self.messages.text(MessageKey::VerifyFormTitle),
+        )
+        .components(vec![CreateActionRow::InputText(username)]),
+      )
+    };
+
+    component.create_response(ctx, response).await?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::bot::tests::test_handler;
+
+  #[test]
+  fn failed_attempts_lock_out_until_they_leave_the_window() {
+    let limiter = AttemptLimiter::new(2, Duration::from_secs(600));
+    let user_id = UserId::new(1);
+    let start = Instant::now();
+
+    limiter.record_failure_at(user_id, start);
+    assert_eq!(limiter.retry_after_at(user_id, start), None);
+
+    limiter.record_failure_at(user_id, start + Duration::from_secs(100));
+    assert_eq!(
+      limiter.retry_after_at(user_id, start + Duration::from_secs(100)),
+      Some(Duration::from_secs(500))
+    );
+    // The first failure left the window, one more attempt is allowed
+    assert_eq!(
+      limiter.retry_after_at(user_id, start + Duration::from_secs(600)),
+      None
+    );
+  }
+
+  #[test]
+  fn pruning_forgets_users_without_recent_failures() {
+    let limiter = AttemptLimiter::new(2, Duration::from_secs(600));
+    let start = Instant::now();
+    limiter.record_failure_at(UserId::new(1), start);
+    limiter.record_failure_at(UserId::new(2), start + Duration::from_secs(300));
+
+    limiter.prune(start + Duration::from_secs(700));
+
+    let failures = limiter.failures.lock().unwrap();
+    assert!(!failures.contains_key(&UserId::new(1)));
+    assert_eq!(failures[&UserId::new(2)].len(), 1);
+  }
+
+  #[tokio::test]
+  async fn expired_human_gates_are_forgotten() {
+    let handler = test_handler("human_gates", &[]).await;
+    let start = Instant::now();
+    let gate = |shown_at| ("Notch".to_string(), "Main".to_string(), shown_at);
+    handler.human_gates.lock().unwrap().extend([
+      (UserId::new(1), gate(start)),
+      (UserId::new(2), gate(start + HUMAN_GATE_WINDOW)),
+    ]);
+
+    handler.forget_expired_verifications(start + HUMAN_GATE_WINDOW);
+
+    let gates = handler.human_gates.lock().unwrap();
+    assert!(!gates.contains_key(&UserId::new(1)));
+    assert!(gates.contains_key(&UserId::new(2)));
+  }
+
+  #[test]
+  fn link_codes_avoid_characters_that_are_easy_to_mix_up() {
+    let code = generate_link_code();
+
+    assert_eq!(code.len(), LINK_CODE_LENGTH);
+    assert!(!code.contains(['0', 'O', '1', 'I']), "{code}");
+    assert_ne!(code, generate_link_code());
+  }
+
+  #[test]
+  fn web_verify_tokens_are_long_and_random() {
+    let token = generate_web_verify_token();
+
+    assert_eq!(token.len(), 64);
+    assert!(token.chars().all(|char| char.is_ascii_hexdigit()));
+    assert_ne!(token, generate_web_verify_token());
+  }
+
+  #[test]
+  fn secrets_are_compared_whole() {
+    assert!(same_secret("secret", "secret"));
+    assert!(!same_secret("secre", "secret"));
+    assert!(!same_secret("secret!", "secret"));
+    assert!(!same_secret("", "secret"));
+  }
+  #[tokio::test]
+  async fn invalid_web_usernames_are_rejected_before_the_token_is_used() {
+    let handler = test_handler(
+      "web_verify",
+      &[
+        ("WEB_VERIFY_URL", "https://example.com/verify?token={token}"),
+        ("WEB_VERIFY_LISTEN_ADDRESS", "127.0.0.1:0"),
+        ("WEB_VERIFY_SECRET", "secret"),
+      ],
+    )
+    .await;
+    handler.store.write(|data| {
+      data.web_verify_tokens.push(WebVerifyToken {
+        discord_id: 1,
+        token: "token".to_string(),
+        expires_at: i64::MAX,
+      })
+    });
+    let request = |username: &str| web::Request {
+      method: "GET".to_string(),
+      path: "/callback".to_string(),
+      query: [("token", "token"), ("username", username)]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect(),
+      headers: HashMap::from([("authorization".to_string(), "Bearer secret".to_string())]),
+    };
+    let web_verify = handler.web_verify.as_ref().unwrap();
+
+    for username in ["a b", "Notch;op", "x"] {
+      let (status, _) = handler
+        .web_verify_request(web_verify, &request(username))
+        .unwrap_err();
+      assert_eq!(status, 400, "{username}");
+    }
+    assert_eq!(handler.store.read(|data| data.web_verify_tokens.len()), 1);
+
+    // A valid name uses up the token, the verification itself goes on from there
+    assert_eq!(
+      handler.web_verify_request(web_verify, &request(" @Notch ")),
+      Ok((UserId::new(1), "Notch"))
+    );
+    assert!(handler.store.read(|data| data.web_verify_tokens.is_empty()));
+  }
+}
@@ -0,0 +1,158 @@
+//! The chat bridge between a Discord channel and the in-game chat. Player chat is read from the
+//! server's log, since RCON can't subscribe to chat
+
+use regex::Regex;
+use std::io::{self, SeekFrom};
+use std::path::PathBuf;
+use std::sync::LazyLock;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// Like "[12:34:56] [Server thread/INFO]: <Alice> hi", with an optional logger name on Forge and
+/// the "[Not Secure]" marker of unsigned messages
+static CHAT_LINE: LazyLock<Regex> = LazyLock::new(|| {
+  Regex::new(r"^\[[^\]]+\] \[[^\]]+/INFO\](?: \[[^\]]+\])?: (?:\[Not Secure\] )?<(\w{1,16})> (.*)$")
+    .unwrap()
+});
+
+/// A message a player sent in the in-game chat
+#[derive(Debug, PartialEq)]
+pub struct ChatLine {
+  pub player: String,
+  pub message: String,
+}
+
+pub fn parse_chat_line(line: &str) -> Option<ChatLine> {
+  let captures = CHAT_LINE.captures(line.trim_end())?;
+
+  Some(ChatLine {
+    player: captures[1].to_string(),
+    message: captures[2].to_string(),
+  })
+}
+
+/// Splits the message into parts of at most `max_chars` characters, at spaces where possible
+pub fn split_message(message: &str, max_chars: usize) -> Vec<String> {
+  let mut parts = Vec::new();
+  let mut rest = message.trim();
+
+  while rest.chars().count() > max_chars {
+    let limit = rest
+      .char_indices()
+      .nth(max_chars)
+      .map_or(rest.len(), |(index, _)| index);
+    let end = match rest[..limit].rfind(' ') {
+      Some(space) if space > 0 => space,
+      _ => limit,
+    };
+
+    parts.push(rest[..end].trim_end().to_string());
+    rest = rest[end..].trim_start();
+  }
+
+  if !rest.is_empty() {
+    parts.push(rest.to_string());
+  }
+
+  parts
+}
+
+/// Follows a log file like `tail -f`, starting at its end. A file that got shorter was rotated,
+/// then it's read from the start again
+pub struct LogTail {
+  path: PathBuf,
+  /// `None` until the first read, which skips what was logged before
+  position: Option<u64>,
+  /// The end of a line that isn't finished yet
+  partial: String,
+}
+
+impl LogTail {
+  pub fn new(path: impl Into<PathBuf>) -> Self {
+    Self {
+      path: path.into(),
+      position: None,
+      partial: String::new(),
+    }
+  }
+
+  /// The lines logged since the last read
+  pub async fn read_lines(&mut self) -> io::Result<Vec<String>> {
+    let mut file = File::open(&self.path).await?;
+    let length = file.metadata().await?.len();
+
+    let position = match self.position {
+      None => {
+        self.position = Some(length);
+        return Ok(Vec::new());
+      }
+      Some(position) if position > length => {
+        self.partial.clear();
+        0
+      }
+      Some(position) => position,
+    };
+
+    file.seek(SeekFrom::Start(position)).await?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes).await?;
+    self.position = Some(position + bytes.len() as u64);
+
+    self.partial.push_str(&String::from_utf8_lossy(&bytes));
+    let Some(last_newline) = self.partial.rfind('\n') else {
+      return Ok(Vec::new());
+    };
+
+    let lines = self.partial[..last_newline]
+      .lines()
+      .map(str::to_string)
+      .collect();
+    self.partial.drain(..=last_newline);
+
+    Ok(lines)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn chat_lines_are_parsed() {
+    let chat_line = |player: &str, message: &str| {
+      Some(ChatLine {
+        player: player.to_string(),
+        message: message.to_string(),
+      })
+    };
+
+    assert_eq!(
+      parse_chat_line("[12:34:56] [Server thread/INFO]: <Alice> hello there\r"),
+      chat_line("Alice", "hello there")
+    );
+    assert_eq!(
+      parse_chat_line("[12:34:56] [Async Chat Thread - #0/INFO]: [Not Secure] <Bob_2> hi"),
+      chat_line("Bob_2", "hi")
+    );
+    assert_eq!(
+      parse_chat_line(
+        "[12Mar2024 12:34:56.789] [Server thread/INFO] [net.minecraft.server.MinecraftServer/]: <Carl> yo"
+      ),
+      chat_line("Carl", "yo")
+    );
+    assert_eq!(
+      parse_chat_line("[12:34:56] [Server thread/INFO]: Alice joined the game"),
+      None
+    );
+  }
+
+  #[test]
+  fn long_messages_are_split_at_spaces() {
+    assert_eq!(
+      split_message("one two three four", 9),
+      vec!["one two", "three", "four"]
+    );
+    assert_eq!(split_message("abcdefgh", 3), vec!["abc", "def", "gh"]);
+    assert_eq!(split_message("  ", 3), Vec::<String>::new());
+  }
+}
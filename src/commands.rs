@@ -0,0 +1,1226 @@
+//! The logic behind the slash commands, kept apart from Serenity so it can be tested without a
+//! live connection. `interaction_create` only turns the interaction into plain inputs and the
+//! `Reply` back into a Serenity response
+
+use crate::bot::is_banned;
+use crate::messages::{MessageKey, Messages};
+use crate::mojang::{get_mojang_profile, mojang_failure_message, MojangError, MojangProfile};
+use crate::offline::offline_uuid;
+use crate::rcon::{is_unanswered, parse_whitelist_add_output, WhitelistResult};
+use crate::status::{EditionCounts, ServerSnapshot};
+use crate::store::{Link, PlayerSighting};
+use serenity::all::{
+  Colour, CreateAttachment, CreateEmbed, CreateEmbedFooter, CreateInteractionResponseMessage,
+  EditInteractionResponse,
+};
+use serenity::async_trait;
+use std::io;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// A connection that can run commands on the minecraft server
+#[async_trait]
+pub trait Rcon: Send {
+  async fn run_command(&mut self, command: &str) -> io::Result<String>;
+}
+
+/// Gives out RCON connections to the minecraft server
+#[async_trait]
+pub trait RconConnector: Sync {
+  async fn connect(&self) -> io::Result<Box<dyn Rcon + '_>>;
+}
+
+#[async_trait]
+pub trait Mojang: Sync {
+  async fn profile(&self, username: &str) -> Result<MojangProfile, MojangError>;
+}
+
+/// The real Mojang API
+pub struct MojangApi {
+  pub http: reqwest::Client,
+}
+
+#[async_trait]
+impl Mojang for MojangApi {
+  async fn profile(&self, username: &str) -> Result<MojangProfile, MojangError> {
+    get_mojang_profile(&self.http, username).await
+  }
+}
+
+/// For offline mode servers, where every name is an account with a UUID derived from it
+pub struct OfflineProfiles;
+
+#[async_trait]
+impl Mojang for OfflineProfiles {
+  async fn profile(&self, username: &str) -> Result<MojangProfile, MojangError> {
+    Ok(MojangProfile {
+      id: offline_uuid(username),
+      name: username.to_string(),
+    })
+  }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ReplyEmbed {
+  pub title: String,
+  pub description: Option<String>,
+  /// Name, value and whether it's inline
+  pub fields: Vec<(String, String, bool)>,
+  pub footer: Option<String>,
+  pub thumbnail: Option<String>,
+  pub colour: Colour,
+  /// A file sent along with the embed, by its name and contents. The embed shows it with an
+  /// `attachment://<name>` URL
+  pub attachment: Option<(String, Vec<u8>)>,
+}
+
+impl ReplyEmbed {
+  pub fn new(title: impl Into<String>, colour: Colour) -> Self {
+    Self {
+      title: title.into(),
+      description: None,
+      fields: Vec::new(),
+      footer: None,
+      thumbnail: None,
+      colour,
+      attachment: None,
+    }
+  }
+
+  pub fn field(mut self, name: &str, value: impl Into<String>, inline: bool) -> Self {
+    self.fields.push((name.to_string(), value.into(), inline));
+    self
+  }
+}
+
+/// What a command answers with
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+  Text(String),
+  Embed(ReplyEmbed),
+}
+
+impl From<ReplyEmbed> for CreateEmbed {
+  fn from(reply_embed: ReplyEmbed) -> Self {
+    let mut embed = CreateEmbed::new()
+      .title(reply_embed.title)
+      .colour(reply_embed.colour);
+
+    if let Some(description) = reply_embed.description {
+      embed = embed.description(description);
+    }
+    for (name, value, inline) in reply_embed.fields {
+      embed = embed.field(name, value, inline);
+    }
+    if let Some(footer) = reply_embed.footer {
+      embed = embed.footer(CreateEmbedFooter::new(footer));
+    }
+    if let Some(thumbnail) = reply_embed.thumbnail {
+      embed = embed.thumbnail(thumbnail);
+    }
+
+    embed
+  }
+}
+
+impl From<Reply> for CreateInteractionResponseMessage {
+  fn from(reply: Reply) -> Self {
+    match reply {
+      Reply::Text(content) => CreateInteractionResponseMessage::new().content(content),
+      Reply::Embed(mut reply_embed) => {
+        let mut message = CreateInteractionResponseMessage::new();
+        if let Some((name, data)) = reply_embed.attachment.take() {
+          message = message.add_file(CreateAttachment::bytes(data, name));
+        }
+        message.embed(reply_embed.into())
+      }
+    }
+  }
+}
+
+/// For deferred commands
+impl From<Reply> for EditInteractionResponse {
+  fn from(reply: Reply) -> Self {
+    match reply {
+      Reply::Text(content) => EditInteractionResponse::new().content(content),
+      Reply::Embed(mut reply_embed) => {
+        let mut edit = EditInteractionResponse::new();
+        if let Some((name, data)) = reply_embed.attachment.take() {
+          edit = edit.new_attachment(CreateAttachment::bytes(data, name));
+        }
+        edit.embed(reply_embed.into())
+      }
+    }
+  }
+}
+
+fn snapshot_age(snapshot: &ServerSnapshot, messages: &Messages) -> String {
+  messages.render(
+    MessageKey::StatusAge,
+    &[("seconds", &snapshot.fetched_at.elapsed().as_secs())],
+  )
+}
+
+/// `/status` from the latest snapshot
+pub fn status(snapshot: Option<&ServerSnapshot>, messages: &Messages) -> Reply {
+  let Some(snapshot) = snapshot else {
+    return Reply::Text(messages.text(MessageKey::StatusNotChecked));
+  };
+
+  let mut embed = if snapshot.online {
+    ReplyEmbed::new(
+      messages.text(MessageKey::StatusOnlineTitle),
+      Colour::DARK_GREEN,
+    )
+    .field(
+      &messages.text(MessageKey::StatusPlayersField),
+      format!("{}/{}", snapshot.players_online, snapshot.players_max),
+      true,
+    )
+  } else {
+    ReplyEmbed::new(messages.text(MessageKey::StatusOfflineTitle), Colour::RED)
+  };
+
+  if let Some(editions) = &snapshot.editions {
+    embed = embed
+      .field("Java", EditionCounts::format_count(editions.java), true)
+      .field(
+        "Bedrock",
+        EditionCounts::format_count(editions.bedrock),
+        true,
+      );
+  }
+
+  embed.footer = Some(snapshot_age(snapshot, messages));
+  Reply::Embed(embed)
+}
+
+/// `/players` from the latest snapshot
+pub fn players(snapshot: Option<&ServerSnapshot>, messages: &Messages) -> Reply {
+  let Some(snapshot) = snapshot else {
+    return Reply::Text(messages.text(MessageKey::StatusNotChecked));
+  };
+
+  let age = snapshot_age(snapshot, messages);
+
+  if !snapshot.online {
+    return Reply::Text(messages.render(MessageKey::PlayersOffline, &[("age", &age)]));
+  }
+
+  let mut description = if snapshot.players_online == 0 {
+    messages.text(MessageKey::PlayersNobody)
+  } else {
+    snapshot
+      .names
+      .iter()
+      .map(|name| format!("- {name}"))
+      .collect::<Vec<_>>()
+      .join("\n")
+  };
+
+  let unlisted = (snapshot.players_online as usize).saturating_sub(snapshot.names.len());
+  if unlisted > 0 {
+    description.push('\n');
+    description.push_str(&messages.render(MessageKey::PlayersUnlisted, &[("count", &unlisted)]));
+  }
+
+  let mut embed = ReplyEmbed::new(
+    messages.render(
+      MessageKey::PlayersTitle,
+      &[
+        ("online", &snapshot.players_online),
+        ("max", &snapshot.players_max),
+      ],
+    ),
+    Colour::DARK_GREEN,
+  );
+  embed.description = Some(description);
+  embed.footer = Some(age);
+
+  Reply::Embed(embed)
+}
+
+/// What a status ping told `/server-info`
+pub struct ServerInfo {
+  pub version: String,
+  pub protocol: u16,
+  /// As plain text
+  pub motd: String,
+  pub players_online: u32,
+  pub players_max: u32,
+  /// How long the ping took to answer
+  pub latency: Duration,
+  /// The PNG image of the server icon
+  pub favicon: Option<Vec<u8>>,
+}
+
+/// `/server-info` from a fresh status ping, `None` if the server didn't answer. `last_contact` is
+/// when the status checks last reached it, as a timestamp
+pub fn server_info(
+  address: &str,
+  info: Option<ServerInfo>,
+  last_contact: Option<i64>,
+  messages: &Messages,
+) -> Reply {
+  let Some(info) = info else {
+    return Reply::Text(match last_contact {
+      Some(last_contact) => messages.render(
+        MessageKey::ServerInfoOffline,
+        &[("last_contact", &format!("<t:{last_contact}:R>"))],
+      ),
+      None => messages.text(MessageKey::ServerInfoNeverReached),
+    });
+  };
+
+  let mut embed = ReplyEmbed::new(address, Colour::DARK_GREEN)
+    .field(
+      &messages.text(MessageKey::ServerInfoVersionField),
+      messages.render(
+        MessageKey::ServerInfoVersion,
+        &[("version", &info.version), ("protocol", &info.protocol)],
+      ),
+      true,
+    )
+    .field(
+      &messages.text(MessageKey::StatusPlayersField),
+      format!("{}/{}", info.players_online, info.players_max),
+      true,
+    )
+    .field(
+      &messages.text(MessageKey::ServerInfoLatencyField),
+      messages.render(
+        MessageKey::ServerInfoLatency,
+        &[("milliseconds", &info.latency.as_millis())],
+      ),
+      true,
+    );
+  embed.description = Some(info.motd).filter(|motd| !motd.is_empty());
+  if let Some(favicon) = info.favicon {
+    embed.thumbnail = Some("attachment://favicon.png".to_string());
+    embed.attachment = Some(("favicon.png".to_string(), favicon));
+  }
+
+  Reply::Embed(embed)
+}
+
+/// An account `/whois` found
+pub struct WhoisAccount<'a> {
+  pub link: &'a Link,
+  /// The server the account is whitelisted on, `None` when there is only one
+  pub server_name: Option<&'a str>,
+  /// The account's name right now, when it changed since it was linked
+  pub current_name: Option<&'a str>,
+}
+
+/// `/whois` from the accounts linked to the member or the username
+pub fn whois(title: &str, accounts: &[WhoisAccount]) -> Reply {
+  let mut embed = ReplyEmbed::new(title, Colour::BLUE);
+
+  for account in accounts {
+    let link = account.link;
+    let name = account.current_name.unwrap_or(&link.minecraft_name);
+
+    let mut value = format!(
+      "Discord: <@{}>\nUUID: `{}`\nVerified: <t:{}:f>",
+      link.discord_id, link.uuid, link.linked_at
+    );
+    if account.current_name.is_some() {
+      value.push_str(&format!("\nLinked as: {}", link.minecraft_name));
+    }
+
+    embed = match account.server_name {
+      Some(server_name) => embed.field(&format!("{name} on {server_name}"), value, false),
+      None => embed.field(name, value, false),
+    };
+  }
+
+  embed.thumbnail = accounts
+    .first()
+    .map(|account| format!("https://mc-heads.net/avatar/{}", account.link.uuid));
+
+  Reply::Embed(embed)
+}
+
+/// Like "3h 20m", or "5m" under an hour
+fn format_playtime(seconds: u64) -> String {
+  let minutes = seconds / 60;
+  if minutes < 60 {
+    format!("{minutes}m")
+  } else {
+    format!("{}h {}m", minutes / 60, minutes % 60)
+  }
+}
+
+/// `/lastseen` from what the status checks saw. `partial` is whether the server lists fewer
+/// players than are online, so some can't be seen
+pub fn last_seen(
+  username: &str,
+  sighting: Option<&PlayerSighting>,
+  interval: Duration,
+  partial: bool,
+  messages: &Messages,
+) -> Reply {
+  let mut caveat = messages.render(
+    MessageKey::LastSeenCaveat,
+    &[("seconds", &interval.as_secs())],
+  );
+  if partial {
+    caveat.push(' ');
+    caveat.push_str(&messages.text(MessageKey::LastSeenPartial));
+  }
+
+  let Some(sighting) = sighting else {
+    return Reply::Text(messages.render(
+      MessageKey::LastSeenNever,
+      &[("username", &username), ("caveat", &caveat)],
+    ));
+  };
+
+  let mut embed = ReplyEmbed::new(
+    messages.render(MessageKey::LastSeenTitle, &[("name", &sighting.name)]),
+    Colour::BLUE,
+  )
+  .field(
+    &messages.text(MessageKey::LastSeenOnlineField),
+    format!("<t:{}:R>", sighting.last_seen),
+    true,
+  )
+  .field(
+    &messages.text(MessageKey::LastSeenPlaytimeField),
+    messages.render(
+      MessageKey::LastSeenPlaytime,
+      &[("playtime", &format_playtime(sighting.playtime_seconds))],
+    ),
+    true,
+  );
+  embed.footer = Some(caveat);
+  embed.thumbnail = sighting
+    .uuid
+    .as_ref()
+    .map(|uuid| format!("https://mc-heads.net/avatar/{uuid}"));
+
+  Reply::Embed(embed)
+}
+
+/// Why a typed username can't be a Minecraft username
+#[derive(Debug, PartialEq)]
+pub enum UsernameError {
+  TooShort,
+  TooLong,
+  Spaces,
+  InvalidCharacter(char),
+}
+
+impl UsernameError {
+  pub fn message(&self, username: &str, messages: &Messages) -> String {
+    match self {
+      Self::TooShort => messages.render(MessageKey::UsernameTooShort, &[("username", &username)]),
+      Self::TooLong => messages.render(MessageKey::UsernameTooLong, &[("username", &username)]),
+      Self::Spaces => messages.text(MessageKey::UsernameSpaces),
+      Self::InvalidCharacter(char) => {
+        messages.render(MessageKey::UsernameInvalidCharacter, &[("character", char)])
+      }
+    }
+  }
+}
+
+/// Checks the username the way Mojang does, so garbage doesn't cost an API call
+pub fn validate_username(username: &str) -> Result<(), UsernameError> {
+  if let Some(char) = username
+    .chars()
+    .find(|char| !char.is_ascii_alphanumeric() && *char != '_')
+  {
+    return Err(if char.is_whitespace() {
+      UsernameError::Spaces
+    } else {
+      UsernameError::InvalidCharacter(char)
+    });
+  }
+
+  match username.len() {
+    0..=2 => Err(UsernameError::TooShort),
+    3..=16 => Ok(()),
+    _ => Err(UsernameError::TooLong),
+  }
+}
+
+/// Checks the name a moderation command targets, a username or a Floodgate name
+pub fn validate_player_name(
+  name: &str,
+  floodgate_prefix: Option<&str>,
+) -> Result<(), UsernameError> {
+  match floodgate_prefix.and_then(|prefix| name.strip_prefix(prefix)) {
+    // Gamertags can be shorter than Java names, Floodgate replaces their spaces with underscores
+    Some(gamertag) if !gamertag.is_empty() => match validate_username(gamertag) {
+      Err(UsernameError::TooShort) => Ok(()),
+      result => result,
+    },
+    _ => validate_username(name),
+  }
+}
+
+/// Puts free text like a ban reason on a single line, so it can't end the console command early
+/// or start another one
+pub fn console_text(text: &str) -> String {
+  text
+    .split(|char: char| char.is_whitespace() || char.is_control())
+    .filter(|word| !word.is_empty())
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+/// Strips what usually gets pasted around a username: whitespace, a leading `@` and NameMC
+/// profile links like "https://namemc.com/profile/Notch.1"
+pub fn clean_username(input: &str) -> &str {
+  let mut username = input.trim();
+
+  for prefix in ["https://", "http://", "www.", "namemc.com/profile/"] {
+    username = username.strip_prefix(prefix).unwrap_or(username);
+  }
+  if input.contains("namemc.com/profile/") {
+    // NameMC adds a number after a dot to tell accounts that had the same name apart
+    username = username.split(['.', '/']).next().unwrap_or(username);
+  }
+
+  username.strip_prefix('@').unwrap_or(username).trim()
+}
+
+pub struct VerifyRequest<'a> {
+  pub username: &'a str,
+  pub discord_id: u64,
+  /// How many accounts the user already linked on the server
+  pub linked_accounts: usize,
+  /// How many accounts a user can link on the server
+  pub max_accounts: usize,
+  pub check_banlist: bool,
+  /// Whitelist the typed name if the Mojang API can't be reached
+  pub trust_on_mojang_outage: bool,
+}
+
+/// How far `/verify` got with the minecraft side of things
+pub enum VerifyOutcome<'a> {
+  AccountLimitReached {
+    max_accounts: usize,
+  },
+  /// Mojang answered, but not with a profile
+  MojangFailure(MojangError),
+  /// Mojang couldn't be reached, even after retrying
+  MojangUnavailable,
+  LinkedToOtherUser,
+  ServerUnreachable,
+  /// The server took the connection, but didn't answer in time
+  ServerNotResponding,
+  Banned,
+  AccessFailed,
+  /// `whitelist add` said the server doesn't know the account
+  PlayerNotFound,
+  /// The connection is handed back, so the follow up commands don't need a new one.
+  /// The UUID is missing if the typed name was trusted during a Mojang outage
+  Whitelisted {
+    uuid: Option<String>,
+    name: String,
+    already_whitelisted: bool,
+    rcon: Box<dyn Rcon + 'a>,
+  },
+}
+
+impl VerifyOutcome<'_> {
+  /// Whether the attempt counts towards the user's failed attempt limit
+  pub fn is_failed_attempt(&self) -> bool {
+    matches!(
+      self,
+      Self::MojangFailure { .. }
+        | Self::MojangUnavailable
+        | Self::ServerUnreachable
+        | Self::ServerNotResponding
+        | Self::AccessFailed
+    )
+  }
+
+  /// Why connecting to the server failed, telling a hung server apart from an unreachable one
+  pub fn connect_failure(error: &io::Error) -> Self {
+    if is_unanswered(error) {
+      Self::ServerNotResponding
+    } else {
+      Self::ServerUnreachable
+    }
+  }
+
+  /// The response for an outcome that isn't `Whitelisted`
+  pub fn failure_message(&self, username: &str, messages: &Messages) -> String {
+    match self {
+      Self::AccountLimitReached { max_accounts: 1 } => messages.text(MessageKey::AccountLimitOne),
+      Self::AccountLimitReached { max_accounts } => {
+        messages.render(MessageKey::AccountLimit, &[("max", max_accounts)])
+      }
+      Self::MojangFailure(error) => mojang_failure_message(username, error, messages),
+      Self::MojangUnavailable => messages.text(MessageKey::MojangUnavailable),
+      Self::LinkedToOtherUser => messages.text(MessageKey::LinkedToOtherUser),
+      Self::ServerUnreachable => messages.text(MessageKey::ServerUnreachable),
+      Self::ServerNotResponding => messages.text(MessageKey::ServerNotResponding),
+      Self::Banned => messages.text(MessageKey::Banned),
+      Self::AccessFailed => messages.text(MessageKey::AccessFailed),
+      Self::PlayerNotFound => {
+        messages.render(MessageKey::WhitelistPlayerNotFound, &[("name", &username)])
+      }
+      Self::Whitelisted { name, .. } => {
+        messages.render(MessageKey::VerifySuccess, &[("name", name)])
+      }
+    }
+  }
+}
+
+/// Looks the account up and gives it access to the server.
+/// `linked_discord_id` tells which Discord user an account UUID is linked to, if any.
+/// `access_commands` gives the commands that grant the account access, by its exact name
+pub async fn verify_account<'a>(
+  request: VerifyRequest<'_>,
+  mojang: &dyn Mojang,
+  server: &'a dyn RconConnector,
+  linked_discord_id: impl Fn(&str) -> Option<u64>,
+  access_commands: impl FnOnce(&str) -> Vec<String>,
+) -> VerifyOutcome<'a> {
+  if request.linked_accounts >= request.max_accounts {
+    return VerifyOutcome::AccountLimitReached {
+      max_accounts: request.max_accounts,
+    };
+  }
+
+  let (uuid, name) = match mojang.profile(request.username).await {
+    Ok(MojangProfile { id, name }) => (Some(id), name),
+    Err(MojangError::Network(_)) if request.trust_on_mojang_outage => {
+      (None, request.username.to_string())
+    }
+    Err(MojangError::Network(_)) => return VerifyOutcome::MojangUnavailable,
+    Err(error) => return VerifyOutcome::MojangFailure(error),
+  };
+
+  let linked_to_other_user = uuid
+    .as_deref()
+    .and_then(&linked_discord_id)
+    .is_some_and(|discord_id| discord_id != request.discord_id);
+  if linked_to_other_user {
+    return VerifyOutcome::LinkedToOtherUser;
+  }
+
+  let mut rcon = match server.connect().await {
+    Ok(rcon) => rcon,
+    Err(err) => {
+      warn!("Couldn't create an rcon client: {err}");
+      return VerifyOutcome::connect_failure(&err);
+    }
+  };
+
+  if request.check_banlist && is_banned(rcon.as_mut(), &name).await {
+    warn!("Refused to whitelist '{name}', the account is banned");
+    return VerifyOutcome::Banned;
+  }
+
+  let mut already_whitelisted = false;
+  for access_command in access_commands(&name) {
+    let output = match rcon.run_command(&access_command).await {
+      Ok(output) => output,
+      Err(err) if is_unanswered(&err) => return VerifyOutcome::ServerNotResponding,
+      Err(_) => return VerifyOutcome::AccessFailed,
+    };
+
+    // The role commands answer however they like
+    if !access_command.starts_with("whitelist add ") {
+      continue;
+    }
+    match parse_whitelist_add_output(&output) {
+      WhitelistResult::Added => {}
+      WhitelistResult::AlreadyWhitelisted => already_whitelisted = true,
+      WhitelistResult::PlayerNotFound => {
+        warn!("The server doesn't know '{name}', it wasn't whitelisted");
+        return VerifyOutcome::PlayerNotFound;
+      }
+      WhitelistResult::Unknown(output) => {
+        warn!("Unexpected answer to '{access_command}', treating it as done: {output}");
+      }
+    }
+  }
+
+  if already_whitelisted {
+    info!("'{name}' was already on the whitelist");
+  } else {
+    info!("'{name}' was successfully added to the whitelist");
+  }
+  VerifyOutcome::Whitelisted {
+    uuid,
+    name,
+    already_whitelisted,
+    rcon,
+  }
+}
+
+/// The whitelisted names `/import-whitelist` still has to look up, each once. `known` tells the
+/// names that are linked, imported before or otherwise accounted for
+pub fn names_to_import(whitelist: Vec<String>, known: impl Fn(&str) -> bool) -> Vec<String> {
+  let mut names: Vec<String> = Vec::new();
+  for name in whitelist {
+    if !known(&name) && !names.iter().any(|added| added.eq_ignore_ascii_case(&name)) {
+      names.push(name);
+    }
+  }
+  names
+}
+
+/// What `/import-whitelist` did
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportReport {
+  pub whitelisted: usize,
+  pub imported: usize,
+  /// Names that couldn't be looked up, with why
+  pub failed: Vec<(String, String)>,
+}
+
+impl ImportReport {
+  pub fn summary(&self) -> String {
+    let already_known = self.whitelisted - self.imported - self.failed.len();
+    let mut summary = format!(
+      "Imported {} of the {} whitelisted names, they're linked to whoever verifies them. {already_known} were already known.",
+      self.imported, self.whitelisted
+    );
+
+    if !self.failed.is_empty() {
+      let failed: Vec<String> = self
+        .failed
+        .iter()
+        .map(|(name, reason)| format!("{name} ({reason})"))
+        .collect();
+      summary.push_str(&format!(
+        "\nCouldn't look up {}, running the import again retries them: {}",
+        failed.len(),
+        failed.join(", ")
+      ));
+    }
+
+    summary
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::rcon::NoAnswer;
+  use crate::store::Platform;
+  use std::collections::HashMap;
+  use std::sync::{Arc, Mutex};
+  use std::time::Instant;
+
+  struct FakeMojang(Result<MojangProfile, MojangError>);
+
+  #[async_trait]
+  impl Mojang for FakeMojang {
+    async fn profile(&self, _username: &str) -> Result<MojangProfile, MojangError> {
+      self.0.clone()
+    }
+  }
+
+  fn notch() -> FakeMojang {
+    FakeMojang(Ok(MojangProfile {
+      id: "069a79f444e94726a5befca90e38aaf5".to_string(),
+      name: "Notch".to_string(),
+    }))
+  }
+
+  fn mojang_down() -> FakeMojang {
+    FakeMojang(Err(MojangError::Network("connection refused".to_string())))
+  }
+
+  /// Records the commands it ran, answering each with `output`, or failing if there is none
+  struct FakeRcon {
+    ran: Arc<Mutex<Vec<String>>>,
+    output: Option<String>,
+  }
+
+  #[async_trait]
+  impl Rcon for FakeRcon {
+    async fn run_command(&mut self, command: &str) -> io::Result<String> {
+      self.ran.lock().unwrap().push(command.to_string());
+      self
+        .output
+        .clone()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::ConnectionReset, "connection reset"))
+    }
+  }
+
+  struct FakeServer {
+    online: bool,
+    output: Option<String>,
+    ran: Arc<Mutex<Vec<String>>>,
+  }
+
+  impl FakeServer {
+    fn online(output: &str) -> Self {
+      Self {
+        online: true,
+        output: Some(output.to_string()),
+        ran: Arc::default(),
+      }
+    }
+
+    fn offline() -> Self {
+      Self {
+        online: false,
+        output: None,
+        ran: Arc::default(),
+      }
+    }
+
+    fn ran(&self) -> Vec<String> {
+      self.ran.lock().unwrap().clone()
+    }
+  }
+
+  #[async_trait]
+  impl RconConnector for FakeServer {
+    async fn connect(&self) -> io::Result<Box<dyn Rcon + '_>> {
+      if !self.online {
+        return Err(io::Error::new(
+          io::ErrorKind::ConnectionRefused,
+          "connection refused",
+        ));
+      }
+
+      Ok(Box::new(FakeRcon {
+        ran: self.ran.clone(),
+        output: self.output.clone(),
+      }))
+    }
+  }
+
+  fn request(check_banlist: bool) -> VerifyRequest<'static> {
+    VerifyRequest {
+      username: "notch",
+      discord_id: 1,
+      linked_accounts: 0,
+      max_accounts: 1,
+      check_banlist,
+      trust_on_mojang_outage: false,
+    }
+  }
+
+  fn whitelist(name: &str) -> Vec<String> {
+    vec![format!("whitelist add {name}")]
+  }
+
+  #[tokio::test]
+  async fn verify_whitelists_the_exact_name() {
+    let server = FakeServer::online("Added Notch to the whitelist");
+
+    let outcome = verify_account(request(false), &notch(), &server, |_| None, whitelist).await;
+
+    assert!(matches!(outcome, VerifyOutcome::Whitelisted { ref name, .. } if name == "Notch"));
+    assert_eq!(server.ran(), ["whitelist add Notch"]);
+  }
+
+  #[tokio::test]
+  async fn verify_follows_the_whitelist_answer() {
+    let server = FakeServer::online("Player is already whitelisted");
+    let outcome = verify_account(request(false), &notch(), &server, |_| None, whitelist).await;
+    assert!(matches!(
+      outcome,
+      VerifyOutcome::Whitelisted {
+        already_whitelisted: true,
+        ..
+      }
+    ));
+
+    let server = FakeServer::online("That player does not exist");
+    let outcome = verify_account(request(false), &notch(), &server, |_| None, whitelist).await;
+    assert!(matches!(outcome, VerifyOutcome::PlayerNotFound));
+    assert_eq!(
+      outcome.failure_message("Notch", &Messages::default()),
+      "The server couldn't find the account 'Notch', so it wasn't whitelisted. Check the name and try again."
+    );
+  }
+
+  #[tokio::test]
+  async fn verify_refuses_an_account_linked_to_someone_else() {
+    let server = FakeServer::online("");
+
+    let outcome = verify_account(request(false), &notch(), &server, |_| Some(2), whitelist).await;
+
+    assert!(matches!(outcome, VerifyOutcome::LinkedToOtherUser));
+    assert!(!outcome.is_failed_attempt());
+    assert!(server.ran().is_empty());
+  }
+
+  #[tokio::test]
+  async fn verify_allows_relinking_your_own_account() {
+    let server = FakeServer::online("");
+
+    let outcome = verify_account(request(false), &notch(), &server, |_| Some(1), whitelist).await;
+
+    assert!(matches!(outcome, VerifyOutcome::Whitelisted { .. }));
+  }
+
+  #[tokio::test]
+  async fn verify_reports_mojang_failures() {
+    let server = FakeServer::online("");
+    let mojang = FakeMojang(Err(MojangError::NotFound));
+
+    let outcome = verify_account(request(false), &mojang, &server, |_| None, whitelist).await;
+
+    assert!(outcome.is_failed_attempt());
+    assert_eq!(
+      outcome.failure_message("nobody", &Messages::default()),
+      "There isn't a Mojang user with 'nobody' username. Please try again."
+    );
+    assert!(server.ran().is_empty());
+  }
+
+  #[tokio::test]
+  async fn verify_reports_an_offline_server() {
+    let server = FakeServer::offline();
+    let outcome = verify_account(request(false), &notch(), &server, |_| None, whitelist).await;
+
+    assert!(matches!(outcome, VerifyOutcome::ServerUnreachable));
+    assert!(outcome.is_failed_attempt());
+  }
+
+  #[test]
+  fn a_silent_server_is_told_apart_from_an_offline_one() {
+    let silent = io::Error::new(io::ErrorKind::TimedOut, NoAnswer("the rcon login"));
+    let refused = io::Error::new(io::ErrorKind::ConnectionRefused, "connection refused");
+
+    let outcome = VerifyOutcome::connect_failure(&silent);
+    assert!(matches!(outcome, VerifyOutcome::ServerNotResponding));
+    assert!(outcome
+      .failure_message("Notch", &Messages::default())
+      .starts_with("The server accepted the connection but didn't respond"));
+    assert!(matches!(
+      VerifyOutcome::connect_failure(&refused),
+      VerifyOutcome::ServerUnreachable
+    ));
+  }
+
+  #[test]
+  fn imports_skip_known_and_repeated_names() {
+    let whitelist = ["Alice", "Bob", "alice", "Carol"]
+      .map(str::to_string)
+      .to_vec();
+
+    let names = names_to_import(whitelist, |name| name.eq_ignore_ascii_case("bob"));
+
+    assert_eq!(names, ["Alice", "Carol"]);
+
+    let report = ImportReport {
+      whitelisted: 4,
+      imported: 1,
+      failed: vec![("Carol".to_string(), "no such profile".to_string())],
+    };
+    assert_eq!(
+      report.summary(),
+      "Imported 1 of the 4 whitelisted names, they're linked to whoever verifies them. 2 were already known.\nCouldn't look up 1, running the import again retries them: Carol (no such profile)"
+    );
+  }
+
+  #[tokio::test]
+  async fn verify_skips_everything_at_the_account_limit() {
+    let server = FakeServer::online("");
+    let request = VerifyRequest {
+      linked_accounts: 2,
+      max_accounts: 2,
+      ..request(false)
+    };
+
+    let outcome = verify_account(request, &mojang_down(), &server, |_| None, whitelist).await;
+
+    assert!(matches!(
+      outcome,
+      VerifyOutcome::AccountLimitReached { max_accounts: 2 }
+    ));
+    assert!(server.ran().is_empty());
+  }
+
+  #[tokio::test]
+  async fn verify_allows_another_account_below_the_limit() {
+    let server = FakeServer::online("Added Notch to the whitelist");
+    let request = VerifyRequest {
+      linked_accounts: 1,
+      max_accounts: 2,
+      ..request(false)
+    };
+
+    let outcome = verify_account(request, &notch(), &server, |_| None, whitelist).await;
+
+    assert!(matches!(outcome, VerifyOutcome::Whitelisted { .. }));
+    assert_eq!(server.ran(), ["whitelist add Notch"]);
+  }
+
+  #[tokio::test]
+  async fn verify_in_offline_mode_uses_the_offline_uuid() {
+    let server = FakeServer::online("Added notch to the whitelist");
+
+    let outcome = verify_account(
+      request(false),
+      &OfflineProfiles,
+      &server,
+      |_| None,
+      whitelist,
+    )
+    .await;
+
+    assert!(matches!(
+      outcome,
+      VerifyOutcome::Whitelisted { uuid: Some(ref uuid), ref name, .. }
+        if uuid == &offline_uuid("notch") && name == "notch"
+    ));
+    assert_eq!(server.ran(), ["whitelist add notch"]);
+  }
+
+  #[tokio::test]
+  async fn verify_trusts_the_typed_name_during_an_outage() {
+    let server = FakeServer::online("");
+    let request = VerifyRequest {
+      trust_on_mojang_outage: true,
+      ..request(false)
+    };
+
+    let outcome = verify_account(request, &mojang_down(), &server, |_| None, whitelist).await;
+
+    assert!(matches!(
+      outcome,
+      VerifyOutcome::Whitelisted { uuid: None, ref name, .. } if name == "notch"
+    ));
+    assert_eq!(server.ran(), ["whitelist add notch"]);
+  }
+
+  #[tokio::test]
+  async fn verify_refuses_banned_accounts() {
+    let server = FakeServer::online("There are 1 ban(s):Notch was banned by Server: Griefing");
+
+    let outcome = verify_account(request(true), &notch(), &server, |_| None, whitelist).await;
+
+    assert!(matches!(outcome, VerifyOutcome::Banned));
+    assert_eq!(server.ran(), ["banlist players"]);
+  }
+
+  #[test]
+  fn usernames_of_3_to_16_characters_are_valid() {
+    assert_eq!(validate_username("abc"), Ok(()));
+    assert_eq!(validate_username("Notch_1234567890"), Ok(()));
+    assert_eq!(validate_username("ab"), Err(UsernameError::TooShort));
+    assert_eq!(validate_username(""), Err(UsernameError::TooShort));
+    assert_eq!(
+      validate_username("Notch_12345678901"),
+      Err(UsernameError::TooLong)
+    );
+  }
+
+  #[test]
+  fn usernames_with_other_characters_are_rejected() {
+    assert_eq!(validate_username("Not ch"), Err(UsernameError::Spaces));
+    assert_eq!(
+      validate_username("Notch!"),
+      Err(UsernameError::InvalidCharacter('!'))
+    );
+    assert_eq!(
+      validate_username("Nötch"),
+      Err(UsernameError::InvalidCharacter('ö'))
+    );
+    assert_eq!(
+      validate_username("Notch😀"),
+      Err(UsernameError::InvalidCharacter('😀'))
+    );
+  }
+
+  #[test]
+  fn moderated_names_can_be_floodgate_names() {
+    assert_eq!(validate_player_name("Notch", Some(".")), Ok(()));
+    assert_eq!(validate_player_name(".Ab", Some(".")), Ok(()));
+    assert_eq!(validate_player_name(".Steve_Gamer", Some(".")), Ok(()));
+    assert_eq!(
+      validate_player_name("Notch stop", None),
+      Err(UsernameError::Spaces)
+    );
+    assert_eq!(
+      validate_player_name("@a", None),
+      Err(UsernameError::InvalidCharacter('@'))
+    );
+    assert_eq!(
+      validate_player_name(".Ab", None),
+      Err(UsernameError::InvalidCharacter('.'))
+    );
+  }
+
+  #[test]
+  fn console_text_is_one_line() {
+    assert_eq!(
+      console_text(" Griefing\nop Alice\r\n\tand  more "),
+      "Griefing op Alice and more"
+    );
+    assert_eq!(console_text("spam\u{0}\u{7}ming"), "spam ming");
+    assert_eq!(console_text(" \n "), "");
+  }
+
+  #[test]
+  fn pasted_usernames_are_cleaned() {
+    assert_eq!(clean_username("  Notch "), "Notch");
+    assert_eq!(clean_username("@Notch"), "Notch");
+    assert_eq!(
+      clean_username("https://namemc.com/profile/Notch.1"),
+      "Notch"
+    );
+    assert_eq!(clean_username("namemc.com/profile/Notch"), "Notch");
+    assert_eq!(clean_username("Not ch"), "Not ch");
+  }
+
+  fn snapshot(online: bool, players_online: u32, names: &[&str]) -> ServerSnapshot {
+    ServerSnapshot {
+      online,
+      players_online,
+      players_max: 20,
+      names: names.iter().map(|name| name.to_string()).collect(),
+      uuids: HashMap::new(),
+      editions: None,
+      version: None,
+      motd: None,
+      fetched_at: Instant::now(),
+    }
+  }
+
+  #[test]
+  fn last_seen_says_it_is_sampled() {
+    let sighting = PlayerSighting {
+      uuid: None,
+      name: "Alice".to_string(),
+      last_seen: 1_700_000_000,
+      playtime_seconds: 3 * 60 * 60 + 20 * 60,
+    };
+
+    let Reply::Embed(embed) = last_seen(
+      "alice",
+      Some(&sighting),
+      Duration::from_secs(60),
+      true,
+      &Messages::default(),
+    ) else {
+      panic!("expected an embed");
+    };
+    assert_eq!(embed.title, "Last seen: Alice");
+    assert_eq!(embed.fields[1].1, "about 3h 20m");
+    assert!(embed
+      .footer
+      .unwrap()
+      .contains("only lists some of the players"));
+
+    assert_eq!(
+      last_seen("Bob", None, Duration::from_secs(60), false, &Messages::default()),
+      Reply::Text(
+        "'Bob' hasn't been seen online. The server is checked every 60 seconds, so short visits are missed and the playtime is approximate."
+          .to_string()
+      )
+    );
+  }
+
+  #[test]
+  fn whois_shows_renamed_accounts_by_their_current_name() {
+    let link = Link {
+      discord_id: 1,
+      uuid: "069a79f444e94726a5befca90e38aaf5".to_string(),
+      minecraft_name: "OldName".to_string(),
+      linked_at: 1700000000,
+      expiry_warned: false,
+      suspended: false,
+      banned: false,
+      server: None,
+      platform: Platform::Java,
+      xuid: None,
+    };
+    let account = WhoisAccount {
+      link: &link,
+      server_name: None,
+      current_name: Some("Notch"),
+    };
+
+    let Reply::Embed(embed) = whois("Who is Notch", &[account]) else {
+      panic!("expected an embed");
+    };
+
+    assert_eq!(embed.fields[0].0, "Notch");
+    assert!(embed.fields[0].1.contains("Linked as: OldName"));
+    assert!(embed.fields[0].1.contains("<@1>"));
+    assert_eq!(
+      embed.thumbnail.as_deref(),
+      Some("https://mc-heads.net/avatar/069a79f444e94726a5befca90e38aaf5")
+    );
+  }
+
+  #[test]
+  fn status_before_the_first_check() {
+    assert_eq!(
+      status(None, &Messages::default()),
+      Reply::Text("The server status hasn't been checked yet. Try again in a moment.".to_string())
+    );
+  }
+
+  #[test]
+  fn status_shows_the_player_count() {
+    let Reply::Embed(embed) = status(Some(&snapshot(true, 3, &[])), &Messages::default()) else {
+      panic!("expected an embed");
+    };
+
+    assert_eq!(embed.title, "🎮 Server online");
+    assert_eq!(
+      embed.fields,
+      [("Players".to_string(), "3/20".to_string(), true)]
+    );
+  }
+
+  #[test]
+  fn players_mentions_unlisted_players() {
+    let Reply::Embed(embed) = players(Some(&snapshot(true, 3, &["Alice"])), &Messages::default())
+    else {
+      panic!("expected an embed");
+    };
+
+    assert_eq!(embed.title, "Players online (3/20)");
+    assert_eq!(
+      embed.description.as_deref(),
+      Some("- Alice\n...and 2 more (the server only lists some of the players)")
+    );
+  }
+
+  #[test]
+  fn players_when_offline() {
+    let reply = players(Some(&snapshot(false, 0, &[])), &Messages::default());
+
+    assert!(matches!(reply, Reply::Text(text) if text.starts_with("The server is offline.")));
+  }
+
+  #[test]
+  fn server_info_attaches_the_favicon() {
+    let info = ServerInfo {
+      version: "Paper 1.21".to_string(),
+      protocol: 767,
+      motd: "A Minecraft Server".to_string(),
+      players_online: 2,
+      players_max: 20,
+      latency: Duration::from_millis(42),
+      favicon: Some(vec![0x89, b'P', b'N', b'G']),
+    };
+
+    let Reply::Embed(embed) = server_info("mc.example.com", Some(info), None, &Messages::default())
+    else {
+      panic!("expected an embed");
+    };
+    assert_eq!(embed.fields[0].1, "Paper 1.21 (protocol 767)");
+    assert_eq!(embed.fields[2].1, "42 ms");
+    assert_eq!(embed.thumbnail.as_deref(), Some("attachment://favicon.png"));
+    assert_eq!(embed.attachment.unwrap().0, "favicon.png");
+
+    assert_eq!(
+      server_info(
+        "mc.example.com",
+        None,
+        Some(1_700_000_000),
+        &Messages::default()
+      ),
+      Reply::Text("The server is offline. It last answered <t:1700000000:R>.".to_string())
+    );
+  }
+}
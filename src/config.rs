@@ -0,0 +1,1282 @@
+//! The core settings, read from a TOML file (`bot.toml`, or the path in `CONFIG_PATH`). The
+//! environment variables of the same settings take precedence, so `.env` only setups keep working.
+//! Extra servers can only be listed in the file. The other settings only come from the
+//! environment, and are checked together with the core ones
+
+use crate::address::parse_address;
+use crate::alerts::AlertThresholds;
+use crate::bot::{
+  parse_role_commands, parse_role_ttl_days, MojangOutagePolicy, RoleCommand, VerifyHours,
+};
+use crate::logging::LogFormat;
+use crate::rcon::RCON_TIMEOUT;
+use crate::schedule::{Cron, Schedule, Timezone};
+use chrono::{FixedOffset, NaiveTime};
+use regex::Regex;
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::time::Duration;
+
+const DEFAULT_CONFIG_PATH: &str = "bot.toml";
+const DEFAULT_RCON_PORT: u16 = 25575;
+/// Discord's blue
+const DEFAULT_ROLE_COLOUR: u32 = 0x3498DB;
+/// How long a request to the Mojang or Geyser API or a webhook can take by default
+const DEFAULT_HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long failed verification attempts count towards the limit by default
+const DEFAULT_VERIFY_ATTEMPT_WINDOW: Duration = Duration::from_secs(10 * 60);
+/// Console commands `/rcon` refuses unless ALLOW_DANGEROUS_RCON is set
+const DEFAULT_RCON_BLOCKLIST: &str = "stop,restart,op,deop";
+const DEFAULT_PLAYTIME_REGEX: &str = r"(?P<name>\w+): (?P<value>\d+)";
+
+/// The config file, every field is optional since the environment can provide it
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+  server_address: Option<String>,
+  rcon_port: Option<u16>,
+  query_port: Option<u16>,
+  udp_query_port: Option<u16>,
+  rcon_password: Option<String>,
+  status_channel_id: Option<u64>,
+  verify_channel_id: Option<u64>,
+  status_interval_seconds: Option<u64>,
+  verified_role_name: Option<String>,
+  verified_role_color: Option<String>,
+  verified_role_hoist: Option<bool>,
+  server_name: Option<String>,
+  servers: Vec<ServerConfig>,
+  guilds: Vec<GuildConfig>,
+  reconcile: FileReconcileConfig,
+  timezone: Option<String>,
+  log_format: Option<String>,
+  schedules: Vec<ScheduleConfig>,
+}
+
+/// The `[reconcile]` section
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct FileReconcileConfig {
+  channel_id: Option<u64>,
+  interval_hours: Option<u64>,
+  auto_fix: Option<bool>,
+}
+
+/// A `[[schedules]]` entry, like a warning before a nightly restart
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct ScheduleConfig {
+  /// Like "55 3 * * *": minute, hour, day of the month, month and day of the week
+  cron: String,
+  /// Run on the main server over RCON
+  command: Option<String>,
+  /// Posted in the main server's status channel
+  message: Option<String>,
+}
+
+fn default_rcon_port() -> u16 {
+  DEFAULT_RCON_PORT
+}
+
+/// A minecraft server from the same Discord, besides the main one. Only the config file can list
+/// them, as `[[servers]]`
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+  /// Shown in the `/verify` server choice
+  pub name: String,
+  pub address: String,
+  #[serde(default = "default_rcon_port")]
+  pub rcon_port: u16,
+  /// `None` looks for an SRV record, like the Minecraft client does
+  #[serde(default)]
+  pub query_port: Option<u16>,
+  /// The port of the query protocol (`enable-query` in `server.properties`), tried when the status
+  /// ping fails. `None` skips it
+  #[serde(default)]
+  pub udp_query_port: Option<u16>,
+  pub rcon_password: String,
+  pub status_channel_id: Option<u64>,
+}
+
+/// Another Discord the bot verifies members in, like a partner community playing on the same
+/// server. Only the config file can list them, as `[[guilds]]`
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GuildConfig {
+  pub id: u64,
+  pub verify_channel_id: u64,
+  /// Renamed to the main server's status
+  pub status_channel_id: Option<u64>,
+  /// The role verified members get. `None` uses the Verified role by its name, created if it
+  /// doesn't exist
+  pub role_id: Option<u64>,
+}
+
+/// The check that the whitelist, the stored links and the Verified role still agree
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReconcileConfig {
+  /// Where the report is posted. Without a channel, there is no check
+  pub channel_id: Option<u64>,
+  pub interval: Duration,
+  /// Re-add the missing whitelist entries of linked accounts
+  pub auto_fix: bool,
+}
+
+#[derive(Debug)]
+pub struct Config {
+  /// The main server, which everything besides `/verify` works with
+  pub server: ServerConfig,
+  /// Only `/verify` and the status channels cover these
+  pub extra_servers: Vec<ServerConfig>,
+  /// The Discords besides the one with the verify channel
+  pub guilds: Vec<GuildConfig>,
+  pub verify_channel_id: u64,
+  pub status_interval: Duration,
+  pub verified_role_name: String,
+  /// Only used when the bot creates the role, or the setting changes
+  pub verified_role_colour: u32,
+  /// Show verified members separately in the member list
+  pub verified_role_hoist: bool,
+  pub reconcile: ReconcileConfig,
+  /// What the schedules run by. `None` uses `TIMEZONE_OFFSET`
+  pub timezone: Option<Timezone>,
+  pub schedules: Vec<Schedule>,
+  pub log_format: LogFormat,
+  pub settings: Settings,
+}
+
+/// Verifying through a companion website instead of in Discord
+#[derive(Debug)]
+pub struct WebVerifyConfig {
+  /// The verification page, `{token}` is replaced with the user's one-time token
+  pub url: String,
+  /// Where the callback server listens, like "0.0.0.0:8080"
+  pub listen_address: String,
+  /// The website has to send it with every callback
+  pub secret: String,
+}
+
+/// Relays chat between a Discord channel and the in-game chat
+#[derive(Debug)]
+pub struct ChatBridgeConfig {
+  pub channel_id: u64,
+  /// Posts the in-game chat to the channel, as each player with their own name and head
+  pub webhook_url: Option<String>,
+  /// The server's `logs/latest.log`, where the in-game chat is read from
+  pub log_path: Option<String>,
+}
+
+/// The settings besides the core ones, which only the environment sets
+#[derive(Debug)]
+pub struct Settings {
+  pub discord_token: String,
+  pub store_path: String,
+  pub status_online_format: Option<String>,
+  pub status_offline_format: Option<String>,
+  pub status_smoothing_samples: usize,
+  pub status_channel_rename: bool,
+  pub status_embed: bool,
+  pub update_presence: bool,
+  pub presence_interval: Duration,
+  pub bedrock_port: Option<u16>,
+  pub full_server_note: bool,
+  pub sync_nickname: bool,
+  pub timezone_offset: FixedOffset,
+  pub verify_hours: Option<VerifyHours>,
+  pub daily_summary_time: Option<NaiveTime>,
+  pub role_retry_attempts: u32,
+  pub staff_role_name: Option<String>,
+  pub staff_verify_command: Option<String>,
+  pub role_commands: Vec<RoleCommand>,
+  pub role_commands_replace_whitelist: bool,
+  pub playtime_command: Option<String>,
+  pub playtime_pattern: Regex,
+  pub allowed_guild_ids: Option<Vec<u64>>,
+  pub leave_unlisted_guilds: bool,
+  pub verification_ttl_days: Option<i64>,
+  pub verification_role_ttl_days: Vec<(String, Option<i64>)>,
+  pub verification_expiry_warning_days: i64,
+  pub web_verify: Option<WebVerifyConfig>,
+  pub chat_bridge: Option<ChatBridgeConfig>,
+  pub alert_user_id: Option<u64>,
+  pub audit_channel_id: Option<u64>,
+  pub alert_thresholds: AlertThresholds,
+  pub error_webhook_url: Option<String>,
+  pub http_timeout: Duration,
+  pub rcon_connect_timeout: Duration,
+  pub rcon_command_timeout: Duration,
+  pub bedrock_verify: bool,
+  pub floodgate_prefix: String,
+  pub floodgate_command: Option<String>,
+  pub link_command: String,
+  pub link_code_ttl: Duration,
+  pub online_commands: Vec<String>,
+  pub mojang_outage_policy: MojangOutagePolicy,
+  pub offline_mode: bool,
+  pub clean_verify_channel: bool,
+  pub max_accounts_per_user: usize,
+  pub ban_on_discord_kick: bool,
+  /// The entries of RCON_BLOCKLIST, as they were written
+  pub rcon_blocklist: Vec<String>,
+  pub allow_dangerous_rcon: bool,
+  pub check_banlist: bool,
+  pub verify_max_failed_attempts: usize,
+  pub verify_attempt_window: Duration,
+  pub verify_concurrency: usize,
+  pub name_recently_freed_days: i64,
+  pub last_seen_retention_days: i64,
+  pub send_welcome_dm: bool,
+  pub welcome_dm_text: Option<String>,
+  pub server_join_address: Option<String>,
+  pub name_history_url: Option<String>,
+  pub teleport_command: Option<String>,
+  pub ingame_role_name: Option<String>,
+  pub metrics_addr: Option<String>,
+  pub require_human_gate: bool,
+  pub verify_challenge: bool,
+}
+
+/// Collects every problem with the settings, so they can all be fixed at once
+struct Problems(Vec<String>);
+
+impl Problems {
+  /// The environment variable if it's set, otherwise the file's value
+  fn value<T: std::str::FromStr>(
+    &mut self,
+    env: &impl Fn(&str) -> Option<String>,
+    name: &str,
+    file_value: Option<T>,
+  ) -> Option<T> {
+    match env(name).filter(|value| !value.trim().is_empty()) {
+      Some(value) => match value.trim().parse() {
+        Ok(value) => Some(value),
+        Err(_) => {
+          self.0.push(format!("{name} is invalid: '{value}'"));
+          None
+        }
+      },
+      None => file_value,
+    }
+  }
+
+  /// Like `value`, but reports it as missing when neither has it. A blank file value, like
+  /// `rcon_password = ""`, counts as missing
+  fn required<T: std::str::FromStr + std::fmt::Display>(
+    &mut self,
+    env: &impl Fn(&str) -> Option<String>,
+    name: &str,
+    file_value: Option<T>,
+  ) -> Option<T> {
+    let is_invalid = env(name).is_some_and(|value| !value.trim().is_empty());
+    let file_value = file_value.filter(|value| !value.to_string().trim().is_empty());
+    let value = self.value(env, name, file_value);
+
+    if value.is_none() && !is_invalid {
+      self.0.push(format!(
+        "{name} is missing, set it in the environment or as {} in the config file",
+        name.to_lowercase()
+      ));
+    }
+    value
+  }
+
+  /// A setting only the environment has
+  fn env<T: std::str::FromStr>(
+    &mut self,
+    env: &impl Fn(&str) -> Option<String>,
+    name: &str,
+  ) -> Option<T> {
+    self.value(env, name, None)
+  }
+
+  /// Parses the setting with `parse`, which returns `None` if it's invalid
+  fn parsed<T>(
+    &mut self,
+    env: &impl Fn(&str) -> Option<String>,
+    name: &str,
+    parse: impl FnOnce(&str) -> Option<T>,
+  ) -> Option<T> {
+    let value: String = self.env(env, name)?;
+    let parsed = parse(value.trim());
+    if parsed.is_none() {
+      self.0.push(format!("{name} is invalid: '{value}'"));
+    }
+    parsed
+  }
+
+  /// A count that can't be 0, like a limit that would block everything
+  fn count(&mut self, env: &impl Fn(&str) -> Option<String>, name: &str) -> Option<usize> {
+    let count = self.env(env, name);
+    if count == Some(0) {
+      self.0.push(format!("{name} has to be at least 1"));
+    }
+    count
+  }
+
+  /// A format for a channel name, which Discord doesn't allow to be empty. Unset uses the
+  /// messages file
+  fn channel_name(&mut self, env: &impl Fn(&str) -> Option<String>, name: &str) -> Option<String> {
+    if env(name).is_some_and(|value| value.trim().is_empty()) {
+      self.0.push(format!(
+        "{name} can't be empty, Discord needs a channel name"
+      ));
+    }
+    self.env(env, name)
+  }
+
+  /// A setting that has to be set, because `because` is
+  fn env_required(
+    &mut self,
+    env: &impl Fn(&str) -> Option<String>,
+    name: &str,
+    because: &str,
+  ) -> Option<String> {
+    let value = self.env(env, name);
+    if value.is_none() {
+      self.0.push(format!(
+        "{name} is missing, it has to be set in the environment{because}"
+      ));
+    }
+    value
+  }
+}
+
+/// Parses a hex color like "#3498db"
+fn parse_hex_colour(colour: &str) -> Option<u32> {
+  let hex = colour.trim().trim_start_matches('#');
+  if hex.len() != 6 {
+    return None;
+  }
+
+  u32::from_str_radix(hex, 16).ok()
+}
+
+impl Problems {
+  /// Splits the port off the address, which has to agree with the separately set query port
+  fn split_address(
+    &mut self,
+    setting: &str,
+    address: &str,
+    query_port: Option<u16>,
+  ) -> Option<(String, Option<u16>)> {
+    let Some((host, port)) = parse_address(address) else {
+      self.0.push(format!("{setting} is invalid: '{address}'"));
+      return None;
+    };
+
+    match (port, query_port) {
+      (Some(port), Some(query_port)) if port != query_port => {
+        self.0.push(format!(
+          "{setting} has the port {port}, but the query port is set to {query_port}"
+        ));
+        None
+      }
+      (port, query_port) => Some((host, port.or(query_port))),
+    }
+  }
+}
+
+impl Config {
+  /// Loads the config file and applies the environment on top. Every missing or invalid setting
+  /// is in the error
+  pub fn load() -> Result<Self, Vec<String>> {
+    let explicit_path = env::var("CONFIG_PATH").ok();
+    let path = explicit_path.as_deref().unwrap_or(DEFAULT_CONFIG_PATH);
+
+    let file = match fs::read_to_string(path) {
+      Ok(contents) => toml::from_str(&contents)
+        .map_err(|error| vec![format!("Couldn't parse the config file {path}: {error}")])?,
+      // Only the default path is optional
+      Err(_) if explicit_path.is_none() => FileConfig::default(),
+      Err(error) => {
+        return Err(vec![format!(
+          "Couldn't read the config file {path}: {error}"
+        )])
+      }
+    };
+
+    Self::from_sources(file, |name| env::var(name).ok())
+  }
+
+  /// The config from only the environment, for the tests of other modules
+  #[cfg(test)]
+  pub(crate) fn from_env(env: impl Fn(&str) -> Option<String>) -> Result<Self, Vec<String>> {
+    Self::from_sources(FileConfig::default(), env)
+  }
+
+  fn from_sources(
+    file: FileConfig,
+    env: impl Fn(&str) -> Option<String>,
+  ) -> Result<Self, Vec<String>> {
+    let mut problems = Problems(Vec::new());
+
+    let server_address = problems.required(&env, "SERVER_ADDRESS", file.server_address);
+    let rcon_port = problems.value(&env, "RCON_PORT", file.rcon_port);
+    let query_port = problems.value(&env, "QUERY_PORT", file.query_port);
+    let udp_query_port = problems.value(&env, "UDP_QUERY_PORT", file.udp_query_port);
+    let rcon_password = problems.required(&env, "RCON_PASSWORD", file.rcon_password);
+    let status_channel_id =
+      problems.value(&env, "DISCORD_STATUS_CHANNEL_ID", file.status_channel_id);
+    let verify_channel_id =
+      problems.required(&env, "DISCORD_VERIFY_CHANNEL_ID", file.verify_channel_id);
+    let status_interval_seconds = problems.value(
+      &env,
+      "STATUS_INTERVAL_SECONDS",
+      file.status_interval_seconds,
+    );
+    let verified_role_name = problems.value(&env, "VERIFIED_ROLE_NAME", file.verified_role_name);
+    let verified_role_colour = problems
+      .value(&env, "VERIFIED_ROLE_COLOR", file.verified_role_color)
+      .and_then(|colour: String| {
+        let parsed = parse_hex_colour(&colour);
+        if parsed.is_none() {
+          problems.0.push(format!(
+            "VERIFIED_ROLE_COLOR is invalid: '{colour}', use a hex color like #3498db"
+          ));
+        }
+        parsed
+      });
+    let verified_role_hoist = problems.value(&env, "VERIFIED_ROLE_HOIST", file.verified_role_hoist);
+    let server_name = problems
+      .value(&env, "SERVER_NAME", file.server_name)
+      .unwrap_or_else(|| "main".to_string());
+
+    let reconcile_channel_id =
+      problems.value(&env, "RECONCILE_CHANNEL_ID", file.reconcile.channel_id);
+    let reconcile_interval_hours = problems.value(
+      &env,
+      "RECONCILE_INTERVAL_HOURS",
+      file.reconcile.interval_hours,
+    );
+    let reconcile_auto_fix = problems.value(&env, "RECONCILE_AUTO_FIX", file.reconcile.auto_fix);
+
+    let timezone = problems
+      .value(&env, "TIMEZONE", file.timezone)
+      .and_then(|name: String| match Timezone::load(&name) {
+        Ok(timezone) => Some(timezone),
+        Err(error) => {
+          problems.0.push(format!("TIMEZONE is invalid: {error}"));
+          None
+        }
+      });
+
+    let log_format = problems
+      .value(&env, "LOG_FORMAT", file.log_format)
+      .and_then(|format: String| {
+        let parsed = format.parse().ok();
+        if parsed.is_none() {
+          problems.0.push(format!(
+            "LOG_FORMAT is invalid: '{format}', use text or json"
+          ));
+        }
+        parsed
+      });
+
+    let mut schedules = Vec::new();
+    for schedule in file.schedules {
+      if schedule.command.is_none() && schedule.message.is_none() {
+        problems.0.push(format!(
+          "The schedule '{}' needs a command or a message",
+          schedule.cron
+        ));
+        continue;
+      }
+      match Cron::parse(&schedule.cron) {
+        Ok(cron) => schedules.push(Schedule {
+          expression: schedule.cron,
+          cron,
+          command: schedule.command,
+          message: schedule.message,
+        }),
+        Err(error) => problems.0.push(error),
+      }
+    }
+
+    let settings = Settings::from_env(&mut problems, &env);
+
+    // The address can carry the query port, like players type it
+    let (server_address, query_port) = match server_address {
+      Some(server_address) => {
+        match problems.split_address("SERVER_ADDRESS", &server_address, query_port) {
+          Some((host, query_port)) => (Some(host), query_port),
+          None => (None, None),
+        }
+      }
+      None => (None, query_port),
+    };
+
+    let mut extra_servers = file.servers;
+    for server in &mut extra_servers {
+      let setting = format!("The address of the server '{}'", server.name);
+      if let Some((host, query_port)) =
+        problems.split_address(&setting, &server.address, server.query_port)
+      {
+        server.address = host;
+        server.query_port = query_port;
+      }
+    }
+
+    let mut names = vec![server_name.to_lowercase()];
+    for server in &extra_servers {
+      if server.rcon_password.trim().is_empty() {
+        problems.0.push(format!(
+          "The server '{}' in [[servers]] has no rcon_password",
+          server.name
+        ));
+      }
+      if server.name.trim().is_empty() {
+        problems
+          .0
+          .push("A server in [[servers]] has no name".to_string());
+      } else if names.contains(&server.name.to_lowercase()) {
+        problems
+          .0
+          .push(format!("The server name '{}' is used twice", server.name));
+      }
+      names.push(server.name.to_lowercase());
+    }
+
+    let mut guild_ids = Vec::new();
+    for guild in &file.guilds {
+      if guild_ids.contains(&guild.id) {
+        problems
+          .0
+          .push(format!("The guild {} is in [[guilds]] twice", guild.id));
+      }
+      if Some(guild.verify_channel_id) == verify_channel_id {
+        problems.0.push(format!(
+          "The guild {} has the main verify channel, it doesn't need to be in [[guilds]]",
+          guild.id
+        ));
+      }
+      guild_ids.push(guild.id);
+    }
+
+    if status_interval_seconds == Some(0) {
+      problems
+        .0
+        .push("STATUS_INTERVAL_SECONDS has to be at least 1".to_string());
+    }
+    if reconcile_interval_hours == Some(0) {
+      problems
+        .0
+        .push("RECONCILE_INTERVAL_HOURS has to be at least 1".to_string());
+    }
+
+    match (server_address, rcon_password, verify_channel_id, settings) {
+      (Some(server_address), Some(rcon_password), Some(verify_channel_id), Some(settings))
+        if problems.0.is_empty() =>
+      {
+        Ok(Self {
+          server: ServerConfig {
+            name: server_name,
+            address: server_address,
+            rcon_port: rcon_port.unwrap_or(DEFAULT_RCON_PORT),
+            query_port,
+            udp_query_port,
+            rcon_password,
+            status_channel_id,
+          },
+          extra_servers,
+          guilds: file.guilds,
+          verify_channel_id,
+          status_interval: Duration::from_secs(status_interval_seconds.unwrap_or(5 * 60)),
+          verified_role_name: verified_role_name.unwrap_or_else(|| "Verified".to_string()),
+          verified_role_colour: verified_role_colour.unwrap_or(DEFAULT_ROLE_COLOUR),
+          verified_role_hoist: verified_role_hoist.unwrap_or(true),
+          reconcile: ReconcileConfig {
+            channel_id: reconcile_channel_id,
+            interval: Duration::from_secs(reconcile_interval_hours.unwrap_or(24) * 60 * 60),
+            auto_fix: reconcile_auto_fix.unwrap_or(false),
+          },
+          timezone,
+          schedules,
+          log_format: log_format.unwrap_or_default(),
+          settings,
+        })
+      }
+      _ => Err(problems.0),
+    }
+  }
+}
+
+/// Splits a list like "a;b;c", leaving out the empty entries
+fn split_list(list: &str, separator: char) -> Vec<String> {
+  list
+    .split(separator)
+    .map(str::trim)
+    .filter(|entry| !entry.is_empty())
+    .map(str::to_string)
+    .collect()
+}
+
+impl Settings {
+  /// Reads the settings, adding every problem. `None` if DISCORD_TOKEN is missing
+  fn from_env(problems: &mut Problems, env: &impl Fn(&str) -> Option<String>) -> Option<Self> {
+    let discord_token = problems.env_required(env, "DISCORD_TOKEN", "");
+    let seconds = |seconds: Option<u64>, default| {
+      seconds.map_or(default, |seconds| Duration::from_secs(seconds.max(1)))
+    };
+
+    let timezone_offset = problems
+      .env(env, "TIMEZONE_OFFSET")
+      .unwrap_or(FixedOffset::east_opt(0).unwrap());
+    let verify_hours = problems.parsed(env, "VERIFY_HOURS", |hours| {
+      VerifyHours::parse(hours, timezone_offset)
+    });
+    let daily_summary_time = problems.parsed(env, "DAILY_SUMMARY_TIME", |time| {
+      NaiveTime::parse_from_str(time, "%H:%M").ok()
+    });
+
+    let playtime_pattern = problems
+      .parsed(env, "PLAYTIME_REGEX", |pattern| Regex::new(pattern).ok())
+      .unwrap_or_else(|| Regex::new(DEFAULT_PLAYTIME_REGEX).unwrap());
+    let allowed_guild_ids = problems.parsed(env, "ALLOWED_GUILD_IDS", |ids| {
+      ids
+        .split(',')
+        .map(|id| id.trim().parse().ok())
+        .collect::<Option<Vec<u64>>>()
+    });
+
+    let web_verify = problems
+      .env::<String>(env, "WEB_VERIFY_URL")
+      .and_then(|url| {
+        let because = ", since WEB_VERIFY_URL is set";
+        let listen_address = problems.env_required(env, "WEB_VERIFY_LISTEN_ADDRESS", because);
+        let secret = problems.env_required(env, "WEB_VERIFY_SECRET", because);
+
+        Some(WebVerifyConfig {
+          url,
+          listen_address: listen_address?,
+          secret: secret?,
+        })
+      });
+
+    let chat_bridge = problems
+      .env(env, "CHAT_BRIDGE_CHANNEL_ID")
+      .map(|channel_id| {
+        let webhook_url = problems.env::<String>(env, "CHAT_BRIDGE_WEBHOOK_URL");
+        let log_path = problems.env::<String>(env, "CHAT_BRIDGE_LOG_PATH");
+        if webhook_url.is_some() != log_path.is_some() {
+          problems.0.push(
+            "Expected both CHAT_BRIDGE_WEBHOOK_URL and CHAT_BRIDGE_LOG_PATH, or neither"
+              .to_string(),
+          );
+        }
+
+        ChatBridgeConfig {
+          channel_id,
+          webhook_url,
+          log_path,
+        }
+      });
+
+    let minutes =
+      |minutes: Option<u64>, default: u64| Duration::from_secs(minutes.unwrap_or(default) * 60);
+    let alert_thresholds = AlertThresholds {
+      failures: problems.env(env, "ALERT_FAILURE_THRESHOLD").unwrap_or(5),
+      downtime: minutes(problems.env(env, "ALERT_DOWNTIME_MINUTES"), 30),
+      cooldown: minutes(problems.env(env, "ALERT_COOLDOWN_MINUTES"), 60),
+    };
+
+    // Without a prefix, gamertags couldn't be told apart from Java names
+    let floodgate_prefix = match env("FLOODGATE_PREFIX") {
+      Some(prefix) if prefix.trim().is_empty() => {
+        problems
+          .0
+          .push("FLOODGATE_PREFIX can't be empty".to_string());
+        String::new()
+      }
+      Some(prefix) => prefix.trim().to_string(),
+      None => ".".to_string(),
+    };
+
+    Some(Self {
+      store_path: problems
+        .env(env, "STORE_PATH")
+        .unwrap_or_else(|| "store.json".to_string()),
+      status_online_format: problems.channel_name(env, "STATUS_ONLINE_FORMAT"),
+      status_offline_format: problems.channel_name(env, "STATUS_OFFLINE_FORMAT"),
+      status_smoothing_samples: problems.env(env, "STATUS_SMOOTHING_SAMPLES").unwrap_or(1),
+      status_channel_rename: problems.env(env, "STATUS_CHANNEL_RENAME").unwrap_or(true),
+      status_embed: problems.env(env, "STATUS_EMBED").unwrap_or(false),
+      update_presence: problems.env(env, "UPDATE_PRESENCE").unwrap_or(true),
+      presence_interval: seconds(
+        problems.env(env, "PRESENCE_INTERVAL_SECONDS"),
+        Duration::from_secs(60),
+      ),
+      bedrock_port: problems.env(env, "BEDROCK_PORT"),
+      full_server_note: problems.env(env, "FULL_SERVER_NOTE").unwrap_or(false),
+      sync_nickname: problems.env(env, "SYNC_NICKNAME").unwrap_or(true),
+      timezone_offset,
+      verify_hours,
+      daily_summary_time,
+      role_retry_attempts: problems.env(env, "ROLE_RETRY_ATTEMPTS").unwrap_or(3),
+      staff_role_name: problems.env(env, "STAFF_ROLE_NAME"),
+      staff_verify_command: problems.env(env, "STAFF_VERIFY_COMMAND"),
+      role_commands: problems
+        .parsed(env, "ROLE_COMMANDS", parse_role_commands)
+        .unwrap_or_default(),
+      role_commands_replace_whitelist: problems
+        .env(env, "ROLE_COMMANDS_REPLACE_WHITELIST")
+        .unwrap_or(false),
+      playtime_command: problems.env(env, "PLAYTIME_COMMAND"),
+      playtime_pattern,
+      allowed_guild_ids,
+      leave_unlisted_guilds: problems.env(env, "LEAVE_UNLISTED_GUILDS").unwrap_or(false),
+      verification_ttl_days: problems.env(env, "VERIFICATION_TTL_DAYS"),
+      verification_role_ttl_days: problems
+        .parsed(env, "VERIFICATION_ROLE_TTL_DAYS", parse_role_ttl_days)
+        .unwrap_or_default(),
+      verification_expiry_warning_days: problems
+        .env(env, "VERIFICATION_EXPIRY_WARNING_DAYS")
+        .unwrap_or(3),
+      web_verify,
+      chat_bridge,
+      alert_user_id: problems.env(env, "ALERT_USER_ID"),
+      audit_channel_id: problems.env(env, "DISCORD_AUDIT_CHANNEL_ID"),
+      alert_thresholds,
+      error_webhook_url: problems.env(env, "ERROR_WEBHOOK_URL"),
+      http_timeout: seconds(
+        problems.env(env, "HTTP_TIMEOUT_SECONDS"),
+        DEFAULT_HTTP_TIMEOUT,
+      ),
+      rcon_connect_timeout: seconds(
+        problems.env(env, "RCON_CONNECT_TIMEOUT_SECONDS"),
+        RCON_TIMEOUT,
+      ),
+      rcon_command_timeout: seconds(
+        problems.env(env, "RCON_COMMAND_TIMEOUT_SECONDS"),
+        RCON_TIMEOUT,
+      ),
+      bedrock_verify: problems.env(env, "BEDROCK_VERIFY").unwrap_or(false),
+      floodgate_prefix,
+      floodgate_command: problems.env(env, "FLOODGATE_COMMAND"),
+      link_command: problems
+        .env(env, "LINK_COMMAND")
+        .unwrap_or_else(|| "/discord link {code}".to_string()),
+      link_code_ttl: minutes(problems.env(env, "LINK_CODE_TTL_MINUTES"), 10),
+      online_commands: problems
+        .env::<String>(env, "ONLINE_COMMANDS")
+        .map(|commands| split_list(&commands, ';'))
+        .unwrap_or_default(),
+      mojang_outage_policy: problems
+        .parsed(env, "MOJANG_OUTAGE_POLICY", MojangOutagePolicy::parse)
+        .unwrap_or(MojangOutagePolicy::Reject),
+      offline_mode: problems.env(env, "OFFLINE_MODE").unwrap_or(false),
+      clean_verify_channel: problems.env(env, "CLEAN_VERIFY_CHANNEL").unwrap_or(true),
+      max_accounts_per_user: problems.count(env, "MAX_ACCOUNTS_PER_USER").unwrap_or(1),
+      ban_on_discord_kick: problems.env(env, "BAN_ON_DISCORD_KICK").unwrap_or(false),
+      rcon_blocklist: split_list(
+        &problems
+          .env::<String>(env, "RCON_BLOCKLIST")
+          .unwrap_or_else(|| DEFAULT_RCON_BLOCKLIST.to_string()),
+        ',',
+      ),
+      allow_dangerous_rcon: problems.env(env, "ALLOW_DANGEROUS_RCON").unwrap_or(false),
+      check_banlist: problems.env(env, "CHECK_BANLIST").unwrap_or(false),
+      verify_max_failed_attempts: problems
+        .count(env, "VERIFY_MAX_FAILED_ATTEMPTS")
+        .unwrap_or(5),
+      verify_attempt_window: problems
+        .env::<u64>(env, "VERIFY_ATTEMPT_WINDOW_MINUTES")
+        .map_or(DEFAULT_VERIFY_ATTEMPT_WINDOW, |minutes| {
+          Duration::from_secs(minutes * 60)
+        }),
+      verify_concurrency: problems.count(env, "VERIFY_CONCURRENCY").unwrap_or(3),
+      name_recently_freed_days: problems.env(env, "NAME_RECENTLY_FREED_DAYS").unwrap_or(30),
+      last_seen_retention_days: problems.env(env, "LAST_SEEN_RETENTION_DAYS").unwrap_or(90),
+      send_welcome_dm: problems.env(env, "SEND_WELCOME_DM").unwrap_or(false),
+      welcome_dm_text: problems.env(env, "WELCOME_DM_TEXT"),
+      server_join_address: problems.env(env, "SERVER_JOIN_ADDRESS"),
+      name_history_url: problems.env(env, "NAME_HISTORY_URL"),
+      teleport_command: problems.env(env, "POST_VERIFY_TELEPORT_COMMAND"),
+      ingame_role_name: problems.env(env, "INGAME_ROLE_NAME"),
+      metrics_addr: problems.env(env, "METRICS_ADDR"),
+      require_human_gate: problems.env(env, "REQUIRE_HUMAN_GATE").unwrap_or(false),
+      verify_challenge: problems.env(env, "VERIFY_CHALLENGE").unwrap_or(false),
+      // Last, so the settings above are checked even without it
+      discord_token: discord_token?,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::HashMap;
+
+  /// The environment with the variables, and a Discord token
+  fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let vars: HashMap<String, String> = [("DISCORD_TOKEN", "token")]
+      .iter()
+      .chain(vars)
+      .map(|(name, value)| (name.to_string(), value.to_string()))
+      .collect();
+    move |name| vars.get(name).cloned()
+  }
+
+  #[test]
+  fn the_environment_overrides_the_file() {
+    let file: FileConfig = toml::from_str(
+      r#"
+        server_address = "file.example.com"
+        rcon_password = "file password"
+        verify_channel_id = 1
+        rcon_port = 25580
+      "#,
+    )
+    .unwrap();
+
+    let config = Config::from_sources(file, env(&[("SERVER_ADDRESS", "env.example.com")])).unwrap();
+
+    assert_eq!(config.server.address, "env.example.com");
+    assert_eq!(config.server.rcon_password, "file password");
+    assert_eq!(config.server.rcon_port, 25580);
+    assert_eq!(config.server.query_port, None);
+    assert_eq!(config.verified_role_name, "Verified");
+    assert!(config.extra_servers.is_empty());
+  }
+
+  #[test]
+  fn the_log_format_can_be_in_the_file() {
+    let file = || -> FileConfig {
+      toml::from_str(
+        r#"
+          server_address = "example.com"
+          rcon_password = "secret"
+          verify_channel_id = 1
+          log_format = "json"
+        "#,
+      )
+      .unwrap()
+    };
+
+    let config = Config::from_sources(file(), env(&[])).unwrap();
+    assert_eq!(config.log_format, LogFormat::Json);
+
+    let config = Config::from_sources(file(), env(&[("LOG_FORMAT", "text")])).unwrap();
+    assert_eq!(config.log_format, LogFormat::Text);
+
+    let problems = Config::from_sources(file(), env(&[("LOG_FORMAT", "yaml")])).unwrap_err();
+    assert_eq!(
+      problems,
+      ["LOG_FORMAT is invalid: 'yaml', use text or json"]
+    );
+  }
+
+  #[test]
+  fn extra_servers_are_read_from_the_file() {
+    let file: FileConfig = toml::from_str(
+      r#"
+        server_address = "survival.example.com"
+        server_name = "Survival"
+        rcon_password = "secret"
+        verify_channel_id = 1
+
+        [[servers]]
+        name = "Creative"
+        address = "creative.example.com"
+        rcon_password = "other secret"
+        status_channel_id = 2
+        udp_query_port = 25585
+      "#,
+    )
+    .unwrap();
+
+    let config = Config::from_sources(file, env(&[])).unwrap();
+
+    assert_eq!(config.server.name, "Survival");
+    assert_eq!(
+      config.extra_servers,
+      [ServerConfig {
+        name: "Creative".to_string(),
+        address: "creative.example.com".to_string(),
+        rcon_port: 25575,
+        query_port: None,
+        udp_query_port: Some(25585),
+        rcon_password: "other secret".to_string(),
+        status_channel_id: Some(2),
+      }]
+    );
+  }
+
+  #[test]
+  fn the_reconcile_section_is_read() {
+    let file: FileConfig = toml::from_str(
+      r#"
+        server_address = "survival.example.com"
+        rcon_password = "secret"
+        verify_channel_id = 1
+
+        [reconcile]
+        channel_id = 3
+        auto_fix = true
+      "#,
+    )
+    .unwrap();
+
+    let config = Config::from_sources(file, env(&[("RECONCILE_INTERVAL_HOURS", "12")])).unwrap();
+
+    assert_eq!(
+      config.reconcile,
+      ReconcileConfig {
+        channel_id: Some(3),
+        interval: Duration::from_secs(12 * 60 * 60),
+        auto_fix: true,
+      }
+    );
+  }
+
+  #[test]
+  fn schedules_are_read_from_the_file() {
+    let file: FileConfig = toml::from_str(
+      r#"
+        server_address = "survival.example.com"
+        rcon_password = "secret"
+        verify_channel_id = 1
+        timezone = "+02:00"
+
+        [[schedules]]
+        cron = "55 3 * * *"
+        message = "Restarting in 5 minutes"
+
+        [[schedules]]
+        cron = "0 4 * * *"
+        command = "stop"
+
+        [[schedules]]
+        cron = "0 25 * * *"
+        command = "stop"
+      "#,
+    )
+    .unwrap();
+
+    let problems = Config::from_sources(file, env(&[])).unwrap_err();
+    assert_eq!(problems, ["The hour '25' of '0 25 * * *' is invalid"]);
+
+    let file: FileConfig = toml::from_str(
+      r#"
+        server_address = "survival.example.com"
+        rcon_password = "secret"
+        verify_channel_id = 1
+
+        [[schedules]]
+        cron = "0 4 * * sun"
+        command = "stop"
+      "#,
+    )
+    .unwrap();
+
+    let config = Config::from_sources(file, env(&[("TIMEZONE", "UTC")])).unwrap();
+    assert_eq!(
+      config.timezone,
+      Some(Timezone::Fixed(chrono::FixedOffset::east_opt(0).unwrap()))
+    );
+    assert_eq!(config.schedules.len(), 1);
+    assert_eq!(config.schedules[0].command.as_deref(), Some("stop"));
+  }
+
+  #[test]
+  fn the_role_colour_is_hex() {
+    let file: FileConfig = toml::from_str(
+      r##"
+        server_address = "survival.example.com"
+        rcon_password = "secret"
+        verify_channel_id = 1
+        verified_role_color = "#e91e63"
+      "##,
+    )
+    .unwrap();
+
+    let config = Config::from_sources(file, env(&[])).unwrap();
+    assert_eq!(config.verified_role_colour, 0xE91E63);
+    assert!(config.verified_role_hoist);
+
+    let problems = Config::from_sources(
+      FileConfig::default(),
+      env(&[
+        ("SERVER_ADDRESS", "example.com"),
+        ("RCON_PASSWORD", "secret"),
+        ("DISCORD_VERIFY_CHANNEL_ID", "1"),
+        ("VERIFIED_ROLE_COLOR", "blue"),
+      ]),
+    )
+    .unwrap_err();
+    assert_eq!(
+      problems,
+      ["VERIFIED_ROLE_COLOR is invalid: 'blue', use a hex color like #3498db"]
+    );
+  }
+
+  #[test]
+  fn the_address_can_have_the_query_port() {
+    let file: FileConfig = toml::from_str(
+      r#"
+        rcon_password = "secret"
+        verify_channel_id = 1
+
+        [[servers]]
+        name = "Creative"
+        address = "creative.example.com:25570"
+        rcon_password = "other secret"
+        query_port = 25571
+      "#,
+    )
+    .unwrap();
+
+    let problems = Config::from_sources(
+      file,
+      env(&[("SERVER_ADDRESS", "survival.example.com:25566")]),
+    )
+    .unwrap_err();
+    assert_eq!(
+      problems,
+      ["The address of the server 'Creative' has the port 25570, but the query port is set to 25571"]
+    );
+
+    let config = Config::from_sources(
+      FileConfig::default(),
+      env(&[
+        ("SERVER_ADDRESS", "survival.example.com:25566"),
+        ("RCON_PASSWORD", "secret"),
+        ("DISCORD_VERIFY_CHANNEL_ID", "1"),
+      ]),
+    )
+    .unwrap();
+    assert_eq!(config.server.address, "survival.example.com");
+    assert_eq!(config.server.query_port, Some(25566));
+  }
+
+  #[test]
+  fn blank_file_values_are_missing() {
+    let file: FileConfig = toml::from_str(
+      r#"
+        server_address = " "
+        rcon_password = ""
+        verify_channel_id = 1
+
+        [[servers]]
+        name = "Creative"
+        address = ""
+        rcon_password = "  "
+      "#,
+    )
+    .unwrap();
+
+    let problems = Config::from_sources(file, env(&[])).unwrap_err();
+
+    assert_eq!(
+      problems,
+      [
+        "SERVER_ADDRESS is missing, set it in the environment or as server_address in the config file",
+        "RCON_PASSWORD is missing, set it in the environment or as rcon_password in the config file",
+        "The address of the server 'Creative' is invalid: ''",
+        "The server 'Creative' in [[servers]] has no rcon_password",
+      ]
+    );
+  }
+
+  #[test]
+  fn server_names_have_to_be_unique() {
+    let file: FileConfig = toml::from_str(
+      r#"
+        server_address = "survival.example.com"
+        rcon_password = "secret"
+        verify_channel_id = 1
+
+        [[servers]]
+        name = "Main"
+        address = "creative.example.com"
+        rcon_password = "other secret"
+      "#,
+    )
+    .unwrap();
+
+    let problems = Config::from_sources(file, env(&[])).unwrap_err();
+
+    assert_eq!(problems, ["The server name 'Main' is used twice"]);
+  }
+
+  #[test]
+  fn every_problem_is_reported() {
+    let result = Config::from_sources(
+      FileConfig::default(),
+      env(&[("RCON_PORT", "not a port"), ("RCON_PASSWORD", "secret")]),
+    );
+
+    let problems = result.unwrap_err();
+    assert_eq!(problems.len(), 3, "{problems:?}");
+    assert!(problems[0].starts_with("SERVER_ADDRESS is missing"));
+    assert!(problems[1].starts_with("RCON_PORT is invalid"));
+    assert!(problems[2].starts_with("DISCORD_VERIFY_CHANNEL_ID is missing"));
+  }
+
+  #[test]
+  fn an_invalid_required_setting_is_not_also_missing() {
+    let problems = Config::from_sources(
+      FileConfig::default(),
+      env(&[
+        ("SERVER_ADDRESS", "example.com"),
+        ("RCON_PASSWORD", "secret"),
+        ("DISCORD_VERIFY_CHANNEL_ID", "abc"),
+      ]),
+    )
+    .unwrap_err();
+
+    assert_eq!(problems, ["DISCORD_VERIFY_CHANNEL_ID is invalid: 'abc'"]);
+  }
+
+  #[test]
+  fn other_guilds_are_read_from_the_file() {
+    let file: FileConfig = toml::from_str(
+      r#"
+        server_address = "mc.example.com"
+        rcon_password = "secret"
+        verify_channel_id = 1
+
+        [[guilds]]
+        id = 10
+        verify_channel_id = 11
+        role_id = 12
+
+        [[guilds]]
+        id = 10
+        verify_channel_id = 1
+      "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+      Config::from_sources(file, env(&[])).unwrap_err(),
+      [
+        "The guild 10 is in [[guilds]] twice",
+        "The guild 10 has the main verify channel, it doesn't need to be in [[guilds]]",
+      ]
+    );
+
+    let file: FileConfig = toml::from_str(
+      r#"
+        server_address = "mc.example.com"
+        rcon_password = "secret"
+        verify_channel_id = 1
+
+        [[guilds]]
+        id = 10
+        verify_channel_id = 11
+        status_channel_id = 13
+      "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+      Config::from_sources(file, env(&[])).unwrap().guilds,
+      [GuildConfig {
+        id: 10,
+        verify_channel_id: 11,
+        status_channel_id: Some(13),
+        role_id: None,
+      }]
+    );
+  }
+  #[test]
+  fn every_other_setting_is_checked_at_once() {
+    let problems = Config::from_sources(FileConfig::default(), |name: &str| {
+      [
+        ("SERVER_ADDRESS", "example.com"),
+        ("RCON_PASSWORD", "secret"),
+        ("DISCORD_VERIFY_CHANNEL_ID", "1"),
+        ("RCON_COMMAND_TIMEOUT_SECONDS", "soon"),
+        ("FLOODGATE_PREFIX", " "),
+        ("VERIFY_HOURS", "9-5"),
+        ("WEB_VERIFY_URL", "https://example.com/verify?token={token}"),
+      ]
+      .iter()
+      .find(|(variable, _)| *variable == name)
+      .map(|(_, value)| value.to_string())
+    })
+    .unwrap_err();
+
+    assert_eq!(
+      problems,
+      [
+        "DISCORD_TOKEN is missing, it has to be set in the environment",
+        "VERIFY_HOURS is invalid: '9-5'",
+        "WEB_VERIFY_LISTEN_ADDRESS is missing, it has to be set in the environment, since WEB_VERIFY_URL is set",
+        "WEB_VERIFY_SECRET is missing, it has to be set in the environment, since WEB_VERIFY_URL is set",
+        "FLOODGATE_PREFIX can't be empty",
+        "RCON_COMMAND_TIMEOUT_SECONDS is invalid: 'soon'",
+      ]
+    );
+
+    let config = Config::from_sources(
+      FileConfig::default(),
+      env(&[
+        ("SERVER_ADDRESS", "example.com"),
+        ("RCON_PASSWORD", "secret"),
+        ("DISCORD_VERIFY_CHANNEL_ID", "1"),
+        ("RCON_BLOCKLIST", "stop, ,op"),
+        ("VERIFY_ATTEMPT_WINDOW_MINUTES", "5"),
+      ]),
+    )
+    .unwrap();
+    assert_eq!(config.settings.rcon_blocklist, ["stop", "op"]);
+    assert_eq!(
+      config.settings.verify_attempt_window,
+      Duration::from_secs(5 * 60)
+    );
+    assert_eq!(config.settings.floodgate_prefix, ".");
+    assert_eq!(
+      config.settings.mojang_outage_policy,
+      MojangOutagePolicy::Reject
+    );
+  }
+  #[test]
+  fn status_formats_cant_be_empty() {
+    let problems = Config::from_sources(
+      FileConfig::default(),
+      env(&[
+        ("SERVER_ADDRESS", "example.com"),
+        ("RCON_PASSWORD", "secret"),
+        ("DISCORD_VERIFY_CHANNEL_ID", "1"),
+        ("STATUS_ONLINE_FORMAT", ""),
+        ("STATUS_OFFLINE_FORMAT", " "),
+      ]),
+    )
+    .unwrap_err();
+
+    assert_eq!(
+      problems,
+      [
+        "STATUS_ONLINE_FORMAT can't be empty, Discord needs a channel name",
+        "STATUS_OFFLINE_FORMAT can't be empty, Discord needs a channel name",
+      ]
+    );
+  }
+  #[test]
+  fn limits_cant_be_zero() {
+    let problems = Config::from_sources(
+      FileConfig::default(),
+      env(&[
+        ("SERVER_ADDRESS", "example.com"),
+        ("RCON_PASSWORD", "secret"),
+        ("DISCORD_VERIFY_CHANNEL_ID", "1"),
+        ("VERIFY_MAX_FAILED_ATTEMPTS", "0"),
+        ("VERIFY_CONCURRENCY", "0"),
+        ("MAX_ACCOUNTS_PER_USER", "0"),
+      ]),
+    )
+    .unwrap_err();
+
+    assert_eq!(
+      problems,
+      [
+        "MAX_ACCOUNTS_PER_USER has to be at least 1",
+        "VERIFY_MAX_FAILED_ATTEMPTS has to be at least 1",
+        "VERIFY_CONCURRENCY has to be at least 1",
+      ]
+    );
+  }
+}
@@ -0,0 +1,42 @@
+//! What can go wrong while handling a Discord event
+
+use serenity::Error as SerenityError;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum BotError {
+  /// A Discord API call failed, e.g. because of missing permissions
+  Discord(SerenityError),
+  /// The minecraft server couldn't be reached over RCON
+  Rcon(io::Error),
+  /// The Mojang API couldn't be reached or answered with something unexpected
+  Mojang(String),
+  /// The bot's configuration or its registered commands don't match what Discord sends
+  Config(String),
+}
+
+impl fmt::Display for BotError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Discord(error) => write!(f, "Discord error: {error}"),
+      Self::Rcon(error) => write!(f, "RCON error: {error}"),
+      Self::Mojang(message) => write!(f, "Mojang API error: {message}"),
+      Self::Config(message) => write!(f, "configuration error: {message}"),
+    }
+  }
+}
+
+impl std::error::Error for BotError {}
+
+impl From<SerenityError> for BotError {
+  fn from(error: SerenityError) -> Self {
+    Self::Discord(error)
+  }
+}
+
+impl From<io::Error> for BotError {
+  fn from(error: io::Error) -> Self {
+    Self::Rcon(error)
+  }
+}
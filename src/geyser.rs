@@ -0,0 +1,225 @@
+//! Bedrock players joining through Geyser and Floodgate. They have an Xbox gamertag instead of a
+//! Java account, which the Geyser global API turns into the XUID Floodgate builds their UUID from
+
+use crate::commands::{Mojang, UsernameError};
+use crate::mojang::{read_body, request_error, MojangError, MojangProfile};
+use serde::Deserialize;
+use serenity::async_trait;
+use std::fmt;
+
+/// Where gamertags are turned into XUIDs
+const XUID_API_URL: &str = "https://api.geysermc.org/v2/xbox/xuid/";
+
+/// Floodgate cuts the names of Bedrock players to the Java length limit
+const MAX_NAME_LENGTH: usize = 16;
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum XuidResponse {
+  Found { xuid: u64 },
+  Failure { message: String },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum GeyserError {
+  /// The API doesn't know the gamertag
+  NotFound,
+  RateLimited,
+  /// The API couldn't be reached or answered with something unexpected
+  Network(String),
+}
+
+impl fmt::Display for GeyserError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::NotFound => write!(f, "no such gamertag"),
+      Self::RateLimited => write!(f, "rate limited"),
+      Self::Network(reason) => write!(f, "{reason}"),
+    }
+  }
+}
+
+impl From<GeyserError> for MojangError {
+  fn from(error: GeyserError) -> Self {
+    match error {
+      GeyserError::NotFound => Self::NotFound,
+      GeyserError::RateLimited => Self::RateLimited { retry_after: None },
+      GeyserError::Network(reason) => Self::Network(reason),
+    }
+  }
+}
+
+fn read_xuid_response(status: u16, body: &str) -> Result<u64, GeyserError> {
+  if status == 429 {
+    return Err(GeyserError::RateLimited);
+  }
+
+  match serde_json::from_str(body) {
+    Ok(XuidResponse::Found { xuid }) if status == 200 => Ok(xuid),
+    Ok(XuidResponse::Failure { .. }) if matches!(status, 400 | 404) => Err(GeyserError::NotFound),
+    Ok(XuidResponse::Failure { message }) => Err(GeyserError::Network(format!(
+      "the Geyser API answered {status}: {message}"
+    ))),
+    Ok(XuidResponse::Found { .. }) | Err(_) => Err(GeyserError::Network(format!(
+      "unexpected answer from the Geyser API ({status})"
+    ))),
+  }
+}
+
+/// Looks the gamertag's XUID up with the Geyser global API
+pub async fn fetch_xuid(http: &reqwest::Client, gamertag: &str) -> Result<u64, GeyserError> {
+  let response = http
+    .get(format!("{XUID_API_URL}{}", gamertag.replace(' ', "%20")))
+    .send()
+    .await
+    .map_err(|error| GeyserError::Network(request_error(&error)))?;
+
+  let status = response.status().as_u16();
+  let body = read_body(response).await.map_err(GeyserError::Network)?;
+
+  read_xuid_response(status, &body)
+}
+
+/// The UUID Floodgate gives the player, without dashes like Mojang's ids. The XUID is its lower
+/// half, the upper half is zero
+pub fn floodgate_uuid(xuid: u64) -> String {
+  format!("{xuid:032x}")
+}
+
+/// The XUID in a Floodgate UUID, `None` for a Java account
+pub fn floodgate_xuid(uuid: &str) -> Option<u64> {
+  let uuid = uuid.replace('-', "");
+  let (upper, lower) = uuid.split_at_checked(16)?;
+
+  if lower.len() != 16 || upper.chars().any(|char| char != '0') {
+    return None;
+  }
+  u64::from_str_radix(lower, 16).ok()
+}
+
+/// Gamertags have letters, digits and spaces, and up to 15 of them
+pub fn validate_gamertag(gamertag: &str) -> Result<(), UsernameError> {
+  if let Some(char) = gamertag
+    .chars()
+    .find(|char| !char.is_ascii_alphanumeric() && *char != ' ')
+  {
+    return Err(UsernameError::InvalidCharacter(char));
+  }
+
+  match gamertag.len() {
+    0 => Err(UsernameError::TooShort),
+    1..=15 => Ok(()),
+    _ => Err(UsernameError::TooLong),
+  }
+}
+
+/// How Bedrock players are told apart and whitelisted
+pub struct Floodgate {
+  /// What Floodgate puts in front of the gamertags, `.` by default
+  pub prefix: String,
+  /// Floodgate's whitelist command, like `fwhitelist`. `None` uses `whitelist add` with the
+  /// Floodgate name
+  pub command: Option<String>,
+  pub http: reqwest::Client,
+}
+
+impl Floodgate {
+  /// Whether the name is a gamertag with the Floodgate prefix
+  pub fn is_bedrock_name(&self, username: &str) -> bool {
+    username.starts_with(&self.prefix)
+  }
+
+  pub fn gamertag<'a>(&self, username: &'a str) -> &'a str {
+    username.strip_prefix(&self.prefix).unwrap_or(username)
+  }
+
+  /// The name the player has on the Java server, with spaces replaced like Floodgate does
+  pub fn floodgate_name(&self, gamertag: &str) -> String {
+    format!("{}{}", self.prefix, gamertag.replace(' ', "_"))
+      .chars()
+      .take(MAX_NAME_LENGTH)
+      .collect()
+  }
+
+  pub fn whitelist_command(&self, name: &str) -> String {
+    match &self.command {
+      Some(command) => format!("{command} add {}", self.gamertag(name)),
+      None => format!("whitelist add {name}"),
+    }
+  }
+}
+
+/// Bedrock accounts, by their gamertag with or without the prefix
+#[async_trait]
+impl Mojang for Floodgate {
+  async fn profile(&self, username: &str) -> Result<MojangProfile, MojangError> {
+    let gamertag = self.gamertag(username);
+    let xuid = fetch_xuid(&self.http, gamertag).await?;
+
+    Ok(MojangProfile {
+      id: floodgate_uuid(xuid),
+      name: self.floodgate_name(gamertag),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn xuid_responses_are_parsed() {
+    assert_eq!(
+      read_xuid_response(200, r#"{"xuid":2535428650840391}"#),
+      Ok(2535428650840391)
+    );
+    assert_eq!(
+      read_xuid_response(
+        404,
+        r#"{"message":"Unable to find user in our cache. Please try specifying the xuid instead"}"#
+      ),
+      Err(GeyserError::NotFound)
+    );
+    assert_eq!(read_xuid_response(429, ""), Err(GeyserError::RateLimited));
+    assert!(matches!(
+      read_xuid_response(502, "<html>Bad Gateway</html>"),
+      Err(GeyserError::Network(_))
+    ));
+  }
+
+  #[test]
+  fn floodgate_uuids_hold_the_xuid() {
+    let uuid = floodgate_uuid(2535428650840391);
+
+    assert_eq!(uuid, "0000000000000000000901f57c163147");
+    assert_eq!(
+      floodgate_xuid("00000000-0000-0000-0009-01f57c163147"),
+      Some(2535428650840391)
+    );
+    assert_eq!(floodgate_xuid("069a79f444e94726a5befca90e38aaf5"), None);
+  }
+
+  #[test]
+  fn gamertags_become_floodgate_names() {
+    let floodgate = Floodgate {
+      prefix: ".".to_string(),
+      command: Some("fwhitelist".to_string()),
+      http: reqwest::Client::new(),
+    };
+
+    assert_eq!(floodgate.floodgate_name("Steve Gamer"), ".Steve_Gamer");
+    assert_eq!(
+      floodgate.floodgate_name("LongGamertag123"),
+      ".LongGamertag123"
+    );
+    assert_eq!(
+      floodgate.whitelist_command(".Steve_Gamer"),
+      "fwhitelist add Steve_Gamer"
+    );
+    assert_eq!(validate_gamertag("Steve Gamer"), Ok(()));
+    assert_eq!(
+      validate_gamertag("Steve.Gamer"),
+      Err(UsernameError::InvalidCharacter('.'))
+    );
+  }
+}
@@ -0,0 +1,23 @@
+pub mod address;
+pub mod alerts;
+pub mod bedrock;
+pub mod bot;
+pub mod chat;
+pub mod commands;
+pub mod config;
+pub mod error;
+pub mod geyser;
+pub mod logging;
+pub mod messages;
+pub mod metrics;
+pub mod mojang;
+pub mod observers;
+pub mod offline;
+pub mod ping;
+pub mod rcon;
+pub mod schedule;
+pub mod status;
+pub mod store;
+pub mod web;
+
+pub use bot::run;
@@ -0,0 +1,89 @@
+//! The log output: a level per target from `RUST_LOG`, the fields of the spans around each line,
+//! and optionally one JSON object per line for log aggregation
+
+use std::fmt;
+use std::str::FromStr;
+use tracing_subscriber::EnvFilter;
+
+/// Serenity and the HTTP stack below it are chatty, only their problems are shown by default
+const DEFAULT_FILTER: &str = "info,serenity=warn,tungstenite=warn,tokio_tungstenite=warn,hyper=warn,reqwest=warn,h2=warn,rustls=warn";
+
+/// How each log line is printed, set by LOG_FORMAT
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LogFormat {
+  /// Human readable lines
+  #[default]
+  Text,
+  /// One JSON object per line
+  Json,
+}
+
+impl FromStr for LogFormat {
+  type Err = ();
+
+  fn from_str(format: &str) -> Result<Self, Self::Err> {
+    match format.trim().to_lowercase().as_str() {
+      "text" => Ok(Self::Text),
+      "json" => Ok(Self::Json),
+      _ => Err(()),
+    }
+  }
+}
+
+impl fmt::Display for LogFormat {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(match self {
+      Self::Text => "text",
+      Self::Json => "json",
+    })
+  }
+}
+
+/// The filter from `RUST_LOG`, or the default when it isn't set or can't be parsed
+fn filter(directives: Option<&str>) -> EnvFilter {
+  directives
+    .and_then(|directives| EnvFilter::try_new(directives).ok())
+    .unwrap_or_else(|| EnvFilter::new(DEFAULT_FILTER))
+}
+
+/// Starts logging. `RUST_LOG` overrides the default levels
+pub fn init(format: LogFormat) {
+  let directives = std::env::var("RUST_LOG").ok();
+  let builder = tracing_subscriber::fmt().with_env_filter(filter(directives.as_deref()));
+
+  let result = match format {
+    LogFormat::Text => builder.try_init(),
+    LogFormat::Json => builder.json().try_init(),
+  };
+  if result.is_err() {
+    eprintln!("Logging was already set up");
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn directives_are_parsed() {
+    assert_eq!(
+      filter(Some("warn,serenity=off,mc_discord_bot::store=debug")).to_string(),
+      "mc_discord_bot::store=debug,serenity=off,warn"
+    );
+  }
+
+  #[test]
+  fn formats_are_parsed() {
+    assert_eq!(" JSON ".parse(), Ok(LogFormat::Json));
+    assert_eq!("text".parse(), Ok(LogFormat::Text));
+    assert_eq!("yaml".parse::<LogFormat>(), Err(()));
+  }
+
+  #[test]
+  fn the_default_is_used_without_valid_directives() {
+    let default = EnvFilter::new(DEFAULT_FILTER).to_string();
+
+    assert_eq!(filter(None).to_string(), default);
+    assert_eq!(filter(Some("serenity=loud")).to_string(), default);
+  }
+}
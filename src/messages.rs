@@ -0,0 +1,895 @@
+//! The messages members see, so a community can translate or reword them. They're read from a TOML
+//! file (`messages.toml`, or the path in `MESSAGES_PATH`) of `key = "template"` lines, and the
+//! English text is used for every key the file leaves out. Templates can have `{placeholder}`s,
+//! `{{` and `}}` are literal braces
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Display;
+use std::fs;
+
+const DEFAULT_MESSAGES_PATH: &str = "messages.toml";
+
+/// A message members can see
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MessageKey {
+  StatusNotChecked,
+  StatusAge,
+  StatusOnlineTitle,
+  StatusOfflineTitle,
+  StatusPlayersField,
+  PlayersOffline,
+  PlayersNobody,
+  PlayersUnlisted,
+  PlayersTitle,
+  ServerInfoOffline,
+  ServerInfoNeverReached,
+  ServerInfoVersionField,
+  ServerInfoVersion,
+  ServerInfoLatencyField,
+  ServerInfoLatency,
+  LastSeenCaveat,
+  LastSeenPartial,
+  LastSeenNever,
+  LastSeenTitle,
+  LastSeenOnlineField,
+  LastSeenPlaytimeField,
+  LastSeenPlaytime,
+  UsernameTooShort,
+  UsernameTooLong,
+  UsernameSpaces,
+  UsernameInvalidCharacter,
+  MojangNotFound,
+  MojangInvalidName,
+  MojangRejected,
+  MojangRateLimited,
+  MojangUnavailable,
+  AccountLimitOne,
+  AccountLimit,
+  LinkedToOtherUser,
+  ServerUnreachable,
+  ServerNotResponding,
+  Banned,
+  AccessFailed,
+  WhitelistPlayerNotFound,
+  VerifySuccess,
+  VerifyAlreadyWhitelisted,
+  VerifyRoleMissingPermission,
+  VerifyRoleFailed,
+  VerifyUnvalidated,
+  VerifyQueuedForMojang,
+  VerifyTooManyAttempts,
+  VerifyInQueue,
+  VerifyQueueTurn,
+  VerifyTimedOut,
+  VerifyTimedOutOfflineMode,
+  VerifyHours,
+  BotStarting,
+  VerifiedRoleMissing,
+  ChallengeNotOnline,
+  ChallengeInGame,
+  ChallengeSent,
+  ConfirmNoCode,
+  ConfirmExpired,
+  ConfirmWrongCode,
+  HumanGate,
+  HumanGateButton,
+  HumanGateVerifying,
+  HumanGateExpired,
+  RolePerksFailed,
+  RolePerksDone,
+  StaffPermissionsDone,
+  StaffPermissionsFailed,
+  TeleportNotOnline,
+  TeleportDone,
+  TeleportFailed,
+  NicknameFailed,
+  NameRecentlyChanged,
+  FullServer,
+  WelcomeTitle,
+  WelcomeAddressField,
+  WelcomeDmFailed,
+  UnverifyNotVerifiedName,
+  UnverifyNotVerified,
+  UnverifyServerUnreachable,
+  UnverifyRemoved,
+  UnverifyRemovedLast,
+  UnverifyRoleFailed,
+  VerifyInfoTitle,
+  VerifyInfoDescription,
+  VerifyInfoFooter,
+  VerifyButton,
+  VerifyButtonServer,
+  VerifyFormTitle,
+  VerifyFormUsername,
+  StatusChannelOnline,
+  StatusChannelEditions,
+  StatusChannelOffline,
+}
+
+/// The key of a message in the file, its English text and the placeholders it can have
+struct Definition {
+  key: &'static str,
+  default: &'static str,
+  placeholders: &'static [&'static str],
+}
+
+const fn definition(
+  key: &'static str,
+  default: &'static str,
+  placeholders: &'static [&'static str],
+) -> Definition {
+  Definition {
+    key,
+    default,
+    placeholders,
+  }
+}
+
+impl MessageKey {
+  pub const ALL: [Self; 92] = [
+    Self::StatusNotChecked,
+    Self::StatusAge,
+    Self::StatusOnlineTitle,
+    Self::StatusOfflineTitle,
+    Self::StatusPlayersField,
+    Self::PlayersOffline,
+    Self::PlayersNobody,
+    Self::PlayersUnlisted,
+    Self::PlayersTitle,
+    Self::ServerInfoOffline,
+    Self::ServerInfoNeverReached,
+    Self::ServerInfoVersionField,
+    Self::ServerInfoVersion,
+    Self::ServerInfoLatencyField,
+    Self::ServerInfoLatency,
+    Self::LastSeenCaveat,
+    Self::LastSeenPartial,
+    Self::LastSeenNever,
+    Self::LastSeenTitle,
+    Self::LastSeenOnlineField,
+    Self::LastSeenPlaytimeField,
+    Self::LastSeenPlaytime,
+    Self::UsernameTooShort,
+    Self::UsernameTooLong,
+    Self::UsernameSpaces,
+    Self::UsernameInvalidCharacter,
+    Self::MojangNotFound,
+    Self::MojangInvalidName,
+    Self::MojangRejected,
+    Self::MojangRateLimited,
+    Self::MojangUnavailable,
+    Self::AccountLimitOne,
+    Self::AccountLimit,
+    Self::LinkedToOtherUser,
+    Self::ServerUnreachable,
+    Self::ServerNotResponding,
+    Self::Banned,
+    Self::AccessFailed,
+    Self::WhitelistPlayerNotFound,
+    Self::VerifySuccess,
+    Self::VerifyAlreadyWhitelisted,
+    Self::VerifyRoleMissingPermission,
+    Self::VerifyRoleFailed,
+    Self::VerifyUnvalidated,
+    Self::VerifyQueuedForMojang,
+    Self::VerifyTooManyAttempts,
+    Self::VerifyInQueue,
+    Self::VerifyQueueTurn,
+    Self::VerifyTimedOut,
+    Self::VerifyTimedOutOfflineMode,
+    Self::VerifyHours,
+    Self::BotStarting,
+    Self::VerifiedRoleMissing,
+    Self::ChallengeNotOnline,
+    Self::ChallengeInGame,
+    Self::ChallengeSent,
+    Self::ConfirmNoCode,
+    Self::ConfirmExpired,
+    Self::ConfirmWrongCode,
+    Self::HumanGate,
+    Self::HumanGateButton,
+    Self::HumanGateVerifying,
+    Self::HumanGateExpired,
+    Self::RolePerksFailed,
+    Self::RolePerksDone,
+    Self::StaffPermissionsDone,
+    Self::StaffPermissionsFailed,
+    Self::TeleportNotOnline,
+    Self::TeleportDone,
+    Self::TeleportFailed,
+    Self::NicknameFailed,
+    Self::NameRecentlyChanged,
+    Self::FullServer,
+    Self::WelcomeTitle,
+    Self::WelcomeAddressField,
+    Self::WelcomeDmFailed,
+    Self::UnverifyNotVerifiedName,
+    Self::UnverifyNotVerified,
+    Self::UnverifyServerUnreachable,
+    Self::UnverifyRemoved,
+    Self::UnverifyRemovedLast,
+    Self::UnverifyRoleFailed,
+    Self::VerifyInfoTitle,
+    Self::VerifyInfoDescription,
+    Self::VerifyInfoFooter,
+    Self::VerifyButton,
+    Self::VerifyButtonServer,
+    Self::VerifyFormTitle,
+    Self::VerifyFormUsername,
+    Self::StatusChannelOnline,
+    Self::StatusChannelEditions,
+    Self::StatusChannelOffline,
+  ];
+
+  fn definition(self) -> Definition {
+    match self {
+      Self::StatusNotChecked => definition(
+        "status_not_checked",
+        "The server status hasn't been checked yet. Try again in a moment.",
+        &[],
+      ),
+      Self::StatusAge => definition("status_age", "As of {seconds} seconds ago", &["seconds"]),
+      Self::StatusOnlineTitle => definition("status_online_title", "🎮 Server online", &[]),
+      Self::StatusOfflineTitle => definition("status_offline_title", "🛑 Server offline", &[]),
+      Self::StatusPlayersField => definition("status_players_field", "Players", &[]),
+      Self::PlayersOffline => definition(
+        "players_offline",
+        "The server is offline. ({age})",
+        &["age"],
+      ),
+      Self::PlayersNobody => definition("players_nobody", "Nobody is online right now", &[]),
+      Self::PlayersUnlisted => definition(
+        "players_unlisted",
+        "...and {count} more (the server only lists some of the players)",
+        &["count"],
+      ),
+      Self::PlayersTitle => definition(
+        "players_title",
+        "Players online ({online}/{max})",
+        &["online", "max"],
+      ),
+      Self::ServerInfoOffline => definition(
+        "server_info_offline",
+        "The server is offline. It last answered {last_contact}.",
+        &["last_contact"],
+      ),
+      Self::ServerInfoNeverReached => definition(
+        "server_info_never_reached",
+        "The server is offline, and it hasn't answered since the bot started.",
+        &[],
+      ),
+      Self::ServerInfoVersionField => definition("server_info_version_field", "Version", &[]),
+      Self::ServerInfoVersion => definition(
+        "server_info_version",
+        "{version} (protocol {protocol})",
+        &["version", "protocol"],
+      ),
+      Self::ServerInfoLatencyField => definition("server_info_latency_field", "Latency", &[]),
+      Self::ServerInfoLatency => definition(
+        "server_info_latency",
+        "{milliseconds} ms",
+        &["milliseconds"],
+      ),
+      Self::LastSeenCaveat => definition(
+        "last_seen_caveat",
+        "The server is checked every {seconds} seconds, so short visits are missed and the playtime is approximate.",
+        &["seconds"],
+      ),
+      Self::LastSeenPartial => definition(
+        "last_seen_partial",
+        "The server only lists some of the players online, so not everyone is seen.",
+        &[],
+      ),
+      Self::LastSeenNever => definition(
+        "last_seen_never",
+        "'{username}' hasn't been seen online. {caveat}",
+        &["username", "caveat"],
+      ),
+      Self::LastSeenTitle => definition("last_seen_title", "Last seen: {name}", &["name"]),
+      Self::LastSeenOnlineField => definition("last_seen_online_field", "Last online", &[]),
+      Self::LastSeenPlaytimeField => definition("last_seen_playtime_field", "Playtime", &[]),
+      Self::LastSeenPlaytime => definition(
+        "last_seen_playtime",
+        "about {playtime}",
+        &["playtime"],
+      ),
+      Self::UsernameTooShort => definition(
+        "username_too_short",
+        "'{username}' is too short, usernames are at least 3 characters.",
+        &["username"],
+      ),
+      Self::UsernameTooLong => definition(
+        "username_too_long",
+        "'{username}' is too long, usernames are at most 16 characters.",
+        &["username"],
+      ),
+      Self::UsernameSpaces => definition("username_spaces", "Usernames can't contain spaces.", &[]),
+      Self::UsernameInvalidCharacter => definition(
+        "username_invalid_character",
+        "Usernames can't contain '{character}', only letters, numbers and underscores.",
+        &["character"],
+      ),
+      Self::MojangNotFound => definition(
+        "mojang_not_found",
+        "There isn't a Mojang user with '{username}' username. Please try again.",
+        &["username"],
+      ),
+      Self::MojangInvalidName => definition(
+        "mojang_invalid_name",
+        "'{username}' isn't a valid Minecraft username. Usernames are 3-16 letters, numbers or underscores.",
+        &["username"],
+      ),
+      Self::MojangRejected => definition(
+        "mojang_rejected",
+        "Mojang couldn't look up '{username}' ({reason}). Please try again later.",
+        &["username", "reason"],
+      ),
+      Self::MojangRateLimited => definition(
+        "mojang_rate_limited",
+        "Mojang is getting too many requests right now. Please try again in {seconds} seconds.",
+        &["seconds"],
+      ),
+      Self::MojangUnavailable => definition(
+        "mojang_unavailable",
+        "Couldn't fetch the profile from the Mojang API. Please try again.",
+        &[],
+      ),
+      Self::AccountLimitOne => definition(
+        "account_limit_one",
+        "You have already verified a username, please contact an admin if you have verified the wrong username or need to change it.",
+        &[],
+      ),
+      Self::AccountLimit => definition(
+        "account_limit",
+        "You have already verified {max} usernames, the most one member can verify. Use `/unverify` with a username to remove one first.",
+        &["max"],
+      ),
+      Self::LinkedToOtherUser => definition(
+        "linked_to_other_user",
+        "That Minecraft account is already linked to another Discord user; contact an admin.",
+        &[],
+      ),
+      Self::ServerUnreachable => definition(
+        "server_unreachable",
+        "Could not connect to the minecraft server. Probably because it is offline right now. Try again later",
+        &[],
+      ),
+      Self::ServerNotResponding => definition(
+        "server_not_responding",
+        "The server accepted the connection but didn't respond. It's probably starting up or busy, try again in a minute.",
+        &[],
+      ),
+      Self::Banned => definition("banned", "That account is banned on this server.", &[]),
+      Self::AccessFailed => definition(
+        "access_failed",
+        "Something went wrong... The server is probably offline right now. Try again when the server is online",
+        &[],
+      ),
+      Self::WhitelistPlayerNotFound => definition(
+        "whitelist_player_not_found",
+        "The server couldn't find the account '{name}', so it wasn't whitelisted. Check the name and try again.",
+        &["name"],
+      ),
+      Self::VerifySuccess => definition(
+        "verify_success",
+        "'{name}' was successfully added to the whitelist!",
+        &["name"],
+      ),
+      Self::VerifyAlreadyWhitelisted => definition(
+        "verify_already_whitelisted",
+        "'{name}' was already on the whitelist, it's linked to your account now!",
+        &["name"],
+      ),
+      Self::VerifyRoleMissingPermission => definition(
+        "verify_role_missing_permission",
+        "'{name}' was added to the whitelist, but I don't have permission to give you the Verified role. Please ask an admin to check that my role can manage roles and is above the Verified role.",
+        &["name"],
+      ),
+      Self::VerifyRoleFailed => definition(
+        "verify_role_failed",
+        "'{name}' was added to the whitelist, but I couldn't give you the Verified role. Please contact an admin.",
+        &["name"],
+      ),
+      Self::VerifyUnvalidated => definition(
+        "verify_unvalidated",
+        "Mojang is down right now, so your username was whitelisted as typed. It'll be checked once Mojang is back.",
+        &[],
+      ),
+      Self::VerifyQueuedForMojang => definition(
+        "verify_queued_for_mojang",
+        "Mojang is down right now. Your verification is queued and I'll DM you once it goes through.",
+        &[],
+      ),
+      Self::VerifyTooManyAttempts => definition(
+        "verify_too_many_attempts",
+        "Too many attempts, try again in {minutes} minutes.",
+        &["minutes"],
+      ),
+      Self::VerifyInQueue => definition(
+        "verify_in_queue",
+        "You're in a short queue, hang tight...",
+        &[],
+      ),
+      Self::VerifyQueueTurn => definition(
+        "verify_queue_turn",
+        "It's your turn, verifying now...",
+        &[],
+      ),
+      Self::VerifyTimedOut => definition(
+        "verify_timed_out",
+        "Verifying is taking long, the server or Mojang might be struggling. The result follows here once it's done.",
+        &[],
+      ),
+      Self::VerifyTimedOutOfflineMode => definition(
+        "verify_timed_out_offline_mode",
+        "Verifying is taking long, the server might be struggling. The result follows here once it's done.",
+        &[],
+      ),
+      Self::VerifyHours => definition(
+        "verify_hours",
+        "Verification is available {hours} server time.",
+        &["hours"],
+      ),
+      Self::BotStarting => definition(
+        "bot_starting",
+        "The bot is still starting up, please try again in a moment.",
+        &[],
+      ),
+      Self::VerifiedRoleMissing => definition(
+        "verified_role_missing",
+        "The Verified role is missing, please contact an admin.",
+        &[],
+      ),
+      Self::ChallengeNotOnline => definition(
+        "challenge_not_online",
+        "'{username}' has to be online on the server to get a verification code. Join the server, then use /verify again.",
+        &["username"],
+      ),
+      Self::ChallengeInGame => definition(
+        "challenge_in_game",
+        "Your Discord verification code is {code}. Use /confirm {code} in Discord within {minutes} minutes.",
+        &["code", "minutes"],
+      ),
+      Self::ChallengeSent => definition(
+        "challenge_sent",
+        "I sent a code to '{name}' in-game. Use `/confirm <code>` within {minutes} minutes to finish verifying.",
+        &["name", "minutes"],
+      ),
+      Self::ConfirmNoCode => definition(
+        "confirm_no_code",
+        "You don't have a code to confirm, use /verify first.",
+        &[],
+      ),
+      Self::ConfirmExpired => definition(
+        "confirm_expired",
+        "Your code expired, use /verify again to get a new one.",
+        &[],
+      ),
+      Self::ConfirmWrongCode => definition(
+        "confirm_wrong_code",
+        "That code is wrong, check the message you got in-game.",
+        &[],
+      ),
+      Self::HumanGate => definition(
+        "human_gate",
+        "Click the button within {seconds} seconds to verify '{username}'.",
+        &["seconds", "username"],
+      ),
+      Self::HumanGateButton => definition("human_gate_button", "I'm human", &[]),
+      Self::HumanGateVerifying => definition("human_gate_verifying", "Verifying...", &[]),
+      Self::HumanGateExpired => definition(
+        "human_gate_expired",
+        "This button expired, please use /verify again.",
+        &[],
+      ),
+      Self::RolePerksFailed => definition(
+        "role_perks_failed",
+        "Setting up the perks of some of your roles failed, please ask an admin to do it.",
+        &[],
+      ),
+      Self::RolePerksDone => definition(
+        "role_perks_done",
+        "The perks of your roles were set up on the server too.",
+        &[],
+      ),
+      Self::StaffPermissionsDone => definition(
+        "staff_permissions_done",
+        "As a staff member, you were also given staff permissions on the server.",
+        &[],
+      ),
+      Self::StaffPermissionsFailed => definition(
+        "staff_permissions_failed",
+        "Giving you staff permissions on the server failed, please ask an admin to do it.",
+        &[],
+      ),
+      Self::TeleportNotOnline => definition(
+        "teleport_not_online",
+        "You're not online right now, so you'll start wherever the server puts new players.",
+        &[],
+      ),
+      Self::TeleportDone => definition("teleport_done", "You were teleported to spawn.", &[]),
+      Self::TeleportFailed => definition(
+        "teleport_failed",
+        "Teleporting you to spawn failed, but you're still verified.",
+        &[],
+      ),
+      Self::NicknameFailed => definition(
+        "nickname_failed",
+        "I couldn't change your nickname to your Minecraft name, please change it yourself.",
+        &[],
+      ),
+      Self::NameRecentlyChanged => definition(
+        "name_recently_changed",
+        "Heads up: the account only changed its name to '{name}' {days} days ago. If this name used to be yours, it now belongs to someone else, so please contact an admin.",
+        &["name", "days"],
+      ),
+      Self::FullServer => definition(
+        "full_server",
+        "Note: the server is currently full; you may need to wait for a slot.",
+        &[],
+      ),
+      Self::WelcomeTitle => definition("welcome_title", "Welcome, {name}!", &["name"]),
+      Self::WelcomeAddressField => definition("welcome_address_field", "Server address", &[]),
+      Self::WelcomeDmFailed => definition(
+        "welcome_dm_failed",
+        "I couldn't DM you the welcome message (your DMs might be closed). Join the server at `{address}`.",
+        &["address"],
+      ),
+      Self::UnverifyNotVerifiedName => definition(
+        "unverify_not_verified_name",
+        "You haven't verified '{username}'.",
+        &["username"],
+      ),
+      Self::UnverifyNotVerified => definition(
+        "unverify_not_verified",
+        "You haven't verified a Minecraft account.",
+        &[],
+      ),
+      Self::UnverifyServerUnreachable => definition(
+        "unverify_server_unreachable",
+        "Couldn't reach the server, so nothing was changed. Try again when it's online.",
+        &[],
+      ),
+      Self::UnverifyRemoved => definition(
+        "unverify_removed",
+        "'{names}' was removed from the whitelist.",
+        &["names"],
+      ),
+      Self::UnverifyRemovedLast => definition(
+        "unverify_removed_last",
+        "'{names}' was removed from the whitelist and you are no longer verified.",
+        &["names"],
+      ),
+      Self::UnverifyRoleFailed => definition(
+        "unverify_role_failed",
+        "'{names}' was removed from the whitelist, but I couldn't remove your Verified role. Please contact an admin.",
+        &["names"],
+      ),
+      Self::VerifyInfoTitle => definition("verify_info_title", "Verification Ready!", &[]),
+      Self::VerifyInfoDescription => definition(
+        "verify_info_description",
+        "Type `/verify <username>` or click the button below to add your minecraft profile to the server whitelist.",
+        &[],
+      ),
+      Self::VerifyInfoFooter => definition(
+        "verify_info_footer",
+        "Minecraft Verification Bot",
+        &[],
+      ),
+      Self::VerifyButton => definition("verify_button", "Verify", &[]),
+      Self::VerifyButtonServer => definition(
+        "verify_button_server",
+        "Verify on {server}",
+        &["server"],
+      ),
+      Self::VerifyFormTitle => definition("verify_form_title", "Verify", &[]),
+      Self::VerifyFormUsername => definition(
+        "verify_form_username",
+        "Minecraft username",
+        &[],
+      ),
+      Self::StatusChannelOnline => definition(
+        "status_channel_online",
+        "🎮 Players online: {online} 🎮",
+        &["online", "max", "editions"],
+      ),
+      Self::StatusChannelEditions => definition(
+        "status_channel_editions",
+        "🎮 {editions}, Total: {online} 🎮",
+        &["online", "max", "editions"],
+      ),
+      Self::StatusChannelOffline => definition(
+        "status_channel_offline",
+        "🛑 Server offline 🛑",
+        &[],
+      ),
+    }
+  }
+
+  /// Whether the message is the name of the status channel
+  fn is_channel_name(self) -> bool {
+    matches!(
+      self,
+      Self::StatusChannelOnline | Self::StatusChannelEditions | Self::StatusChannelOffline
+    )
+  }
+
+  /// The key of the message in the messages file
+  pub fn key(self) -> &'static str {
+    self.definition().key
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+  Text(String),
+  Placeholder(String),
+}
+
+/// A message split into its text and placeholders
+#[derive(Clone, Debug)]
+struct Template {
+  source: String,
+  parts: Vec<Part>,
+}
+
+impl Template {
+  /// Only the given placeholders are allowed, so a typo shows up when the file is loaded instead
+  /// of in a reply
+  fn parse(source: &str, placeholders: &[&str]) -> Result<Self, String> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut chars = source.chars().peekable();
+
+    while let Some(char) = chars.next() {
+      match char {
+        '{' if chars.peek() == Some(&'{') => {
+          chars.next();
+          text.push('{');
+        }
+        '}' if chars.peek() == Some(&'}') => {
+          chars.next();
+          text.push('}');
+        }
+        '{' => {
+          let mut name = String::new();
+          loop {
+            match chars.next() {
+              Some('}') => break,
+              Some('{') => return Err("has a '{' inside a placeholder".to_string()),
+              Some(char) => name.push(char),
+              None => return Err(format!("has an unclosed placeholder '{{{name}'")),
+            }
+          }
+          if !placeholders.contains(&name.as_str()) {
+            return Err(if placeholders.is_empty() {
+              format!("has the placeholder {{{name}}}, but can't have any")
+            } else {
+              format!(
+                "has the placeholder {{{name}}}, but can only have {}",
+                placeholders
+                  .iter()
+                  .map(|placeholder| format!("{{{placeholder}}}"))
+                  .collect::<Vec<_>>()
+                  .join(", ")
+              )
+            });
+          }
+          if !text.is_empty() {
+            parts.push(Part::Text(std::mem::take(&mut text)));
+          }
+          parts.push(Part::Placeholder(name));
+        }
+        '}' => return Err("has a '}' without a '{', write '}}' for a brace".to_string()),
+        char => text.push(char),
+      }
+    }
+    if !text.is_empty() {
+      parts.push(Part::Text(text));
+    }
+
+    Ok(Self {
+      source: source.to_string(),
+      parts,
+    })
+  }
+}
+
+/// The messages to show, the English ones unless the file replaced them
+#[derive(Clone, Debug)]
+pub struct Messages {
+  templates: HashMap<MessageKey, Template>,
+}
+
+impl Default for Messages {
+  fn default() -> Self {
+    let templates = MessageKey::ALL
+      .into_iter()
+      .map(|message| {
+        let definition = message.definition();
+        let template = Template::parse(definition.default, definition.placeholders)
+          .expect("The default messages are valid");
+        (message, template)
+      })
+      .collect();
+
+    Self { templates }
+  }
+}
+
+impl Messages {
+  /// Loads the messages file. Also returns the keys the file doesn't have, which keep the English
+  /// text. Every invalid message is in the error
+  pub fn load() -> Result<(Self, Vec<&'static str>), Vec<String>> {
+    let explicit_path = env::var("MESSAGES_PATH").ok();
+    let path = explicit_path.as_deref().unwrap_or(DEFAULT_MESSAGES_PATH);
+
+    match fs::read_to_string(path) {
+      Ok(contents) => Self::parse(&contents).map_err(|problems| {
+        problems
+          .into_iter()
+          .map(|problem| format!("{path}: {problem}"))
+          .collect()
+      }),
+      // Only the default path is optional, without it every message is in English
+      Err(_) if explicit_path.is_none() => Ok((Self::default(), Vec::new())),
+      Err(error) => Err(vec![format!(
+        "Couldn't read the messages file {path}: {error}"
+      )]),
+    }
+  }
+
+  /// Reads the messages from a TOML table of keys to templates
+  pub fn parse(contents: &str) -> Result<(Self, Vec<&'static str>), Vec<String>> {
+    let mut file: HashMap<String, String> = toml::from_str(contents)
+      .map_err(|error| vec![format!("Couldn't parse the messages: {error}")])?;
+
+    let mut messages = Self::default();
+    let mut missing = Vec::new();
+    let mut problems = Vec::new();
+
+    for message in MessageKey::ALL {
+      let definition = message.definition();
+      let Some(source) = file.remove(definition.key) else {
+        missing.push(definition.key);
+        continue;
+      };
+
+      match Template::parse(&source, definition.placeholders) {
+        Ok(_) if message.is_channel_name() && source.trim().is_empty() => problems.push(format!(
+          "{} can't be empty, Discord needs a channel name",
+          definition.key
+        )),
+        Ok(template) => {
+          messages.templates.insert(message, template);
+        }
+        Err(problem) => problems.push(format!("{} {problem}", definition.key)),
+      }
+    }
+
+    let mut unknown: Vec<String> = file.into_keys().collect();
+    unknown.sort();
+    problems.extend(
+      unknown
+        .into_iter()
+        .map(|key| format!("{key} isn't a message")),
+    );
+
+    if problems.is_empty() {
+      Ok((messages, missing))
+    } else {
+      Err(problems)
+    }
+  }
+
+  /// The message with its placeholders filled in. A placeholder without a value is left out
+  pub fn render(&self, message: MessageKey, values: &[(&str, &dyn Display)]) -> String {
+    let mut rendered = String::new();
+
+    for part in &self.templates[&message].parts {
+      match part {
+        Part::Text(text) => rendered.push_str(text),
+        Part::Placeholder(name) => {
+          if let Some((_, value)) = values.iter().find(|(key, _)| key == name) {
+            rendered.push_str(&value.to_string());
+          }
+        }
+      }
+    }
+
+    rendered
+  }
+
+  /// A message without placeholders
+  pub fn text(&self, message: MessageKey) -> String {
+    self.render(message, &[])
+  }
+
+  /// The message as written, for the templates that are filled in elsewhere
+  pub fn template(&self, message: MessageKey) -> &str {
+    &self.templates[&message].source
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn placeholders_are_filled_in() {
+    let messages = Messages::default();
+
+    assert_eq!(
+      messages.render(MessageKey::VerifySuccess, &[("name", &"Notch")]),
+      "'Notch' was successfully added to the whitelist!"
+    );
+    assert_eq!(
+      messages.render(MessageKey::PlayersTitle, &[("online", &3), ("max", &20)]),
+      "Players online (3/20)"
+    );
+  }
+
+  #[test]
+  fn the_file_replaces_messages_and_the_rest_stay_english() {
+    let (messages, missing) = Messages::parse(
+      r#"
+        verify_success = "'{name}' steht jetzt auf der Whitelist! {{:}}"
+        banned = "Dieser Account ist gesperrt."
+      "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+      messages.render(MessageKey::VerifySuccess, &[("name", &"Notch")]),
+      "'Notch' steht jetzt auf der Whitelist! {:}"
+    );
+    assert_eq!(
+      messages.text(MessageKey::Banned),
+      "Dieser Account ist gesperrt."
+    );
+    assert_eq!(
+      messages.text(MessageKey::PlayersNobody),
+      "Nobody is online right now"
+    );
+    assert_eq!(missing.len(), MessageKey::ALL.len() - 2);
+    assert!(missing.contains(&"players_nobody"));
+  }
+
+  #[test]
+  fn malformed_messages_are_rejected() {
+    let problems = Messages::parse(
+      r#"
+        verify_success = "'{username}' is whitelisted"
+        banned = "Banned {"
+        players_nobody = "Nobody }"
+        status_channel_offline = " "
+        not_a_message = "Hi"
+      "#,
+    )
+    .unwrap_err();
+
+    assert_eq!(
+      problems,
+      [
+        "players_nobody has a '}' without a '{', write '}}' for a brace",
+        "banned has an unclosed placeholder '{'",
+        "verify_success has the placeholder {username}, but can only have {name}",
+        "status_channel_offline can't be empty, Discord needs a channel name",
+        "not_a_message isn't a message",
+      ]
+    );
+  }
+
+  #[test]
+  fn the_keys_are_unique() {
+    let mut keys: Vec<&str> = MessageKey::ALL
+      .iter()
+      .map(|message| message.key())
+      .collect();
+    keys.sort();
+    keys.dedup();
+
+    assert_eq!(keys.len(), MessageKey::ALL.len());
+  }
+}
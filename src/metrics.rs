@@ -0,0 +1,179 @@
+//! Counters and gauges for Prometheus, served on `/metrics` together with a `/healthz` check when
+//! METRICS_ADDR is set. They live in a static, so every part of the bot can count without being
+//! handed a registry
+
+use crate::mojang::MojangError;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+pub static METRICS: Metrics = Metrics::new();
+
+pub struct Metrics {
+  pub verifications_attempted: AtomicU64,
+  pub verifications_succeeded: AtomicU64,
+  pub verifications_failed: AtomicU64,
+  pub rcon_connection_failures: AtomicU64,
+  mojang_rejected: AtomicU64,
+  mojang_rate_limited: AtomicU64,
+  mojang_network: AtomicU64,
+  pub players_online: AtomicU64,
+  pub server_reachable: AtomicBool,
+}
+
+impl Metrics {
+  const fn new() -> Self {
+    Self {
+      verifications_attempted: AtomicU64::new(0),
+      verifications_succeeded: AtomicU64::new(0),
+      verifications_failed: AtomicU64::new(0),
+      rcon_connection_failures: AtomicU64::new(0),
+      mojang_rejected: AtomicU64::new(0),
+      mojang_rate_limited: AtomicU64::new(0),
+      mojang_network: AtomicU64::new(0),
+      players_online: AtomicU64::new(0),
+      server_reachable: AtomicBool::new(false),
+    }
+  }
+
+  pub fn count(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+  }
+
+  /// Counts a failed Mojang lookup by its class. Unknown names are answers, not errors
+  pub fn record_mojang_error(&self, error: &MojangError) {
+    let counter = match error {
+      MojangError::NotFound => return,
+      MojangError::Rejected(_) => &self.mojang_rejected,
+      MojangError::RateLimited { .. } => &self.mojang_rate_limited,
+      MojangError::Network(_) => &self.mojang_network,
+    };
+    Self::count(counter);
+  }
+
+  pub fn record_server(&self, online: bool, players_online: u32) {
+    self.server_reachable.store(online, Ordering::Relaxed);
+    self.players_online.store(
+      if online { players_online.into() } else { 0 },
+      Ordering::Relaxed,
+    );
+  }
+
+  /// Everything in the Prometheus text format
+  pub fn render(&self) -> String {
+    let load = |value: &AtomicU64| value.load(Ordering::Relaxed);
+    let mut text = String::new();
+
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(&str, u64)]| {
+      let _ = writeln!(text, "# HELP mc_discord_bot_{name} {help}");
+      let _ = writeln!(text, "# TYPE mc_discord_bot_{name} {kind}");
+      for (labels, value) in samples {
+        let _ = writeln!(text, "mc_discord_bot_{name}{labels} {value}");
+      }
+    };
+
+    metric(
+      "verifications_attempted_total",
+      "counter",
+      "Verifications that were started",
+      &[("", load(&self.verifications_attempted))],
+    );
+    metric(
+      "verifications_succeeded_total",
+      "counter",
+      "Verifications that whitelisted the account",
+      &[("", load(&self.verifications_succeeded))],
+    );
+    metric(
+      "verifications_failed_total",
+      "counter",
+      "Verifications that were refused or went wrong",
+      &[("", load(&self.verifications_failed))],
+    );
+    metric(
+      "rcon_connection_failures_total",
+      "counter",
+      "RCON connections that couldn't be made or authenticated",
+      &[("", load(&self.rcon_connection_failures))],
+    );
+    metric(
+      "mojang_errors_total",
+      "counter",
+      "Failed Mojang API lookups by class",
+      &[
+        ("{class=\"rejected\"}", load(&self.mojang_rejected)),
+        ("{class=\"rate_limited\"}", load(&self.mojang_rate_limited)),
+        ("{class=\"network\"}", load(&self.mojang_network)),
+      ],
+    );
+    metric(
+      "players_online",
+      "gauge",
+      "Players on the main server as of the latest status check",
+      &[("", load(&self.players_online))],
+    );
+    metric(
+      "server_reachable",
+      "gauge",
+      "Whether the main server answered the latest status check",
+      &[("", self.server_reachable.load(Ordering::Relaxed).into())],
+    );
+
+    text
+  }
+}
+
+/// Healthy once the gateway was ready and the status loop finished a tick within two of its
+/// intervals
+pub fn is_healthy(
+  ready: bool,
+  last_status_tick: Option<Instant>,
+  interval: Duration,
+  now: Instant,
+) -> bool {
+  ready && last_status_tick.is_some_and(|tick| now.saturating_duration_since(tick) <= interval * 2)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn metrics_are_rendered_for_prometheus() {
+    let metrics = Metrics::new();
+    Metrics::count(&metrics.verifications_attempted);
+    metrics.record_mojang_error(&MojangError::RateLimited { retry_after: None });
+    metrics.record_mojang_error(&MojangError::NotFound);
+    metrics.record_server(true, 7);
+
+    let text = metrics.render();
+
+    assert!(text.contains("# TYPE mc_discord_bot_verifications_attempted_total counter\n"));
+    assert!(text.contains("\nmc_discord_bot_verifications_attempted_total 1\n"));
+    assert!(text.contains("\nmc_discord_bot_mojang_errors_total{class=\"rate_limited\"} 1\n"));
+    assert!(text.contains("\nmc_discord_bot_mojang_errors_total{class=\"network\"} 0\n"));
+    assert!(text.contains("\nmc_discord_bot_players_online 7\n"));
+    assert!(text.contains("\nmc_discord_bot_server_reachable 1\n"));
+  }
+
+  #[test]
+  fn health_needs_ready_and_a_recent_tick() {
+    let now = Instant::now();
+    let interval = Duration::from_secs(60);
+
+    assert!(is_healthy(
+      true,
+      Some(now - Duration::from_secs(90)),
+      interval,
+      now
+    ));
+    assert!(!is_healthy(
+      true,
+      Some(now - Duration::from_secs(150)),
+      interval,
+      now
+    ));
+    assert!(!is_healthy(true, None, interval, now));
+    assert!(!is_healthy(false, Some(now), interval, now));
+  }
+}
@@ -0,0 +1,472 @@
+//! Looking up Minecraft accounts with the Mojang API
+
+use crate::error::BotError;
+use crate::messages::{MessageKey, Messages};
+use crate::metrics::METRICS;
+use serde::Deserialize;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tokio::time;
+use tracing::warn;
+
+/// How many times a Mojang lookup is tried before giving up
+const MOJANG_ATTEMPTS: u32 = 3;
+
+/// The wait before the first retry, doubled for every retry after it
+const MOJANG_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// The longest Retry-After that is waited out before giving up, longer waits are left to the user
+const MOJANG_MAX_RETRY_AFTER: Duration = Duration::from_secs(10);
+
+/// How long a looked up profile is reused, so repeated attempts don't hit the Mojang API again
+const MOJANG_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// How long connecting to an HTTP API can take
+const HTTP_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The most of an answer that is read. Profiles are a few hundred bytes, so anything close to this
+/// isn't an answer to the lookup
+const MAX_RESPONSE_SIZE: usize = 64 * 1024;
+
+/// Where profiles are looked up by name
+const PROFILE_API_URL: &str = "https://api.mojang.com/users/profiles/minecraft/";
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum MojangResponse {
+  Success {
+    id: String,
+    name: String,
+  },
+  Failure {
+    path: String,
+    #[serde(rename = "errorMessage")]
+    error_message: String,
+  },
+}
+
+/// An account as the Mojang API knows it
+#[derive(Clone, Debug, PartialEq)]
+pub struct MojangProfile {
+  pub id: String,
+  pub name: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MojangError {
+  /// No account has the name
+  NotFound,
+  /// Mojang refused the lookup, like for an invalid name. Holds Mojang's message
+  Rejected(String),
+  /// Too many requests, with how long Mojang asked to wait if it said so
+  RateLimited { retry_after: Option<Duration> },
+  /// Mojang couldn't be reached or answered with something unexpected
+  Network(String),
+}
+
+impl MojangError {
+  /// Whether trying again later could give a different answer
+  pub fn is_transient(&self) -> bool {
+    matches!(self, Self::RateLimited { .. } | Self::Network(_))
+  }
+}
+
+impl fmt::Display for MojangError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::NotFound => write!(f, "no such profile"),
+      Self::Rejected(message) => write!(f, "rejected: {message}"),
+      Self::RateLimited {
+        retry_after: Some(retry_after),
+      } => write!(f, "rate limited for {}s", retry_after.as_secs()),
+      Self::RateLimited { retry_after: None } => write!(f, "rate limited"),
+      Self::Network(reason) => write!(f, "{reason}"),
+    }
+  }
+}
+
+/// The client for the Mojang and Geyser APIs and the webhooks, shared so connections are reused.
+/// A request gives up after `timeout`, answer included
+pub fn http_client(timeout: Duration) -> reqwest::Client {
+  reqwest::Client::builder()
+    .connect_timeout(HTTP_CONNECT_TIMEOUT.min(timeout))
+    .timeout(timeout)
+    .build()
+    .expect("Couldn't build the HTTP client")
+}
+
+/// Describes a failed request, saying so plainly when it timed out
+pub fn request_error(error: &reqwest::Error) -> String {
+  if error.is_timeout() {
+    format!("the request timed out ({error})")
+  } else {
+    error.to_string()
+  }
+}
+
+/// Reads the body of the answer, refusing one larger than `MAX_RESPONSE_SIZE`
+pub async fn read_body(mut response: reqwest::Response) -> Result<String, String> {
+  let too_large = || format!("the answer is larger than {} KiB", MAX_RESPONSE_SIZE / 1024);
+  if response
+    .content_length()
+    .is_some_and(|length| length > MAX_RESPONSE_SIZE as u64)
+  {
+    return Err(too_large());
+  }
+
+  let mut body = Vec::new();
+  while let Some(chunk) = response
+    .chunk()
+    .await
+    .map_err(|error| request_error(&error))?
+  {
+    if body.len() + chunk.len() > MAX_RESPONSE_SIZE {
+      return Err(too_large());
+    }
+    body.extend_from_slice(&chunk);
+  }
+
+  Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// Parses a Mojang API response. Anything that isn't JSON, like an HTML error page from an
+/// intermediary, is an upstream problem rather than an answer about the user
+pub fn parse_mojang_response(
+  content_type: Option<&str>,
+  body: &str,
+) -> Result<MojangResponse, String> {
+  let content_type = content_type.unwrap_or("none");
+  if !content_type.contains("json") {
+    return Err(format!("unexpected content type {content_type}"));
+  }
+
+  serde_json::from_str(body).map_err(|error| format!("couldn't parse the response: {error}"))
+}
+
+/// Turns a profile lookup's response into the profile or what went wrong. `retry_after` is the
+/// Retry-After header
+fn read_profile_response(
+  status: u16,
+  retry_after: Option<&str>,
+  content_type: Option<&str>,
+  body: &str,
+) -> Result<MojangProfile, MojangError> {
+  match status {
+    429 => {
+      return Err(MojangError::RateLimited {
+        retry_after: retry_after
+          .and_then(|seconds| seconds.trim().parse().ok())
+          .map(Duration::from_secs),
+      })
+    }
+    // Older versions of the API answered unknown names with an empty 204
+    204 | 404 if body.trim().is_empty() => return Err(MojangError::NotFound),
+    _ => {}
+  }
+
+  match parse_mojang_response(content_type, body).map_err(MojangError::Network)? {
+    MojangResponse::Success { id, name } => Ok(MojangProfile { id, name }),
+    MojangResponse::Failure { error_message, .. }
+      if error_message.contains("Couldn't find any profile") =>
+    {
+      Err(MojangError::NotFound)
+    }
+    MojangResponse::Failure { error_message, .. }
+      if error_message.contains("TooManyRequests") || error_message.contains("rate limit") =>
+    {
+      Err(MojangError::RateLimited { retry_after: None })
+    }
+    MojangResponse::Failure {
+      path,
+      error_message,
+    } => {
+      warn!("Mojang rejected the lookup of {path}: {error_message}");
+      Err(MojangError::Rejected(error_message))
+    }
+  }
+}
+
+async fn fetch_mojang_profile(
+  http: &reqwest::Client,
+  username: &str,
+) -> Result<MojangProfile, MojangError> {
+  fetch_mojang_profile_from(http, PROFILE_API_URL, username)
+    .await
+    .inspect_err(|error| METRICS.record_mojang_error(error))
+}
+
+/// Looks the profile up once, at `api_url` followed by the name
+pub async fn fetch_mojang_profile_from(
+  http: &reqwest::Client,
+  api_url: &str,
+  username: &str,
+) -> Result<MojangProfile, MojangError> {
+  let response = http
+    .get(format!("{api_url}{username}"))
+    .send()
+    .await
+    .map_err(|error| MojangError::Network(request_error(&error)))?;
+
+  let status = response.status().as_u16();
+  let header = |name| {
+    response
+      .headers()
+      .get(name)
+      .and_then(|value| value.to_str().ok())
+      .map(str::to_string)
+  };
+  let retry_after = header(reqwest::header::RETRY_AFTER);
+  let content_type = header(reqwest::header::CONTENT_TYPE);
+  let body = read_body(response).await.map_err(MojangError::Network)?;
+
+  read_profile_response(
+    status,
+    retry_after.as_deref(),
+    content_type.as_deref(),
+    &body,
+  )
+}
+
+/// Profiles looked up recently, by lowercase name
+static MOJANG_CACHE: LazyLock<Mutex<HashMap<String, (MojangProfile, Instant)>>> =
+  LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// A random extra wait of up to half the delay, so retries of parallel lookups spread out
+fn jittered(delay: Duration) -> Duration {
+  let jitter = RandomState::new().build_hasher().finish() % (delay.as_millis() as u64 / 2 + 1);
+  delay + Duration::from_millis(jitter)
+}
+
+/// Looks the profile up, retrying transient problems a few times with a growing delay. Profiles
+/// are cached for a while
+pub async fn get_mojang_profile(
+  http: &reqwest::Client,
+  username: &str,
+) -> Result<MojangProfile, MojangError> {
+  let key = username.to_lowercase();
+  if let Some((profile, fetched_at)) = MOJANG_CACHE.lock().unwrap().get(&key) {
+    if fetched_at.elapsed() < MOJANG_CACHE_TTL {
+      return Ok(profile.clone());
+    }
+  }
+
+  let mut delay = MOJANG_RETRY_DELAY;
+  for attempt in 1..=MOJANG_ATTEMPTS {
+    let error = match fetch_mojang_profile(http, username).await {
+      Ok(profile) => {
+        let mut cache = MOJANG_CACHE.lock().unwrap();
+        cache.retain(|_, (_, fetched_at)| fetched_at.elapsed() < MOJANG_CACHE_TTL);
+        cache.insert(key, (profile.clone(), Instant::now()));
+        return Ok(profile);
+      }
+      Err(error) if !error.is_transient() || attempt == MOJANG_ATTEMPTS => return Err(error),
+      Err(error) => error,
+    };
+
+    warn!(
+      "Looking up '{username}' on Mojang failed (attempt {attempt}/{MOJANG_ATTEMPTS}): {error}"
+    );
+    let wait = match error {
+      MojangError::RateLimited {
+        retry_after: Some(retry_after),
+      } if retry_after > MOJANG_MAX_RETRY_AFTER => return Err(error),
+      MojangError::RateLimited {
+        retry_after: Some(retry_after),
+      } => retry_after,
+      _ => jittered(delay),
+    };
+    time::sleep(wait).await;
+    delay *= 2;
+  }
+
+  unreachable!("The last attempt always returns")
+}
+
+/// Formats an undashed uuid, like Mojang returns them, as 8-4-4-4-12. `None` if it isn't a uuid
+pub fn dashed_uuid(uuid: &str) -> Option<String> {
+  let hex: String = uuid.chars().filter(|char| *char != '-').collect();
+  if hex.len() != 32 || !hex.chars().all(|char| char.is_ascii_hexdigit()) {
+    return None;
+  }
+
+  let hex = hex.to_lowercase();
+  Some(format!(
+    "{}-{}-{}-{}-{}",
+    &hex[0..8],
+    &hex[8..12],
+    &hex[12..16],
+    &hex[16..20],
+    &hex[20..32]
+  ))
+}
+
+/// Looks up the current name of an account through the session server
+pub async fn get_profile_by_uuid(
+  http: &reqwest::Client,
+  uuid: &str,
+) -> Result<MojangResponse, BotError> {
+  let response = http
+    .get(format!(
+      "https://sessionserver.mojang.com/session/minecraft/profile/{uuid}"
+    ))
+    .send()
+    .await
+    .map_err(|error| BotError::Mojang(request_error(&error)))?;
+
+  let content_type = response
+    .headers()
+    .get(reqwest::header::CONTENT_TYPE)
+    .and_then(|content_type| content_type.to_str().ok())
+    .map(str::to_string);
+  let body = read_body(response).await.map_err(BotError::Mojang)?;
+
+  parse_mojang_response(content_type.as_deref(), &body).map_err(BotError::Mojang)
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NameHistoryEntry {
+  pub name: String,
+  /// Unix timestamp in milliseconds, missing for the account's original name
+  #[serde(rename = "changedToAt")]
+  pub changed_to_at: Option<i64>,
+}
+
+/// Gets the name history of an account, in the format of the old Mojang name history API.
+/// Returns `None` if the history couldn't be fetched
+pub async fn get_name_history(
+  http: &reqwest::Client,
+  url_template: &str,
+  uuid: &str,
+) -> Option<Vec<NameHistoryEntry>> {
+  let response = http
+    .get(url_template.replace("{uuid}", uuid))
+    .send()
+    .await
+    .ok()?;
+  serde_json::from_str(&read_body(response).await.ok()?).ok()
+}
+
+/// Turns a failure from the Mojang API into something the user can act on
+pub fn mojang_failure_message(username: &str, error: &MojangError, messages: &Messages) -> String {
+  match error {
+    MojangError::NotFound => {
+      messages.render(MessageKey::MojangNotFound, &[("username", &username)])
+    }
+    MojangError::Rejected(message) if message.contains("Invalid profile name") => {
+      messages.render(MessageKey::MojangInvalidName, &[("username", &username)])
+    }
+    MojangError::Rejected(message) => messages.render(
+      MessageKey::MojangRejected,
+      &[("username", &username), ("reason", message)],
+    ),
+    MojangError::RateLimited { retry_after } => messages.render(
+      MessageKey::MojangRateLimited,
+      &[(
+        "seconds",
+        &retry_after.map_or(60, |retry_after| retry_after.as_secs().max(1)),
+      )],
+    ),
+    MojangError::Network(_) => messages.text(MessageKey::MojangUnavailable),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn uuids_are_dashed() {
+    assert_eq!(
+      dashed_uuid("069A79F444E94726A5BEFCA90E38AAF5").as_deref(),
+      Some("069a79f4-44e9-4726-a5be-fca90e38aaf5")
+    );
+    assert_eq!(
+      dashed_uuid("069a79f4-44e9-4726-a5be-fca90e38aaf5").as_deref(),
+      Some("069a79f4-44e9-4726-a5be-fca90e38aaf5")
+    );
+  }
+
+  #[test]
+  fn non_uuids_are_not_dashed() {
+    assert_eq!(dashed_uuid("069a79f444e94726a5befca90e38aaf"), None);
+    assert_eq!(dashed_uuid("Notch"), None);
+  }
+
+  #[test]
+  fn mojang_html_error_pages_are_upstream_errors() {
+    let body = "<html><body><h1>502 Bad Gateway</h1></body></html>";
+
+    let result = parse_mojang_response(Some("text/html; charset=utf-8"), body);
+
+    assert_eq!(
+      result.unwrap_err(),
+      "unexpected content type text/html; charset=utf-8"
+    );
+  }
+
+  #[test]
+  fn mojang_profiles_are_parsed() {
+    let body = r#"{"id":"069a79f444e94726a5befca90e38aaf5","name":"Notch"}"#;
+
+    let result = parse_mojang_response(Some("application/json"), body);
+
+    assert!(matches!(result, Ok(MojangResponse::Success { name, .. }) if name == "Notch"));
+  }
+
+  #[test]
+  fn profile_lookups_are_classified() {
+    let not_found = r#"{"path":"/users/profiles/minecraft/nobody_here","errorMessage":"Couldn't find any profile with name nobody_here"}"#;
+    let invalid =
+      r#"{"path":"/users/profiles/minecraft/a b","errorMessage":"Invalid profile name"}"#;
+
+    assert_eq!(
+      read_profile_response(404, None, Some("application/json"), not_found),
+      Err(MojangError::NotFound)
+    );
+    assert_eq!(
+      read_profile_response(204, None, None, ""),
+      Err(MojangError::NotFound)
+    );
+    assert_eq!(
+      read_profile_response(400, None, Some("application/json"), invalid),
+      Err(MojangError::Rejected("Invalid profile name".to_string()))
+    );
+    assert!(matches!(
+      read_profile_response(502, None, Some("text/html"), "<html>Bad Gateway</html>"),
+      Err(MojangError::Network(_))
+    ));
+  }
+
+  #[test]
+  fn rate_limits_keep_the_retry_after() {
+    assert_eq!(
+      read_profile_response(429, Some("30"), None, ""),
+      Err(MojangError::RateLimited {
+        retry_after: Some(Duration::from_secs(30))
+      })
+    );
+    assert_eq!(
+      mojang_failure_message(
+        "Notch",
+        &MojangError::RateLimited {
+          retry_after: Some(Duration::from_secs(30))
+        },
+        &Messages::default()
+      ),
+      "Mojang is getting too many requests right now. Please try again in 30 seconds."
+    );
+  }
+
+  #[test]
+  fn mojang_failures_are_answers() {
+    let body = r#"{"path":"/users/profiles/minecraft/nobody_here","errorMessage":"Couldn't find any profile with name nobody_here"}"#;
+
+    let result = parse_mojang_response(Some("application/json"), body);
+
+    assert!(matches!(result, Ok(MojangResponse::Failure { .. })));
+  }
+}
@@ -0,0 +1,189 @@
+//! Side effects of a successful verification or an admin action, kept out of the command flow
+//! itself
+
+use serenity::all::*;
+use serenity::async_trait;
+use tracing::warn;
+
+/// What happened to a Minecraft account
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccountAction {
+  Verify,
+  Unverify,
+  ForceVerify,
+  ForceUnverify,
+  /// Taken off the whitelist by the bot, like after the member left
+  AutomaticRemoval,
+}
+
+impl AccountAction {
+  fn title(self) -> &'static str {
+    match self {
+      Self::Verify => "Verify",
+      Self::Unverify => "Unverify",
+      Self::ForceVerify => "Force verify",
+      Self::ForceUnverify => "Force unverify",
+      Self::AutomaticRemoval => "Automatic removal",
+    }
+  }
+
+  /// Each action has its own, so the audit channel can be skimmed
+  fn colour(self) -> Colour {
+    match self {
+      Self::Verify => Colour::DARK_GREEN,
+      Self::Unverify => Colour::LIGHT_GREY,
+      Self::ForceVerify => Colour::BLUE,
+      Self::ForceUnverify => Colour::ORANGE,
+      Self::AutomaticRemoval => Colour::PURPLE,
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AccountOutcome {
+  Done,
+  /// The server couldn't be reached, it happens once it can
+  Queued,
+  Failed(String),
+}
+
+/// A verification or a removal, for the audit channel
+pub struct AccountEvent<'a> {
+  pub action: AccountAction,
+  pub discord_id: UserId,
+  pub minecraft_name: &'a str,
+  /// `None` when it isn't known, like for a name Mojang doesn't have
+  pub uuid: Option<&'a str>,
+  pub outcome: AccountOutcome,
+  /// Who did it, for the forced actions
+  pub admin: Option<&'a User>,
+  /// Why, or what changed, like "Left the Discord"
+  pub reason: Option<&'a str>,
+}
+
+#[async_trait]
+pub trait VerificationObserver: Send + Sync {
+  /// Called after a user's minecraft account was whitelisted
+  async fn on_verified(
+    &self,
+    _ctx: &Context,
+    _discord_id: UserId,
+    _minecraft_name: &str,
+    _uuid: &str,
+  ) {
+  }
+
+  /// Called after an admin changed something about the server, like turning the whitelist off
+  async fn on_admin_action(&self, _ctx: &Context, _admin: &User, _action: &str) {}
+
+  /// Called after every verification or removal of an account, whether it worked or not
+  async fn on_account_event(&self, _ctx: &Context, _event: &AccountEvent<'_>) {}
+}
+
+/// Posts every verification, removal and admin action to an audit channel
+pub struct AuditChannelObserver {
+  channel_id: ChannelId,
+}
+
+impl AuditChannelObserver {
+  pub fn new(channel_id: ChannelId) -> Self {
+    Self { channel_id }
+  }
+
+  /// The action already happened, so a failure here is only worth a log line
+  async fn post(&self, ctx: &Context, embed: CreateEmbed) {
+    if let Err(error) = self
+      .channel_id
+      .send_message(ctx, CreateMessage::new().embed(embed))
+      .await
+    {
+      warn!("Couldn't post to the audit channel: {error}");
+    }
+  }
+}
+
+fn account_embed(event: &AccountEvent) -> CreateEmbed {
+  let outcome = match &event.outcome {
+    AccountOutcome::Done => "Done".to_string(),
+    AccountOutcome::Queued => "Queued until the server is online".to_string(),
+    AccountOutcome::Failed(reason) => format!("Failed: {reason}"),
+  };
+
+  let mut embed = CreateEmbed::new()
+    .title(event.action.title())
+    .field("Discord user", event.discord_id.mention().to_string(), true)
+    .field("Minecraft name", event.minecraft_name, true)
+    .field("UUID", event.uuid.unwrap_or("unknown"), true)
+    .field("Outcome", outcome, false);
+  if let Some(admin) = event.admin {
+    embed = embed.field("By", admin.mention().to_string(), true);
+  }
+  if let Some(reason) = event.reason {
+    embed = embed.field("Reason", reason, true);
+  }
+
+  embed
+    .timestamp(Timestamp::now())
+    .colour(event.action.colour())
+}
+
+#[async_trait]
+impl VerificationObserver for AuditChannelObserver {
+  async fn on_account_event(&self, ctx: &Context, event: &AccountEvent<'_>) {
+    self.post(ctx, account_embed(event)).await;
+  }
+
+  async fn on_admin_action(&self, ctx: &Context, admin: &User, action: &str) {
+    let embed = CreateEmbed::new()
+      .title("Admin action")
+      .field("Admin", admin.mention().to_string(), true)
+      .field("Action", action, true)
+      .timestamp(Timestamp::now())
+      .colour(Colour::GOLD);
+
+    self.post(ctx, embed).await;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn account_embeds_show_the_outcome() {
+    let event = AccountEvent {
+      action: AccountAction::AutomaticRemoval,
+      discord_id: UserId::new(1),
+      minecraft_name: "Alice",
+      uuid: None,
+      outcome: AccountOutcome::Queued,
+      admin: None,
+      reason: Some("Left the Discord"),
+    };
+
+    let embed = serde_json::to_value(account_embed(&event)).unwrap();
+    let fields: Vec<(&str, &str)> = embed["fields"]
+      .as_array()
+      .unwrap()
+      .iter()
+      .map(|field| {
+        (
+          field["name"].as_str().unwrap(),
+          field["value"].as_str().unwrap(),
+        )
+      })
+      .collect();
+
+    assert_eq!(embed["title"], "Automatic removal");
+    assert_eq!(
+      fields,
+      [
+        ("Discord user", "<@1>"),
+        ("Minecraft name", "Alice"),
+        ("UUID", "unknown"),
+        ("Outcome", "Queued until the server is online"),
+        ("Reason", "Left the Discord"),
+      ]
+    );
+  }
+}
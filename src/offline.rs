@@ -0,0 +1,95 @@
+//! Offline mode (cracked) servers don't ask Mojang who an account is. They derive its UUID from
+//! the name instead, as a version 3 UUID of "OfflinePlayer:<name>", which is what's stored for it
+
+/// The per-round shift amounts of MD5
+const SHIFTS: [u32; 64] = [
+  7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14,
+  20, 5, 9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10, 15, 21, 6,
+  10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+];
+
+/// MD5, which the standard library doesn't have. Only used for offline UUIDs, not for security
+fn md5(message: &[u8]) -> [u8; 16] {
+  // The constants are the binary integer parts of the sines of 1 to 64
+  let constants: Vec<u32> = (1..=64)
+    .map(|i: i32| (f64::from(i).sin().abs() * 4_294_967_296.0) as u32)
+    .collect();
+
+  let mut padded = message.to_vec();
+  padded.push(0x80);
+  while padded.len() % 64 != 56 {
+    padded.push(0);
+  }
+  padded.extend_from_slice(&((message.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+  let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+  for chunk in padded.chunks(64) {
+    let words: Vec<u32> = chunk
+      .chunks(4)
+      .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+      .collect();
+    let [mut a, mut b, mut c, mut d] = state;
+
+    for i in 0..64 {
+      let (f, g) = match i / 16 {
+        0 => ((b & c) | (!b & d), i),
+        1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+        2 => (b ^ c ^ d, (3 * i + 5) % 16),
+        _ => (c ^ (b | !d), (7 * i) % 16),
+      };
+      let rotated = a
+        .wrapping_add(f)
+        .wrapping_add(constants[i])
+        .wrapping_add(words[g])
+        .rotate_left(SHIFTS[i]);
+      (a, b, c, d) = (d, b.wrapping_add(rotated), b, c);
+    }
+
+    for (value, added) in state.iter_mut().zip([a, b, c, d]) {
+      *value = value.wrapping_add(added);
+    }
+  }
+
+  let mut digest = [0; 16];
+  for (bytes, value) in digest.chunks_mut(4).zip(state) {
+    bytes.copy_from_slice(&value.to_le_bytes());
+  }
+  digest
+}
+
+/// The UUID an offline mode server gives the name, without dashes like Mojang's ids
+pub fn offline_uuid(name: &str) -> String {
+  let mut bytes = md5(format!("OfflinePlayer:{name}").as_bytes());
+  // Version 3, and the IETF variant
+  bytes[6] = (bytes[6] & 0x0f) | 0x30;
+  bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+  bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn hex(bytes: [u8; 16]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+  }
+
+  #[test]
+  fn md5_matches_the_rfc_vectors() {
+    assert_eq!(hex(md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+    assert_eq!(hex(md5(b"abc")), "900150983cd24fb0d6963f7d28e17f72");
+    assert_eq!(
+      hex(md5(
+        b"12345678901234567890123456789012345678901234567890123456789012345678901234567890"
+      )),
+      "57edf4a22be3c955ac49da2e2107b67a"
+    );
+  }
+
+  #[test]
+  fn offline_uuids_match_the_server() {
+    assert_eq!(offline_uuid("Notch"), "b50ad385829d3141a2167e7d7539ba7f");
+    assert_eq!(offline_uuid("jeb_"), "a762f5604fce3236812ab80efff0b62b");
+  }
+}
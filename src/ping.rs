@@ -0,0 +1,105 @@
+//! A bare-bones Server List Ping that keeps the raw JSON response, since `mc_query::status`
+//! throws away the fields modded servers add (`modinfo`, `forgeData`)
+
+use serde_json::Value;
+use std::io;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+fn write_varint(buffer: &mut Vec<u8>, value: i32) {
+  let mut value = value as u32;
+
+  loop {
+    if value & !0x7F == 0 {
+      buffer.push(value as u8);
+      return;
+    }
+
+    buffer.push((value & 0x7F | 0x80) as u8);
+    value >>= 7;
+  }
+}
+
+async fn read_varint(stream: &mut TcpStream) -> io::Result<i32> {
+  let mut value = 0;
+
+  for position in 0..5 {
+    let byte = stream.read_u8().await?;
+    value |= ((byte & 0x7F) as i32) << (7 * position);
+
+    if byte & 0x80 == 0 {
+      return Ok(value);
+    }
+  }
+
+  Err(io::Error::new(
+    io::ErrorKind::InvalidData,
+    "varint is too long",
+  ))
+}
+
+async fn write_packet(stream: &mut TcpStream, data: &[u8]) -> io::Result<()> {
+  let mut packet = Vec::new();
+  write_varint(&mut packet, data.len() as i32);
+  packet.extend_from_slice(data);
+
+  stream.write_all(&packet).await
+}
+
+/// Pings the server and returns the status response as raw JSON
+pub async fn status_json(host: &str, port: u16) -> io::Result<Value> {
+  let mut stream = TcpStream::connect((host, port)).await?;
+
+  let mut handshake = Vec::new();
+  write_varint(&mut handshake, 0x00);
+  write_varint(&mut handshake, -1);
+  write_varint(&mut handshake, host.len() as i32);
+  handshake.extend_from_slice(host.as_bytes());
+  handshake.extend_from_slice(&port.to_be_bytes());
+  write_varint(&mut handshake, 1);
+
+  write_packet(&mut stream, &handshake).await?;
+  write_packet(&mut stream, &[0x00]).await?;
+
+  let _packet_length = read_varint(&mut stream).await?;
+  let _packet_id = read_varint(&mut stream).await?;
+  let json_length = read_varint(&mut stream).await?;
+
+  let mut json = vec![0; json_length.max(0) as usize];
+  stream.read_exact(&mut json).await?;
+
+  serde_json::from_slice(&json).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+/// Lists the mods a modded server reports in its status response.
+/// Handles both the old Forge `modinfo` and the newer `forgeData` formats
+pub fn mod_list(status: &Value) -> Vec<String> {
+  let old_forge = status["modinfo"]["modList"]
+    .as_array()
+    .into_iter()
+    .flatten()
+    .filter_map(|module| {
+      let id = module["modid"].as_str()?;
+      Some(format!(
+        "{id} {}",
+        module["version"].as_str().unwrap_or_default()
+      ))
+    });
+
+  let new_forge = status["forgeData"]["mods"]
+    .as_array()
+    .into_iter()
+    .flatten()
+    .filter_map(|module| {
+      let id = module["modId"].as_str()?;
+      Some(format!(
+        "{id} {}",
+        module["modmarker"].as_str().unwrap_or_default()
+      ))
+    });
+
+  old_forge
+    .chain(new_forge)
+    .map(|name| name.trim().to_string())
+    .collect()
+}
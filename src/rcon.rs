@@ -0,0 +1,469 @@
+//! Talking to the server over RCON, and reading the answers of its commands
+
+use mc_query::errors::RconProtocolError;
+use mc_query::rcon::RconClient;
+use regex::Regex;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+use tokio::time;
+use tracing::warn;
+
+/// How long connecting or running an RCON command takes by default, so a hung server can't stall
+/// an interaction past Discord's deadline
+pub const RCON_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The server took the connection, but didn't answer the login or a command in time. Unlike a
+/// server that can't be reached, it's likely starting up or overloaded
+#[derive(Debug)]
+pub struct NoAnswer(pub &'static str);
+
+impl fmt::Display for NoAnswer {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{} timed out", self.0)
+  }
+}
+
+impl Error for NoAnswer {}
+
+/// Whether the server accepted the connection but didn't answer
+pub fn is_unanswered(error: &io::Error) -> bool {
+  error.get_ref().is_some_and(|inner| inner.is::<NoAnswer>())
+}
+
+/// Waits for the server's answer, giving up after `timeout`
+async fn answer_within<T>(
+  timeout: Duration,
+  what: &'static str,
+  answer: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+  time::timeout(timeout, answer)
+    .await
+    .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, NoAnswer(what))))
+}
+
+/// Removes the `§` formatting codes minecraft uses for colors
+pub fn strip_color_codes(text: &str) -> String {
+  let mut stripped = String::with_capacity(text.len());
+  let mut chars = text.chars();
+
+  while let Some(char) = chars.next() {
+    if char == '§' {
+      chars.next();
+    } else {
+      stripped.push(char);
+    }
+  }
+
+  stripped
+}
+
+/// Parses the output of the Bukkit/Paper `plugins` command.
+/// Returns `None` if the server doesn't know the command (like vanilla servers)
+pub fn parse_plugins_output(output: &str) -> Option<Vec<String>> {
+  let output = strip_color_codes(output);
+
+  if !output.contains("Plugins") {
+    return None;
+  }
+
+  let plugins = output
+    .lines()
+    .map(|line| {
+      let line = line.trim();
+      // Headers like "Bukkit Plugins:" or Spigot's "Plugins (3): A, B, C" on a single line
+      match line.split_once(':') {
+        Some((_, rest)) if line.contains("Plugins") => rest.trim(),
+        _ => line.trim_start_matches('-').trim(),
+      }
+    })
+    .flat_map(|line| line.split(", "))
+    .map(str::trim)
+    .filter(|plugin| !plugin.is_empty())
+    .map(str::to_string)
+    .collect();
+
+  Some(plugins)
+}
+
+/// Players from the output of the `list` command
+#[derive(Debug, PartialEq)]
+pub struct PlayerList {
+  pub online: u32,
+  pub max: u32,
+  pub names: Vec<String>,
+}
+
+/// Parses the output of the vanilla `list` command, like
+/// "There are 2 of a max of 20 players online: Alice, Bob", or the older
+/// "There are 2/20 players online:" with the names on the next line
+pub fn parse_list_output(output: &str) -> Option<PlayerList> {
+  let output = strip_color_codes(output);
+  let counts = Regex::new(r"There are (\d+) ?(?:of a max of |/ ?)(\d+) players online")
+    .unwrap()
+    .captures(&output)?;
+
+  let (_, names) = output.split_once(':')?;
+  let names = names
+    .split(',')
+    .map(str::trim)
+    .filter(|name| !name.is_empty())
+    .map(str::to_string)
+    .collect();
+
+  Some(PlayerList {
+    online: counts[1].parse().ok()?,
+    max: counts[2].parse().ok()?,
+    names,
+  })
+}
+
+/// Parses the output of `whitelist list`, like "There are 2 whitelisted player(s): Alice, Bob",
+/// the older "There are 3 (out of 5 seen) whitelisted players:" with "Alice, Bob and Carol" on the
+/// next line, or "There are no whitelisted players"
+pub fn parse_whitelist_output(output: &str) -> Option<Vec<String>> {
+  let output = strip_color_codes(output);
+
+  if output.contains("There are no whitelisted players") {
+    return Some(Vec::new());
+  }
+
+  if !output.contains("whitelisted player") {
+    return None;
+  }
+
+  let (_, names) = output.split_once(':')?;
+  Some(
+    names
+      .split(',')
+      // Names can't contain spaces, so " and " only ever joins the last two
+      .flat_map(|names| names.split(" and "))
+      .map(str::trim)
+      .filter(|name| !name.is_empty())
+      .map(str::to_string)
+      .collect(),
+  )
+}
+
+/// Parses the output of `banlist players`, like
+/// "There are 2 ban(s):Alice was banned by Server: Griefing" (entries on separate lines or not),
+/// the older "There are 2 total banned players:" with a comma separated list, or "There are no bans"
+pub fn parse_banlist_output(output: &str) -> Option<Vec<String>> {
+  let output = strip_color_codes(output);
+
+  if output.contains("There are no bans") {
+    return Some(Vec::new());
+  }
+
+  let (header, entries) = output.split_once(':')?;
+  if !header.contains("ban") {
+    return None;
+  }
+
+  let names = if entries.contains(" was banned by ") {
+    Regex::new(r"(\w+) was banned by ")
+      .unwrap()
+      .captures_iter(entries)
+      .map(|captures| captures[1].to_string())
+      .collect()
+  } else {
+    entries
+      .split([',', '\n'])
+      .map(str::trim)
+      .filter(|name| !name.is_empty())
+      .map(str::to_string)
+      .collect()
+  };
+
+  Some(names)
+}
+
+/// What the server answered to `whitelist add`
+#[derive(Debug, PartialEq)]
+pub enum WhitelistResult {
+  Added,
+  AlreadyWhitelisted,
+  /// The server couldn't look the account up, like an offline mode server that never saw the name
+  PlayerNotFound,
+  /// Anything else, like the answer of a whitelist plugin
+  Unknown(String),
+}
+
+/// Parses the answer to `whitelist add`, like "Added Alice to the whitelist", "Player is already
+/// whitelisted" or "That player does not exist", and before 1.13 "Could not add Alice to the
+/// whitelist"
+pub fn parse_whitelist_add_output(output: &str) -> WhitelistResult {
+  let output = strip_color_codes(output);
+  let output = output.trim();
+  let lowercase = output.to_lowercase();
+
+  if lowercase.contains("already whitelisted") {
+    WhitelistResult::AlreadyWhitelisted
+  } else if lowercase.contains("player does not exist") || lowercase.starts_with("could not add") {
+    WhitelistResult::PlayerNotFound
+  } else if lowercase.starts_with("added ") && lowercase.ends_with("to the whitelist") {
+    WhitelistResult::Added
+  } else {
+    WhitelistResult::Unknown(output.to_string())
+  }
+}
+
+/// What the server answered to `kick` or `ban`
+#[derive(Debug, PartialEq)]
+pub enum ModerationResult {
+  Done,
+  /// Nobody with the name is online (kick), or the account doesn't exist (ban)
+  PlayerNotFound,
+  /// Like banning a player who's already banned
+  NothingChanged,
+  /// Anything else, like the answer of a moderation plugin or an error
+  Unknown(String),
+}
+
+/// Parses the answer to `kick` or `ban`, like "Kicked Alice: Griefing", "Banned Alice: Griefing",
+/// "No player was found", "That player does not exist" or "Nothing changed. The player is already
+/// banned", and before 1.13 "Kicked Alice from the game" or "Could not ban player Alice"
+pub fn parse_moderation_output(output: &str) -> ModerationResult {
+  let output = strip_color_codes(output);
+  let output = output.trim();
+  let lowercase = output.to_lowercase();
+
+  if lowercase.starts_with("kicked ") || lowercase.starts_with("banned ") {
+    ModerationResult::Done
+  } else if lowercase.contains("no player was found")
+    || lowercase.contains("player does not exist")
+    || lowercase.starts_with("could not ")
+  {
+    ModerationResult::PlayerNotFound
+  } else if lowercase.starts_with("nothing changed") {
+    ModerationResult::NothingChanged
+  } else {
+    ModerationResult::Unknown(output.to_string())
+  }
+}
+
+pub fn is_rcon_auth_failure(error: &io::Error) -> bool {
+  error
+    .get_ref()
+    .and_then(|inner| inner.downcast_ref::<RconProtocolError>())
+    .is_some_and(|inner| matches!(inner, RconProtocolError::AuthFailed))
+}
+
+/// Connects, giving up after `timeout`. The client still has to authenticate
+pub async fn connect(host: &str, port: u16, timeout: Duration) -> io::Result<RconClient> {
+  time::timeout(timeout, RconClient::new(host, port))
+    .await
+    .unwrap_or_else(|_| {
+      Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        "connecting to rcon timed out",
+      ))
+    })
+}
+
+/// Logs in, giving up after `timeout`
+pub async fn authenticate(
+  rcon_client: &mut RconClient,
+  password: &str,
+  timeout: Duration,
+) -> io::Result<()> {
+  answer_within(
+    timeout,
+    "the rcon login",
+    rcon_client.authenticate(password),
+  )
+  .await
+}
+
+/// Runs a command over a kept open connection, connecting first if there isn't one. A connection
+/// that broke since the last command is replaced once, a timed out command isn't retried
+pub async fn run_on_connection<F, Fut>(
+  connection: &mut Option<RconClient>,
+  command: &str,
+  timeout: Duration,
+  mut connect: F,
+) -> io::Result<String>
+where
+  F: FnMut() -> Fut,
+  Fut: Future<Output = io::Result<RconClient>>,
+{
+  let reused = connection.is_some();
+
+  for attempt in 1..=2 {
+    let rcon_client = match connection.as_mut() {
+      Some(rcon_client) => rcon_client,
+      None => connection.insert(
+        connect()
+          .await
+          .inspect_err(|err| warn!("Couldn't create an rcon client: {err}"))?,
+      ),
+    };
+
+    let result = answer_within(
+      timeout,
+      "the rcon command",
+      rcon_client.run_command(command),
+    )
+    .await;
+
+    match result {
+      Ok(output) => return Ok(output),
+      Err(err) => {
+        *connection = None;
+
+        let retry = reused && attempt == 1 && err.kind() != io::ErrorKind::TimedOut;
+        if !retry {
+          return Err(err);
+        }
+        warn!("The rcon connection broke, reconnecting: {err}");
+      }
+    }
+  }
+
+  unreachable!("the second attempt always returns")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn whitelist_output_is_parsed() {
+    assert_eq!(
+      parse_whitelist_output("There are 2 whitelisted player(s): Alice, Bob"),
+      Some(vec!["Alice".to_string(), "Bob".to_string()])
+    );
+    assert_eq!(
+      parse_whitelist_output("There are 1 whitelisted players: Alice"),
+      Some(vec!["Alice".to_string()])
+    );
+    assert_eq!(
+      parse_whitelist_output("There are no whitelisted players"),
+      Some(Vec::new())
+    );
+    assert_eq!(parse_whitelist_output("Unknown command"), None);
+  }
+
+  #[test]
+  fn old_whitelist_output_is_parsed() {
+    assert_eq!(
+      parse_whitelist_output(
+        "§6There are 3 (out of 5 seen) whitelisted players:\nAlice, Bob and Carol"
+      ),
+      Some(vec![
+        "Alice".to_string(),
+        "Bob".to_string(),
+        "Carol".to_string()
+      ])
+    );
+  }
+
+  #[test]
+  fn whitelist_add_output_is_parsed() {
+    // 1.21, 1.16 and 1.8
+    assert_eq!(
+      parse_whitelist_add_output("Added Notch to the whitelist"),
+      WhitelistResult::Added
+    );
+    assert_eq!(
+      parse_whitelist_add_output("Player is already whitelisted"),
+      WhitelistResult::AlreadyWhitelisted
+    );
+    assert_eq!(
+      parse_whitelist_add_output("That player does not exist"),
+      WhitelistResult::PlayerNotFound
+    );
+    // 1.12
+    assert_eq!(
+      parse_whitelist_add_output("Could not add Notch to the whitelist"),
+      WhitelistResult::PlayerNotFound
+    );
+    // Paper colors its answers
+    assert_eq!(
+      parse_whitelist_add_output("§7Added Notch to the whitelist\n"),
+      WhitelistResult::Added
+    );
+    assert_eq!(
+      parse_whitelist_add_output("Whitelist is managed by EasyWhitelist"),
+      WhitelistResult::Unknown("Whitelist is managed by EasyWhitelist".to_string())
+    );
+  }
+
+  #[test]
+  fn moderation_output_is_parsed() {
+    assert_eq!(
+      parse_moderation_output("Kicked Alice: Griefing"),
+      ModerationResult::Done
+    );
+    assert_eq!(
+      parse_moderation_output("§7Banned Alice: Banned by an operator"),
+      ModerationResult::Done
+    );
+    assert_eq!(
+      parse_moderation_output("No player was found"),
+      ModerationResult::PlayerNotFound
+    );
+    assert_eq!(
+      parse_moderation_output("That player does not exist"),
+      ModerationResult::PlayerNotFound
+    );
+    // 1.12
+    assert_eq!(
+      parse_moderation_output("Could not ban player Alice"),
+      ModerationResult::PlayerNotFound
+    );
+    assert_eq!(
+      parse_moderation_output("Nothing changed. The player is already banned"),
+      ModerationResult::NothingChanged
+    );
+    assert_eq!(
+      parse_moderation_output("Unknown or incomplete command"),
+      ModerationResult::Unknown("Unknown or incomplete command".to_string())
+    );
+  }
+
+  #[test]
+  fn list_output_is_parsed() {
+    let player_list = parse_list_output("There are 2 of a max of 20 players online: Alice, Bob");
+
+    assert_eq!(
+      player_list,
+      Some(PlayerList {
+        online: 2,
+        max: 20,
+        names: vec!["Alice".to_string(), "Bob".to_string()],
+      })
+    );
+  }
+
+  #[test]
+  fn old_list_output_is_parsed() {
+    let player_list = parse_list_output("§6There are §c1§6/§c10§6 players online:\nAlice");
+
+    assert_eq!(
+      player_list,
+      Some(PlayerList {
+        online: 1,
+        max: 10,
+        names: vec!["Alice".to_string()],
+      })
+    );
+  }
+
+  #[test]
+  fn empty_list_output_has_no_names() {
+    let player_list = parse_list_output("There are 0 of a max of 20 players online: ");
+
+    assert_eq!(
+      player_list.map(|player_list| player_list.names),
+      Some(Vec::new())
+    );
+  }
+
+  #[test]
+  fn unknown_list_output_is_rejected() {
+    assert_eq!(parse_list_output("Unknown command"), None);
+  }
+}
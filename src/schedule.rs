@@ -0,0 +1,724 @@
+//! Scheduled announcements and commands, like a warning before a nightly restart. Entries are
+//! cron expressions in a timezone from the system's zoneinfo files, so they keep firing at the
+//! same local time across DST changes. Zones are read by hand, like the SRV lookup, with the
+//! POSIX rule at the end of the file covering the years past its last listed transition
+
+use chrono::{DateTime, Datelike, Days, FixedOffset, NaiveDate, NaiveDateTime, TimeDelta, Utc};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+const DEFAULT_ZONEINFO_DIR: &str = "/usr/share/zoneinfo";
+
+/// How many days ahead the next run is looked for. Enough for February 29th, across a skipped leap
+/// year like 2100
+const MAX_LOOKAHEAD_DAYS: u64 = 8 * 366;
+
+/// How a date within a year is given in a POSIX rule
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RuleDate {
+  /// `Jn`, 1 to 365, February 29th is never counted
+  Julian(u16),
+  /// `n`, 0 to 365, February 29th is counted
+  DayOfYear(u16),
+  /// `Mm.w.d`, the `w`th `d` weekday of the month, where the 5th is the last one
+  MonthWeekday { month: u32, week: u32, weekday: u32 },
+}
+
+impl RuleDate {
+  fn date(self, year: i32) -> Option<NaiveDate> {
+    match self {
+      Self::Julian(day) => {
+        let date = NaiveDate::from_yo_opt(year, day.into())?;
+        // Counting from March on skips the leap day
+        if date.leap_year() && date.month() > 2 {
+          date.succ_opt()
+        } else {
+          Some(date)
+        }
+      }
+      Self::DayOfYear(day) => NaiveDate::from_yo_opt(year, u32::from(day) + 1),
+      Self::MonthWeekday {
+        month,
+        week,
+        weekday,
+      } => {
+        let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+        let offset = (weekday + 7 - first.weekday().num_days_from_sunday()) % 7;
+        let mut date = first + Days::new(u64::from(offset + (week - 1) * 7));
+        // There is no 5th weekday in the month, so it's the last one
+        while date.month() != month {
+          date = date - Days::new(7);
+        }
+        Some(date)
+      }
+    }
+  }
+}
+
+/// Daylight saving time of a POSIX rule. Offsets are seconds east of UTC, times are seconds
+/// after the local midnight
+#[derive(Clone, Debug, PartialEq)]
+struct DstRule {
+  offset: i32,
+  start: RuleDate,
+  start_time: i32,
+  end: RuleDate,
+  end_time: i32,
+}
+
+/// A POSIX TZ string like "CET-1CEST,M3.5.0,M10.5.0/3"
+#[derive(Clone, Debug, PartialEq)]
+struct PosixRule {
+  std_offset: i32,
+  dst: Option<DstRule>,
+}
+
+/// Reads the POSIX TZ string from the front, a character at a time
+struct PosixParser<'a> {
+  rest: &'a str,
+}
+
+impl PosixParser<'_> {
+  fn peek(&self) -> Option<char> {
+    self.rest.chars().next()
+  }
+
+  fn eat(&mut self, char: char) -> bool {
+    match self.rest.strip_prefix(char) {
+      Some(rest) => {
+        self.rest = rest;
+        true
+      }
+      None => false,
+    }
+  }
+
+  fn name(&mut self) -> Option<()> {
+    let length = if self.rest.starts_with('<') {
+      self.rest.find('>')? + 1
+    } else {
+      self
+        .rest
+        .find(|char: char| !char.is_ascii_alphabetic())
+        .unwrap_or(self.rest.len())
+    };
+    if length < 3 {
+      return None;
+    }
+    self.rest = &self.rest[length..];
+    Some(())
+  }
+
+  fn number(&mut self) -> Option<i32> {
+    let length = self
+      .rest
+      .find(|char: char| !char.is_ascii_digit())
+      .unwrap_or(self.rest.len());
+    let number = self.rest[..length].parse().ok()?;
+    self.rest = &self.rest[length..];
+    Some(number)
+  }
+
+  /// `[+-]hh[:mm[:ss]]` in seconds
+  fn time(&mut self) -> Option<i32> {
+    let sign = if self.eat('-') {
+      -1
+    } else {
+      self.eat('+');
+      1
+    };
+
+    let mut seconds = self.number()? * 60 * 60;
+    if self.eat(':') {
+      seconds += self.number()? * 60;
+      if self.eat(':') {
+        seconds += self.number()?;
+      }
+    }
+    Some(sign * seconds)
+  }
+
+  fn rule_date(&mut self) -> Option<(RuleDate, i32)> {
+    let date = if self.eat('J') {
+      RuleDate::Julian(
+        u16::try_from(self.number()?)
+          .ok()
+          .filter(|day| (1..=365).contains(day))?,
+      )
+    } else if self.eat('M') {
+      let month = self.number()?;
+      self.eat('.').then_some(())?;
+      let week = self.number()?;
+      self.eat('.').then_some(())?;
+      let weekday = self.number()?;
+      if !(1..=12).contains(&month) || !(1..=5).contains(&week) || !(0..=6).contains(&weekday) {
+        return None;
+      }
+      RuleDate::MonthWeekday {
+        month: month as u32,
+        week: week as u32,
+        weekday: weekday as u32,
+      }
+    } else {
+      RuleDate::DayOfYear(
+        u16::try_from(self.number()?)
+          .ok()
+          .filter(|day| *day <= 365)?,
+      )
+    };
+
+    // The change happens at 02:00 local time unless it says otherwise
+    let time = if self.eat('/') {
+      self.time()?
+    } else {
+      2 * 60 * 60
+    };
+    Some((date, time))
+  }
+}
+
+impl PosixRule {
+  fn parse(tz: &str) -> Option<Self> {
+    let mut parser = PosixParser { rest: tz.trim() };
+
+    parser.name()?;
+    // POSIX offsets count west of UTC
+    let std_offset = -parser.time()?;
+    if parser.rest.is_empty() {
+      return Some(Self {
+        std_offset,
+        dst: None,
+      });
+    }
+
+    parser.name()?;
+    let offset = match parser.peek() {
+      Some(',') => std_offset + 60 * 60,
+      _ => -parser.time()?,
+    };
+    parser.eat(',').then_some(())?;
+    let (start, start_time) = parser.rule_date()?;
+    parser.eat(',').then_some(())?;
+    let (end, end_time) = parser.rule_date()?;
+
+    parser.rest.is_empty().then_some(Self {
+      std_offset,
+      dst: Some(DstRule {
+        offset,
+        start,
+        start_time,
+        end,
+        end_time,
+      }),
+    })
+  }
+
+  fn offset_at(&self, timestamp: i64) -> i32 {
+    let Some(dst) = &self.dst else {
+      return self.std_offset;
+    };
+
+    let Some(year) =
+      DateTime::from_timestamp(timestamp + i64::from(self.std_offset), 0).map(|local| local.year())
+    else {
+      return self.std_offset;
+    };
+    // When the changes happen, in UTC. The start is given in standard time, the end in DST
+    let change = |date: RuleDate, time: i32, offset: i32| {
+      let midnight = date.date(year)?.and_hms_opt(0, 0, 0)?.and_utc().timestamp();
+      Some(midnight + i64::from(time) - i64::from(offset))
+    };
+    let (Some(start), Some(end)) = (
+      change(dst.start, dst.start_time, self.std_offset),
+      change(dst.end, dst.end_time, dst.offset),
+    ) else {
+      return self.std_offset;
+    };
+
+    // South of the equator, DST spans the turn of the year
+    let is_dst = if start < end {
+      (start..end).contains(&timestamp)
+    } else {
+      !(end..start).contains(&timestamp)
+    };
+    if is_dst {
+      dst.offset
+    } else {
+      self.std_offset
+    }
+  }
+}
+
+/// A zone from a TZif file: the offsets it had, and the rule for after the last one
+#[derive(Clone, Debug, PartialEq)]
+pub struct Zone {
+  /// Like "Europe/Berlin"
+  name: String,
+  /// When each offset started, as a Unix timestamp, oldest first
+  transitions: Vec<(i64, i32)>,
+  /// The offset before the first transition
+  initial_offset: i32,
+  rule: Option<PosixRule>,
+}
+
+/// Reads big-endian integers off the front of the file
+struct TzifReader<'a> {
+  bytes: &'a [u8],
+}
+
+impl<'a> TzifReader<'a> {
+  fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+    let taken = self.bytes.get(..length)?;
+    self.bytes = &self.bytes[length..];
+    Some(taken)
+  }
+
+  fn u32(&mut self) -> Option<usize> {
+    let bytes = self.take(4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+  }
+
+  fn i32(&mut self) -> Option<i32> {
+    let bytes = self.take(4)?;
+    Some(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+  }
+
+  fn i64(&mut self) -> Option<i64> {
+    let bytes: [u8; 8] = self.take(8)?.try_into().ok()?;
+    Some(i64::from_be_bytes(bytes))
+  }
+}
+
+/// The counts of a TZif header: UT/local indicators, standard/wall indicators, leap seconds,
+/// transitions, local time types and abbreviation characters
+struct TzifCounts {
+  isut: usize,
+  isstd: usize,
+  leap: usize,
+  time: usize,
+  types: usize,
+  chars: usize,
+}
+
+impl Zone {
+  fn parse_tzif(name: &str, bytes: &[u8]) -> Option<Self> {
+    let mut reader = TzifReader { bytes };
+    let header = |reader: &mut TzifReader| {
+      if reader.take(4)? != b"TZif" {
+        return None;
+      }
+      let version = reader.take(1)?[0];
+      reader.take(15)?;
+      let counts = TzifCounts {
+        isut: reader.u32()?,
+        isstd: reader.u32()?,
+        leap: reader.u32()?,
+        time: reader.u32()?,
+        types: reader.u32()?,
+        chars: reader.u32()?,
+      };
+      Some((version, counts))
+    };
+
+    let (version, counts) = header(&mut reader)?;
+    // Version 2 and later repeat the data with 64-bit times, which is the part that's read
+    let (counts, time_size) = if version >= b'2' {
+      reader.take(
+        counts.time * 5
+          + counts.types * 6
+          + counts.chars
+          + counts.leap * 8
+          + counts.isstd
+          + counts.isut,
+      )?;
+      (header(&mut reader)?.1, 8)
+    } else {
+      (counts, 4)
+    };
+
+    let mut times = Vec::with_capacity(counts.time);
+    for _ in 0..counts.time {
+      times.push(if time_size == 8 {
+        reader.i64()?
+      } else {
+        reader.i32()?.into()
+      });
+    }
+    let type_indices = reader.take(counts.time)?.to_vec();
+    let mut offsets = Vec::with_capacity(counts.types);
+    for _ in 0..counts.types {
+      offsets.push(reader.i32()?);
+      reader.take(2)?;
+    }
+    reader.take(counts.chars + counts.leap * (time_size + 4) + counts.isstd + counts.isut)?;
+
+    let transitions = times
+      .into_iter()
+      .zip(type_indices)
+      .map(|(time, index)| Some((time, *offsets.get(usize::from(index))?)))
+      .collect::<Option<Vec<_>>>()?;
+
+    // The footer is the POSIX rule between newlines, which can be empty
+    let rule = if version >= b'2' {
+      let footer = std::str::from_utf8(reader.bytes).ok()?;
+      let footer = footer.strip_prefix('\n')?.split('\n').next()?;
+      if footer.is_empty() {
+        None
+      } else {
+        Some(PosixRule::parse(footer)?)
+      }
+    } else {
+      None
+    };
+
+    Some(Self {
+      name: name.to_string(),
+      transitions,
+      initial_offset: *offsets.first()?,
+      rule,
+    })
+  }
+
+  fn offset_at(&self, timestamp: i64) -> i32 {
+    let index = self
+      .transitions
+      .partition_point(|(start, _)| *start <= timestamp);
+
+    match (index, &self.rule) {
+      (index, Some(rule)) if index == self.transitions.len() => rule.offset_at(timestamp),
+      (0, _) => self.initial_offset,
+      (index, _) => self.transitions[index - 1].1,
+    }
+  }
+}
+
+/// Where schedules are run by the clock
+#[derive(Clone, Debug, PartialEq)]
+pub enum Timezone {
+  Fixed(FixedOffset),
+  Zone(Zone),
+}
+
+impl Timezone {
+  /// A zone like "Europe/Berlin" from the zoneinfo files (in `TZDIR`, or the system's), or an
+  /// offset like "+02:00"
+  pub fn load(name: &str) -> Result<Self, String> {
+    let name = name.trim();
+    if name.eq_ignore_ascii_case("utc") {
+      return Ok(Self::Fixed(FixedOffset::east_opt(0).unwrap()));
+    }
+    if let Ok(offset) = name.parse() {
+      return Ok(Self::Fixed(offset));
+    }
+
+    if name.is_empty() || name.starts_with('/') || name.split('/').any(|part| part == "..") {
+      return Err(format!("'{name}' isn't a timezone name"));
+    }
+    let directory = env::var("TZDIR").unwrap_or_else(|_| DEFAULT_ZONEINFO_DIR.to_string());
+    let path = PathBuf::from(directory).join(name);
+
+    let bytes = fs::read(&path)
+      .map_err(|error| format!("Couldn't read the timezone {}: {error}", path.display()))?;
+    Zone::parse_tzif(name, &bytes)
+      .map(Self::Zone)
+      .ok_or_else(|| format!("{} isn't a valid timezone file", path.display()))
+  }
+
+  pub fn name(&self) -> String {
+    match self {
+      Self::Fixed(offset) => offset.to_string(),
+      Self::Zone(zone) => zone.name.clone(),
+    }
+  }
+
+  /// The offset from UTC at the moment, in seconds
+  fn offset_at(&self, timestamp: i64) -> i32 {
+    match self {
+      Self::Fixed(offset) => offset.local_minus_utc(),
+      Self::Zone(zone) => zone.offset_at(timestamp),
+    }
+  }
+
+  pub fn to_local(&self, time: DateTime<Utc>) -> NaiveDateTime {
+    let offset = self.offset_at(time.timestamp());
+    time.naive_utc() + TimeDelta::new(offset.into(), 0).unwrap_or_default()
+  }
+
+  /// When the local time happens. A time skipped by a change to DST happens as if the clock
+  /// hadn't changed yet, an hour later on the new clock, and a time that happens twice when DST
+  /// ends is the first one
+  pub fn to_utc(&self, local: NaiveDateTime) -> DateTime<Utc> {
+    let local_timestamp = local.and_utc().timestamp();
+    // Zones don't change their offset twice within a day
+    let before = self.offset_at(local_timestamp - 24 * 60 * 60);
+    let after = self.offset_at(local_timestamp + 24 * 60 * 60);
+
+    let timestamp = [before, after]
+      .into_iter()
+      .map(|offset| local_timestamp - i64::from(offset))
+      .filter(|timestamp| i64::from(self.offset_at(*timestamp)) == local_timestamp - timestamp)
+      .min()
+      .unwrap_or(local_timestamp - i64::from(before));
+
+    DateTime::from_timestamp(timestamp, 0).unwrap_or_default()
+  }
+}
+
+/// The fields of a cron expression, each as the set of values it allows
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cron {
+  minutes: u64,
+  hours: u64,
+  days: u64,
+  months: u64,
+  /// Sunday is 0
+  weekdays: u64,
+  /// With both the day of the month and the day of the week restricted, either one can match,
+  /// like in cron
+  days_restricted: bool,
+  weekdays_restricted: bool,
+}
+
+const MONTH_NAMES: [&str; 12] = [
+  "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// Parses a field like "1-5", "*/15" or "mon,wed,fri" into a bit per allowed value. `names`
+/// stand for the values from `min` on
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Option<u64> {
+  let value = |text: &str| -> Option<u32> {
+    let text = text.to_lowercase();
+    match names.iter().position(|name| *name == text) {
+      Some(index) => Some(min + index as u32),
+      None => text.parse().ok(),
+    }
+  };
+
+  let mut allowed = 0;
+  for item in field.split(',') {
+    let (range, step) = match item.split_once('/') {
+      Some((range, step)) => (range, step.parse().ok().filter(|step| *step > 0)?),
+      None => (item, 1),
+    };
+    let (start, end) = match range {
+      "*" => (min, max),
+      range => match range.split_once('-') {
+        Some((start, end)) => (value(start)?, value(end)?),
+        // "5/10" is every 10 from 5 on
+        None if step > 1 => (value(range)?, max),
+        None => (value(range)?, value(range)?),
+      },
+    };
+    if start < min || end > max || start > end {
+      return None;
+    }
+
+    for value in (start..=end).step_by(step as usize) {
+      allowed |= 1 << value;
+    }
+  }
+  Some(allowed)
+}
+
+impl Cron {
+  /// Parses the five fields: minute, hour, day of the month, month and day of the week
+  pub fn parse(expression: &str) -> Result<Self, String> {
+    let fields: Vec<&str> = expression.split_whitespace().collect();
+    let [minutes, hours, days, months, weekdays] = fields[..] else {
+      return Err(format!(
+        "'{expression}' needs 5 fields: minute, hour, day of the month, month and day of the week"
+      ));
+    };
+    let invalid =
+      |name: &str, field: &str| format!("The {name} '{field}' of '{expression}' is invalid");
+
+    let mut weekday_bits = parse_field(weekdays, 0, 7, &WEEKDAY_NAMES)
+      .ok_or_else(|| invalid("day of the week", weekdays))?;
+    // 7 is Sunday too
+    if weekday_bits & 1 << 7 != 0 {
+      weekday_bits = (weekday_bits & !(1 << 7)) | 1;
+    }
+
+    Ok(Self {
+      minutes: parse_field(minutes, 0, 59, &[]).ok_or_else(|| invalid("minute", minutes))?,
+      hours: parse_field(hours, 0, 23, &[]).ok_or_else(|| invalid("hour", hours))?,
+      days: parse_field(days, 1, 31, &[]).ok_or_else(|| invalid("day of the month", days))?,
+      months: parse_field(months, 1, 12, &MONTH_NAMES).ok_or_else(|| invalid("month", months))?,
+      weekdays: weekday_bits,
+      days_restricted: days != "*",
+      weekdays_restricted: weekdays != "*",
+    })
+  }
+
+  fn matches_date(&self, date: NaiveDate) -> bool {
+    let bit = |bits: u64, value: u32| bits & 1 << value != 0;
+    let day = bit(self.days, date.day());
+    let weekday = bit(self.weekdays, date.weekday().num_days_from_sunday());
+
+    let day_matches = match (self.days_restricted, self.weekdays_restricted) {
+      (true, true) => day || weekday,
+      _ => day && weekday,
+    };
+    day_matches && bit(self.months, date.month())
+  }
+
+  /// The first time after `after` the expression matches, in the timezone's local time
+  pub fn next_after(&self, after: DateTime<Utc>, timezone: &Timezone) -> Option<DateTime<Utc>> {
+    // A day earlier, since a time skipped by DST can land on the next day
+    let start = timezone.to_local(after).date() - Days::new(1);
+
+    for day in 0..MAX_LOOKAHEAD_DAYS {
+      let date = start + Days::new(day);
+      if !self.matches_date(date) {
+        continue;
+      }
+
+      for hour in (0..24).filter(|hour| self.hours & 1 << hour != 0) {
+        for minute in (0..60).filter(|minute| self.minutes & 1 << minute != 0) {
+          let time = timezone.to_utc(date.and_hms_opt(hour, minute, 0)?);
+          if time > after {
+            return Some(time);
+          }
+        }
+      }
+    }
+
+    None
+  }
+}
+
+/// A `[[schedules]]` entry, ready to run
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schedule {
+  /// As it was configured, for the logs
+  pub expression: String,
+  pub cron: Cron,
+  /// Run over RCON, skipped while the server is offline
+  pub command: Option<String>,
+  /// Posted in the status channel
+  pub message: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn utc(text: &str) -> DateTime<Utc> {
+    text.parse().unwrap()
+  }
+
+  fn berlin() -> Timezone {
+    Timezone::Zone(Zone {
+      name: "Europe/Berlin".to_string(),
+      transitions: Vec::new(),
+      initial_offset: 3600,
+      rule: PosixRule::parse("CET-1CEST,M3.5.0,M10.5.0/3"),
+    })
+  }
+
+  #[test]
+  fn posix_rules_are_parsed() {
+    assert_eq!(
+      PosixRule::parse("<+0330>-3:30"),
+      Some(PosixRule {
+        std_offset: 3 * 3600 + 1800,
+        dst: None,
+      })
+    );
+    assert_eq!(
+      PosixRule::parse("EST5EDT,M3.2.0,M11.1.0"),
+      Some(PosixRule {
+        std_offset: -5 * 3600,
+        dst: Some(DstRule {
+          offset: -4 * 3600,
+          start: RuleDate::MonthWeekday {
+            month: 3,
+            week: 2,
+            weekday: 0,
+          },
+          start_time: 7200,
+          end: RuleDate::MonthWeekday {
+            month: 11,
+            week: 1,
+            weekday: 0,
+          },
+          end_time: 7200,
+        }),
+      })
+    );
+    assert_eq!(PosixRule::parse("EST5EDT,M13.1.0,M11.1.0"), None);
+  }
+
+  #[test]
+  fn dst_changes_on_the_last_sunday() {
+    let berlin = berlin();
+
+    // 2024-03-31 and 2024-10-27 are the last Sundays of March and October
+    assert_eq!(
+      berlin.offset_at(utc("2024-03-31T00:59:59Z").timestamp()),
+      3600
+    );
+    assert_eq!(
+      berlin.offset_at(utc("2024-03-31T01:00:00Z").timestamp()),
+      7200
+    );
+    assert_eq!(
+      berlin.offset_at(utc("2024-10-27T00:59:59Z").timestamp()),
+      7200
+    );
+    assert_eq!(
+      berlin.offset_at(utc("2024-10-27T01:00:00Z").timestamp()),
+      3600
+    );
+  }
+
+  #[test]
+  fn cron_fields_are_parsed() {
+    let cron = Cron::parse("*/15 4 1-7 * mon,fri").unwrap();
+
+    assert_eq!(cron.minutes, 1 << 0 | 1 << 15 | 1 << 30 | 1 << 45);
+    assert_eq!(cron.hours, 1 << 4);
+    assert_eq!(cron.weekdays, 1 << 1 | 1 << 5);
+    assert!(Cron::parse("60 4 * * *").is_err());
+    assert!(Cron::parse("0 4 * *").is_err());
+  }
+
+  #[test]
+  fn nightly_runs_keep_the_local_time_across_dst() {
+    let cron = Cron::parse("0 4 * * *").unwrap();
+    let berlin = berlin();
+
+    // 04:00 is 03:00 UTC in winter and 02:00 UTC in summer
+    assert_eq!(
+      cron.next_after(utc("2024-03-30T12:00:00Z"), &berlin),
+      Some(utc("2024-03-31T02:00:00Z"))
+    );
+    assert_eq!(
+      cron.next_after(utc("2024-03-29T12:00:00Z"), &berlin),
+      Some(utc("2024-03-30T03:00:00Z"))
+    );
+  }
+
+  #[test]
+  fn skipped_and_repeated_times_run_once() {
+    let cron = Cron::parse("30 2 * * *").unwrap();
+    let berlin = berlin();
+
+    // 02:30 doesn't exist on 2024-03-31, it runs at 03:30 on the new clock
+    assert_eq!(
+      cron.next_after(utc("2024-03-30T12:00:00Z"), &berlin),
+      Some(utc("2024-03-31T01:30:00Z"))
+    );
+    // 02:30 happens twice on 2024-10-27, only the first one runs
+    let first = cron
+      .next_after(utc("2024-10-26T12:00:00Z"), &berlin)
+      .unwrap();
+    assert_eq!(first, utc("2024-10-27T00:30:00Z"));
+    assert_eq!(
+      cron.next_after(first, &berlin),
+      Some(utc("2024-10-28T01:30:00Z"))
+    );
+  }
+}
@@ -0,0 +1,506 @@
+//! What the status checks learned about the server, and how it's shown in the status channel and
+//! the presence
+
+use crate::rcon::strip_color_codes;
+use crate::store::StatusState;
+use serenity::all::{ActivityData, ChannelId, Context, EditChannel, HttpError, OnlineStatus};
+use serenity::Error as SerenityError;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::time;
+
+/// The longest channel name Discord accepts
+pub const CHANNEL_NAME_LIMIT: usize = 100;
+
+/// Discord allows two renames of a channel per ten minutes
+pub const CHANNEL_RENAMES_PER_WINDOW: usize = 2;
+pub const CHANNEL_RENAME_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// Serenity waits out a rate limit before answering, a rename that takes longer than this is given
+/// up on so the status tick doesn't hang
+const CHANNEL_RENAME_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// What the status loop last learned about the minecraft server
+#[derive(Clone, Debug)]
+pub struct ServerSnapshot {
+  pub online: bool,
+  pub players_online: u32,
+  pub players_max: u32,
+  /// The players the server chose to list, which can be fewer than are online
+  pub names: Vec<String>,
+  /// The uuids of the listed players by name, only the status ping has them
+  pub uuids: HashMap<String, String>,
+  /// Per edition player counts, only when a Bedrock port is configured
+  pub editions: Option<EditionCounts>,
+  /// The game version the Java server reported
+  pub version: Option<String>,
+  /// The Java server's MOTD, without formatting
+  pub motd: Option<String>,
+  pub fetched_at: Instant,
+}
+
+/// How the Java server's status was found out. The status ping is tried first, the others are
+/// fallbacks for servers that fail it now and then
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StatusProbe {
+  Ping,
+  Query,
+  RconList,
+}
+
+impl fmt::Display for StatusProbe {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(match self {
+      Self::Ping => "status ping",
+      Self::Query => "query protocol",
+      Self::RconList => "RCON list",
+    })
+  }
+}
+
+/// Players online on each edition, `None` if that edition didn't answer
+#[derive(Clone, Debug)]
+pub struct EditionCounts {
+  pub java: Option<u32>,
+  pub bedrock: Option<u32>,
+}
+
+impl EditionCounts {
+  pub fn format_count(count: Option<u32>) -> String {
+    count.map_or_else(|| "offline".to_string(), |count| count.to_string())
+  }
+
+  /// Like "Java: 5, Bedrock: 2"
+  pub fn describe(&self) -> String {
+    format!(
+      "Java: {}, Bedrock: {}",
+      Self::format_count(self.java),
+      Self::format_count(self.bedrock)
+    )
+  }
+}
+
+impl ServerSnapshot {
+  pub fn offline() -> Self {
+    Self {
+      online: false,
+      players_online: 0,
+      players_max: 0,
+      names: Vec::new(),
+      uuids: HashMap::new(),
+      editions: None,
+      version: None,
+      motd: None,
+      fetched_at: Instant::now(),
+    }
+  }
+
+  /// The name the status channel should have
+  pub fn channel_name(&self, format: &StatusFormat) -> String {
+    if self.online {
+      format.render_online(
+        self.players_online,
+        self.players_max,
+        self.editions.as_ref(),
+      )
+    } else {
+      fit_channel_name(&format.offline)
+    }
+  }
+
+  /// The activity the bot should show
+  pub fn presence_text(&self) -> String {
+    if self.online {
+      format!("{}/{} online", self.players_online, self.players_max)
+    } else {
+      "Server offline".to_string()
+    }
+  }
+}
+
+/// The status channel names. The online one can have `{online}`, `{max}` and `{editions}` (like
+/// "Java: 5, Bedrock: 2", with a Bedrock port) placeholders
+pub struct StatusFormat {
+  pub online: String,
+  pub offline: String,
+}
+
+impl StatusFormat {
+  pub fn render_online(
+    &self,
+    players_online: u32,
+    players_max: u32,
+    editions: Option<&EditionCounts>,
+  ) -> String {
+    let editions = editions.map(EditionCounts::describe).unwrap_or_default();
+    let name = self
+      .online
+      .replace("{online}", &players_online.to_string())
+      .replace("{max}", &players_max.to_string())
+      .replace("{editions}", &editions);
+
+    fit_channel_name(&name)
+  }
+}
+
+/// Cuts the name off at Discord's channel name limit
+pub fn fit_channel_name(name: &str) -> String {
+  name
+    .chars()
+    .take(CHANNEL_NAME_LIMIT)
+    .collect::<String>()
+    .trim()
+    .to_string()
+}
+
+/// Keeps a channel's renames within Discord's limit
+#[derive(Default)]
+pub struct RenameLimiter {
+  /// The renames within the last window, the oldest first
+  renames: VecDeque<Instant>,
+}
+
+impl RenameLimiter {
+  /// How long until the channel can be renamed again, `None` if it can be now
+  pub fn retry_after(&mut self, now: Instant) -> Option<Duration> {
+    while self
+      .renames
+      .front()
+      .is_some_and(|renamed_at| now.duration_since(*renamed_at) >= CHANNEL_RENAME_WINDOW)
+    {
+      self.renames.pop_front();
+    }
+
+    if self.renames.len() < CHANNEL_RENAMES_PER_WINDOW {
+      return None;
+    }
+    Some(CHANNEL_RENAME_WINDOW - now.duration_since(self.renames[0]))
+  }
+
+  pub fn renamed(&mut self, now: Instant) {
+    self.renames.push_back(now);
+  }
+
+  /// Discord rate limited a rename, so the renames it counted aren't all known. Waits a full
+  /// window to be safe
+  pub fn rate_limited(&mut self, now: Instant) {
+    self.renames.clear();
+    for _ in 0..CHANNEL_RENAMES_PER_WINDOW {
+      self.renames.push_back(now);
+    }
+  }
+}
+
+/// Why a channel wasn't renamed
+pub enum RenameError {
+  /// The limit was used up, the channel can be renamed again after this long
+  TooSoon(Duration),
+  /// Discord answered with a 429, or serenity was still waiting out one
+  RateLimited,
+  Discord(SerenityError),
+}
+
+impl fmt::Display for RenameError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::TooSoon(retry_after) => write!(
+        f,
+        "Discord only allows {CHANNEL_RENAMES_PER_WINDOW} renames per 10 minutes, retrying after {}s",
+        retry_after.as_secs()
+      ),
+      Self::RateLimited => write!(
+        f,
+        "Discord rate limited the rename, retrying after {}s",
+        CHANNEL_RENAME_WINDOW.as_secs()
+      ),
+      Self::Discord(error) => error.fmt(f),
+    }
+  }
+}
+
+/// Whether Discord answered with a 429
+fn is_rate_limited(error: &SerenityError) -> bool {
+  matches!(
+    error,
+    SerenityError::Http(HttpError::UnsuccessfulRequest(response))
+      if response.status_code.as_u16() == 429
+  )
+}
+
+/// Renames the channel if the limiter allows it
+pub async fn rename_channel(
+  ctx: &Context,
+  limiter: &Mutex<RenameLimiter>,
+  channel_id: ChannelId,
+  name: &str,
+) -> Result<(), RenameError> {
+  if let Some(retry_after) = limiter.lock().unwrap().retry_after(Instant::now()) {
+    return Err(RenameError::TooSoon(retry_after));
+  }
+
+  let renamed = time::timeout(
+    CHANNEL_RENAME_TIMEOUT,
+    channel_id.edit(ctx, EditChannel::new().name(name)),
+  )
+  .await;
+
+  let mut limiter = limiter.lock().unwrap();
+  match renamed {
+    Ok(Ok(_)) => {
+      limiter.renamed(Instant::now());
+      Ok(())
+    }
+    Ok(Err(error)) if is_rate_limited(&error) => {
+      limiter.rate_limited(Instant::now());
+      Err(RenameError::RateLimited)
+    }
+    Ok(Err(error)) => Err(RenameError::Discord(error)),
+    Err(_) => {
+      limiter.rate_limited(Instant::now());
+      Err(RenameError::RateLimited)
+    }
+  }
+}
+
+/// How many failed pings in a row it takes to announce that the server went offline
+const OFFLINE_AFTER_FAILURES: u32 = 2;
+
+/// Decides when the server went offline or came back, ignoring a single failed ping
+#[derive(Default)]
+pub struct StatusTransitions {
+  /// What was last decided, `None` until the first ping
+  online: Option<bool>,
+  failures: u32,
+}
+
+impl StatusTransitions {
+  /// Returns the new state if it changed. The state found at startup isn't a change
+  pub fn observe(&mut self, online: bool) -> Option<bool> {
+    if online {
+      self.failures = 0;
+    } else {
+      self.failures += 1;
+      if self.failures < OFFLINE_AFTER_FAILURES && self.online.is_some() {
+        return None;
+      }
+    }
+
+    match self.online.replace(online) {
+      Some(previous) if previous != online => Some(online),
+      _ => None,
+    }
+  }
+}
+
+/// Takes the median of the last few player counts, so players joining and leaving right at a
+/// status check don't make the displayed count jump around
+pub struct PlayerCountSmoother {
+  pub samples: usize,
+  recent: VecDeque<u32>,
+}
+
+impl PlayerCountSmoother {
+  pub fn new(samples: usize) -> Self {
+    Self {
+      samples: samples.max(1),
+      recent: VecDeque::new(),
+    }
+  }
+
+  /// Adds the sample and returns the count to display. Going offline starts over
+  pub fn smooth(&mut self, online: bool, players_online: u32) -> u32 {
+    if !online {
+      self.recent.clear();
+      return players_online;
+    }
+
+    self.recent.push_back(players_online);
+    if self.recent.len() > self.samples {
+      self.recent.pop_front();
+    }
+
+    let mut sorted: Vec<u32> = self.recent.iter().copied().collect();
+    sorted.sort_unstable();
+    sorted[sorted.len() / 2]
+  }
+}
+
+/// The activity and online status the bot should show for an applied status
+pub fn status_presence(state: &StatusState) -> (ActivityData, OnlineStatus) {
+  let online_status = if state.online {
+    OnlineStatus::Online
+  } else {
+    OnlineStatus::DoNotDisturb
+  };
+
+  (ActivityData::playing(&state.presence), online_status)
+}
+
+/// The MOTD as plain text. Servers pad the lines with spaces to center them in the server list,
+/// which only looks odd in Discord
+pub fn plain_motd(motd: &str) -> String {
+  strip_color_codes(motd)
+    .lines()
+    .map(str::trim)
+    .filter(|line| !line.is_empty())
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+/// The image of a favicon like "data:image/png;base64,iVBOR...". Older servers break the base64
+/// into lines
+pub fn decode_favicon(favicon: &str) -> Option<Vec<u8>> {
+  let (_, data) = favicon.split_once(";base64,")?;
+
+  let mut bytes = Vec::with_capacity(data.len() * 3 / 4);
+  let mut buffer = 0u32;
+  let mut bits = 0;
+  for char in data.bytes() {
+    let value = match char {
+      b'A'..=b'Z' => char - b'A',
+      b'a'..=b'z' => char - b'a' + 26,
+      b'0'..=b'9' => char - b'0' + 52,
+      b'+' => 62,
+      b'/' => 63,
+      b'=' | b'\n' | b'\r' => continue,
+      _ => return None,
+    };
+
+    buffer = buffer << 6 | value as u32;
+    bits += 6;
+    if bits >= 8 {
+      bits -= 8;
+      bytes.push((buffer >> bits) as u8);
+    }
+  }
+
+  (!bytes.is_empty()).then_some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn smoothing_takes_the_median_of_recent_counts() {
+    let mut smoother = PlayerCountSmoother::new(3);
+
+    assert_eq!(smoother.smooth(true, 5), 5);
+    assert_eq!(smoother.smooth(true, 20), 20);
+    assert_eq!(smoother.smooth(true, 6), 6);
+    // The 5 dropped out, leaving 20, 6 and 7
+    assert_eq!(smoother.smooth(true, 7), 7);
+  }
+
+  #[test]
+  fn smoothing_starts_over_after_going_offline() {
+    let mut smoother = PlayerCountSmoother::new(3);
+    smoother.smooth(true, 10);
+    smoother.smooth(true, 10);
+
+    assert_eq!(smoother.smooth(false, 0), 0);
+    assert_eq!(smoother.smooth(true, 2), 2);
+  }
+
+  #[test]
+  fn one_sample_is_no_smoothing() {
+    let mut smoother = PlayerCountSmoother::new(1);
+    smoother.smooth(true, 10);
+
+    assert_eq!(smoother.smooth(true, 3), 3);
+  }
+
+  #[test]
+  fn a_single_failed_ping_isnt_an_outage() {
+    let mut transitions = StatusTransitions::default();
+
+    assert_eq!(transitions.observe(true), None);
+    assert_eq!(transitions.observe(false), None);
+    assert_eq!(transitions.observe(true), None);
+    assert_eq!(transitions.observe(false), None);
+    assert_eq!(transitions.observe(false), Some(false));
+    assert_eq!(transitions.observe(false), None);
+    assert_eq!(transitions.observe(true), Some(true));
+  }
+
+  #[test]
+  fn the_state_at_startup_isnt_announced() {
+    let mut transitions = StatusTransitions::default();
+
+    assert_eq!(transitions.observe(false), None);
+    assert_eq!(transitions.observe(false), None);
+    assert_eq!(transitions.observe(true), Some(true));
+  }
+
+  #[test]
+  fn status_formats_fill_in_the_counts() {
+    let format = StatusFormat {
+      online: "{online}/{max} on | {editions}".to_string(),
+      offline: "down".to_string(),
+    };
+    let editions = EditionCounts {
+      java: Some(3),
+      bedrock: None,
+    };
+
+    assert_eq!(
+      format.render_online(3, 20, Some(&editions)),
+      "3/20 on | Java: 3, Bedrock: offline"
+    );
+    assert_eq!(format.render_online(0, 20, None), "0/20 on |");
+    assert_eq!(ServerSnapshot::offline().channel_name(&format), "down");
+  }
+
+  #[test]
+  fn long_channel_names_are_cut_off() {
+    let format = StatusFormat {
+      online: format!("{} {{online}}", "🎮".repeat(99)),
+      offline: "down".to_string(),
+    };
+
+    assert_eq!(format.render_online(5, 20, None), "🎮".repeat(99));
+    assert_eq!(fit_channel_name(&"a".repeat(150)).len(), CHANNEL_NAME_LIMIT);
+  }
+
+  #[test]
+  fn renames_are_limited_per_window() {
+    let mut limiter = RenameLimiter::default();
+    let start = Instant::now();
+
+    assert_eq!(limiter.retry_after(start), None);
+    limiter.renamed(start);
+    limiter.renamed(start + Duration::from_secs(60));
+    assert_eq!(
+      limiter.retry_after(start + Duration::from_secs(120)),
+      Some(CHANNEL_RENAME_WINDOW - Duration::from_secs(120))
+    );
+    assert_eq!(limiter.retry_after(start + CHANNEL_RENAME_WINDOW), None);
+
+    limiter.rate_limited(start + CHANNEL_RENAME_WINDOW);
+    assert_eq!(
+      limiter.retry_after(start + CHANNEL_RENAME_WINDOW),
+      Some(CHANNEL_RENAME_WINDOW)
+    );
+  }
+
+  #[test]
+  fn motds_are_plain_text() {
+    assert_eq!(
+      plain_motd("   §6§lA Minecraft Server§r   \n  §7Survival §a1.21  "),
+      "A Minecraft Server\nSurvival 1.21"
+    );
+    assert_eq!(plain_motd("§"), "");
+  }
+
+  #[test]
+  fn favicons_are_decoded() {
+    assert_eq!(
+      decode_favicon("data:image/png;base64,iVBORw0K\nGgo="),
+      Some(vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A])
+    );
+    assert_eq!(decode_favicon("data:image/png;base64,not base64"), None);
+    assert_eq!(decode_favicon("iVBORw0KGgo="), None);
+  }
+}
@@ -0,0 +1,633 @@
+//! The bot's persistent state, kept in a single JSON file
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tracing::warn;
+
+/// What the status loop last applied to Discord
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StatusState {
+  pub channel_name: String,
+  pub presence: String,
+  pub online: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ModerationKind {
+  Kick,
+  Ban,
+}
+
+/// A kick or ban issued through the bot
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ModerationAction {
+  pub minecraft_name: String,
+  pub kind: ModerationKind,
+  pub reason: Option<String>,
+  pub moderator_id: u64,
+  pub moderator_name: String,
+  /// Unix timestamp in seconds
+  pub issued_at: i64,
+}
+
+/// Which edition an account plays with
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Platform {
+  #[default]
+  Java,
+  /// Through Geyser, with a Floodgate name and UUID
+  Bedrock,
+}
+
+/// A Discord user's verified minecraft account
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Link {
+  pub discord_id: u64,
+  pub uuid: String,
+  pub minecraft_name: String,
+  /// Unix timestamp in seconds
+  pub linked_at: i64,
+  /// Whether the user was warned that the verification is about to expire
+  #[serde(default)]
+  pub expiry_warned: bool,
+  /// The server the account is whitelisted on, `None` for the main server
+  #[serde(default)]
+  pub server: Option<String>,
+  /// Whether a moderator took the Verified role away, which takes the account off the whitelist
+  /// until the role is given back
+  #[serde(default)]
+  pub suspended: bool,
+  /// Whether the user was banned from Discord, which banned the account on the server too. Kept
+  /// so lifting the Discord ban can pardon it
+  #[serde(default)]
+  pub banned: bool,
+  #[serde(default)]
+  pub platform: Platform,
+  /// The Xbox id of a Bedrock account
+  #[serde(default)]
+  pub xuid: Option<u64>,
+}
+
+/// Which Discord user verified which minecraft account. Each account can only be linked once per
+/// server
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(transparent)]
+pub struct LinkStore {
+  links: Vec<Link>,
+}
+
+impl Link {
+  /// Whether the account should be on the whitelist
+  pub fn is_active(&self) -> bool {
+    !self.suspended && !self.banned
+  }
+
+  fn is(&self, uuid: &str, server: Option<&str>) -> bool {
+    self.uuid == uuid && self.server.as_deref() == server
+  }
+}
+
+impl LinkStore {
+  pub fn len(&self) -> usize {
+    self.links.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.links.is_empty()
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &Link> {
+    self.links.iter()
+  }
+
+  /// The account's link on any server
+  pub fn by_uuid(&self, uuid: &str) -> Option<&Link> {
+    self.links.iter().find(|link| link.uuid == uuid)
+  }
+
+  pub fn by_discord_id(&self, discord_id: u64) -> impl Iterator<Item = &Link> {
+    self
+      .links
+      .iter()
+      .filter(move |link| link.discord_id == discord_id)
+  }
+
+  pub fn by_uuid_mut(&mut self, uuid: &str, server: Option<&str>) -> Option<&mut Link> {
+    self.links.iter_mut().find(|link| link.is(uuid, server))
+  }
+
+  pub fn remove(&mut self, uuid: &str, server: Option<&str>) -> Option<Link> {
+    let index = self.links.iter().position(|link| link.is(uuid, server))?;
+    Some(self.links.remove(index))
+  }
+
+  /// Adds the link like `upsert`, but leaves an account linked to another Discord user on the
+  /// same server alone. The error is who it's linked to
+  pub fn link(&mut self, link: Link) -> Result<(), u64> {
+    match self.by_uuid_mut(&link.uuid, link.server.as_deref()) {
+      Some(existing) if existing.discord_id != link.discord_id => Err(existing.discord_id),
+      _ => {
+        self.upsert(link);
+        Ok(())
+      }
+    }
+  }
+
+  /// Adds the link, replacing the existing link of the same minecraft account on the same server
+  pub fn upsert(&mut self, link: Link) {
+    match self
+      .links
+      .iter_mut()
+      .find(|existing| existing.is(&link.uuid, link.server.as_deref()))
+    {
+      Some(existing) => *existing = link,
+      None => self.links.push(link),
+    }
+  }
+}
+
+/// A `/verify` that was queued because the Mojang API was down
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PendingVerification {
+  pub discord_id: u64,
+  pub username: String,
+  /// Unix timestamp in seconds
+  pub requested_at: i64,
+  /// `None` for the main server
+  #[serde(default)]
+  pub server: Option<String>,
+}
+
+/// An account whitelisted by its typed name during a Mojang outage, not checked against Mojang yet
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UnvalidatedVerification {
+  pub discord_id: u64,
+  pub minecraft_name: String,
+  /// Unix timestamp in seconds
+  pub verified_at: i64,
+  /// `None` for the main server
+  #[serde(default)]
+  pub server: Option<String>,
+}
+
+/// A code for a server side Discord linking plugin, issued by `/link-discord`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LinkCode {
+  pub discord_id: u64,
+  pub code: String,
+  /// Unix timestamp in seconds
+  pub expires_at: i64,
+}
+
+/// A one-time token for verifying through the companion website
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WebVerifyToken {
+  pub discord_id: u64,
+  pub token: String,
+  /// Unix timestamp in seconds
+  pub expires_at: i64,
+}
+
+/// A whitelist entry added with `/whitelist-add`, outside the verification flow. It has no Discord
+/// link on purpose, so it isn't drift
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UnmanagedEntry {
+  pub uuid: String,
+  pub minecraft_name: String,
+  /// The admin who added it
+  pub added_by: u64,
+  /// Unix timestamp in seconds
+  pub added_at: i64,
+}
+
+/// A whitelist entry from before the bot, imported with `/import-whitelist`. Whoever verifies the
+/// name claims it and gets the link
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct UnclaimedEntry {
+  pub uuid: String,
+  pub minecraft_name: String,
+  /// `None` for the main server
+  pub server: Option<String>,
+  /// Unix timestamp in seconds
+  pub imported_at: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum OperationKind {
+  WhitelistAdd,
+  WhitelistRemove,
+  Ban,
+  Pardon,
+}
+
+/// A whitelist change made automatically while the server couldn't be reached, replayed in order
+/// once it's back online
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PendingOperation {
+  pub kind: OperationKind,
+  pub discord_id: u64,
+  pub minecraft_name: String,
+  /// `None` for the main server
+  pub server: Option<String>,
+  /// The member left the guild, so the link is forgotten once the account is off the whitelist
+  #[serde(default)]
+  pub forget_link: bool,
+  /// Unix timestamp in seconds
+  pub queued_at: i64,
+}
+
+impl PendingOperation {
+  fn is_for(&self, minecraft_name: &str, server: Option<&str>) -> bool {
+    self.minecraft_name == minecraft_name && self.server.as_deref() == server
+  }
+}
+
+/// The most players online at once on a day
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DailyPeak {
+  pub day: NaiveDate,
+  pub players: u32,
+}
+
+/// How many days of peaks are kept
+const DAILY_PEAK_DAYS: usize = 30;
+
+/// How many days of activity are kept for the daily summary
+const DAILY_ACTIVITY_DAYS: usize = 7;
+
+/// What the status checks saw during a day, in the configured timezone, for the daily summary
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct DailyActivity {
+  pub day: NaiveDate,
+  /// The most players online at once
+  pub peak_players: u32,
+  /// When the peak was first reached, as a Unix timestamp in seconds
+  pub peak_at: Option<i64>,
+  /// The players seen online, by uuid, or by lowercase name when it isn't known
+  pub players: Vec<String>,
+  /// How much of the day the status checks covered, in seconds
+  pub observed_seconds: u64,
+  /// How much of that the server was offline
+  pub offline_seconds: u64,
+  pub verifications: u32,
+  /// Whether the bot was started again during the day, losing the checks in between
+  pub restarted: bool,
+}
+
+impl Default for DailyActivity {
+  fn default() -> Self {
+    Self {
+      day: NaiveDate::MIN,
+      peak_players: 0,
+      peak_at: None,
+      players: Vec::new(),
+      observed_seconds: 0,
+      offline_seconds: 0,
+      verifications: 0,
+      restarted: false,
+    }
+  }
+}
+
+impl DailyActivity {
+  /// Whether the checks missed part of the day, with some slack for ticks that run late
+  pub fn is_partial(&self) -> bool {
+    self.restarted || self.observed_seconds < 24 * 60 * 60 * 9 / 10
+  }
+}
+
+/// A player the status checks saw online. Only sampled, a player who joins and leaves between two
+/// checks is never seen
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PlayerSighting {
+  /// Without dashes. `None` when the player was only ever seen by name, like in RCON `list`
+  pub uuid: Option<String>,
+  /// The name the player was last seen with
+  pub name: String,
+  /// Unix timestamp in seconds
+  pub last_seen: i64,
+  /// The checks they were seen in, times the time between checks
+  pub playtime_seconds: u64,
+}
+
+/// The uuid as Mojang has it, the status ping has dashes in it
+fn undashed_uuid(uuid: &str) -> String {
+  uuid.replace('-', "").to_lowercase()
+}
+
+/// What the bot keeps about one of the other guilds
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct GuildState {
+  /// The Verified role it found or created, when the config doesn't name one
+  pub role_id: Option<u64>,
+  pub verify_message_id: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct StoreData {
+  pub status: Option<StatusState>,
+  /// The pinned status embed in the status channel
+  pub status_message_id: Option<u64>,
+  /// The Verified role, so it's still found after being renamed
+  pub verified_role_id: Option<u64>,
+  /// The verify info message in the verify channel
+  pub verify_message_id: Option<u64>,
+  /// The role and verify info message of each of the other guilds, by guild id
+  pub guilds: HashMap<u64, GuildState>,
+  pub links: LinkStore,
+  pub moderation: Vec<ModerationAction>,
+  pub pending_verifications: Vec<PendingVerification>,
+  pub unvalidated: Vec<UnvalidatedVerification>,
+  pub link_codes: Vec<LinkCode>,
+  pub web_verify_tokens: Vec<WebVerifyToken>,
+  pub unmanaged: Vec<UnmanagedEntry>,
+  pub unclaimed: Vec<UnclaimedEntry>,
+  pub pending_operations: Vec<PendingOperation>,
+  /// When each successful verification happened, as Unix timestamps in seconds
+  pub verifications: Vec<i64>,
+  pub daily_peaks: Vec<DailyPeak>,
+  /// The latest days, oldest first
+  pub daily_activity: Vec<DailyActivity>,
+  pub sightings: Vec<PlayerSighting>,
+}
+
+impl StoreData {
+  /// Queues the operation, unless the last one queued for the account is the same. Returns
+  /// whether it was queued
+  pub fn queue_operation(&mut self, operation: PendingOperation) -> bool {
+    let last = self
+      .pending_operations
+      .iter()
+      .rfind(|queued| queued.is_for(&operation.minecraft_name, operation.server.as_deref()));
+    if last.is_some_and(|last| {
+      last.kind == operation.kind
+        && last.discord_id == operation.discord_id
+        && last.forget_link == operation.forget_link
+    }) {
+      return false;
+    }
+
+    self.pending_operations.push(operation);
+    true
+  }
+
+  /// Whether the operation no longer matches the links, like the removal of an account that was
+  /// verified again since. Stale operations are dropped instead of replayed
+  pub fn is_stale(&self, operation: &PendingOperation) -> bool {
+    let server = operation.server.as_deref();
+    let link = self.links.iter().find(|link| {
+      link.minecraft_name == operation.minecraft_name && link.server.as_deref() == server
+    });
+
+    match operation.kind {
+      OperationKind::WhitelistAdd => {
+        let unvalidated = self.unvalidated.iter().any(|verification| {
+          verification.minecraft_name == operation.minecraft_name
+            && verification.server.as_deref() == server
+        });
+        !unvalidated && !link.is_some_and(Link::is_active)
+      }
+      // A member who left keeps an active link until the removal ran, so only a newer link counts
+      OperationKind::WhitelistRemove => link.is_some_and(|link| {
+        link.is_active() && (!operation.forget_link || link.linked_at > operation.queued_at)
+      }),
+      OperationKind::Ban => false,
+      OperationKind::Pardon => link.is_some_and(|link| link.banned),
+    }
+  }
+
+  pub fn verifications_since(&self, since: i64) -> usize {
+    self
+      .verifications
+      .iter()
+      .filter(|verified_at| **verified_at >= since)
+      .count()
+  }
+
+  /// Raises the day's peak if more players are online than before
+  pub fn record_players(&mut self, day: NaiveDate, players: u32) {
+    match self.daily_peaks.iter_mut().find(|peak| peak.day == day) {
+      Some(peak) => peak.players = peak.players.max(players),
+      None => {
+        self.daily_peaks.push(DailyPeak { day, players });
+        if self.daily_peaks.len() > DAILY_PEAK_DAYS {
+          self.daily_peaks.remove(0);
+        }
+      }
+    }
+  }
+
+  pub fn peak_players_since(&self, since: NaiveDate) -> Option<&DailyPeak> {
+    self
+      .daily_peaks
+      .iter()
+      .filter(|peak| peak.day >= since)
+      .max_by_key(|peak| peak.players)
+  }
+
+  pub fn activity(&self, day: NaiveDate) -> Option<&DailyActivity> {
+    self
+      .daily_activity
+      .iter()
+      .find(|activity| activity.day == day)
+  }
+
+  /// The day's activity, started if there is none yet. Only the latest days are kept
+  fn activity_mut(&mut self, day: NaiveDate) -> &mut DailyActivity {
+    match self
+      .daily_activity
+      .iter()
+      .position(|activity| activity.day == day)
+    {
+      Some(position) => &mut self.daily_activity[position],
+      None => {
+        self.daily_activity.push(DailyActivity {
+          day,
+          ..DailyActivity::default()
+        });
+        if self.daily_activity.len() > DAILY_ACTIVITY_DAYS {
+          self.daily_activity.remove(0);
+        }
+        self.daily_activity.last_mut().unwrap()
+      }
+    }
+  }
+
+  /// Counts a status check covering `interval` seconds towards the day. `players` are the ones it
+  /// listed, by name and uuid if the check had it
+  pub fn record_activity(
+    &mut self,
+    day: NaiveDate,
+    now: i64,
+    interval: u64,
+    online: bool,
+    players_online: u32,
+    players: &[(String, Option<String>)],
+  ) {
+    let activity = self.activity_mut(day);
+    activity.observed_seconds += interval;
+    if !online {
+      activity.offline_seconds += interval;
+      return;
+    }
+
+    if players_online > activity.peak_players {
+      activity.peak_players = players_online;
+      activity.peak_at = Some(now);
+    }
+    for (name, uuid) in players {
+      let key = uuid
+        .as_deref()
+        .map_or_else(|| name.to_lowercase(), undashed_uuid);
+      if !activity.players.contains(&key) {
+        activity.players.push(key);
+      }
+    }
+  }
+
+  pub fn record_verification(&mut self, day: NaiveDate) {
+    self.activity_mut(day).verifications += 1;
+  }
+
+  /// Notes that the bot started again, if it already saw part of the day before
+  pub fn record_restart(&mut self, day: NaiveDate) {
+    if let Some(activity) = self
+      .daily_activity
+      .iter_mut()
+      .find(|activity| activity.day == day)
+    {
+      activity.restarted = true;
+    }
+  }
+
+  /// Counts a status check the players were seen online in, by name and uuid if the check had
+  /// it. A player is found by their uuid, so a rename keeps their playtime, and by name only when
+  /// one of the two uuids isn't known
+  pub fn record_sightings(
+    &mut self,
+    players: &[(String, Option<String>)],
+    now: i64,
+    interval: u64,
+  ) {
+    for (name, uuid) in players {
+      let uuid = uuid.as_deref().map(undashed_uuid);
+      let position = self
+        .sightings
+        .iter()
+        .position(|sighting| uuid.is_some() && sighting.uuid == uuid)
+        .or_else(|| {
+          self.sightings.iter().position(|sighting| {
+            sighting.name.eq_ignore_ascii_case(name) && (sighting.uuid.is_none() || uuid.is_none())
+          })
+        });
+
+      match position {
+        Some(position) => {
+          let sighting = &mut self.sightings[position];
+          sighting.name.clone_from(name);
+          sighting.uuid = uuid.or(sighting.uuid.take());
+          sighting.last_seen = now;
+          sighting.playtime_seconds += interval;
+        }
+        None => self.sightings.push(PlayerSighting {
+          uuid,
+          name: name.clone(),
+          last_seen: now,
+          playtime_seconds: interval,
+        }),
+      }
+    }
+  }
+
+  /// Forgets the players not seen since then
+  pub fn prune_sightings(&mut self, before: i64) {
+    self
+      .sightings
+      .retain(|sighting| sighting.last_seen >= before);
+  }
+
+  /// The player by uuid if it's known, otherwise the one last seen with the name
+  pub fn sighting(&self, name: &str, uuid: Option<&str>) -> Option<&PlayerSighting> {
+    let uuid = uuid.map(undashed_uuid);
+    self
+      .sightings
+      .iter()
+      .find(|sighting| uuid.is_some() && sighting.uuid == uuid)
+      .or_else(|| {
+        self
+          .sightings
+          .iter()
+          .filter(|sighting| sighting.name.eq_ignore_ascii_case(name))
+          .max_by_key(|sighting| sighting.last_seen)
+      })
+  }
+}
+
+pub struct Store {
+  path: PathBuf,
+  data: Mutex<StoreData>,
+}
+
+impl Store {
+  /// Loads the store from the file. A missing or corrupted file results in an empty store
+  pub fn load(path: impl Into<PathBuf>) -> Self {
+    let path = path.into();
+
+    let data = match fs::read_to_string(&path) {
+      Ok(json) => serde_json::from_str(&json).unwrap_or_else(|error| {
+        warn!(
+          "Couldn't parse the store at {}, starting with an empty one: {error}",
+          path.display()
+        );
+        StoreData::default()
+      }),
+      Err(_) => StoreData::default(),
+    };
+
+    Self {
+      path,
+      data: Mutex::new(data),
+    }
+  }
+
+  pub fn read<T>(&self, read: impl FnOnce(&StoreData) -> T) -> T {
+    read(&self.data.lock().unwrap())
+  }
+
+  /// Changes the data and saves it to the file right away
+  pub fn write<T>(&self, write: impl FnOnce(&mut StoreData) -> T) -> T {
+    let mut data = self.data.lock().unwrap();
+    let result = write(&mut data);
+
+    if let Err(error) = self.save(&data) {
+      warn!(
+        "Couldn't save the store to {}: {error}",
+        self.path.display()
+      );
+    }
+
+    result
+  }
+
+  /// Saves the data once more, e.g. before the bot exits. Waits for a write that's in progress
+  pub fn flush(&self) {
+    self.write(|_| ());
+  }
+
+  /// Writes to a temporary file first, so a crash mid-write can't corrupt the store
+  fn save(&self, data: &StoreData) -> std::io::Result<()> {
+    let json = serde_json::to_string_pretty(data)?;
+    let temporary_path = self.path.with_extension("tmp");
+
+    fs::write(&temporary_path, json)?;
+    fs::rename(&temporary_path, &self.path)
+  }
+}
@@ -0,0 +1,177 @@
+//! A bare-bones HTTP/1.1 server side, just enough for the web verification callback. Requests
+//! are small GETs from a companion website, so there is no need for a full HTTP stack
+
+use std::collections::HashMap;
+use std::io;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+/// Requests are tiny, anything bigger isn't a callback
+const MAX_HEAD_LENGTH: usize = 8 * 1024;
+
+#[derive(Debug)]
+pub struct Request {
+  pub method: String,
+  pub path: String,
+  pub query: HashMap<String, String>,
+  /// Lowercase header names
+  pub headers: HashMap<String, String>,
+}
+
+fn invalid_data(message: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Decodes `%XX` escapes and `+` in a query string component
+fn percent_decode(text: &str) -> String {
+  let bytes = text.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut index = 0;
+
+  while index < bytes.len() {
+    match bytes[index] {
+      b'+' => decoded.push(b' '),
+      b'%' => {
+        let escaped = bytes
+          .get(index + 1..index + 3)
+          .and_then(|hex| std::str::from_utf8(hex).ok())
+          .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match escaped {
+          Some(byte) => {
+            decoded.push(byte);
+            index += 2;
+          }
+          None => decoded.push(b'%'),
+        }
+      }
+      byte => decoded.push(byte),
+    }
+    index += 1;
+  }
+
+  String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+  query
+    .split('&')
+    .filter(|pair| !pair.is_empty())
+    .map(|pair| {
+      let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+      (percent_decode(key), percent_decode(value))
+    })
+    .collect()
+}
+
+/// Reads the request line and the headers. The body is ignored
+pub async fn read_request(stream: impl AsyncRead + Unpin) -> io::Result<Request> {
+  // One byte more than allowed, so a head that's too long can be told apart from one that just fits
+  let mut reader = BufReader::new(stream.take(MAX_HEAD_LENGTH as u64 + 1));
+  let mut head_length = 0;
+
+  let mut request_line = String::new();
+  head_length += reader.read_line(&mut request_line).await?;
+  if head_length > MAX_HEAD_LENGTH {
+    return Err(invalid_data("request head is too long"));
+  }
+
+  let mut parts = request_line.split_whitespace();
+  let method = parts
+    .next()
+    .ok_or_else(|| invalid_data("missing method"))?
+    .to_string();
+  let target = parts.next().ok_or_else(|| invalid_data("missing path"))?;
+  let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+  let mut headers = HashMap::new();
+  loop {
+    let mut line = String::new();
+    let length = reader.read_line(&mut line).await?;
+    head_length += length;
+
+    if head_length > MAX_HEAD_LENGTH {
+      return Err(invalid_data("request head is too long"));
+    }
+    if length == 0 || line.trim().is_empty() {
+      break;
+    }
+
+    if let Some((name, value)) = line.split_once(':') {
+      headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+    }
+  }
+
+  Ok(Request {
+    method,
+    path: path.to_string(),
+    query: parse_query(query),
+    headers,
+  })
+}
+
+pub async fn write_response(stream: &mut TcpStream, status: u16, body: &str) -> io::Result<()> {
+  let reason = match status {
+    200 => "OK",
+    400 => "Bad Request",
+    401 => "Unauthorized",
+    404 => "Not Found",
+    405 => "Method Not Allowed",
+    503 => "Service Unavailable",
+    _ => "Error",
+  };
+
+  let response = format!(
+    "HTTP/1.1 {status} {reason}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+    body.len()
+  );
+
+  stream.write_all(response.as_bytes()).await?;
+  stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn query_values_are_decoded() {
+    let query = parse_query("token=ab%2Fcd&username=Some+Name&flag");
+
+    assert_eq!(query["token"], "ab/cd");
+    assert_eq!(query["username"], "Some Name");
+    assert_eq!(query["flag"], "");
+  }
+
+  #[tokio::test]
+  async fn requests_are_read() {
+    let request = read_request(
+      &b"GET /callback?token=abc HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n"[..],
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(request.method, "GET");
+    assert_eq!(request.path, "/callback");
+    assert_eq!(request.query["token"], "abc");
+    assert_eq!(request.headers["authorization"], "Bearer secret");
+  }
+
+  #[tokio::test]
+  async fn oversized_heads_are_rejected() {
+    let long_header = format!("X-Padding: {}\r\n", "a".repeat(MAX_HEAD_LENGTH));
+    let head = format!("GET /callback HTTP/1.1\r\n{long_header}\r\n");
+    let error = read_request(head.as_bytes()).await.unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+    let long_line = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_HEAD_LENGTH));
+    let error = read_request(long_line.as_bytes()).await.unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+  }
+
+  #[test]
+  fn broken_escapes_are_kept() {
+    assert_eq!(percent_decode("100%"), "100%");
+    assert_eq!(percent_decode("%zz"), "%zz");
+  }
+}
@@ -0,0 +1,159 @@
+//! Profile lookups against a small in-process HTTP server standing in for the Mojang API
+
+use mc_discord_bot::mojang::{fetch_mojang_profile_from, http_client, MojangError, MojangProfile};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::time;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The answer to a lookup of the name, as the status line, extra headers and the body
+fn answer(name: &str) -> (&'static str, &'static str, String) {
+  match name {
+    "Alice" => (
+      "200 OK",
+      "Content-Type: application/json\r\n",
+      r#"{"id": "069a79f444e94726a5befca90e38aaf5", "name": "Alice"}"#.to_string(),
+    ),
+    "Nobody" => (
+      "404 Not Found",
+      "Content-Type: application/json\r\n",
+      r#"{"path": "/users/profiles/minecraft/Nobody", "errorMessage": "Couldn't find any profile with name Nobody"}"#.to_string(),
+    ),
+    "Busy" => ("429 Too Many Requests", "Retry-After: 30\r\n", String::new()),
+    "Broken" => (
+      "200 OK",
+      "Content-Type: application/json\r\n",
+      "{\"id\": ".to_string(),
+    ),
+    // Far more than a profile, like a misbehaving proxy
+    "Huge" => (
+      "200 OK",
+      "Content-Type: application/json\r\n",
+      format!(r#"{{"id": "{}", "name": "Huge"}}"#, "0".repeat(1024 * 1024)),
+    ),
+    _ => (
+      "502 Bad Gateway",
+      "Content-Type: text/html\r\n",
+      "<html>Bad Gateway</html>".to_string(),
+    ),
+  }
+}
+
+/// Starts a server that accepts connections and never answers, and returns its URL
+async fn start_silent_server() -> String {
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let port = listener.local_addr().unwrap().port();
+
+  tokio::spawn(async move {
+    while let Ok((stream, _)) = listener.accept().await {
+      tokio::spawn(async move {
+        time::sleep(Duration::from_secs(60)).await;
+        drop(stream);
+      });
+    }
+  });
+
+  format!("http://127.0.0.1:{port}/profiles/")
+}
+
+/// Starts the server and returns the URL the names are appended to
+async fn start_server() -> String {
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let port = listener.local_addr().unwrap().port();
+
+  tokio::spawn(async move {
+    while let Ok((mut stream, _)) = listener.accept().await {
+      tokio::spawn(async move {
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        while !request.ends_with(b"\r\n\r\n") {
+          let read = stream.read(&mut buffer).await.unwrap();
+          if read == 0 {
+            return;
+          }
+          request.extend_from_slice(&buffer[..read]);
+        }
+
+        // Like "GET /profiles/Alice HTTP/1.1"
+        let request = String::from_utf8_lossy(&request);
+        let path = request.split(' ').nth(1).unwrap_or_default();
+        let name = path.rsplit('/').next().unwrap_or_default();
+
+        let (status, headers, body) = answer(name);
+        let response = format!(
+          "HTTP/1.1 {status}\r\n{headers}Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+          body.len()
+        );
+        let _ = stream.write_all(response.as_bytes()).await;
+      });
+    }
+  });
+
+  format!("http://127.0.0.1:{port}/profiles/")
+}
+
+#[tokio::test]
+async fn profiles_are_found() {
+  let api_url = start_server().await;
+
+  assert_eq!(
+    fetch_mojang_profile_from(&http_client(TIMEOUT), &api_url, "Alice").await,
+    Ok(MojangProfile {
+      id: "069a79f444e94726a5befca90e38aaf5".to_string(),
+      name: "Alice".to_string(),
+    })
+  );
+}
+
+#[tokio::test]
+async fn unknown_names_are_not_found() {
+  let api_url = start_server().await;
+
+  assert_eq!(
+    fetch_mojang_profile_from(&http_client(TIMEOUT), &api_url, "Nobody").await,
+    Err(MojangError::NotFound)
+  );
+}
+
+#[tokio::test]
+async fn rate_limits_are_reported_with_the_wait() {
+  let api_url = start_server().await;
+
+  assert_eq!(
+    fetch_mojang_profile_from(&http_client(TIMEOUT), &api_url, "Busy").await,
+    Err(MojangError::RateLimited {
+      retry_after: Some(Duration::from_secs(30))
+    })
+  );
+}
+
+#[tokio::test]
+async fn malformed_answers_are_upstream_problems() {
+  let api_url = start_server().await;
+
+  for name in ["Broken", "Gateway", "Huge"] {
+    let error = fetch_mojang_profile_from(&http_client(TIMEOUT), &api_url, name)
+      .await
+      .unwrap_err();
+    assert!(matches!(error, MojangError::Network(_)), "{name}: {error}");
+  }
+}
+
+#[tokio::test]
+async fn a_silent_api_times_out() {
+  let api_url = start_silent_server().await;
+
+  let started = Instant::now();
+  let error =
+    fetch_mojang_profile_from(&http_client(Duration::from_millis(200)), &api_url, "Alice")
+      .await
+      .unwrap_err();
+
+  assert!(
+    matches!(&error, MojangError::Network(reason) if reason.contains("timed out")),
+    "{error}"
+  );
+  assert!(started.elapsed() < TIMEOUT);
+}
@@ -0,0 +1,220 @@
+//! The RCON helpers against a small in-process server that speaks the protocol
+
+use mc_discord_bot::rcon::{self, is_rcon_auth_failure, is_unanswered};
+use std::io;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time;
+
+const PASSWORD: &str = "hunter2";
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Short, so the tests of servers that never answer don't take long
+const SHORT_TIMEOUT: Duration = Duration::from_millis(200);
+
+const RESPONSE: i32 = 0;
+const COMMAND: i32 = 2;
+const LOGIN: i32 = 3;
+
+async fn read_packet(stream: &mut TcpStream) -> io::Result<(i32, i32, String)> {
+  let length = stream.read_i32_le().await?;
+  let request_id = stream.read_i32_le().await?;
+  let packet_type = stream.read_i32_le().await?;
+
+  let mut payload = vec![0; length as usize - 8];
+  stream.read_exact(&mut payload).await?;
+  // The payload ends with two null bytes
+  payload.truncate(payload.len() - 2);
+
+  Ok((request_id, packet_type, String::from_utf8(payload).unwrap()))
+}
+
+async fn write_packet(
+  stream: &mut TcpStream,
+  request_id: i32,
+  packet_type: i32,
+  payload: &str,
+) -> io::Result<()> {
+  let mut packet = Vec::new();
+  packet.extend_from_slice(&(payload.len() as i32 + 10).to_le_bytes());
+  packet.extend_from_slice(&request_id.to_le_bytes());
+  packet.extend_from_slice(&packet_type.to_le_bytes());
+  packet.extend_from_slice(payload.as_bytes());
+  packet.extend_from_slice(&[0, 0]);
+  stream.write_all(&packet).await
+}
+
+/// Logs the client in, then answers `list` like a server with one player online. After
+/// `commands_per_connection` commands it hangs up, like a restarting server
+async fn serve_connection(mut stream: TcpStream, commands_per_connection: usize) -> io::Result<()> {
+  let (request_id, packet_type, password) = read_packet(&mut stream).await?;
+  assert_eq!(packet_type, LOGIN);
+
+  // The login answer has the type of a command, and a request id of -1 for a wrong password
+  if password != PASSWORD {
+    return write_packet(&mut stream, -1, COMMAND, "").await;
+  }
+  write_packet(&mut stream, request_id, COMMAND, "").await?;
+
+  for _ in 0..commands_per_connection {
+    let (request_id, packet_type, command) = read_packet(&mut stream).await?;
+    assert_eq!(packet_type, COMMAND);
+
+    let output = match command.as_str() {
+      "list" => "There are 1 of a max of 20 players online: Alice",
+      _ => "Unknown command",
+    };
+    write_packet(&mut stream, request_id, RESPONSE, output).await?;
+  }
+
+  Ok(())
+}
+
+/// Starts a server that accepts connections and never answers. With `log_in` it answers the
+/// login first, so only the commands go unanswered
+async fn start_silent_server(log_in: bool) -> u16 {
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let port = listener.local_addr().unwrap().port();
+
+  tokio::spawn(async move {
+    while let Ok((mut stream, _)) = listener.accept().await {
+      tokio::spawn(async move {
+        if log_in {
+          let (request_id, _, _) = read_packet(&mut stream).await?;
+          write_packet(&mut stream, request_id, COMMAND, "").await?;
+        }
+        // Keeps the connection open without a word
+        time::sleep(Duration::from_secs(60)).await;
+        io::Result::Ok(())
+      });
+    }
+  });
+
+  port
+}
+
+/// Starts the server and returns its port
+async fn start_server(commands_per_connection: usize) -> u16 {
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let port = listener.local_addr().unwrap().port();
+
+  tokio::spawn(async move {
+    while let Ok((stream, _)) = listener.accept().await {
+      tokio::spawn(serve_connection(stream, commands_per_connection));
+    }
+  });
+
+  port
+}
+
+#[tokio::test]
+async fn commands_run_after_logging_in() {
+  let port = start_server(usize::MAX).await;
+
+  let mut rcon_client = rcon::connect("127.0.0.1", port, TIMEOUT).await.unwrap();
+  rcon::authenticate(&mut rcon_client, PASSWORD, TIMEOUT)
+    .await
+    .unwrap();
+  let output = rcon_client.run_command("list").await.unwrap();
+
+  let players = rcon::parse_list_output(&output).unwrap();
+  assert_eq!(players.online, 1);
+  assert_eq!(players.names, ["Alice"]);
+}
+
+#[tokio::test]
+async fn wrong_passwords_are_auth_failures() {
+  let port = start_server(usize::MAX).await;
+
+  let mut rcon_client = rcon::connect("127.0.0.1", port, TIMEOUT).await.unwrap();
+  let error = rcon::authenticate(&mut rcon_client, "wrong", TIMEOUT)
+    .await
+    .unwrap_err();
+
+  assert!(is_rcon_auth_failure(&error));
+}
+
+#[tokio::test]
+async fn a_dropped_connection_is_replaced_once() {
+  let port = start_server(1).await;
+  let connect = || async {
+    let mut rcon_client = rcon::connect("127.0.0.1", port, TIMEOUT).await?;
+    rcon::authenticate(&mut rcon_client, PASSWORD, TIMEOUT).await?;
+    Ok(rcon_client)
+  };
+
+  let mut connection = None;
+  for _ in 0..3 {
+    let output = rcon::run_on_connection(&mut connection, "list", TIMEOUT, connect)
+      .await
+      .unwrap();
+    assert!(output.contains("Alice"));
+  }
+}
+
+#[tokio::test]
+async fn an_unreachable_server_is_an_error() {
+  // Nothing listens on the port anymore once the listener is dropped
+  let port = {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap().port()
+  };
+
+  let mut connection = None;
+  let result = rcon::run_on_connection(&mut connection, "list", TIMEOUT, || {
+    rcon::connect("127.0.0.1", port, TIMEOUT)
+  })
+  .await;
+
+  assert!(result.is_err());
+  assert!(connection.is_none());
+}
+
+#[tokio::test]
+async fn a_silent_login_times_out() {
+  let port = start_silent_server(false).await;
+
+  let mut rcon_client = rcon::connect("127.0.0.1", port, TIMEOUT).await.unwrap();
+  let started = Instant::now();
+  let error = rcon::authenticate(&mut rcon_client, PASSWORD, SHORT_TIMEOUT)
+    .await
+    .unwrap_err();
+
+  assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+  assert!(is_unanswered(&error));
+  assert!(started.elapsed() < TIMEOUT);
+}
+
+#[tokio::test]
+async fn a_silent_command_times_out_and_drops_the_connection() {
+  let port = start_silent_server(true).await;
+  let connect = || async {
+    let mut rcon_client = rcon::connect("127.0.0.1", port, TIMEOUT).await?;
+    rcon::authenticate(&mut rcon_client, PASSWORD, TIMEOUT).await?;
+    Ok(rcon_client)
+  };
+
+  let mut connection = None;
+  let started = Instant::now();
+  let error = rcon::run_on_connection(&mut connection, "list", SHORT_TIMEOUT, connect)
+    .await
+    .unwrap_err();
+
+  assert!(is_unanswered(&error));
+  assert!(connection.is_none());
+  assert!(started.elapsed() < TIMEOUT);
+}
+
+#[tokio::test]
+async fn an_unreachable_server_is_not_a_silent_one() {
+  let port = {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap().port()
+  };
+
+  let error = rcon::connect("127.0.0.1", port, TIMEOUT).await.unwrap_err();
+
+  assert!(!is_unanswered(&error));
+}
@@ -0,0 +1,102 @@
+//! Last seen, the sampled playtime and the daily activity, from the players the status checks see
+
+use chrono::{Days, NaiveDate};
+use mc_discord_bot::store::{Store, StoreData};
+
+const NOTCH_UUID: &str = "069a79f4-44e9-4726-a5be-fca90e38aaf5";
+
+#[test]
+fn renamed_players_keep_their_playtime() {
+  let mut data = StoreData::default();
+
+  data.record_sightings(
+    &[("Notch".to_string(), Some(NOTCH_UUID.to_string()))],
+    100,
+    60,
+  );
+  // RCON `list` has no uuids, the name is enough
+  data.record_sightings(&[("notch".to_string(), None)], 160, 60);
+  data.record_sightings(
+    &[("Notch2".to_string(), Some(NOTCH_UUID.to_string()))],
+    220,
+    60,
+  );
+
+  let sighting = data
+    .sighting("Notch", Some("069a79f444e94726a5befca90e38aaf5"))
+    .unwrap();
+  assert_eq!(sighting.name, "Notch2");
+  assert_eq!(sighting.last_seen, 220);
+  assert_eq!(sighting.playtime_seconds, 180);
+  assert_eq!(data.sightings.len(), 1);
+}
+
+#[test]
+fn sightings_survive_restarts_until_pruned() {
+  let path = std::env::temp_dir().join(format!(
+    "mc_discord_bot_sightings_{}.json",
+    std::process::id()
+  ));
+
+  Store::load(&path).write(|data| {
+    data.record_sightings(&[("Alice".to_string(), None)], 100, 60);
+    data.record_sightings(&[("Bob".to_string(), None)], 500, 60);
+  });
+  let store = Store::load(&path);
+  store.write(|data| data.prune_sightings(200));
+  let names: Vec<String> = Store::load(&path).read(|data| {
+    data
+      .sightings
+      .iter()
+      .map(|sighting| sighting.name.clone())
+      .collect()
+  });
+  let _ = std::fs::remove_file(&path);
+
+  assert_eq!(names, ["Bob"]);
+}
+
+#[test]
+fn daily_activity_counts_peaks_players_and_downtime() {
+  let mut data = StoreData::default();
+  let day = NaiveDate::from_ymd_opt(2026, 10, 14).unwrap();
+  let players = |names: &[&str]| -> Vec<(String, Option<String>)> {
+    names.iter().map(|name| (name.to_string(), None)).collect()
+  };
+
+  data.record_activity(day, 100, 60, true, 1, &players(&["Alice"]));
+  data.record_activity(day, 160, 60, true, 2, &players(&["alice", "Bob"]));
+  data.record_activity(day, 220, 60, true, 2, &players(&["Bob", "Carol"]));
+  data.record_activity(day, 280, 60, false, 0, &[]);
+  data.record_verification(day);
+
+  let activity = data.activity(day).unwrap();
+  assert_eq!(activity.peak_players, 2);
+  // The first time the peak was reached
+  assert_eq!(activity.peak_at, Some(160));
+  assert_eq!(activity.players, ["alice", "bob", "carol"]);
+  assert_eq!(activity.observed_seconds, 240);
+  assert_eq!(activity.offline_seconds, 60);
+  assert_eq!(activity.verifications, 1);
+  assert!(activity.is_partial());
+}
+
+#[test]
+fn daily_activity_keeps_a_week_and_notes_restarts() {
+  let mut data = StoreData::default();
+  let first_day = NaiveDate::from_ymd_opt(2026, 10, 1).unwrap();
+
+  for offset in 0..10 {
+    data.record_activity(first_day + Days::new(offset), 0, 24 * 60 * 60, true, 0, &[]);
+  }
+  let last_day = first_day + Days::new(9);
+  // Starting on a day without checks isn't a restart
+  data.record_restart(last_day + Days::new(1));
+  data.record_restart(last_day);
+
+  assert_eq!(data.daily_activity.len(), 7);
+  assert!(data.activity(first_day).is_none());
+  assert!(!data.activity(last_day - Days::new(1)).unwrap().is_partial());
+  assert!(data.activity(last_day).unwrap().is_partial());
+  assert!(data.activity(last_day + Days::new(1)).is_none());
+}
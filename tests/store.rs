@@ -0,0 +1,88 @@
+//! The queue of whitelist changes made while the server was offline
+
+use mc_discord_bot::store::{Link, OperationKind, PendingOperation, Platform, Store, StoreData};
+
+fn operation(kind: OperationKind, name: &str, queued_at: i64) -> PendingOperation {
+  PendingOperation {
+    kind,
+    discord_id: 1,
+    minecraft_name: name.to_string(),
+    server: None,
+    forget_link: kind == OperationKind::WhitelistRemove,
+    queued_at,
+  }
+}
+
+fn link(name: &str, linked_at: i64) -> Link {
+  Link {
+    discord_id: 1,
+    uuid: format!("{name}-uuid"),
+    minecraft_name: name.to_string(),
+    linked_at,
+    expiry_warned: false,
+    server: None,
+    suspended: false,
+    banned: false,
+    platform: Platform::Java,
+    xuid: None,
+  }
+}
+
+#[test]
+fn identical_consecutive_operations_are_queued_once() {
+  let mut data = StoreData::default();
+
+  assert!(data.queue_operation(operation(OperationKind::Ban, "Alice", 10)));
+  assert!(!data.queue_operation(operation(OperationKind::Ban, "Alice", 20)));
+  assert!(data.queue_operation(operation(OperationKind::Ban, "Bob", 20)));
+  assert!(data.queue_operation(operation(OperationKind::Pardon, "Alice", 30)));
+  assert!(data.queue_operation(operation(OperationKind::Ban, "Alice", 40)));
+
+  assert_eq!(data.pending_operations.len(), 4);
+}
+
+#[test]
+fn removals_of_accounts_verified_again_are_stale() {
+  let mut data = StoreData::default();
+  data.links.upsert(link("Alice", 100));
+
+  assert!(!data.is_stale(&operation(OperationKind::WhitelistRemove, "Alice", 200)));
+  assert!(data.is_stale(&operation(OperationKind::WhitelistRemove, "Alice", 50)));
+  assert!(!data.is_stale(&operation(OperationKind::WhitelistAdd, "Alice", 50)));
+  assert!(data.is_stale(&operation(OperationKind::WhitelistAdd, "Bob", 50)));
+}
+
+#[test]
+fn accounts_linked_to_someone_else_are_not_taken_over() {
+  let mut data = StoreData::default();
+  assert_eq!(data.links.link(link("Alice", 100)), Ok(()));
+  // The same user linking again only updates the link
+  assert_eq!(data.links.link(link("Alice", 200)), Ok(()));
+
+  let taken = Link {
+    discord_id: 2,
+    ..link("Alice", 300)
+  };
+  assert_eq!(data.links.link(taken.clone()), Err(1));
+  assert_eq!(data.links.by_uuid("Alice-uuid").unwrap().discord_id, 1);
+  assert_eq!(data.links.by_uuid("Alice-uuid").unwrap().linked_at, 200);
+
+  // Each server has its own links
+  let other_server = Link {
+    server: Some("creative".to_string()),
+    ..taken
+  };
+  assert_eq!(data.links.link(other_server), Ok(()));
+}
+
+#[test]
+fn the_queue_survives_restarts() {
+  let path = std::env::temp_dir().join(format!("mc_discord_bot_queue_{}.json", std::process::id()));
+  let queued = operation(OperationKind::WhitelistRemove, "Alice", 10);
+
+  Store::load(&path).write(|data| data.queue_operation(queued.clone()));
+  let operations = Store::load(&path).read(|data| data.pending_operations.clone());
+  let _ = std::fs::remove_file(&path);
+
+  assert_eq!(operations, [queued]);
+}